@@ -0,0 +1,82 @@
+//! Measures a delta sync finding no changes, the most common sync of
+//! connections scheduled every few minutes, which should end as soon as the
+//! deployment responds.
+//!
+//! Besides the sync, each iteration makes the calls that an update request
+//! makes before syncing an existing connection: checking the credentials and
+//! upgrading the state sent by Fivetran. Existing connections don’t list the
+//! tables of the deployment, so `get_tables_and_columns` isn’t called. The
+//! gRPC layer, the parsing of the configuration and the HTTP client of
+//! `ConvexConnector` are excluded: they need a real deployment.
+//!
+//! Run with `cargo bench --features test-util`.
+
+use convex_fivetran_source::{
+    convex_api::Source,
+    schema_cache::SchemaCache,
+    sync::{
+        sync,
+        State,
+        SyncOptions,
+        UpdateMessage,
+    },
+    testing::FakeSource,
+};
+use criterion::{
+    criterion_group,
+    criterion_main,
+    Criterion,
+};
+use futures::TryStreamExt;
+use tokio::runtime::Runtime;
+
+/// Syncs the source and returns the messages emitted.
+fn run_sync(runtime: &Runtime, source: &FakeSource, state: Option<State>) -> Vec<UpdateMessage> {
+    runtime
+        .block_on(
+            sync(
+                source.clone(),
+                state,
+                SyncOptions::default(),
+                SchemaCache::default(),
+            )
+            .try_collect(),
+        )
+        .expect("The sync failed")
+}
+
+/// Handles an update request of an existing connection, from the state JSON
+/// sent by Fivetran, like `ConvexConnector` does against a deployment.
+fn run_update(runtime: &Runtime, source: &FakeSource, state_json: &str) -> Vec<UpdateMessage> {
+    let state = runtime
+        .block_on(async {
+            source.test_streaming_export_connection().await?;
+            State::upgrade(serde_json::from_str(state_json)?, source).await
+        })
+        .expect("The update request failed");
+    run_sync(runtime, source, Some(state))
+}
+
+fn empty_delta_sync(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("Failed to start the runtime");
+    let source = FakeSource::seeded();
+    let state = run_sync(&runtime, &source, None)
+        .into_iter()
+        .filter_map(|message| match message {
+            UpdateMessage::Checkpoint(state) => Some(state),
+            _ => None,
+        })
+        .last();
+    let state_json = serde_json::to_string(&state.expect("The initial sync emitted no checkpoint"))
+        .expect("Failed to serialize the state");
+
+    c.bench_function("empty_delta_sync", |b| {
+        b.iter(|| run_update(&runtime, &source, &state_json))
+    });
+}
+
+criterion_group!(benches, empty_delta_sync);
+criterion_main!(benches);
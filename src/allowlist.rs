@@ -0,0 +1,125 @@
+use std::{
+    collections::HashSet,
+    path::PathBuf,
+};
+
+use anyhow::Context;
+use url::Url;
+
+use crate::log_warning;
+
+/// A list of deployment origins (e.g. `http://localhost:3210`) that connections
+/// may use even when the connector doesn’t allow all hosts. This lets operators
+/// permit individual self-hosted deployments without restarting the connector
+/// with `--allow-all-hosts`.
+///
+/// The list is read from a file containing one origin per line (lines starting
+/// with `#` are ignored). The file is read again every time a connection is
+/// validated, so it can be edited while the connector is running.
+#[derive(Debug)]
+pub struct HostAllowlist {
+    path: PathBuf,
+}
+
+impl HostAllowlist {
+    pub fn new(path: PathBuf) -> Self {
+        HostAllowlist { path }
+    }
+
+    /// Returns true if the origin of the given deployment URL is in the
+    /// allowlist. An allowlist file that can’t be read allows nothing.
+    pub fn allows(&self, deploy_url: &Url) -> bool {
+        match self.read() {
+            Ok(origins) => origins.contains(&deploy_url.origin().ascii_serialization()),
+            Err(error) => {
+                log_warning(&format!("{error:#}"));
+                false
+            },
+        }
+    }
+
+    fn read(&self) -> anyhow::Result<HashSet<String>> {
+        let contents = std::fs::read_to_string(&self.path).with_context(|| {
+            format!(
+                "Couldn’t read the host allowlist at {}",
+                self.path.display()
+            )
+        })?;
+        parse_allowlist(&contents)
+    }
+}
+
+fn parse_allowlist(contents: &str) -> anyhow::Result<HashSet<String>> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(parse_origin)
+        .collect()
+}
+
+/// Normalizes an origin of the allowlist (e.g. `https://convex.example.com/`
+/// becomes `https://convex.example.com`).
+pub fn parse_origin(origin: &str) -> anyhow::Result<String> {
+    let url = Url::parse(origin)
+        .with_context(|| format!("Invalid origin in the host allowlist: {origin}"))?;
+    Ok(url.origin().ascii_serialization())
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[test]
+    fn parses_origins_and_ignores_comments() -> anyhow::Result<()> {
+        let origins = parse_allowlist(
+            "# Self-hosted deployments\n\nhttp://localhost:3210\n  https://convex.example.com/  \n",
+        )?;
+
+        assert_eq!(
+            origins,
+            HashSet::from([
+                "http://localhost:3210".to_string(),
+                "https://convex.example.com".to_string(),
+            ])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn refuses_invalid_origins() {
+        assert!(parse_allowlist("localhost").is_err());
+    }
+
+    #[test]
+    fn only_allows_listed_origins() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!("allowlist-{}", Uuid::new_v4()));
+        std::fs::write(&path, "http://localhost:3210\n")?;
+        let allowlist = HostAllowlist::new(path.clone());
+
+        assert!(allowlist.allows(&Url::parse("http://localhost:3210")?));
+        assert!(!allowlist.allows(&Url::parse("http://localhost:8000")?));
+        assert!(!allowlist.allows(&Url::parse("https://localhost:3210")?));
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn picks_up_changes_to_the_file() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!("allowlist-{}", Uuid::new_v4()));
+        std::fs::write(&path, "")?;
+        let allowlist = HostAllowlist::new(path.clone());
+        let url = Url::parse("http://localhost:3210")?;
+        assert!(!allowlist.allows(&url));
+
+        std::fs::write(&path, "http://localhost:3210\n")?;
+        assert!(allowlist.allows(&url));
+
+        std::fs::remove_file(&path)?;
+        assert!(!allowlist.allows(&url));
+        Ok(())
+    }
+}
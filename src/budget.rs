@@ -0,0 +1,114 @@
+use std::{
+    sync::Arc,
+    time::Duration,
+};
+
+use async_stream::try_stream;
+use futures::{
+    Stream,
+    StreamExt,
+};
+
+use crate::{
+    clock::Clock,
+    fivetran_sdk::LogLevel,
+    log,
+    sync::UpdateMessage,
+};
+
+/// Ends an update stream at the first checkpoint it emits after running for
+/// `budget`, so that long syncs are split into sessions that Fivetran resumes
+/// from the last checkpoint, instead of running for hours at the risk of being
+/// interrupted. Nothing is lost since the stream ends right after the
+/// checkpoint.
+pub fn with_time_budget(
+    stream: impl Stream<Item = anyhow::Result<UpdateMessage>>,
+    budget: Duration,
+    clock: Arc<dyn Clock>,
+) -> impl Stream<Item = anyhow::Result<UpdateMessage>> {
+    try_stream! {
+        let start = clock.now();
+        let mut stream = Box::pin(stream);
+        while let Some(message) = stream.next().await {
+            let message = message?;
+            let is_checkpoint = matches!(message, UpdateMessage::Checkpoint(_));
+            yield message;
+
+            let elapsed = clock.now().duration_since(start).unwrap_or_default();
+            if is_checkpoint && elapsed >= budget {
+                let log_msg = format!(
+                    "The sync reached its maximum duration of {} minutes. Ending it at this \
+                     checkpoint, the next sync continues from there.",
+                    budget.as_secs() / 60
+                );
+                log(&log_msg);
+                yield UpdateMessage::Log(LogLevel::Info, log_msg);
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        time::SystemTime,
+    };
+
+    use futures::stream;
+
+    use super::*;
+    use crate::{
+        clock::FakeClock,
+        sync::{
+            Checkpoint,
+            State,
+        },
+    };
+
+    fn checkpoint(cursor: i64) -> UpdateMessage {
+        UpdateMessage::Checkpoint(State::create(
+            Checkpoint::DeltaUpdates {
+                cursor: cursor.into(),
+            },
+            HashSet::new(),
+        ))
+    }
+
+    #[tokio::test]
+    async fn ends_at_the_first_checkpoint_after_the_budget() {
+        let clock = Arc::new(FakeClock::new(SystemTime::UNIX_EPOCH));
+        let advancing_clock = clock.clone();
+        let messages =
+            stream::iter([checkpoint(1), checkpoint(2), checkpoint(3)]).map(move |message| {
+                advancing_clock.advance(Duration::from_secs(60));
+                Ok(message)
+            });
+
+        let results: Vec<UpdateMessage> =
+            with_time_budget(messages, Duration::from_secs(120), clock)
+                .map(Result::unwrap)
+                .collect()
+                .await;
+
+        assert_eq!(results.len(), 3);
+        assert!(matches!(results[1], UpdateMessage::Checkpoint(_)));
+        let UpdateMessage::Log(LogLevel::Info, ref message) = results[2] else {
+            panic!("Expected the sync to end with a log");
+        };
+        assert!(message.contains("maximum duration of 2 minutes"));
+    }
+
+    #[tokio::test]
+    async fn doesnt_end_streams_within_the_budget() {
+        let clock = Arc::new(FakeClock::new(SystemTime::UNIX_EPOCH));
+        let messages = stream::iter([Ok(checkpoint(1)), Ok(checkpoint(2))]);
+
+        let results: Vec<_> = with_time_budget(messages, Duration::from_secs(60), clock)
+            .collect()
+            .await;
+
+        assert_eq!(results.len(), 2);
+    }
+}
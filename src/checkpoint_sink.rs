@@ -0,0 +1,209 @@
+//! Stores where the checkpoints sent to Fivetran are mirrored, so that a sync
+//! can be resumed from its last checkpoint when the Fivetran connection is
+//! recreated and its state is lost.
+
+use std::{
+    collections::{
+        BTreeMap,
+        HashMap,
+    },
+    fmt::Debug,
+    path::PathBuf,
+    sync::atomic::{
+        AtomicU64,
+        Ordering,
+    },
+};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use sha2::{
+    Digest,
+    Sha256,
+};
+use url::Url;
+
+use crate::sync::State;
+
+/// Identifies the connection whose checkpoints are mirrored. Several
+/// connections can sync the same deployment (e.g. with different table
+/// filters), so the key also covers a hash of the whole configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionKey {
+    pub deploy_url: Url,
+    configuration_hash: String,
+}
+
+impl ConnectionKey {
+    /// The key of the connection with the given configuration, as sent by
+    /// Fivetran, syncing the given deployment.
+    pub fn new(deploy_url: Url, configuration: &HashMap<String, String>) -> Self {
+        let mut hasher = Sha256::new();
+        for (key, value) in configuration.iter().collect::<BTreeMap<_, _>>() {
+            // The lengths keep the keys and values from running into each
+            // other.
+            for text in [key, value] {
+                hasher.update(text.len().to_le_bytes());
+                hasher.update(text.as_bytes());
+            }
+        }
+        let configuration_hash = hasher
+            .finalize()
+            .iter()
+            .take(8)
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        ConnectionKey {
+            deploy_url,
+            configuration_hash,
+        }
+    }
+}
+
+/// A store receiving a copy of every checkpoint emitted for a connection.
+#[async_trait]
+pub trait CheckpointSink: Debug + Send + Sync {
+    /// Stores the state of the last checkpoint emitted for the connection,
+    /// replacing the previous one.
+    async fn store(&self, connection: &ConnectionKey, state: &State) -> anyhow::Result<()>;
+
+    /// The state of the last checkpoint stored for the connection, if any.
+    async fn load(&self, connection: &ConnectionKey) -> anyhow::Result<Option<State>>;
+}
+
+/// Mirrors the checkpoints to a JSON file per connection in a local directory.
+/// Files are replaced atomically, so a crash never leaves a partial state.
+#[derive(Debug)]
+pub struct FileCheckpointSink {
+    directory: PathBuf,
+}
+
+/// Numbers the temporary files written by this process, so that concurrent
+/// writes never share one.
+static TEMPORARY_FILES: AtomicU64 = AtomicU64::new(0);
+
+impl FileCheckpointSink {
+    pub fn new(directory: PathBuf) -> Self {
+        FileCheckpointSink { directory }
+    }
+
+    /// The file of a connection, named after the host (and port) of its
+    /// deployment and the hash of its configuration.
+    pub fn path(&self, connection: &ConnectionKey) -> PathBuf {
+        let deploy_url = &connection.deploy_url;
+        let host = deploy_url.host_str().unwrap_or("deployment");
+        let hash = &connection.configuration_hash;
+        let file_name = match deploy_url.port() {
+            Some(port) => format!("{host}_{port}_{hash}.json"),
+            None => format!("{host}_{hash}.json"),
+        };
+        self.directory.join(file_name)
+    }
+}
+
+#[async_trait]
+impl CheckpointSink for FileCheckpointSink {
+    async fn store(&self, connection: &ConnectionKey, state: &State) -> anyhow::Result<()> {
+        let path = self.path(connection);
+        let temporary_path = path.with_extension(format!(
+            "json.{}.{}.tmp",
+            std::process::id(),
+            TEMPORARY_FILES.fetch_add(1, Ordering::Relaxed)
+        ));
+        tokio::fs::write(&temporary_path, serde_json::to_vec(state)?)
+            .await
+            .with_context(|| format!("Failed to write {}", temporary_path.display()))?;
+        if let Err(error) = tokio::fs::rename(&temporary_path, &path).await {
+            let _ = tokio::fs::remove_file(&temporary_path).await;
+            return Err(error).with_context(|| format!("Failed to replace {}", path.display()));
+        }
+        Ok(())
+    }
+
+    /// Loads the state of the connection, upgrading it to the current layout
+    /// if it was stored by an older version of the connector. The mirrored
+    /// states always track the tables synced, so no source is needed to
+    /// upgrade them (see [`State::upgrade`]).
+    async fn load(&self, connection: &ConnectionKey) -> anyhow::Result<Option<State>> {
+        let path = self.path(connection);
+        let contents = match tokio::fs::read(&path).await {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => {
+                return Err(error).with_context(|| format!("Failed to read {}", path.display()))
+            },
+        };
+        let state = serde_json::from_slice(&contents)
+            .map_err(anyhow::Error::from)
+            .and_then(State::migrate)
+            .with_context(|| format!("Invalid state in {}", path.display()))?;
+        Ok(Some(state))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use maplit::hashmap;
+
+    use super::*;
+    use crate::sync::Checkpoint;
+
+    #[tokio::test]
+    async fn stores_the_last_checkpoint_of_each_connection() -> anyhow::Result<()> {
+        let directory = std::env::temp_dir().join(format!(
+            "convex_fivetran_source_checkpoints_{}",
+            std::process::id()
+        ));
+        tokio::fs::create_dir_all(&directory).await?;
+        let sink = FileCheckpointSink::new(directory.clone());
+        let deploy_url = Url::parse("https://aware-llama-900.convex.cloud")?;
+        let configuration = hashmap! {
+            "url".to_string() => deploy_url.to_string(),
+            "key".to_string() => "prod:aware-llama-900|key".to_string(),
+        };
+        let connection = ConnectionKey::new(deploy_url.clone(), &configuration);
+        let mut filtered_configuration = configuration.clone();
+        filtered_configuration.insert("tableFilter".to_string(), "messages".to_string());
+        let filtered_connection = ConnectionKey::new(deploy_url, &filtered_configuration);
+        let other_url = Url::parse("http://127.0.0.1:3210")?;
+        let other_connection = ConnectionKey::new(other_url, &HashMap::new());
+
+        assert_eq!(sink.load(&connection).await?, None);
+        for cursor in [1, 2] {
+            let state = State::create(
+                Checkpoint::DeltaUpdates {
+                    cursor: cursor.into(),
+                },
+                HashSet::from(["messages".to_string()]),
+            );
+            sink.store(&connection, &state).await?;
+        }
+
+        let Some(state) = sink.load(&connection).await? else {
+            panic!("The checkpoint wasn’t stored");
+        };
+        assert_eq!(
+            state.checkpoint,
+            Checkpoint::DeltaUpdates { cursor: 2.into() }
+        );
+        assert_eq!(sink.load(&filtered_connection).await?, None);
+        assert_eq!(sink.load(&other_connection).await?, None);
+        assert_eq!(
+            sink.path(&other_connection),
+            directory.join("127.0.0.1_3210_e3b0c44298fc1c14.json")
+        );
+        let mut entries = tokio::fs::read_dir(&directory).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            assert_eq!(
+                entry.path(),
+                sink.path(&connection),
+                "Leftover temporary file"
+            );
+        }
+
+        tokio::fs::remove_dir_all(&directory).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,68 @@
+use std::time::{
+    Duration,
+    SystemTime,
+};
+
+use async_trait::async_trait;
+
+/// The source of time used by the connector, so that time-dependent behavior
+/// (such as pacing) can be tested without waiting.
+#[async_trait]
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+
+    async fn sleep(&self, duration: Duration);
+}
+
+/// The actual wall clock.
+pub struct SystemClock;
+
+#[async_trait]
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        tokio::time::sleep(duration).await
+    }
+}
+
+/// A clock that only advances when something sleeps on it, recording the
+/// durations of the sleeps.
+#[cfg(test)]
+pub struct FakeClock {
+    now: std::sync::Mutex<SystemTime>,
+    sleeps: std::sync::Mutex<Vec<Duration>>,
+}
+
+#[cfg(test)]
+impl FakeClock {
+    pub fn new(now: SystemTime) -> Self {
+        FakeClock {
+            now: std::sync::Mutex::new(now),
+            sleeps: std::sync::Mutex::new(vec![]),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+
+    pub fn sleeps(&self) -> Vec<Duration> {
+        self.sleeps.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl Clock for FakeClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock().unwrap()
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        self.sleeps.lock().unwrap().push(duration);
+        self.advance(duration);
+    }
+}
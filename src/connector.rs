@@ -1,32 +1,60 @@
+use std::{
+    collections::{
+        BTreeMap,
+        BTreeSet,
+        HashMap,
+    },
+    sync::Arc,
+    time::Duration,
+};
+
 use futures::{
     stream::BoxStream,
     StreamExt,
     TryStreamExt,
 };
+use prost::Message;
 use tonic::{
     Request,
     Response,
     Status,
 };
+use tracing_futures::Instrument;
+use url::Url;
 
 use crate::{
+    allowlist::HostAllowlist,
+    checkpoint_sink::{
+        CheckpointSink,
+        ConnectionKey,
+    },
     config::{
         AllowAllHosts,
         Config,
     },
     convex_api::{
         ConvexApi,
+        DeployUrls,
+        HttpOptions,
         Source,
     },
+    correlation::{
+        current_sync_id,
+        in_sync,
+        new_sync_id,
+        stream_in_sync,
+        with_sync_id,
+    },
+    deprecation::Deprecation,
+    error::ConnectorError,
     fivetran_sdk::{
         connector_server::Connector,
         schema_response,
         test_response,
-        Column,
         ConfigurationFormRequest,
         ConfigurationFormResponse,
         ConfigurationTest,
-        DataType,
+        Schema as FivetranSchema,
         SchemaRequest,
         SchemaResponse,
         Table,
@@ -38,78 +66,473 @@ use crate::{
         UpdateResponse as FivetranUpdateResponse,
     },
     log,
+    log_debug,
+    log_warning,
+    logging::{
+        self,
+        Level,
+    },
+    metrics,
+    panic::{
+        catch_panics,
+        catch_panics_in,
+    },
+    redact::redact,
+    restart::restart_on_transient_errors,
+    schema::{
+        component_schemas,
+        fetch_json_schemas,
+        fivetran_tables,
+        sample_missing_schemas,
+        table_renames,
+        tables_and_columns,
+        ROOT_COMPONENT_SCHEMA_NAME,
+    },
+    schema_cache::SchemaCaches,
+    settings::LiveSettings,
+    status::ActiveSyncs,
     sync::{
-        sync,
+        sync_deployments,
+        AdditionalDeployment,
         State,
+        SyncOptions,
+        UpdateMessage,
     },
+    throttle::BackfillThrottle,
+    watchdog::watchdog,
 };
 
 /// Implements the gRPC server endpoints used by Fivetran.
 #[derive(Debug)]
 pub struct ConvexConnector {
     pub allow_all_hosts: AllowAllHosts,
+    pub host_allowlist: Option<HostAllowlist>,
+    pub http_options: HttpOptions,
+    pub settings: LiveSettings,
+    pub schema_caches: SchemaCaches,
+    pub active_syncs: ActiveSyncs,
+
+    /// The URLs of the deployments that connections name rather than give.
+    pub deploy_urls: DeployUrls,
+
+    /// The maximum size of a message sent to Fivetran, if different from the
+    /// gRPC default.
+    pub max_encoding_message_size: Option<usize>,
+
+    /// If set, update streams making no progress for this long are aborted.
+    pub stall_timeout: Option<Duration>,
+
+    /// If set, update streams emit a heartbeat every time a call to the
+    /// Convex API has been pending for this long.
+    pub heartbeat_interval: Option<Duration>,
+
+    /// The number of times an update stream failing with a transient error is
+    /// restarted from its last checkpoint before the error is returned.
+    pub max_sync_restarts: u32,
+
+    /// If set, the checkpoints sent to Fivetran are also mirrored to this
+    /// sink, to recover the state of connections that are recreated.
+    pub checkpoint_sink: Option<Arc<dyn CheckpointSink>>,
 }
 
 type ConnectorResult<T> = Result<Response<T>, Status>;
 
 impl ConvexConnector {
-    async fn _schema(&self, request: Request<SchemaRequest>) -> anyhow::Result<SchemaResponse> {
-        let config =
-            Config::from_parameters(request.into_inner().configuration, self.allow_all_hosts)?;
-        log(&format!("schema request for {}", config.deploy_url));
+    /// Validates the configuration of a connection. Deployments hosted outside
+    /// of Convex cloud are accepted if all hosts are allowed, or if the
+    /// deployment (and every additional deployment) is in the host allowlist
+    /// or in the allowed hosts of the settings. Connections set up with a
+    /// deployment name have their deployment URL resolved first.
+    async fn config(&self, mut configuration: HashMap<String, String>) -> anyhow::Result<Config> {
+        if let Some((deployment_name, access_token)) =
+            Config::requested_deployment_name(&configuration)
+        {
+            let deploy_url = self
+                .deploy_urls
+                .resolve(
+                    &deployment_name,
+                    &access_token,
+                    &self.settings.current().http_options(&self.http_options),
+                )
+                .await?;
+            configuration = Config::with_resolved_deploy_url(configuration, &deploy_url);
+        }
+        let settings = self.settings.current();
+        let allows = |deploy_url: &Url| {
+            settings.allows_host(deploy_url)
+                || self
+                    .host_allowlist
+                    .as_ref()
+                    .is_some_and(|allowlist| allowlist.allows(deploy_url))
+        };
+        let allow_all_hosts = AllowAllHosts(
+            self.allow_all_hosts.0
+                || (Config::requested_deploy_url(&configuration).is_some_and(|url| allows(&url))
+                    && Config::requested_additional_deploy_urls(&configuration)
+                        .iter()
+                        .all(allows)),
+        );
+        Config::from_parameters(configuration, allow_all_hosts)
+    }
 
-        let source = ConvexApi { config };
-
-        let columns = source.get_tables_and_columns().await?;
-
-        let tables = TableList {
-            tables: columns
-                .into_iter()
-                .map(|(table_name, column_names)| Table {
-                    name: table_name.to_string(),
-                    columns: column_names
-                        .into_iter()
-                        .map(|column_name| {
-                            let column_name: String = column_name.to_string();
-                            Column {
-                                name: column_name.clone(),
-                                r#type: match column_name.as_str() {
-                                    "_id" => DataType::String,
-                                    "_creationTime" => DataType::UtcDatetime,
-                                    // We map every non-system column to the “unspecified” data type
-                                    // and let Fivetran infer the correct column type from the data
-                                    // it receives.
-                                    _ => DataType::Unspecified,
-                                } as i32,
-                                primary_key: column_name == "_id",
-                                decimal: None,
-                            }
-                        })
-                        .collect(),
-                })
-                .collect(),
+    /// Creates a client for the Convex API, using the latest settings.
+    fn convex_api(&self, config: Config) -> anyhow::Result<ConvexApi> {
+        ConvexApi::new(
+            config,
+            &self.settings.current().http_options(&self.http_options),
+        )
+    }
+
+    /// Warns when a sync starts from scratch although the checkpoint sink has
+    /// a checkpoint for the connection, which happens when the connection was
+    /// recreated. The state isn’t restored automatically, since starting over
+    /// might be intended.
+    async fn warn_about_mirrored_checkpoint(&self, connection: &ConnectionKey) {
+        let Some(checkpoint_sink) = &self.checkpoint_sink else {
+            return;
         };
+        let deploy_url = &connection.deploy_url;
+        match checkpoint_sink.load(connection).await {
+            Ok(Some(state)) => log_warning(&format!(
+                "The sync of {deploy_url} starts from scratch, but the checkpoint sink has a \
+                 checkpoint for it at {:?}. If the connection was recreated, its state can be \
+                 restored from {checkpoint_sink:?}.",
+                state.checkpoint
+            )),
+            Ok(None) => {},
+            Err(error) => log_warning(&format!(
+                "Failed to load the mirrored checkpoint of {deploy_url}: {error:#}"
+            )),
+        }
+    }
+
+    async fn _schema(
+        &self,
+        request: Request<SchemaRequest>,
+    ) -> Result<SchemaResponse, ConnectorError> {
+        let config = self
+            .config(request.into_inner().configuration)
+            .await
+            .map_err(ConnectorError::InvalidConfiguration)?;
+        log(&format!("schema request for {}", config.deploy_url));
+
+        let source = self.convex_api(config)?;
+        let tables = self.fivetran_tables(&source).await?;
 
         // Here, `WithoutSchema` means that there is no hierarchical level above tables,
         // not that the data is unstructured. Fivetran uses the same meaning of “schema”
         // as Postgres, not the one used in Convex. We do this because the connector is
-        // already set up for a particular Convex deployment.
+        // already set up for a particular Convex deployment. Schemas are only used
+        // when the configuration names one, or to give every component its own.
+        let config = &source.config;
+        let response = if config.sync_components || config.schema_name.is_some() {
+            let root_schema_name = config
+                .schema_name
+                .as_deref()
+                .unwrap_or(ROOT_COMPONENT_SCHEMA_NAME);
+            let mut schemas = component_schemas(tables, root_schema_name);
+            // Additional deployments are synced to their own schema.
+            for deployment_config in config.additional_deployment_configs() {
+                let source = self.convex_api(deployment_config)?;
+                schemas.schemas.push(FivetranSchema {
+                    name: source.config.schema_name.clone().unwrap_or_default(),
+                    tables: self.fivetran_tables(&source).await?,
+                });
+            }
+            schema_response::Response::WithSchema(schemas)
+        } else {
+            schema_response::Response::WithoutSchema(TableList { tables })
+        };
         Ok(SchemaResponse {
-            response: Some(schema_response::Response::WithoutSchema(tables)),
+            response: Some(response),
             selection_not_supported: Some(true),
         })
     }
+
+    /// The tables of a deployment, as declared to Fivetran.
+    async fn fivetran_tables(&self, source: &ConvexApi) -> anyhow::Result<Vec<Table>> {
+        let mut columns = tables_and_columns(source, source.config.sync_components).await?;
+        let schema_cache = self.schema_caches.for_deployment(&source.config.deploy_url);
+        let mut json_schemas = fetch_json_schemas(source, Some(&schema_cache)).await;
+        sample_missing_schemas(source, &mut columns, &mut json_schemas).await;
+
+        Ok(fivetran_tables(
+            columns,
+            &json_schemas,
+            &source.config.schema_options(),
+        ))
+    }
+
+    /// The options of the sync of a deployment. When a new connection starts
+    /// syncing it, its tables whose names only differ by case are renamed the
+    /// same way as in the schema, rather than merged in the destination. The
+    /// syncs resuming from a state use the renames stored in it instead, so the
+    /// tables of the deployment are only fetched for new connections.
+    async fn sync_options(
+        &self,
+        source: &ConvexApi,
+        deprecations: BTreeSet<Deprecation>,
+        new_connection: bool,
+    ) -> anyhow::Result<SyncOptions> {
+        let config = &source.config;
+        let table_renames = if new_connection {
+            let tables = tables_and_columns(source, config.sync_components).await?;
+            table_renames(
+                tables
+                    .keys()
+                    .map(|table_name| table_name.0.as_str())
+                    .filter(|table_name| {
+                        config.table_filter.includes(table_name)
+                            && !config.stats_only_tables.contains(*table_name)
+                    }),
+            )
+        } else {
+            BTreeMap::new()
+        };
+        Ok(SyncOptions {
+            page_pacing: config.page_pacing,
+            page_size: config.page_size,
+            target_page_bytes: config.target_page_bytes,
+            max_fields: config.max_fields,
+            max_nesting_depth: config.max_nesting_depth,
+            presence_columns: config.presence_columns,
+            soft_deletes: config.soft_deletes,
+            timestamp_column: config.timestamp_column,
+            exclude_system_columns: config.exclude_system_columns,
+            child_tables: config.child_tables,
+            nested_objects: config.nested_objects,
+            non_finite_floats: config.non_finite_floats,
+            decimal_columns: config.decimal_columns.clone(),
+            column_types: config.column_types.clone(),
+            column_hashing: config.column_hashing.clone(),
+            column_masking: config.column_masking.clone(),
+            detect_dates: config.detect_dates,
+            sanitize_column_names: config.sanitize_column_names,
+            sync_components: config.sync_components,
+            schema_name: config.schema_name.clone(),
+            stats_only_tables: config.stats_only_tables.clone(),
+            table_filter: config.table_filter.clone(),
+            row_filters: config.row_filters.clone(),
+            table_prefix: config.table_prefix.clone(),
+            files_table: config.files_table,
+            file_download_urls: config.file_download_urls.then(|| config.deploy_url.clone()),
+            table_renames,
+            backfill_throttle: BackfillThrottle {
+                max_rows_per_minute: config.max_rows_per_minute,
+                quiet_hours: config.quiet_hours,
+            },
+            refuse_resync: config.protect_from_resync && !config.allow_resync,
+            heartbeat_interval: self.heartbeat_interval,
+            deprecations,
+            missing_system_tables: source.missing_system_tables().await?,
+            time_budget: config.max_sync_duration,
+            ..SyncOptions::default()
+        })
+    }
+
+    async fn _test(&self, request: Request<TestRequest>) -> ConnectorResult<TestResponse> {
+        log_debug("test request");
+        let config = match self.config(request.into_inner().configuration).await {
+            Ok(config) => config,
+            Err(error) => {
+                return Ok(Response::new(TestResponse {
+                    response: Some(test_response::Response::Failure(redact(&with_sync_id(
+                        &error.to_string(),
+                    )))),
+                }));
+            },
+        };
+        log(&format!("test request for {}", config.deploy_url));
+        let source = match self.convex_api(config) {
+            Ok(source) => source,
+            Err(error) => {
+                return Ok(Response::new(TestResponse {
+                    response: Some(test_response::Response::Failure(redact(&with_sync_id(
+                        &error.to_string(),
+                    )))),
+                }));
+            },
+        };
+
+        // Perform an API request to verify if the credentials work
+        match source.test_streaming_export_connection().await {
+            Ok(_) => Ok(Response::new(TestResponse {
+                response: Some(test_response::Response::Success(true)),
+            })),
+            Err(e) => Ok(Response::new(TestResponse {
+                response: Some(test_response::Response::Failure(redact(&with_sync_id(
+                    &e.to_string(),
+                )))),
+            })),
+        }
+    }
+
+    async fn _update(
+        &self,
+        request: Request<UpdateRequest>,
+    ) -> ConnectorResult<BoxStream<'static, Result<UpdateResponse, Status>>> {
+        log_debug("update request");
+        let inner = request.into_inner();
+        let config = self
+            .config(inner.configuration.clone())
+            .await
+            .map_err(ConnectorError::InvalidConfiguration)?;
+        let connection = ConnectionKey::new(config.deploy_url.clone(), &inner.configuration);
+        let metrics_label = metrics::connection_label(&config.deploy_url);
+        log(&format!(
+            "update request for {} (metrics connection {metrics_label})",
+            config.deploy_url
+        ));
+
+        let state = deserialize_state_json(inner.state_json.as_deref().unwrap_or("{}"))
+            .map_err(ConnectorError::InvalidState)?;
+        let mut deprecations: BTreeSet<Deprecation> = config
+            .unused_fields
+            .iter()
+            .cloned()
+            .map(Deprecation::UnusedConfigField)
+            .collect();
+        if state.as_ref().is_some_and(State::is_legacy) {
+            deprecations.insert(Deprecation::LegacyState);
+        }
+        let source = self
+            .convex_api(config.clone())
+            .map_err(ConnectorError::from)?;
+        check_credentials(&source).await?;
+        let state = match state {
+            Some(state) => Some(
+                State::upgrade(state, &source)
+                    .await
+                    .map_err(ConnectorError::from)?,
+            ),
+            None => None,
+        };
+
+        if state.is_none() {
+            self.warn_about_mirrored_checkpoint(&connection).await;
+        }
+
+        log_debug(&format!(
+            "update request for {} at checkpoint {:?}",
+            config.deploy_url,
+            state.as_ref().map(|s| &s.checkpoint)
+        ));
+
+        let mut options = self
+            .sync_options(&source, deprecations, state.is_none())
+            .await
+            .map_err(ConnectorError::from)?;
+        let schema_cache = self.schema_caches.for_deployment(&config.deploy_url);
+        let mut additional_deployments = vec![];
+        for deployment_config in config.additional_deployment_configs() {
+            let source = self
+                .convex_api(deployment_config)
+                .map_err(ConnectorError::from)?;
+            check_credentials(&source).await?;
+            let schema_name = source.config.schema_name.clone().unwrap_or_default();
+            let new_connection = !state
+                .as_ref()
+                .is_some_and(|state| state.deployments.contains_key(&schema_name));
+            additional_deployments.push(AdditionalDeployment {
+                options: self
+                    .sync_options(&source, BTreeSet::new(), new_connection)
+                    .await
+                    .map_err(ConnectorError::from)?,
+                schema_name,
+                schema_cache: self.schema_caches.for_deployment(&source.config.deploy_url),
+                source,
+            });
+        }
+        // The span covers the whole update stream rather than this call, which
+        // returns as soon as the stream is created.
+        let span = tracing::info_span!(
+            "update",
+            deploy_url = %config.deploy_url,
+            sync_id = current_sync_id().as_deref(),
+        );
+        let max_concurrent_syncs = self.settings.current().max_concurrent_syncs();
+        let status = self
+            .active_syncs
+            .try_register(
+                config.deploy_url.to_string(),
+                state.as_ref().map(|state| state.checkpoint.clone()),
+                max_concurrent_syncs,
+            )
+            .ok_or_else(|| {
+                ConnectorError::Unavailable(anyhow::anyhow!(
+                    "The connector is already running {} syncs, the maximum set by \
+                     maxConcurrentSyncs in its settings file. Fivetran will retry the sync later.",
+                    max_concurrent_syncs.unwrap_or_default()
+                ))
+            })?;
+        let max_message_size = self.max_encoding_message_size;
+        let stall_timeout = self.stall_timeout;
+        let checkpoint_sink = self.checkpoint_sink.clone();
+        let clock = options.clock.clone();
+        let sync =
+            restart_on_transient_errors(state, self.max_sync_restarts, clock, move |state| {
+                let mut sync = sync_deployments(
+                    source.clone(),
+                    state,
+                    options.clone(),
+                    schema_cache.clone(),
+                    additional_deployments.clone(),
+                );
+                // Deprecations and missing system tables are only warned about
+                // once per sync, not again when it is restarted.
+                options.deprecations.clear();
+                options.missing_system_tables.clear();
+                for deployment in &mut additional_deployments {
+                    deployment.options.missing_system_tables.clear();
+                }
+                if let Some(stall_timeout) = stall_timeout {
+                    sync = watchdog(sync, stall_timeout).boxed();
+                }
+                sync
+            });
+        Ok(Response::new(catch_panics(
+            sync.try_filter(|message| {
+                // Don’t send Fivetran the messages below the minimum log level.
+                futures::future::ready(match message {
+                    UpdateMessage::Log(level, _) => logging::enabled(Level::from(*level)),
+                    _ => true,
+                })
+            })
+            .and_then(move |message| {
+                metrics::record_update_message(&metrics_label, &message);
+                status.record(&message);
+                let checkpoint_sink = checkpoint_sink.clone();
+                let connection = connection.clone();
+                async move {
+                    if let (Some(checkpoint_sink), UpdateMessage::Checkpoint(state)) =
+                        (checkpoint_sink, &message)
+                    {
+                        // The sync doesn’t depend on the mirror, so failing to
+                        // store a checkpoint there doesn’t fail it.
+                        if let Err(error) = checkpoint_sink.store(&connection, state).await {
+                            log_warning(&format!("Failed to mirror the checkpoint: {error:#}"));
+                        }
+                    }
+                    to_fivetran_response(message, max_message_size)
+                }
+            })
+            .map_err(|error| Status::from(ConnectorError::from(error)))
+            .instrument(span)
+            .boxed(),
+        )))
+    }
 }
 
 #[tonic::async_trait]
 impl Connector for ConvexConnector {
     type UpdateStream = BoxStream<'static, Result<UpdateResponse, Status>>;
 
+    #[tracing::instrument(skip_all)]
     async fn configuration_form(
         &self,
         _: Request<ConfigurationFormRequest>,
     ) -> ConnectorResult<ConfigurationFormResponse> {
-        log("configuration form request");
+        log_debug("configuration form request");
         Ok(Response::new(ConfigurationFormResponse {
             schema_selection_supported: false,
             table_selection_supported: false,
@@ -121,103 +544,260 @@ impl Connector for ConvexConnector {
         }))
     }
 
+    #[tracing::instrument(skip_all)]
     async fn test(&self, request: Request<TestRequest>) -> ConnectorResult<TestResponse> {
-        log(&format!("test request"));
-        let config =
-            match Config::from_parameters(request.into_inner().configuration, self.allow_all_hosts)
-            {
-                Ok(config) => config,
-                Err(error) => {
-                    return Ok(Response::new(TestResponse {
-                        response: Some(test_response::Response::Failure(error.to_string())),
-                    }));
-                },
-            };
-        log(&format!("test request for {}", config.deploy_url));
-        let source = ConvexApi { config };
-
-        // Perform an API request to verify if the credentials work
-        match source.test_streaming_export_connection().await {
-            Ok(_) => Ok(Response::new(TestResponse {
-                response: Some(test_response::Response::Success(true)),
-            })),
-            Err(e) => Ok(Response::new(TestResponse {
-                response: Some(test_response::Response::Failure(e.to_string())),
-            })),
-        }
+        in_sync(new_sync_id(), catch_panics_in(self._test(request))).await
     }
 
+    #[tracing::instrument(skip_all)]
     async fn schema(&self, request: Request<SchemaRequest>) -> ConnectorResult<SchemaResponse> {
-        log(&format!("schema request"));
-        self._schema(request)
+        in_sync(new_sync_id(), async {
+            log_debug("schema request");
+            catch_panics_in(async {
+                self._schema(request)
+                    .await
+                    .map(Response::new)
+                    .map_err(Status::from)
+            })
             .await
-            .map(Response::new)
-            .map_err(|error| Status::internal(error.to_string()))
+        })
+        .await
     }
 
     async fn update(&self, request: Request<UpdateRequest>) -> ConnectorResult<Self::UpdateStream> {
-        log(&format!("update request"));
-        let inner = request.into_inner();
-        let config = match Config::from_parameters(inner.configuration, self.allow_all_hosts) {
-            Ok(config) => config,
-            Err(error) => {
-                return Err(Status::internal(error.to_string()));
-            },
-        };
-        log(&format!("update request for {}", config.deploy_url));
-
-        let state = deserialize_state_json(inner.state_json.as_deref().unwrap_or("{}"))
-            .map_err(|error| Status::internal(error.to_string()))?;
+        let sync_id = new_sync_id();
+        let response = in_sync(sync_id.clone(), catch_panics_in(self._update(request))).await?;
+        Ok(response.map(|stream| stream_in_sync(sync_id, stream).boxed()))
+    }
+}
 
-        log(&format!(
-            "update request for {} at checkpoint {:?}",
-            config.deploy_url,
-            state.as_ref().map(|s| &s.checkpoint)
-        ));
+/// Checks that the deployment accepts the deploy key before the update stream
+/// starts, so that a rejected key fails the update right away with a clear
+/// status, rather than in the middle of a stream that may already have
+/// truncated tables. Other errors are left to the sync, which retries the
+/// transient ones.
+async fn check_credentials(source: &impl Source) -> Result<(), ConnectorError> {
+    let Err(error) = source.test_streaming_export_connection().await else {
+        return Ok(());
+    };
+    match ConnectorError::from(error) {
+        error @ (ConnectorError::Unauthenticated(_) | ConnectorError::PermissionDenied(_)) => {
+            Err(error)
+        },
+        error => {
+            log_debug(&format!(
+                "Couldn’t check the credentials before the sync: {error}"
+            ));
+            Ok(())
+        },
+    }
+}
 
-        let source = ConvexApi { config };
+/// Converts a message to its gRPC representation. Fails with an explicit error
+/// if the message is larger than the maximum message size, rather than letting
+/// the gRPC layer drop the stream.
+fn to_fivetran_response(
+    message: UpdateMessage,
+    max_message_size: Option<usize>,
+) -> anyhow::Result<FivetranUpdateResponse> {
+    let table_name = match message {
+        UpdateMessage::Update { ref table_name, .. } => Some(table_name.clone()),
+        _ => None,
+    };
+    let response = FivetranUpdateResponse::from(message);
 
-        let sync = sync(source, state);
-        Ok(Response::new(
-            sync.map_ok(FivetranUpdateResponse::from)
-                .map_err(|error| Status::internal(error.to_string()))
-                .boxed(),
-        ))
+    if let Some(max_message_size) = max_message_size {
+        let size = response.encoded_len();
+        if size > max_message_size {
+            let error = match table_name {
+                Some(table_name) => anyhow::anyhow!(
+                    "A document of the table {table_name} is {size} bytes large, which exceeds \
+                     the maximum message size of {max_message_size} bytes. Restart the connector \
+                     with a larger --max-encoding-message-size."
+                ),
+                None => anyhow::anyhow!(
+                    "A message of {size} bytes exceeds the maximum message size of \
+                     {max_message_size} bytes. Restart the connector with a larger \
+                     --max-encoding-message-size."
+                ),
+            };
+            return Err(ConnectorError::ResourceExhausted(error).into());
+        }
     }
+
+    Ok(response)
 }
 
-fn deserialize_state_json(state_json: &str) -> anyhow::Result<Option<State>> {
-    // Deserialize to a serde_json::Value first
+/// Parses the JSON of the state sent by Fivetran, which still has to be
+/// upgraded to the current layout (see [`State::upgrade`]).
+fn deserialize_state_json(state_json: &str) -> anyhow::Result<Option<serde_json::Value>> {
     let state: serde_json::Value = serde_json::from_str(state_json)?;
     // Special case {} - which means we're initializing from fresh state
     let state = if state == serde_json::json!({}) {
         None
     } else {
-        Some(serde_json::from_value(state)?)
+        Some(state)
     };
     Ok(state)
 }
 
 #[cfg(test)]
 mod tests {
-    use super::deserialize_state_json;
-    use crate::sync::{
-        Checkpoint,
-        State,
+    use std::{
+        collections::HashMap,
+        fmt::Display,
+    };
+
+    use async_trait::async_trait;
+    use maplit::hashmap;
+    use reqwest::StatusCode;
+    use url::Url;
+
+    use super::{
+        check_credentials,
+        deserialize_state_json,
+        to_fivetran_response,
+    };
+    use crate::{
+        convex_api::{
+            ApiError,
+            BackendError,
+            DocumentDeltasCursor,
+            DocumentDeltasResponse,
+            FieldName,
+            ListSnapshotCursor,
+            ListSnapshotResponse,
+            Source,
+            TableName,
+        },
+        error::ConnectorError,
+        fivetran_sdk::{
+            value_type::Inner as FivetranValue,
+            LogLevel,
+            OpType,
+        },
+        sync::{
+            Checkpoint,
+            State,
+            UpdateMessage,
+        },
+        testing::FakeSource,
     };
 
+    fn large_update() -> UpdateMessage {
+        UpdateMessage::Update {
+            schema_name: None,
+            table_name: "messages".to_string(),
+            op_type: OpType::Upsert,
+            row: hashmap! {
+                "body".to_string() => FivetranValue::String("a".repeat(1000)),
+            },
+        }
+    }
+
     #[test]
-    fn test_deserialize_state_json() -> anyhow::Result<()> {
+    fn converts_messages_within_the_size_limit() -> anyhow::Result<()> {
+        to_fivetran_response(large_update(), None)?;
+        to_fivetran_response(large_update(), Some(2000))?;
+        to_fivetran_response(
+            UpdateMessage::Log(LogLevel::Info, "Hello".to_string()),
+            Some(100),
+        )?;
+        Ok(())
+    }
+
+    #[test]
+    fn refuses_messages_exceeding_the_size_limit() {
+        let error = to_fivetran_response(large_update(), Some(100)).unwrap_err();
+        assert!(error.to_string().contains("table messages"));
+        assert_eq!(
+            ConnectorError::from(error).code(),
+            tonic::Code::ResourceExhausted
+        );
+    }
+
+    /// A source whose connection test fails with the given status.
+    struct FailingSource(StatusCode);
+
+    impl Display for FailingSource {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str("failing source")
+        }
+    }
+
+    #[async_trait]
+    impl Source for FailingSource {
+        async fn test_streaming_export_connection(&self) -> anyhow::Result<()> {
+            Err(ApiError::Backend {
+                endpoint: "test_streaming_export_connection".to_string(),
+                deploy_url: Url::parse("https://aware-llama-900.convex.cloud")?,
+                status: self.0,
+                error: BackendError {
+                    code: "Failure".to_string(),
+                    message: "The call failed".to_string(),
+                },
+            }
+            .into())
+        }
+
+        async fn list_snapshot(
+            &self,
+            _snapshot: Option<i64>,
+            _cursor: Option<ListSnapshotCursor>,
+            _table_name: Option<String>,
+            _page_size: Option<u32>,
+        ) -> anyhow::Result<ListSnapshotResponse> {
+            unreachable!()
+        }
+
+        async fn document_deltas(
+            &self,
+            _cursor: DocumentDeltasCursor,
+            _table_name: Option<String>,
+        ) -> anyhow::Result<DocumentDeltasResponse> {
+            unreachable!()
+        }
+
+        async fn get_tables_and_columns(
+            &self,
+        ) -> anyhow::Result<HashMap<TableName, Vec<FieldName>>> {
+            unreachable!()
+        }
+    }
+
+    #[tokio::test]
+    async fn rejected_credentials_fail_the_update_right_away() {
+        check_credentials(&FakeSource::seeded()).await.unwrap();
+
+        let error = check_credentials(&FailingSource(StatusCode::UNAUTHORIZED))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), tonic::Code::Unauthenticated);
+        let error = check_credentials(&FailingSource(StatusCode::FORBIDDEN))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), tonic::Code::PermissionDenied);
+
+        // Outages are left to the sync, which retries them.
+        check_credentials(&FailingSource(StatusCode::SERVICE_UNAVAILABLE))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_deserialize_state_json() -> anyhow::Result<()> {
         assert_eq!(deserialize_state_json("{}")?, None);
         assert!(deserialize_state_json("{'invalid':'things'}").is_err());
+        let state = deserialize_state_json(
+            "{ \"version\": 1, \"checkpoint\": { \"DeltaUpdates\": { \"cursor\": 42 } } }",
+        )?
+        .unwrap();
         assert_eq!(
-            deserialize_state_json(
-                "{ \"version\": 1, \"checkpoint\": { \"DeltaUpdates\": { \"cursor\": 42 } } }"
-            )?,
-            Some(State::create(
+            State::upgrade(state, &FakeSource::seeded()).await?,
+            State::create(
                 Checkpoint::DeltaUpdates { cursor: 42.into() },
-                None,
-            ))
+                ["table1", "table2", "table3"].map(String::from).into(),
+            )
         );
         Ok(())
     }
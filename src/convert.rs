@@ -1,4 +1,13 @@
-use std::collections::HashMap;
+//! Conversion of Convex documents to Fivetran rows.
+
+use std::{
+    collections::{
+        BTreeMap,
+        BTreeSet,
+        HashMap,
+    },
+    str::FromStr,
+};
 
 use anyhow::Context;
 #[cfg(test)]
@@ -6,10 +15,21 @@ use convex::ExportContext;
 use convex::Value as ConvexValue;
 use prost_types::Timestamp;
 use serde_json::Value as JsonValue;
+use sha2::{
+    Digest,
+    Sha256,
+};
 
-use crate::fivetran_sdk::value_type::Inner as FivetranValue;
+use crate::{
+    convex_api::ExportFormat,
+    dates::parse_iso_8601,
+    fivetran_sdk::{
+        value_type::Inner as FivetranValue,
+        DataType,
+    },
+};
 
-fn timestamp_from_ms(ms_since_unix_epoch: f64) -> Timestamp {
+pub fn timestamp_from_ms(ms_since_unix_epoch: f64) -> Timestamp {
     let ms_in_s = 1000.0;
     let ns_in_ms = 1_000_000.0;
 
@@ -29,10 +49,36 @@ impl From<ConvexValue> for FivetranValue {
             ConvexValue::String(value) => FivetranValue::String(value),
             ConvexValue::Bytes(value) => FivetranValue::Binary(value),
             ConvexValue::Array(_) | ConvexValue::Object(_) => {
-                FivetranValue::Json(value.export().to_string())
+                FivetranValue::Json(canonical_json(value.export()))
+            },
+        }
+    }
+}
+
+/// Serializes a JSON value canonically: without whitespace, and with the
+/// fields of objects sorted by name at every level. Equal values are always
+/// serialized the same way, whatever the order of their fields, so that the
+/// destination doesn’t see changes where there are none.
+fn canonical_json(value: JsonValue) -> String {
+    fn sort_fields(value: JsonValue) -> JsonValue {
+        match value {
+            JsonValue::Array(elements) => {
+                JsonValue::Array(elements.into_iter().map(sort_fields).collect())
+            },
+            JsonValue::Object(fields) => {
+                let mut fields: Vec<(String, JsonValue)> = fields.into_iter().collect();
+                fields.sort_by(|(a, _), (b, _)| a.cmp(b));
+                JsonValue::Object(
+                    fields
+                        .into_iter()
+                        .map(|(name, value)| (name, sort_fields(value)))
+                        .collect(),
+                )
             },
+            value => value,
         }
     }
+    sort_fields(value).to_string()
 }
 
 /// Converts a Fivetran value to the original Convex value, given its export
@@ -66,10 +112,27 @@ fn roundtrip_fivetran_value(
     })
 }
 
+/// Converts a value exported in the plain JSON format. The types that JSON
+/// can’t represent were already exported as strings or numbers, so the
+/// original Convex type can’t be recovered.
+fn from_plain_json(value: JsonValue) -> FivetranValue {
+    match value {
+        JsonValue::Null => FivetranValue::Null(true),
+        JsonValue::Bool(value) => FivetranValue::Bool(value),
+        JsonValue::Number(ref number) => match number.as_f64() {
+            Some(number) => FivetranValue::Double(number),
+            None => FivetranValue::String(number.to_string()),
+        },
+        JsonValue::String(value) => FivetranValue::String(value),
+        JsonValue::Array(_) | JsonValue::Object(_) => FivetranValue::Json(canonical_json(value)),
+    }
+}
+
 /// Converts a Convex document field to a Fivetran field.
 /// Returns None if the field is skipped in Fivetran.
 fn to_fivetran_field(
     (field_name, field_value): (String, JsonValue),
+    options: &RowOptions,
 ) -> anyhow::Result<Option<(String, FivetranValue)>> {
     let result =
         // Skip most system fields
@@ -85,8 +148,7 @@ fn to_fivetran_field(
                 )?;
                 FivetranValue::UtcDatetime(timestamp_from_ms(milliseconds))
             } else {
-                let convex_value = ConvexValue::try_from(field_value).context("Invalid Convex value")?;
-                convex_value.into()
+                to_fivetran_value(field_value, options)?
             };
 
             Some((field_name, fivetran_value))
@@ -94,14 +156,777 @@ fn to_fivetran_field(
     anyhow::Result::Ok(result)
 }
 
+/// Converts a value of a document.
+fn to_fivetran_value(value: JsonValue, options: &RowOptions) -> anyhow::Result<FivetranValue> {
+    let value = match options.format {
+        ExportFormat::ConvexEncodedJson => {
+            let convex_value = ConvexValue::try_from(value).context("Invalid Convex value")?;
+            convex_value.into()
+        },
+        ExportFormat::Json => from_plain_json(value),
+    };
+    Ok(match value {
+        FivetranValue::Double(number) if !number.is_finite() => match options.non_finite_floats {
+            NonFiniteFloats::Double => FivetranValue::Double(number),
+            NonFiniteFloats::Null => FivetranValue::Null(true),
+            NonFiniteFloats::String => FivetranValue::String(
+                if number.is_nan() {
+                    "NaN"
+                } else if number > 0.0 {
+                    "Infinity"
+                } else {
+                    "-Infinity"
+                }
+                .to_string(),
+            ),
+        },
+        FivetranValue::String(string) if options.detect_dates => {
+            parse_iso_8601(&string).unwrap_or(FivetranValue::String(string))
+        },
+        value => value,
+    })
+}
+
+/// How the floating-point values that aren’t finite numbers (NaN, Infinity
+/// and -Infinity) are synced, since many destinations can’t store them.
+/// Negative zeros are always synced as doubles.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NonFiniteFloats {
+    /// As doubles, for destinations supporting them.
+    #[default]
+    Double,
+    /// As NULL values.
+    Null,
+    /// As the strings `NaN`, `Infinity` and `-Infinity`.
+    String,
+}
+
+impl FromStr for NonFiniteFloats {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "Double" => Ok(NonFiniteFloats::Double),
+            "Null" => Ok(NonFiniteFloats::Null),
+            "String" => Ok(NonFiniteFloats::String),
+            _ => anyhow::bail!("Unknown handling of non-finite numbers: {value}"),
+        }
+    }
+}
+
+/// How the objects of user fields are synced.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NestedObjects {
+    /// As JSON columns.
+    #[default]
+    Json,
+    /// As one column per nested field (see [`flattened_column`]).
+    Columns,
+}
+
+impl FromStr for NestedObjects {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "JSON" => Ok(NestedObjects::Json),
+            "Columns" => Ok(NestedObjects::Columns),
+            _ => anyhow::bail!("Unknown handling of nested objects: {value}"),
+        }
+    }
+}
+
+/// Options changing how documents are converted to rows.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RowOptions {
+    /// The format in which the documents were exported.
+    pub format: ExportFormat,
+
+    /// If set, the user fields of documents having more fields than this (in
+    /// alphabetical order) are moved to a single JSON column,
+    /// [`OVERFLOW_COLUMN`].
+    pub max_fields: Option<usize>,
+
+    /// If set, the arrays and objects nested deeper than this in a field (the
+    /// field value being the first level) are replaced by their JSON
+    /// serialization, as strings.
+    pub max_nesting_depth: Option<usize>,
+
+    /// If set, every user column of a row has a companion boolean column (see
+    /// [`presence_column`]) set when the document has the field, even to
+    /// `null`. Otherwise, missing fields and `null` values can’t be told
+    /// apart: both are NULL in the destination.
+    pub presence_columns: bool,
+
+    /// If set, rows have a [`TIMESTAMP_COLUMN`] containing the `_ts` system
+    /// field of the document: the timestamp of the revision synced.
+    pub timestamp_column: bool,
+
+    /// If set, the system fields of documents other than `_id` (e.g.
+    /// `_creationTime`) aren’t converted to columns.
+    pub exclude_system_columns: bool,
+
+    /// How NaN and infinite numbers are converted.
+    pub non_finite_floats: NonFiniteFloats,
+
+    /// If set, the strings containing ISO 8601 dates (e.g. `2024-03-01`) or
+    /// date times (e.g. `2024-03-01T12:30:00Z`) are converted to dates.
+    pub detect_dates: bool,
+
+    /// If set, the array user fields of documents are synced to child tables
+    /// (see [`child_rows`]) rather than as JSON columns. The row of the
+    /// document only keeps the length of each array, in a column named by
+    /// [`array_length_column`].
+    pub child_tables: bool,
+
+    /// How the object user fields of documents are converted. When they are
+    /// flattened, their nested fields are converted to columns named by
+    /// [`flattened_column`], which don’t have presence columns.
+    pub nested_objects: NestedObjects,
+}
+
+/// Converts a Convex document to a Fivetran row.
 pub fn to_fivetran_row(
-    convex_document: HashMap<String, JsonValue>,
+    mut convex_document: HashMap<String, JsonValue>,
+    options: &RowOptions,
 ) -> anyhow::Result<HashMap<String, FivetranValue>> {
+    if let Some(max_nesting_depth) = options.max_nesting_depth {
+        for (field_name, field_value) in convex_document.iter_mut() {
+            if !field_name.starts_with('_') {
+                cut_nesting(field_value, max_nesting_depth);
+            }
+        }
+    }
+
+    let timestamp = if options.timestamp_column {
+        convex_document.get("_ts").and_then(JsonValue::as_i64)
+    } else {
+        None
+    };
+    if options.exclude_system_columns {
+        convex_document.retain(|field_name, _| !is_excludable_system_field(field_name));
+    }
+
+    let mut array_lengths: Vec<(String, usize)> = vec![];
+    if options.child_tables {
+        convex_document.retain(|field_name, field_value| match field_value {
+            JsonValue::Array(elements) if !field_name.starts_with('_') => {
+                array_lengths.push((array_length_column(field_name), elements.len()));
+                false
+            },
+            _ => true,
+        });
+    }
+
+    // Like arrays, the objects are taken out before the fields are limited.
+    let mut nested_fields: Vec<(String, JsonValue)> = vec![];
+    if options.nested_objects == NestedObjects::Columns {
+        convex_document.retain(|field_name, field_value| {
+            if field_name.starts_with('_') || !is_object(field_value) {
+                return true;
+            }
+            flatten_object(field_name, field_value.take(), &mut nested_fields);
+            false
+        });
+    }
+
+    let overflow = options
+        .max_fields
+        .and_then(|max_fields| take_overflow(&mut convex_document, max_fields));
+
     let possible_object_entries: Vec<Option<(String, FivetranValue)>> = convex_document
         .into_iter()
-        .map(to_fivetran_field)
-        .try_collect()?;
-    Ok(possible_object_entries.into_iter().flatten().collect())
+        .map(|field| to_fivetran_field(field, options))
+        .collect::<anyhow::Result<_>>()?;
+    let mut row: HashMap<String, FivetranValue> =
+        possible_object_entries.into_iter().flatten().collect();
+
+    if options.presence_columns {
+        let user_fields: Vec<String> = row
+            .keys()
+            .filter(|field_name| !field_name.starts_with('_'))
+            .cloned()
+            .collect();
+        for field_name in user_fields {
+            row.insert(presence_column(&field_name), FivetranValue::Bool(true));
+        }
+    }
+
+    for field in nested_fields {
+        row.extend(to_fivetran_field(field, options)?);
+    }
+
+    for (column_name, length) in array_lengths {
+        row.insert(column_name, FivetranValue::Long(length as i64));
+    }
+
+    if let Some(timestamp) = timestamp {
+        row.insert(TIMESTAMP_COLUMN.to_string(), FivetranValue::Long(timestamp));
+    }
+
+    if let Some(overflow) = overflow {
+        row.insert(
+            OVERFLOW_COLUMN.to_string(),
+            FivetranValue::Json(canonical_json(JsonValue::Object(overflow))),
+        );
+    }
+    Ok(row)
+}
+
+/// The column containing the fields of documents exceeding the maximum number
+/// of fields, as a JSON object.
+pub const OVERFLOW_COLUMN: &str = "_overflow";
+
+/// Whether a field is a system field that can be excluded from the rows, which
+/// is every system field but `_id`, the primary key.
+pub fn is_excludable_system_field(field_name: &str) -> bool {
+    field_name.starts_with('_') && field_name != "_id"
+}
+
+/// The column containing the timestamp of the revision of each document
+/// synced, when [`RowOptions::timestamp_column`] is set. Revisions of a
+/// document are applied in increasing timestamp order.
+pub const TIMESTAMP_COLUMN: &str = "_convex_ts";
+
+/// The boolean column marking whether documents have the given field, when
+/// [`RowOptions::presence_columns`] is set. It is `true` when the document has
+/// the field (even if its value is `null`), and NULL when it doesn’t.
+pub fn presence_column(field_name: &str) -> String {
+    format!("{field_name}__present")
+}
+
+/// The precision (total number of digits) and scale (number of digits after
+/// the decimal point) of a column synced as decimal numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecimalType {
+    pub precision: u32,
+    pub scale: u32,
+}
+
+/// The columns synced as decimal numbers rather than doubles, by table and
+/// column name.
+pub type DecimalColumns = BTreeMap<String, BTreeMap<String, DecimalType>>;
+
+/// Converts the numbers of the decimal columns of a row of the given table to
+/// decimal numbers with the scale of their column. Strings representing
+/// numbers are converted too, and other values are kept as is.
+pub fn convert_decimal_columns(
+    table_name: &str,
+    row: &mut HashMap<String, FivetranValue>,
+    decimal_columns: &DecimalColumns,
+) {
+    let Some(columns) = decimal_columns.get(table_name) else {
+        return;
+    };
+    for (column_name, decimal_type) in columns {
+        let Some(value) = row.get_mut(column_name) else {
+            continue;
+        };
+        if let Some(decimal) = to_decimal(value, decimal_type.scale) {
+            *value = FivetranValue::Decimal(decimal);
+        }
+    }
+}
+
+/// Formats a number with `scale` digits after the decimal point, or returns
+/// `None` if the value isn’t a finite number.
+fn to_decimal(value: &FivetranValue, scale: u32) -> Option<String> {
+    let scale = scale as usize;
+    match value {
+        // Longs are formatted without going through a double, which can’t
+        // represent every long.
+        FivetranValue::Long(number) if scale == 0 => Some(number.to_string()),
+        FivetranValue::Long(number) => Some(format!("{number}.{}", "0".repeat(scale))),
+        FivetranValue::Double(number) if number.is_finite() => Some(format!("{number:.scale$}")),
+        FivetranValue::String(string) => match string.trim().parse::<f64>() {
+            Ok(number) if number.is_finite() => Some(format!("{number:.scale$}")),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The columns whose values are replaced by their hash, by table.
+pub type HashedColumns = BTreeMap<String, BTreeSet<String>>;
+
+/// The columns hashed, and how: the SHA-256 hash of the salt followed by the
+/// value, so that values can still be joined on in the destination.
+#[derive(Debug, Clone, Default)]
+pub struct ColumnHashing {
+    pub columns: HashedColumns,
+    pub salt: String,
+}
+
+/// Replaces the values of the hashed columns of a row of the given table by
+/// their hash, as a hexadecimal string. NULL values are kept, so that missing
+/// values don’t all get the same hash.
+pub fn hash_columns(
+    table_name: &str,
+    row: &mut HashMap<String, FivetranValue>,
+    hashing: &ColumnHashing,
+) {
+    let Some(columns) = hashing.columns.get(table_name) else {
+        return;
+    };
+    for column_name in columns {
+        if let Some(value) = row.get_mut(column_name) {
+            if let Some(text) = hashable_text(value) {
+                *value = FivetranValue::String(sha256_hex(&hashing.salt, &text));
+            }
+        }
+    }
+}
+
+/// The text that is hashed for a value, or `None` for NULL values.
+fn hashable_text(value: &FivetranValue) -> Option<String> {
+    Some(match value {
+        FivetranValue::Null(_) => return None,
+        FivetranValue::String(string)
+        | FivetranValue::Json(string)
+        | FivetranValue::Decimal(string) => string.clone(),
+        FivetranValue::Bool(value) => value.to_string(),
+        FivetranValue::Short(number) | FivetranValue::Int(number) => number.to_string(),
+        FivetranValue::Long(number) => number.to_string(),
+        FivetranValue::Float(number) => number.to_string(),
+        FivetranValue::Double(number) => number.to_string(),
+        value => format!("{value:?}"),
+    })
+}
+
+fn sha256_hex(salt: &str, text: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update(text.as_bytes());
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// The columns whose values are masked, by table.
+pub type MaskedColumns = BTreeMap<String, BTreeSet<String>>;
+
+/// The columns masked, and the string replacing their values, if any.
+/// Otherwise, their values are replaced by NULL.
+#[derive(Debug, Clone, Default)]
+pub struct ColumnMasking {
+    pub columns: MaskedColumns,
+    pub mask: Option<String>,
+}
+
+/// Masks the fields of a document of the given table. Fields are masked
+/// before the document is converted, so that their values can’t reach the
+/// destination in child tables or in the overflow column either.
+pub fn mask_fields(
+    table_name: &str,
+    convex_document: &mut HashMap<String, JsonValue>,
+    masking: &ColumnMasking,
+) {
+    let Some(columns) = masking.columns.get(table_name) else {
+        return;
+    };
+    for column_name in columns {
+        if let Some(value) = convex_document.get_mut(column_name) {
+            *value = match masking.mask {
+                Some(ref mask) if !value.is_null() => JsonValue::String(mask.clone()),
+                _ => JsonValue::Null,
+            };
+        }
+    }
+}
+
+/// The data types forced on columns, by table and column name.
+pub type ColumnTypes = BTreeMap<String, BTreeMap<String, DataType>>;
+
+/// Converts the values of the columns of a row of the given table whose data
+/// type is forced to that type. Values that can’t be converted (e.g. a string
+/// that isn’t a number, in a LONG column) are replaced by NULL, so that the
+/// column only ever contains values of its type.
+pub fn convert_column_types(
+    table_name: &str,
+    row: &mut HashMap<String, FivetranValue>,
+    column_types: &ColumnTypes,
+) {
+    let Some(columns) = column_types.get(table_name) else {
+        return;
+    };
+    for (column_name, data_type) in columns {
+        if let Some(value) = row.remove(column_name) {
+            row.insert(column_name.clone(), to_data_type(value, *data_type));
+        }
+    }
+}
+
+/// Converts a value to the given data type, or to NULL if it can’t be.
+fn to_data_type(value: FivetranValue, data_type: DataType) -> FivetranValue {
+    if matches!(value, FivetranValue::Null(_)) {
+        return value;
+    }
+    let converted = match data_type {
+        DataType::Boolean => match value {
+            FivetranValue::Bool(value) => Some(FivetranValue::Bool(value)),
+            FivetranValue::String(ref string) => {
+                string.trim().parse().ok().map(FivetranValue::Bool)
+            },
+            _ => None,
+        },
+        DataType::Short => as_i64(&value)
+            .and_then(|number| i16::try_from(number).ok())
+            .map(|number| FivetranValue::Short(number.into())),
+        DataType::Int => as_i64(&value)
+            .and_then(|number| i32::try_from(number).ok())
+            .map(FivetranValue::Int),
+        DataType::Long => as_i64(&value).map(FivetranValue::Long),
+        DataType::Float => as_f64(&value).map(|number| FivetranValue::Float(number as f32)),
+        DataType::Double => as_f64(&value).map(FivetranValue::Double),
+        DataType::NaiveDate => as_timestamp(&value).map(|timestamp| {
+            FivetranValue::NaiveDate(Timestamp {
+                seconds: timestamp.seconds - timestamp.seconds.rem_euclid(24 * 60 * 60),
+                nanos: 0,
+            })
+        }),
+        DataType::NaiveDatetime => as_timestamp(&value).map(FivetranValue::NaiveDatetime),
+        DataType::UtcDatetime => as_timestamp(&value).map(FivetranValue::UtcDatetime),
+        DataType::String => match value {
+            FivetranValue::Bool(value) => Some(value.to_string()),
+            FivetranValue::Short(number) | FivetranValue::Int(number) => Some(number.to_string()),
+            FivetranValue::Long(number) => Some(number.to_string()),
+            FivetranValue::Float(number) => Some(number.to_string()),
+            FivetranValue::Double(number) => Some(number.to_string()),
+            FivetranValue::String(string)
+            | FivetranValue::Json(string)
+            | FivetranValue::Decimal(string) => Some(string),
+            _ => None,
+        }
+        .map(FivetranValue::String),
+        DataType::Json => match value {
+            FivetranValue::Json(json) => Some(json),
+            FivetranValue::String(string) => Some(JsonValue::String(string).to_string()),
+            FivetranValue::Bool(value) => Some(value.to_string()),
+            value => as_i64(&value).map(|number| number.to_string()).or_else(|| {
+                as_f64(&value)
+                    .and_then(serde_json::Number::from_f64)
+                    .map(|number| number.to_string())
+            }),
+        }
+        .map(FivetranValue::Json),
+        DataType::Binary => match value {
+            FivetranValue::Binary(bytes) => Some(FivetranValue::Binary(bytes)),
+            FivetranValue::String(string) => Some(FivetranValue::Binary(string.into_bytes())),
+            _ => None,
+        },
+        // Decimal columns are configured with their precision and scale instead.
+        _ => Some(value),
+    };
+    converted.unwrap_or(FivetranValue::Null(true))
+}
+
+/// The value as an integer, if it is an integral number.
+fn as_i64(value: &FivetranValue) -> Option<i64> {
+    match value {
+        FivetranValue::Short(number) | FivetranValue::Int(number) => Some((*number).into()),
+        FivetranValue::Long(number) => Some(*number),
+        FivetranValue::String(string) | FivetranValue::Decimal(string) => {
+            string.trim().parse().ok()
+        },
+        _ => as_f64(value)
+            .filter(|number| number.fract() == 0.0 && number.abs() < i64::MAX as f64)
+            .map(|number| number as i64),
+    }
+}
+
+/// The value as a double, if it is a finite number.
+fn as_f64(value: &FivetranValue) -> Option<f64> {
+    let number = match value {
+        FivetranValue::Short(number) | FivetranValue::Int(number) => (*number).into(),
+        FivetranValue::Long(number) => *number as f64,
+        FivetranValue::Float(number) => (*number).into(),
+        FivetranValue::Double(number) => *number,
+        FivetranValue::String(string) | FivetranValue::Decimal(string) => {
+            string.trim().parse().ok()?
+        },
+        _ => return None,
+    };
+    number.is_finite().then_some(number)
+}
+
+/// The value as a point in time: dates and date times, ISO 8601 strings, or
+/// numbers of milliseconds since the Unix epoch (like `_creationTime`).
+fn as_timestamp(value: &FivetranValue) -> Option<Timestamp> {
+    match value {
+        FivetranValue::NaiveDate(timestamp)
+        | FivetranValue::NaiveDatetime(timestamp)
+        | FivetranValue::UtcDatetime(timestamp) => Some(timestamp.clone()),
+        FivetranValue::String(string) => match parse_iso_8601(string.trim())? {
+            FivetranValue::NaiveDate(timestamp)
+            | FivetranValue::NaiveDatetime(timestamp)
+            | FivetranValue::UtcDatetime(timestamp) => Some(timestamp),
+            _ => None,
+        },
+        _ => as_f64(value).map(timestamp_from_ms),
+    }
+}
+
+/// The destination name of a column when column names are sanitized: camelCase
+/// words are separated by underscores, letters are lowercased, and characters
+/// other than letters and digits are replaced by underscores. System columns
+/// keep their names.
+pub fn sanitize_column_name(column_name: &str) -> String {
+    if column_name.starts_with('_') {
+        return column_name.to_string();
+    }
+    let mut sanitized = String::with_capacity(column_name.len());
+    let mut previous: Option<char> = None;
+    for c in column_name.chars() {
+        if c.is_ascii_uppercase()
+            && previous
+                .is_some_and(|previous| previous.is_ascii_lowercase() || previous.is_ascii_digit())
+        {
+            sanitized.push('_');
+        }
+        sanitized.push(if c.is_ascii_alphanumeric() {
+            c.to_ascii_lowercase()
+        } else {
+            '_'
+        });
+        previous = Some(c);
+    }
+    sanitized
+}
+
+/// The sanitized names of columns (see [`sanitize_column_name`]), by original
+/// name. When several columns have the same sanitized name, the column whose
+/// name is already sanitized keeps it, and the others (in alphabetical order)
+/// get a `_2`, `_3`… suffix.
+pub fn sanitized_column_names<'a>(
+    column_names: impl IntoIterator<Item = &'a str>,
+) -> BTreeMap<&'a str, String> {
+    let mut column_names: Vec<&str> = column_names.into_iter().collect();
+    // Columns keeping their name first, so that their name is never taken.
+    column_names.sort_by_key(|column_name| {
+        (
+            sanitize_column_name(column_name) != *column_name,
+            *column_name,
+        )
+    });
+
+    let mut taken: BTreeSet<String> = BTreeSet::new();
+    let mut sanitized_names = BTreeMap::new();
+    for column_name in column_names {
+        let sanitized = sanitize_column_name(column_name);
+        let mut destination_name = sanitized.clone();
+        let mut suffix = 2;
+        while taken.contains(&destination_name) {
+            destination_name = format!("{sanitized}_{suffix}");
+            suffix += 1;
+        }
+        taken.insert(destination_name.clone());
+        sanitized_names.insert(column_name, destination_name);
+    }
+    sanitized_names
+}
+
+/// The destination names of the user columns of a table synced with
+/// sanitized names, by column name.
+pub type ColumnNames = BTreeMap<String, String>;
+
+/// Renames the columns of a row to their sanitized names. The columns synced
+/// before keep the name given in `column_names`, and the new columns of the
+/// row get a name that isn’t taken yet, resolving collisions among them like
+/// [`sanitized_column_names`] does. Their names are added to `column_names`,
+/// so that a column never changes names when a colliding column appears later.
+pub fn sanitize_column_names(
+    row: &mut HashMap<String, FivetranValue>,
+    column_names: &mut ColumnNames,
+) {
+    let new_columns: Vec<&str> = row
+        .keys()
+        .map(String::as_str)
+        .filter(|column_name| {
+            !column_name.starts_with('_') && !column_names.contains_key(*column_name)
+        })
+        .collect();
+    if !new_columns.is_empty() {
+        let mut taken: BTreeSet<String> = column_names.values().cloned().collect();
+        for (column_name, sanitized) in sanitized_column_names(new_columns) {
+            let mut destination_name = sanitized.clone();
+            let mut suffix = 2;
+            while taken.contains(&destination_name) {
+                destination_name = format!("{sanitized}_{suffix}");
+                suffix += 1;
+            }
+            taken.insert(destination_name.clone());
+            column_names.insert(column_name.to_string(), destination_name);
+        }
+    }
+    *row = row
+        .drain()
+        .map(
+            |(column_name, value)| match column_names.get(&column_name) {
+                Some(destination_name) => (destination_name.clone(), value),
+                None => (column_name, value),
+            },
+        )
+        .collect();
+}
+
+/// The table containing the elements of an array field of the documents of a
+/// table, when [`RowOptions::child_tables`] is set.
+pub fn child_table_name(table_name: &str, field_name: &str) -> String {
+    format!("{table_name}__{field_name}")
+}
+
+/// The column of the rows of documents containing the length of an array
+/// field synced to a child table. Child rows whose index is beyond it are left
+/// over from a longer revision of the array.
+pub fn array_length_column(field_name: &str) -> String {
+    format!("{field_name}__length")
+}
+
+/// The columns of the rows of child tables. The parent ID and the index form
+/// their primary key.
+pub const CHILD_PARENT_ID_COLUMN: &str = "_parent_id";
+pub const CHILD_INDEX_COLUMN: &str = "_index";
+pub const CHILD_VALUE_COLUMN: &str = "value";
+
+/// The rows of the child tables of a document, with the name of their table,
+/// when [`RowOptions::child_tables`] is set. Every element of an array user
+/// field is a row, keyed by the `_id` of the document and the index of the
+/// element in the array.
+pub fn child_rows(
+    table_name: &str,
+    convex_document: &HashMap<String, JsonValue>,
+    options: &RowOptions,
+) -> anyhow::Result<Vec<(String, HashMap<String, FivetranValue>)>> {
+    if !options.child_tables {
+        return Ok(vec![]);
+    }
+    let Some(id) = convex_document.get("_id").and_then(JsonValue::as_str) else {
+        return Ok(vec![]);
+    };
+
+    let mut rows = vec![];
+    for (field_name, field_value) in convex_document {
+        let JsonValue::Array(elements) = field_value else {
+            continue;
+        };
+        if field_name.starts_with('_') {
+            continue;
+        }
+        let child_table_name = child_table_name(table_name, field_name);
+        for (index, element) in elements.iter().enumerate() {
+            let row = HashMap::from([
+                (
+                    CHILD_PARENT_ID_COLUMN.to_string(),
+                    FivetranValue::String(id.to_string()),
+                ),
+                (
+                    CHILD_INDEX_COLUMN.to_string(),
+                    FivetranValue::Long(index as i64),
+                ),
+                (
+                    CHILD_VALUE_COLUMN.to_string(),
+                    to_fivetran_value(element.clone(), options)?,
+                ),
+            ]);
+            rows.push((child_table_name.clone(), row));
+        }
+    }
+    Ok(rows)
+}
+
+/// Whether a value is an array or an object, rather than a scalar. Values
+/// encoded by the Convex export format (e.g. `{"$integer": "…"}`) are
+/// scalars: user objects can’t have fields starting with `$`.
+fn is_nested(value: &JsonValue) -> bool {
+    match value {
+        JsonValue::Array(_) => true,
+        JsonValue::Object(object) => {
+            !(object.len() == 1 && object.keys().all(|key| key.starts_with('$')))
+        },
+        _ => false,
+    }
+}
+
+/// Whether a value is a user object, rather than a value encoded by the Convex
+/// export format.
+fn is_object(value: &JsonValue) -> bool {
+    value.is_object() && is_nested(value)
+}
+
+/// The column containing a field of the object of a user field, when objects
+/// are flattened. Deeper fields are named after every field of their path,
+/// e.g. `address__city__name`.
+pub fn flattened_column(field_name: &str, nested_field_name: &str) -> String {
+    format!("{field_name}__{nested_field_name}")
+}
+
+/// Adds the non-object values nested in an object field to `fields`, named by
+/// [`flattened_column`]. Empty objects don’t have any column.
+fn flatten_object(field_name: &str, value: JsonValue, fields: &mut Vec<(String, JsonValue)>) {
+    let JsonValue::Object(object) = value else {
+        return;
+    };
+    for (nested_field_name, nested_value) in object {
+        let column_name = flattened_column(field_name, &nested_field_name);
+        if is_object(&nested_value) {
+            flatten_object(&column_name, nested_value, fields);
+        } else {
+            fields.push((column_name, nested_value));
+        }
+    }
+}
+
+/// Replaces the arrays and objects nested more than `max_depth` levels deep in
+/// a field value (the value itself being the first level) by their JSON
+/// serialization. The recursion stops at the cut-off, so it is bounded by
+/// `max_depth` regardless of the document.
+fn cut_nesting(value: &mut JsonValue, max_depth: usize) {
+    if !is_nested(value) {
+        return;
+    }
+    let children: Vec<&mut JsonValue> = match value {
+        JsonValue::Array(array) => array.iter_mut().collect(),
+        JsonValue::Object(object) => object.values_mut().collect(),
+        _ => return,
+    };
+    for child in children.into_iter().filter(|child| is_nested(child)) {
+        if max_depth <= 1 {
+            *child = JsonValue::String(canonical_json(child.take()));
+        } else {
+            cut_nesting(child, max_depth - 1);
+        }
+    }
+}
+
+/// Removes the user fields exceeding `max_fields` from the document, and
+/// returns them if there are any. The values are kept in the Convex JSON
+/// export format.
+fn take_overflow(
+    convex_document: &mut HashMap<String, JsonValue>,
+    max_fields: usize,
+) -> Option<serde_json::Map<String, JsonValue>> {
+    let mut user_fields: Vec<String> = convex_document
+        .keys()
+        .filter(|field_name| !field_name.starts_with('_'))
+        .cloned()
+        .collect();
+    if user_fields.len() <= max_fields {
+        return None;
+    }
+
+    user_fields.sort();
+    Some(
+        user_fields
+            .into_iter()
+            .skip(max_fields)
+            .map(|field_name| {
+                let value = convex_document.remove(&field_name).unwrap();
+                (field_name, value)
+            })
+            .collect(),
+    )
 }
 
 #[cfg(test)]
@@ -124,14 +949,32 @@ mod tests {
         }
     }
 
+    #[test]
+    fn serializes_nested_values_canonically() {
+        let mut fields = serde_json::Map::new();
+        fields.insert("b".to_string(), json!([{ "d": 1, "c": 2 }]));
+        fields.insert("a".to_string(), json!(null));
+        assert_eq!(
+            canonical_json(JsonValue::Object(fields)),
+            r#"{"a":null,"b":[{"c":2,"d":1}]}"#
+        );
+        assert_eq!(
+            from_plain_json(json!({ "z": true, "y": [1.5] })),
+            FivetranValue::Json(r#"{"y":[1.5],"z":true}"#.to_string())
+        );
+    }
+
     #[test]
     fn ignores_system_fields_except_id_and_creation_time() -> anyhow::Result<()> {
-        let result = to_fivetran_row(hashmap! {
-            "_id".to_string() => json!("2rsfck4e88mvyb011h9k7znq9h1mb00"),
-            "_creationTime".to_string() => json!(1686799242010.5989),
-            "_other_system_field".to_string() => json!("hidden"),
-            "normalField".to_string() => json!("Hello world"),
-        })?;
+        let result = to_fivetran_row(
+            hashmap! {
+                "_id".to_string() => json!("2rsfck4e88mvyb011h9k7znq9h1mb00"),
+                "_creationTime".to_string() => json!(1686799242010.5989),
+                "_other_system_field".to_string() => json!("hidden"),
+                "normalField".to_string() => json!("Hello world"),
+            },
+            &RowOptions::default(),
+        )?;
 
         assert!(result.contains_key("_id"));
         assert!(result.contains_key("_creationTime"));
@@ -144,9 +987,12 @@ mod tests {
     #[test]
     fn can_convert_id() -> anyhow::Result<()> {
         assert_eq!(
-            to_fivetran_row(hashmap! {
-                "_id".to_string() => json!("2rsfck4e88mvyb011h9k7znq9h1mb00"),
-            })?,
+            to_fivetran_row(
+                hashmap! {
+                    "_id".to_string() => json!("2rsfck4e88mvyb011h9k7znq9h1mb00"),
+                },
+                &RowOptions::default(),
+            )?,
             hashmap! {
                 "_id".to_string() => FivetranValue::String("2rsfck4e88mvyb011h9k7znq9h1mb00".to_string()),
             }
@@ -158,14 +1004,697 @@ mod tests {
     #[test]
     fn can_convert_creation_time() -> anyhow::Result<()> {
         assert_eq!(
-            to_fivetran_row(hashmap! {
-                "_creationTime".to_string() => json!(1686799242010.5),
-            })?,
+            to_fivetran_row(
+                hashmap! {
+                    "_creationTime".to_string() => json!(1686799242010.5),
+                },
+                &RowOptions::default(),
+            )?,
+            hashmap! {
+                "_creationTime".to_string() => FivetranValue::UtcDatetime(Timestamp::date_time_nanos(2023, 6, 15, 3, 20, 42, 10500000).unwrap()),
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn moves_fields_exceeding_the_maximum_to_the_overflow_column() -> anyhow::Result<()> {
+        let row = to_fivetran_row(
+            hashmap! {
+                "_id".to_string() => json!("2rsfck4e88mvyb011h9k7znq9h1mb00"),
+                "a".to_string() => json!(1.0),
+                "b".to_string() => json!("two"),
+                "c".to_string() => json!({ "three": 3.0 }),
+            },
+            &RowOptions {
+                max_fields: Some(1),
+                ..RowOptions::default()
+            },
+        )?;
+
+        assert_eq!(row.len(), 3);
+        assert!(row.contains_key("_id"));
+        assert_eq!(row.get("a"), Some(&FivetranValue::Double(1.0)));
+        let Some(FivetranValue::Json(overflow)) = row.get(OVERFLOW_COLUMN) else {
+            panic!("Missing overflow column");
+        };
+        assert_eq!(
+            serde_json::from_str::<JsonValue>(overflow)?,
+            json!({ "b": "two", "c": { "three": 3.0 } })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_documents_within_the_maximum_unchanged() -> anyhow::Result<()> {
+        let document = hashmap! {
+            "_id".to_string() => json!("2rsfck4e88mvyb011h9k7znq9h1mb00"),
+            "a".to_string() => json!(1.0),
+            "b".to_string() => json!("two"),
+        };
+
+        assert_eq!(
+            to_fivetran_row(
+                document.clone(),
+                &RowOptions {
+                    max_fields: Some(2),
+                    ..RowOptions::default()
+                }
+            )?,
+            to_fivetran_row(document, &RowOptions::default())?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn marks_the_presence_of_fields_when_requested() -> anyhow::Result<()> {
+        let document = hashmap! {
+            "_id".to_string() => json!("2rsfck4e88mvyb011h9k7znq9h1mb00"),
+            "name".to_string() => json!("Ada"),
+            "nickname".to_string() => json!(null),
+        };
+
+        assert_eq!(
+            to_fivetran_row(
+                document,
+                &RowOptions {
+                    presence_columns: true,
+                    ..RowOptions::default()
+                }
+            )?,
+            hashmap! {
+                "_id".to_string() => FivetranValue::String("2rsfck4e88mvyb011h9k7znq9h1mb00".to_string()),
+                "name".to_string() => FivetranValue::String("Ada".to_string()),
+                "name__present".to_string() => FivetranValue::Bool(true),
+                "nickname".to_string() => FivetranValue::Null(true),
+                "nickname__present".to_string() => FivetranValue::Bool(true),
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn exposes_the_timestamp_of_documents_when_requested() -> anyhow::Result<()> {
+        let document = hashmap! {
+            "_id".to_string() => json!("2rsfck4e88mvyb011h9k7znq9h1mb00"),
+            "_ts".to_string() => json!(1_700_000_000_123_456_789_i64),
+        };
+
+        let row = to_fivetran_row(document.clone(), &RowOptions::default())?;
+        assert!(!row.contains_key(TIMESTAMP_COLUMN));
+
+        let row = to_fivetran_row(
+            document,
+            &RowOptions {
+                timestamp_column: true,
+                ..RowOptions::default()
+            },
+        )?;
+        assert_eq!(
+            row[TIMESTAMP_COLUMN],
+            FivetranValue::Long(1_700_000_000_123_456_789)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn excludes_system_columns_when_requested() -> anyhow::Result<()> {
+        let document = hashmap! {
+            "_id".to_string() => json!("2rsfck4e88mvyb011h9k7znq9h1mb00"),
+            "_creationTime".to_string() => json!(1686799242010.5),
+            "name".to_string() => json!("Ada"),
+        };
+
+        assert_eq!(
+            to_fivetran_row(
+                document,
+                &RowOptions {
+                    exclude_system_columns: true,
+                    ..RowOptions::default()
+                }
+            )?,
+            hashmap! {
+                "_id".to_string() => FivetranValue::String("2rsfck4e88mvyb011h9k7znq9h1mb00".to_string()),
+                "name".to_string() => FivetranValue::String("Ada".to_string()),
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn syncs_arrays_to_child_tables_when_requested() -> anyhow::Result<()> {
+        let document = hashmap! {
+            "_id".to_string() => json!("2rsfck4e88mvyb011h9k7znq9h1mb00"),
+            "name".to_string() => json!("Ada"),
+            "tags".to_string() => json!(["math", {"kind": "engine"}]),
+        };
+        let options = RowOptions {
+            child_tables: true,
+            ..RowOptions::default()
+        };
+
+        assert_eq!(
+            to_fivetran_row(document.clone(), &options)?,
+            hashmap! {
+                "_id".to_string() => FivetranValue::String("2rsfck4e88mvyb011h9k7znq9h1mb00".to_string()),
+                "name".to_string() => FivetranValue::String("Ada".to_string()),
+                "tags__length".to_string() => FivetranValue::Long(2),
+            }
+        );
+        assert_eq!(
+            child_rows("users", &document, &options)?,
+            vec![
+                (
+                    "users__tags".to_string(),
+                    hashmap! {
+                        "_parent_id".to_string() => FivetranValue::String("2rsfck4e88mvyb011h9k7znq9h1mb00".to_string()),
+                        "_index".to_string() => FivetranValue::Long(0),
+                        "value".to_string() => FivetranValue::String("math".to_string()),
+                    }
+                ),
+                (
+                    "users__tags".to_string(),
+                    hashmap! {
+                        "_parent_id".to_string() => FivetranValue::String("2rsfck4e88mvyb011h9k7znq9h1mb00".to_string()),
+                        "_index".to_string() => FivetranValue::Long(1),
+                        "value".to_string() => FivetranValue::Json(r#"{"kind":"engine"}"#.to_string()),
+                    }
+                ),
+            ]
+        );
+        assert_eq!(
+            child_rows("users", &document, &RowOptions::default())?,
+            vec![]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn converts_plain_json_values() -> anyhow::Result<()> {
+        let document = hashmap! {
+            "_creationTime".to_string() => json!(1686799242010.5),
+            "count".to_string() => json!("123"),
+            "ratio".to_string() => json!(0.5),
+            "tags".to_string() => json!(["a", "b"]),
+            "missing".to_string() => json!(null),
+        };
+
+        assert_eq!(
+            to_fivetran_row(
+                document,
+                &RowOptions {
+                    format: ExportFormat::Json,
+                    ..RowOptions::default()
+                }
+            )?,
             hashmap! {
                 "_creationTime".to_string() => FivetranValue::UtcDatetime(Timestamp::date_time_nanos(2023, 6, 15, 3, 20, 42, 10500000).unwrap()),
+                "count".to_string() => FivetranValue::String("123".to_string()),
+                "ratio".to_string() => FivetranValue::Double(0.5),
+                "tags".to_string() => FivetranValue::Json("[\"a\",\"b\"]".to_string()),
+                "missing".to_string() => FivetranValue::Null(true),
             }
         );
 
         Ok(())
     }
+
+    #[test]
+    fn only_decodes_encoded_values_in_the_encoded_format() -> anyhow::Result<()> {
+        let document = hashmap! {
+            "count".to_string() => json!({ "$integer": "AQAAAAAAAAA=" }),
+        };
+
+        assert_eq!(
+            to_fivetran_row(document.clone(), &RowOptions::default())?,
+            hashmap! { "count".to_string() => FivetranValue::Long(1) },
+        );
+        assert_eq!(
+            to_fivetran_row(
+                document,
+                &RowOptions {
+                    format: ExportFormat::Json,
+                    ..RowOptions::default()
+                }
+            )?,
+            hashmap! {
+                "count".to_string() => FivetranValue::Json(r#"{"$integer":"AQAAAAAAAAA="}"#.to_string()),
+            },
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn decodes_int64_values_to_longs() -> anyhow::Result<()> {
+        let document = hashmap! {
+            "negative".to_string() => json!({ "$integer": "//////////8=" }),
+            "max".to_string() => json!({ "$integer": "/////////38=" }),
+            "nested".to_string() => json!({ "count": { "$integer": "KgAAAAAAAAA=" } }),
+        };
+
+        let row = to_fivetran_row(document, &RowOptions::default())?;
+
+        assert_eq!(row["negative"], FivetranValue::Long(-1));
+        assert_eq!(row["max"], FivetranValue::Long(i64::MAX));
+        let FivetranValue::Json(ref nested) = row["nested"] else {
+            panic!("Unexpected nested value: {:?}", row["nested"]);
+        };
+        // Nested values use the JSON export format, in which Int64 values are
+        // strings in base 10.
+        assert_eq!(nested, r#"{"count":"42"}"#);
+
+        Ok(())
+    }
+
+    #[test]
+    fn decodes_bytes_values_to_binary_values() -> anyhow::Result<()> {
+        let document = hashmap! {
+            "avatar".to_string() => json!({ "$bytes": "iVBORw==" }),
+        };
+
+        assert_eq!(
+            to_fivetran_row(document, &RowOptions::default())?,
+            hashmap! {
+                "avatar".to_string() => FivetranValue::Binary(vec![0x89, b'P', b'N', b'G']),
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn converts_non_finite_numbers_as_requested() -> anyhow::Result<()> {
+        let document = hashmap! {
+            "nan".to_string() => json!({ "$float": "AAAAAAAA+H8=" }),
+            "infinity".to_string() => json!({ "$float": "AAAAAAAA8H8=" }),
+            "negative_infinity".to_string() => json!({ "$float": "AAAAAAAA8P8=" }),
+            "negative_zero".to_string() => json!({ "$float": "AAAAAAAAAIA=" }),
+        };
+        let row = |non_finite_floats| {
+            to_fivetran_row(
+                document.clone(),
+                &RowOptions {
+                    non_finite_floats,
+                    ..RowOptions::default()
+                },
+            )
+        };
+
+        let doubles = row(NonFiniteFloats::Double)?;
+        assert!(matches!(doubles["nan"], FivetranValue::Double(number) if number.is_nan()));
+        assert_eq!(doubles["infinity"], FivetranValue::Double(f64::INFINITY));
+        assert_eq!(
+            doubles["negative_infinity"],
+            FivetranValue::Double(f64::NEG_INFINITY)
+        );
+
+        let nulls = row(NonFiniteFloats::Null)?;
+        assert_eq!(nulls["nan"], FivetranValue::Null(true));
+        assert_eq!(nulls["infinity"], FivetranValue::Null(true));
+        assert_eq!(nulls["negative_infinity"], FivetranValue::Null(true));
+
+        let strings = row(NonFiniteFloats::String)?;
+        assert_eq!(strings["nan"], FivetranValue::String("NaN".to_string()));
+        assert_eq!(
+            strings["infinity"],
+            FivetranValue::String("Infinity".to_string())
+        );
+        assert_eq!(
+            strings["negative_infinity"],
+            FivetranValue::String("-Infinity".to_string())
+        );
+
+        for row in [doubles, nulls, strings] {
+            let FivetranValue::Double(negative_zero) = row["negative_zero"] else {
+                panic!("Unexpected negative zero: {:?}", row["negative_zero"]);
+            };
+            assert!(negative_zero == 0.0 && negative_zero.is_sign_negative());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn converts_decimal_columns() {
+        let mut row = hashmap! {
+            "total".to_string() => FivetranValue::Double(12.3456),
+            "count".to_string() => FivetranValue::Long(-3),
+            "price".to_string() => FivetranValue::String(" 1.5".to_string()),
+            "note".to_string() => FivetranValue::String("free".to_string()),
+            "other".to_string() => FivetranValue::Double(0.5),
+        };
+        let decimal_type = DecimalType {
+            precision: 10,
+            scale: 2,
+        };
+        let decimal_columns: DecimalColumns = BTreeMap::from([(
+            "orders".to_string(),
+            ["total", "count", "price", "note"]
+                .into_iter()
+                .map(|column_name| (column_name.to_string(), decimal_type))
+                .collect(),
+        )]);
+
+        convert_decimal_columns("users", &mut row, &decimal_columns);
+        assert_eq!(row["total"], FivetranValue::Double(12.3456));
+
+        convert_decimal_columns("orders", &mut row, &decimal_columns);
+        assert_eq!(
+            row,
+            hashmap! {
+                "total".to_string() => FivetranValue::Decimal("12.35".to_string()),
+                "count".to_string() => FivetranValue::Decimal("-3.00".to_string()),
+                "price".to_string() => FivetranValue::Decimal("1.50".to_string()),
+                "note".to_string() => FivetranValue::String("free".to_string()),
+                "other".to_string() => FivetranValue::Double(0.5),
+            }
+        );
+    }
+
+    #[test]
+    fn detects_dates_when_requested() -> anyhow::Result<()> {
+        let document = hashmap! {
+            "birthday".to_string() => json!("1815-12-10"),
+            "name".to_string() => json!("Ada"),
+        };
+
+        let row = to_fivetran_row(
+            document.clone(),
+            &RowOptions {
+                detect_dates: true,
+                ..RowOptions::default()
+            },
+        )?;
+        assert_eq!(
+            row["birthday"],
+            FivetranValue::NaiveDate(Timestamp::date(1815, 12, 10).unwrap())
+        );
+        assert_eq!(row["name"], FivetranValue::String("Ada".to_string()));
+
+        let row = to_fivetran_row(document, &RowOptions::default())?;
+        assert_eq!(
+            row["birthday"],
+            FivetranValue::String("1815-12-10".to_string())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn sanitizes_column_names() {
+        assert_eq!(sanitize_column_name("createdAt"), "created_at");
+        assert_eq!(sanitize_column_name("first name"), "first_name");
+        assert_eq!(sanitize_column_name("address.zip"), "address_zip");
+        assert_eq!(sanitize_column_name("item2Price"), "item2_price");
+        assert_eq!(sanitize_column_name("URL"), "url");
+        assert_eq!(sanitize_column_name("_creationTime"), "_creationTime");
+
+        let mut row = hashmap! {
+            "_id".to_string() => FivetranValue::String("1".to_string()),
+            "userName".to_string() => FivetranValue::String("ada".to_string()),
+            "user_name".to_string() => FivetranValue::String("Ada".to_string()),
+            "User Name".to_string() => FivetranValue::String("ADA".to_string()),
+            "isAdmin".to_string() => FivetranValue::Bool(true),
+        };
+        let mut column_names = ColumnNames::new();
+        sanitize_column_names(&mut row, &mut column_names);
+        assert_eq!(
+            row,
+            hashmap! {
+                "_id".to_string() => FivetranValue::String("1".to_string()),
+                "user_name".to_string() => FivetranValue::String("Ada".to_string()),
+                "user_name_2".to_string() => FivetranValue::String("ADA".to_string()),
+                "user_name_3".to_string() => FivetranValue::String("ada".to_string()),
+                "is_admin".to_string() => FivetranValue::Bool(true),
+            }
+        );
+
+        // Rows missing some of the colliding fields are renamed the same way
+        let mut row = hashmap! {
+            "userName".to_string() => FivetranValue::String("ada".to_string()),
+        };
+        sanitize_column_names(&mut row, &mut column_names);
+        assert_eq!(
+            row,
+            hashmap! {
+                "user_name_3".to_string() => FivetranValue::String("ada".to_string()),
+            }
+        );
+
+        // Columns appearing later never take the name of a column synced before
+        let mut row = hashmap! {
+            "is_admin".to_string() => FivetranValue::Bool(false),
+            "isAdmin".to_string() => FivetranValue::Bool(false),
+        };
+        sanitize_column_names(&mut row, &mut column_names);
+        assert_eq!(
+            row,
+            hashmap! {
+                "is_admin".to_string() => FivetranValue::Bool(false),
+                "is_admin_2".to_string() => FivetranValue::Bool(false),
+            }
+        );
+        assert_eq!(column_names["isAdmin"], "is_admin");
+        assert_eq!(column_names["is_admin"], "is_admin_2");
+    }
+
+    #[test]
+    fn hashes_the_hashed_columns() {
+        let row = || {
+            hashmap! {
+                "email".to_string() => FivetranValue::String("alice@example.com".to_string()),
+                "user_id".to_string() => FivetranValue::Long(42),
+                "phone".to_string() => FivetranValue::Null(true),
+                "name".to_string() => FivetranValue::String("Alice".to_string()),
+            }
+        };
+        let hashing = |salt: &str| ColumnHashing {
+            columns: BTreeMap::from([(
+                "users".to_string(),
+                BTreeSet::from(["email", "user_id", "phone"].map(String::from)),
+            )]),
+            salt: salt.to_string(),
+        };
+
+        let mut hashed_row = row();
+        hash_columns("users", &mut hashed_row, &hashing(""));
+        assert_eq!(
+            hashed_row,
+            hashmap! {
+                "email".to_string() => FivetranValue::String(
+                    "ff8d9819fc0e12bf0d24892e45987e249a28dce836a85cad60e28eaaa8c6d976".to_string()
+                ),
+                "user_id".to_string() => FivetranValue::String(
+                    "73475cb40a568e8da8a045ced110137e159f890ac4da883b6b17dc651b3a8049".to_string()
+                ),
+                "phone".to_string() => FivetranValue::Null(true),
+                "name".to_string() => FivetranValue::String("Alice".to_string()),
+            }
+        );
+
+        let mut salted_row = row();
+        hash_columns("users", &mut salted_row, &hashing("pepper"));
+        assert_eq!(
+            salted_row["email"],
+            FivetranValue::String(
+                "8b8d9adc4875c0dca816e3e17b7ac87b45e40945b731fa02e3b42bf101589e21".to_string()
+            )
+        );
+
+        // The columns of other tables aren’t hashed.
+        let mut other_row = row();
+        hash_columns("messages", &mut other_row, &hashing(""));
+        assert_eq!(other_row, row());
+    }
+
+    #[test]
+    fn masks_the_masked_fields() {
+        let document = || {
+            hashmap! {
+                "_id".to_string() => json!("abc"),
+                "ssn".to_string() => json!("123-45-6789"),
+                "aliases".to_string() => json!(["Al", "Ali"]),
+                "phone".to_string() => JsonValue::Null,
+                "name".to_string() => json!("Alice"),
+            }
+        };
+        let masking = |mask: Option<&str>| ColumnMasking {
+            columns: BTreeMap::from([(
+                "users".to_string(),
+                BTreeSet::from(["ssn", "aliases", "phone"].map(String::from)),
+            )]),
+            mask: mask.map(String::from),
+        };
+
+        let mut nulled = document();
+        mask_fields("users", &mut nulled, &masking(None));
+        assert_eq!(
+            nulled,
+            hashmap! {
+                "_id".to_string() => json!("abc"),
+                "ssn".to_string() => JsonValue::Null,
+                "aliases".to_string() => JsonValue::Null,
+                "phone".to_string() => JsonValue::Null,
+                "name".to_string() => json!("Alice"),
+            }
+        );
+
+        let mut masked = document();
+        mask_fields("users", &mut masked, &masking(Some("***")));
+        assert_eq!(masked["ssn"], json!("***"));
+        assert_eq!(masked["aliases"], json!("***"));
+        assert_eq!(masked["phone"], JsonValue::Null);
+
+        let mut other = document();
+        mask_fields("messages", &mut other, &masking(None));
+        assert_eq!(other, document());
+    }
+
+    #[test]
+    fn converts_columns_to_their_forced_type() {
+        let mut row = hashmap! {
+            "count".to_string() => FivetranValue::String("42".to_string()),
+            "invalid_count".to_string() => FivetranValue::String("many".to_string()),
+            "ratio".to_string() => FivetranValue::Long(3),
+            "flag".to_string() => FivetranValue::String("true".to_string()),
+            "label".to_string() => FivetranValue::Double(7.5),
+            "day".to_string() => FivetranValue::String("2024-03-01".to_string()),
+            "seen_at".to_string() => FivetranValue::Double(1_700_000_000_000.0),
+            "metadata".to_string() => FivetranValue::String("x".to_string()),
+            "missing".to_string() => FivetranValue::Null(true),
+            "other".to_string() => FivetranValue::String("42".to_string()),
+        };
+        let column_types: ColumnTypes = BTreeMap::from([(
+            "events".to_string(),
+            BTreeMap::from([
+                ("count".to_string(), DataType::Long),
+                ("invalid_count".to_string(), DataType::Long),
+                ("ratio".to_string(), DataType::Double),
+                ("flag".to_string(), DataType::Boolean),
+                ("label".to_string(), DataType::String),
+                ("day".to_string(), DataType::UtcDatetime),
+                ("seen_at".to_string(), DataType::UtcDatetime),
+                ("metadata".to_string(), DataType::Json),
+                ("missing".to_string(), DataType::Long),
+            ]),
+        )]);
+
+        convert_column_types("events", &mut row, &column_types);
+
+        assert_eq!(
+            row,
+            hashmap! {
+                "count".to_string() => FivetranValue::Long(42),
+                "invalid_count".to_string() => FivetranValue::Null(true),
+                "ratio".to_string() => FivetranValue::Double(3.0),
+                "flag".to_string() => FivetranValue::Bool(true),
+                "label".to_string() => FivetranValue::String("7.5".to_string()),
+                "day".to_string() =>
+                    FivetranValue::UtcDatetime(Timestamp::date(2024, 3, 1).unwrap()),
+                "seen_at".to_string() => FivetranValue::UtcDatetime(
+                    Timestamp::date_time(2023, 11, 14, 22, 13, 20).unwrap()
+                ),
+                "metadata".to_string() => FivetranValue::Json(r#""x""#.to_string()),
+                "missing".to_string() => FivetranValue::Null(true),
+                "other".to_string() => FivetranValue::String("42".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn serializes_values_nested_beyond_the_maximum_depth() -> anyhow::Result<()> {
+        let document = hashmap! {
+            "shallow".to_string() => json!({ "a": [1.0, 2.0] }),
+            "deep".to_string() => json!({ "a": { "b": { "c": "d" } } }),
+            "scalar".to_string() => json!("text"),
+        };
+
+        let row = to_fivetran_row(
+            document,
+            &RowOptions {
+                max_nesting_depth: Some(2),
+                ..RowOptions::default()
+            },
+        )?;
+
+        let Some(FivetranValue::Json(shallow)) = row.get("shallow") else {
+            panic!("Missing shallow column");
+        };
+        assert_eq!(
+            serde_json::from_str::<JsonValue>(shallow)?,
+            json!({ "a": [1.0, 2.0] })
+        );
+        let Some(FivetranValue::Json(deep)) = row.get("deep") else {
+            panic!("Missing deep column");
+        };
+        assert_eq!(
+            serde_json::from_str::<JsonValue>(deep)?,
+            json!({ "a": { "b": r#"{"c":"d"}"# } })
+        );
+        assert_eq!(
+            row.get("scalar"),
+            Some(&FivetranValue::String("text".to_string()))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn flattens_objects_to_columns() -> anyhow::Result<()> {
+        let document = hashmap! {
+            "_id".to_string() => json!("abc"),
+            "address".to_string() => json!({ "city": { "name": "Paris" }, "zip": "75001" }),
+            "tags".to_string() => json!({ "list": ["a"], "empty": {} }),
+            "nothing".to_string() => json!(null),
+        };
+
+        let row = to_fivetran_row(
+            document,
+            &RowOptions {
+                nested_objects: NestedObjects::Columns,
+                presence_columns: true,
+                ..RowOptions::default()
+            },
+        )?;
+
+        assert_eq!(
+            row,
+            hashmap! {
+                "_id".to_string() => FivetranValue::String("abc".to_string()),
+                "address__city__name".to_string() => FivetranValue::String("Paris".to_string()),
+                "address__zip".to_string() => FivetranValue::String("75001".to_string()),
+                "tags__list".to_string() => FivetranValue::Json(r#"["a"]"#.to_string()),
+                "nothing".to_string() => FivetranValue::Null(true),
+                "nothing__present".to_string() => FivetranValue::Bool(true),
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn cuts_pathological_nesting_without_recursing_into_it() {
+        let mut value = json!("leaf");
+        for _ in 0..100 {
+            value = json!([value]);
+        }
+
+        cut_nesting(&mut value, 1);
+
+        let JsonValue::Array(array) = &value else {
+            panic!("The first level should be kept");
+        };
+        let JsonValue::String(serialized) = &array[0] else {
+            panic!("The second level should be serialized");
+        };
+        assert_eq!(serialized.matches('[').count(), 99);
+    }
 }
@@ -1,7 +1,33 @@
+//! The Convex streaming export API, and the [`Source`] trait abstracting it
+//! so that the sync can be tested without a deployment.
+
 use std::{
-    collections::HashMap,
-    fmt::Display,
-    sync::LazyLock,
+    collections::{
+        BTreeSet,
+        HashMap,
+    },
+    fmt::{
+        Debug,
+        Display,
+    },
+    io::{
+        self,
+        Read,
+    },
+    sync::{
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+        Arc,
+        LazyLock,
+        Mutex,
+    },
+    time::{
+        Duration,
+        Instant,
+        SystemTime,
+    },
 };
 
 use anyhow::Context;
@@ -12,6 +38,7 @@ use derive_more::{
     Into,
 };
 use maplit::hashmap;
+use reqwest::StatusCode;
 use schemars::schema::Schema;
 use serde::{
     de::DeserializeOwned,
@@ -19,34 +46,80 @@ use serde::{
     Serialize,
 };
 use serde_json::Value as JsonValue;
+use tokio::{
+    sync::{
+        mpsc,
+        Semaphore,
+    },
+    task::JoinHandle,
+};
 use tonic::codegen::http::{
     HeaderName,
     HeaderValue,
 };
+use url::Url;
 
-use crate::config::Config;
+use crate::{
+    config::{
+        Config,
+        Credentials,
+    },
+    error::ConnectorError,
+    files::STORAGE_TABLE_NAME,
+    log_warning,
+    metrics,
+    redact::{
+        self,
+        SecretRegistration,
+    },
+};
 
 #[allow(clippy::declare_interior_mutable_const)]
 const CONVEX_CLIENT_HEADER: HeaderName = HeaderName::from_static("convex-client");
 
+/// The delay used when a rate-limited response doesn’t specify a valid
+/// `Retry-After` header.
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// The longest delay the connector accepts to wait for before retrying a
+/// rate-limited request.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(10 * 60);
+
+/// The Convex system tables that can be synced. The Convex API only returns
+/// the system tables requested in its `systemTables` parameter.
+pub const SYSTEM_TABLES: [&str; 2] = ["_storage", "_scheduled_functions"];
+
+/// The number of chunks of a response body received ahead of the parser.
+const BODY_CHUNKS_BUFFERED: usize = 16;
+
+/// The Convex management API, which describes the deployments accessible with
+/// an access token.
+static MANAGEMENT_API_URL: LazyLock<Url> =
+    LazyLock::new(|| Url::parse("https://api.convex.dev/v1/").unwrap());
+
 static CONVEX_CLIENT_HEADER_VALUE: LazyLock<HeaderValue> = LazyLock::new(|| {
     let connector_version = env!("CARGO_PKG_VERSION");
     HeaderValue::from_str(&format!("fivetran-export-{connector_version}")).unwrap()
 });
 
-/// The APIs exposed by a Convex backend for streaming export.
+/// The APIs exposed by a Convex backend for streaming export. Sources are
+/// shared with the tasks prefetching pages, so they must be `Sync`.
 #[async_trait]
-pub trait Source: Display + Send {
+pub trait Source: Display + Send + Sync {
     /// An endpoint that confirms the Convex backend is accessible with
     /// streaming export enabled
     async fn test_streaming_export_connection(&self) -> anyhow::Result<()>;
 
     /// See https://docs.convex.dev/http-api/#get-apilist_snapshot
+    ///
+    /// The cursor identifies the last document returned, so a cursor returned
+    /// by a call can be used in a call with a different `page_size`.
     async fn list_snapshot(
         &self,
         snapshot: Option<i64>,
         cursor: Option<ListSnapshotCursor>,
         table_name: Option<String>,
+        page_size: Option<u32>,
     ) -> anyhow::Result<ListSnapshotResponse>;
 
     /// See https://docs.convex.dev/http-api/#get-apidocument_deltas
@@ -58,17 +131,201 @@ pub trait Source: Display + Send {
 
     /// Get a list of columns for each table on the Convex backend.
     async fn get_tables_and_columns(&self) -> anyhow::Result<HashMap<TableName, Vec<FieldName>>>;
+
+    /// Like `get_tables_and_columns`, for the tables of the components
+    /// installed in the deployment, by component path (e.g. `workpool`). The
+    /// root component isn’t included. Sources that don’t support components
+    /// return none.
+    async fn get_component_tables_and_columns(
+        &self,
+    ) -> anyhow::Result<HashMap<String, HashMap<TableName, Vec<FieldName>>>> {
+        Ok(HashMap::new())
+    }
+
+    /// Get the JSON schema of the documents of each table, as validated by the
+    /// deployment. Tables without a schema have a `true` schema. Sources that
+    /// can’t tell the schema of their tables return no tables, in which case
+    /// the types of user columns are inferred from the data.
+    async fn get_json_schemas(&self) -> anyhow::Result<DatabaseSchema> {
+        Ok(DatabaseSchema::default())
+    }
+
+    /// The format of the documents returned by `list_snapshot` and
+    /// `document_deltas`.
+    fn export_format(&self) -> ExportFormat {
+        ExportFormat::ConvexEncodedJson
+    }
+}
+
+/// The formats in which the streaming export API can return documents.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// The lossless format, encoding the values that JSON can’t represent
+    /// (e.g. `{"$integer": "…"}` for Int64 values).
+    #[default]
+    ConvexEncodedJson,
+    /// Plain JSON, for backends not supporting the encoded format. Int64 and
+    /// Bytes values are exported as strings, so they are synced as strings.
+    Json,
+}
+
+impl ExportFormat {
+    /// The value of the `format` query parameter selecting this format.
+    pub fn query_parameter(self) -> &'static str {
+        match self {
+            ExportFormat::ConvexEncodedJson => "convex_encoded_json",
+            ExportFormat::Json => "json",
+        }
+    }
+}
+
+/// Options of the HTTP client used to access the Convex API.
+#[derive(Debug, Clone)]
+pub struct HttpOptions {
+    /// The maximum time allowed to establish a connection to the deployment.
+    pub connect_timeout: Duration,
+
+    /// The maximum time allowed for a whole request, including reading the
+    /// response body.
+    pub request_timeout: Duration,
+
+    /// If set, calls whose response body is larger than this many bytes
+    /// (after decompression) are aborted.
+    pub max_response_size: Option<u64>,
+}
+
+impl Default for HttpOptions {
+    fn default() -> Self {
+        HttpOptions {
+            connect_timeout: Duration::from_secs(30),
+            request_timeout: Duration::from_secs(5 * 60),
+            max_response_size: None,
+        }
+    }
 }
 
 /// Implementation of [`Source`] accessing a real Convex deployment over HTTP.
+#[derive(Clone)]
 pub struct ConvexApi {
     pub config: Config,
+    client: reqwest::Client,
+    http_options: HttpOptions,
+    export_format: ExportFormat,
+    /// The `connection` label of the metrics recorded for this deployment.
+    metrics_label: String,
+    /// Limits the number of requests in flight at the same time, shared by
+    /// the clones of this client.
+    request_slots: Option<Arc<Semaphore>>,
+    /// Set once the deploy key was refused and the secondary deploy key is
+    /// used instead, shared by the clones of this client.
+    uses_secondary_key: Arc<AtomicBool>,
+    /// Keeps the keys of the deployment redacted from the messages of the
+    /// connector until the last clone of this client is dropped.
+    _secrets: Arc<SecretRegistration>,
 }
 
 impl ConvexApi {
+    pub fn new(config: Config, http_options: &HttpOptions) -> anyhow::Result<Self> {
+        let secrets = redact::register_secrets(
+            [
+                Some(config.credentials.secret()),
+                config.secondary_deploy_key.as_deref(),
+            ]
+            .into_iter()
+            .flatten(),
+        );
+
+        // Snapshot pages can be large, so we let the backend compress responses.
+        // reqwest sets the `Accept-Encoding` header and decompresses transparently.
+        let client = reqwest::Client::builder()
+            .gzip(true)
+            .brotli(true)
+            .connect_timeout(http_options.connect_timeout)
+            .timeout(http_options.request_timeout)
+            .build()
+            .context("Failed to create the HTTP client")?;
+
+        Ok(ConvexApi {
+            metrics_label: metrics::connection_label(&config.deploy_url),
+            client,
+            http_options: http_options.clone(),
+            export_format: ExportFormat::default(),
+            request_slots: config
+                .max_concurrent_requests
+                .map(|max_concurrent_requests| Arc::new(Semaphore::new(max_concurrent_requests))),
+            uses_secondary_key: Arc::new(AtomicBool::new(false)),
+            _secrets: Arc::new(secrets),
+            config,
+        })
+    }
+
+    /// The `Authorization` header of a request, using the secondary deploy
+    /// key if requested and configured.
+    fn authorization(&self, secondary_key: bool) -> String {
+        match self.config.secondary_deploy_key {
+            Some(ref secondary_deploy_key) if secondary_key => {
+                Credentials::DeployKey(secondary_deploy_key.clone()).authorization()
+            },
+            _ => self.config.credentials.authorization(),
+        }
+    }
+
+    /// The system tables requested from the Convex API. The files table is
+    /// synced from `_storage`, so it is requested when the files table is
+    /// enabled.
+    fn requested_system_tables(&self) -> BTreeSet<&str> {
+        let mut system_tables: BTreeSet<&str> = self
+            .config
+            .system_tables
+            .iter()
+            .map(String::as_str)
+            .collect();
+        if self.config.files_table {
+            system_tables.insert(STORAGE_TABLE_NAME);
+        }
+        system_tables
+    }
+
+    /// The system tables synced that the deployment doesn’t list. The
+    /// `systemTables` parameter isn’t part of the documented HTTP API (see the
+    /// README): backends that don’t support it ignore it, and don’t return the
+    /// system tables either. Empty system tables aren’t listed either.
+    pub async fn missing_system_tables(&self) -> anyhow::Result<Vec<String>> {
+        let system_tables = self.requested_system_tables();
+        if system_tables.is_empty() {
+            return Ok(vec![]);
+        }
+        let tables_to_columns = self.get_tables_and_columns().await?;
+        Ok(missing_tables(system_tables, &tables_to_columns)
+            .into_iter()
+            .map(str::to_string)
+            .collect())
+    }
+
+    /// The `systemTables` parameter requesting the system tables synced, if
+    /// any.
+    fn system_tables_parameter(&self) -> Option<String> {
+        let system_tables = self.requested_system_tables();
+        if system_tables.is_empty() {
+            return None;
+        }
+        Some(system_tables.into_iter().collect::<Vec<_>>().join(","))
+    }
+
+    /// Requests documents in the given format instead of the default, lossless
+    /// one.
+    pub fn with_export_format(mut self, export_format: ExportFormat) -> Self {
+        self.export_format = export_format;
+        self
+    }
+
     /// Performs a GET HTTP request to a given endpoint of the Convex API using
     /// the given query parameters.
-    async fn get<T: DeserializeOwned>(
+    #[tracing::instrument(
+        skip(self, parameters),
+        fields(deploy_url = %self.config.deploy_url, status = tracing::field::Empty),
+    )]
+    async fn get<T: DeserializeOwned + Send + 'static>(
         &self,
         endpoint: &str,
         parameters: HashMap<&str, Option<String>>,
@@ -88,25 +345,106 @@ impl ConvexApi {
 
         url.query_pairs_mut().extend_pairs(non_null_parameters);
 
-        match reqwest::Client::new()
-            .get(url)
-            .header(CONVEX_CLIENT_HEADER, &*CONVEX_CLIENT_HEADER_VALUE)
-            .header(
-                reqwest::header::AUTHORIZATION,
-                format!("Convex {}", self.config.deploy_key),
-            )
-            .send()
-            .await
-        {
-            Ok(resp) if resp.status().is_success() => Ok(resp
-                .json::<T>()
+        let mut secondary_key = self.uses_secondary_key.load(Ordering::Relaxed);
+        let (response, _slot) = loop {
+            // Every attempt takes a slot, which is kept until the response
+            // body is read.
+            let slot = match self.request_slots {
+                Some(ref request_slots) => Some(
+                    request_slots
+                        .acquire()
+                        .await
+                        .context("The request slots were closed")?,
+                ),
+                None => None,
+            };
+            let started_at = Instant::now();
+            let response = self
+                .client
+                .get(url.clone())
+                .header(CONVEX_CLIENT_HEADER, &*CONVEX_CLIENT_HEADER_VALUE)
+                .header(
+                    reqwest::header::AUTHORIZATION,
+                    self.authorization(secondary_key),
+                )
+                .send()
+                .await;
+            let status = match response {
+                Ok(ref resp) => resp.status().as_u16().to_string(),
+                Err(_) => "error".to_string(),
+            };
+            tracing::Span::current().record("status", status.as_str());
+            metrics::record_http_latency(
+                &self.metrics_label,
+                endpoint,
+                &status,
+                started_at.elapsed(),
+            );
+
+            match response {
+                // The sync waits for the delay requested by the backend before
+                // retrying, so that it can tell Fivetran about it, and without
+                // holding a request slot in the meantime.
+                Ok(resp) if resp.status() == reqwest::StatusCode::TOO_MANY_REQUESTS => {
+                    metrics::RETRIES
+                        .with_label_values(&[&self.metrics_label, endpoint, "rate_limited"])
+                        .inc();
+                    return Err(ApiError::RateLimited {
+                        endpoint: endpoint.to_string(),
+                        deploy_url: self.config.deploy_url.clone(),
+                        retry_after: retry_after(resp.headers(), SystemTime::now())
+                            .unwrap_or(DEFAULT_RETRY_AFTER),
+                    }
+                    .into());
+                },
+                // While a deploy key is rotated, the secondary key takes over
+                // once the primary one is revoked, for every later request.
+                Ok(resp)
+                    if resp.status() == reqwest::StatusCode::UNAUTHORIZED
+                        && !secondary_key
+                        && self.config.secondary_deploy_key.is_some() =>
+                {
+                    secondary_key = true;
+                    if !self.uses_secondary_key.swap(true, Ordering::Relaxed) {
+                        log_warning(&format!(
+                            "The deploy key was refused by {}, using the secondary deploy key \
+                             instead. Replace the deploy key with the secondary one in the \
+                             connector configuration to complete the key rotation.",
+                            self.config.deploy_url,
+                        ));
+                    }
+                },
+                response => break (response, slot),
+            }
+        };
+
+        match response {
+            Ok(resp) if resp.status().is_success() => {
+                let max_response_size = self.http_options.max_response_size;
+                match deserialize_body(resp, max_response_size, |e| {
+                    self.transport_error(endpoint, e)
+                })
                 .await
-                .context("Failed to deserialize query result")?),
+                .context("Failed to deserialize query result")?
+                {
+                    Some(result) => Ok(result),
+                    None => Err(ConnectorError::ResourceExhausted(anyhow::anyhow!(
+                        "The response of {endpoint} on {} exceeds the maximum response size of {} \
+                         bytes. Use a smaller page size (or target page size) in the connector \
+                         configuration, or restart the connector with a larger \
+                         --max-response-size-mb.",
+                        self.config.deploy_url,
+                        max_response_size.unwrap_or_default()
+                    ))
+                    .into()),
+                }
+            },
             Ok(resp) => {
+                let status = resp.status();
                 if let Ok(text) = resp.text().await {
-                    anyhow::bail!(
-                        "Call to {endpoint} on {} returned an unsuccessful response: {text}",
-                        self.config.deploy_url
+                    Err(
+                        ApiError::from_response(endpoint, &self.config.deploy_url, status, text)
+                            .into(),
                     )
                 } else {
                     anyhow::bail!(
@@ -115,11 +453,211 @@ impl ConvexApi {
                     )
                 }
             },
-            Err(e) => anyhow::bail!(e.to_string()),
+            Err(e) => Err(self.transport_error(endpoint, e).into()),
+        }
+    }
+
+    /// The error of a call whose request couldn’t be sent or whose response
+    /// couldn’t be received in full.
+    fn transport_error(&self, endpoint: &str, error: reqwest::Error) -> ApiError {
+        if error.is_timeout() {
+            ApiError::Timeout {
+                endpoint: endpoint.to_string(),
+                deploy_url: self.config.deploy_url.clone(),
+                connect_timeout: self.http_options.connect_timeout,
+                request_timeout: self.http_options.request_timeout,
+            }
+        } else {
+            ApiError::Transport(error)
         }
     }
 }
 
+/// Resolves the URL of a deployment from its name with the Convex management
+/// API, for connections set up with a deployment name and an access token
+/// rather than with the URL of the deployment.
+pub async fn resolve_deploy_url(
+    deployment_name: &str,
+    access_token: &str,
+    http_options: &HttpOptions,
+) -> anyhow::Result<Url> {
+    let _secrets = redact::register_secrets([access_token]);
+    let client = reqwest::Client::builder()
+        .connect_timeout(http_options.connect_timeout)
+        .timeout(http_options.request_timeout)
+        .build()
+        .context("Failed to create the HTTP client")?;
+    let endpoint = format!("deployments/{deployment_name}");
+    let url = MANAGEMENT_API_URL.join(&endpoint)?;
+    let response = client
+        .get(url)
+        .header(CONVEX_CLIENT_HEADER, &*CONVEX_CLIENT_HEADER_VALUE)
+        .header(
+            reqwest::header::AUTHORIZATION,
+            format!("Bearer {access_token}"),
+        )
+        .send()
+        .await
+        .map_err(|error| {
+            if error.is_timeout() {
+                ApiError::Timeout {
+                    endpoint: endpoint.clone(),
+                    deploy_url: MANAGEMENT_API_URL.clone(),
+                    connect_timeout: http_options.connect_timeout,
+                    request_timeout: http_options.request_timeout,
+                }
+            } else {
+                ApiError::Transport(error)
+            }
+        })?;
+    let status = response.status();
+    let body = response.text().await.map_err(ApiError::Transport)?;
+    if !status.is_success() {
+        let error = ApiError::from_response(&endpoint, &MANAGEMENT_API_URL, status, body);
+        return Err(anyhow::Error::from(error)
+            .context(format!("Couldn’t find the deployment {deployment_name}")));
+    }
+    deployment_url(deployment_name, &body)
+}
+
+/// Parses the URL of a deployment from the `deploymentUrl` field of the
+/// response of the management API describing it. The other fields of the
+/// response are ignored.
+fn deployment_url(deployment_name: &str, body: &str) -> anyhow::Result<Url> {
+    let deployment: JsonValue = serde_json::from_str(body).with_context(|| {
+        format!(
+            "The management API returned an invalid description of the deployment \
+             {deployment_name}"
+        )
+    })?;
+    let Some(deployment_url) = deployment.get("deploymentUrl").and_then(JsonValue::as_str) else {
+        anyhow::bail!(
+            "The management API didn’t return the URL of the deployment {deployment_name} (no \
+             deploymentUrl in its response). Enter the URL of the deployment in the connector \
+             configuration instead of its name."
+        );
+    };
+    Url::parse(deployment_url)
+        .with_context(|| format!("Invalid URL of the deployment {deployment_name}"))
+}
+
+/// The deployment URLs resolved with [`resolve_deploy_url`], by deployment name
+/// and access token, so that the management API is called once per connection
+/// rather than on every request. The URL of a deployment never changes, so they
+/// are kept for the lifetime of the connector, while failed lookups are
+/// retried.
+#[derive(Default)]
+pub struct DeployUrls(Mutex<HashMap<(String, String), Url>>);
+
+impl DeployUrls {
+    /// Resolves the URL of a deployment, unless it was resolved already.
+    pub async fn resolve(
+        &self,
+        deployment_name: &str,
+        access_token: &str,
+        http_options: &HttpOptions,
+    ) -> anyhow::Result<Url> {
+        let key = (deployment_name.to_string(), access_token.to_string());
+        let cached = self.0.lock().unwrap().get(&key).cloned();
+        if let Some(deploy_url) = cached {
+            return Ok(deploy_url);
+        }
+        let deploy_url = resolve_deploy_url(deployment_name, access_token, http_options).await?;
+        self.0.lock().unwrap().insert(key, deploy_url.clone());
+        Ok(deploy_url)
+    }
+}
+
+impl Debug for DeployUrls {
+    // The keys contain access tokens, which are never printed.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DeployUrls").finish_non_exhaustive()
+    }
+}
+
+/// Deserializes the JSON body of a response while it is being received,
+/// rather than buffering it first, so that the raw body of a large page is
+/// never held in memory in full next to the documents parsed from it. The
+/// value returned is still complete (e.g. every document of a page), so the
+/// memory used remains proportional to the size of the response.
+/// Returns `None` as soon as the body exceeds `max_size` bytes. Errors
+/// receiving the body are mapped with `transport_error`, like errors sending
+/// the request, so that a body cut off by the network is a transient error.
+async fn deserialize_body<T: DeserializeOwned + Send + 'static>(
+    mut response: reqwest::Response,
+    max_size: Option<u64>,
+    transport_error: impl Fn(reqwest::Error) -> ApiError,
+) -> anyhow::Result<Option<T>> {
+    let (sender, receiver) = mpsc::channel(BODY_CHUNKS_BUFFERED);
+    let parser = spawn_json_parser(receiver);
+    let mut size: u64 = 0;
+    while let Some(chunk) = response.chunk().await.map_err(&transport_error)? {
+        size += chunk.len() as u64;
+        if max_size.is_some_and(|max_size| size > max_size) {
+            // Dropping the parser’s channel stops it.
+            return Ok(None);
+        }
+        if sender.send(chunk.to_vec()).await.is_err() {
+            // The parser stopped early because the body is invalid.
+            break;
+        }
+    }
+    drop(sender);
+    Ok(Some(parser.await??))
+}
+
+/// Parses a JSON value from the chunks received on the channel, on a blocking
+/// thread since the parser reads them synchronously.
+fn spawn_json_parser<T: DeserializeOwned + Send + 'static>(
+    chunks: mpsc::Receiver<Vec<u8>>,
+) -> JoinHandle<serde_json::Result<T>> {
+    tokio::task::spawn_blocking(move || {
+        serde_json::from_reader(ChunkReader {
+            chunks,
+            current: io::Cursor::new(Vec::new()),
+        })
+    })
+}
+
+/// Reads the chunks received on a channel as a contiguous stream of bytes.
+struct ChunkReader {
+    chunks: mpsc::Receiver<Vec<u8>>,
+    current: io::Cursor<Vec<u8>>,
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.current.read(buf)?;
+            if read > 0 || buf.is_empty() {
+                return Ok(read);
+            }
+            match self.chunks.blocking_recv() {
+                Some(chunk) => self.current = io::Cursor::new(chunk),
+                None => return Ok(0),
+            }
+        }
+    }
+}
+
+/// Parses the `Retry-After` header of a rate-limited response, which can either
+/// contain a number of seconds or an HTTP date.
+fn retry_after(headers: &reqwest::header::HeaderMap, now: SystemTime) -> Option<Duration> {
+    let value = headers
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+    let delay = match value.parse::<u64>() {
+        Ok(seconds) => Duration::from_secs(seconds),
+        Err(_) => httpdate::parse_http_date(value)
+            .ok()?
+            .duration_since(now)
+            .unwrap_or(Duration::ZERO),
+    };
+    Some(delay.min(MAX_RETRY_AFTER))
+}
+
 #[async_trait]
 impl Source for ConvexApi {
     async fn test_streaming_export_connection(&self) -> anyhow::Result<()> {
@@ -132,17 +670,25 @@ impl Source for ConvexApi {
         snapshot: Option<i64>,
         cursor: Option<ListSnapshotCursor>,
         table_name: Option<String>,
+        page_size: Option<u32>,
     ) -> anyhow::Result<ListSnapshotResponse> {
-        self.get(
-            "list_snapshot",
-            hashmap! {
-                "snapshot" => snapshot.map(|n| n.to_string()),
-                "cursor" => cursor.map(|n| n.to_string()),
-                "tableName" => table_name,
-                "format" => Some("convex_encoded_json".to_string()),
-            },
-        )
-        .await
+        let response = self
+            .get(
+                "list_snapshot",
+                hashmap! {
+                    "snapshot" => snapshot.map(|n| n.to_string()),
+                    "cursor" => cursor.map(|n| n.to_string()),
+                    "tableName" => table_name,
+                    "pageSize" => page_size.map(|n| n.to_string()),
+                    "format" => Some(self.export_format.query_parameter().to_string()),
+                    "systemTables" => self.system_tables_parameter(),
+                },
+            )
+            .await?;
+        metrics::PAGES_FETCHED
+            .with_label_values(&[&self.metrics_label, "list_snapshot"])
+            .inc();
+        Ok(response)
     }
 
     async fn document_deltas(
@@ -150,36 +696,159 @@ impl Source for ConvexApi {
         cursor: DocumentDeltasCursor,
         table_name: Option<String>,
     ) -> anyhow::Result<DocumentDeltasResponse> {
+        let response = self
+            .get(
+                "document_deltas",
+                hashmap! {
+                    "cursor" => Some(cursor.to_string()),
+                    "tableName" => table_name,
+                    "format" => Some(self.export_format.query_parameter().to_string()),
+                    "systemTables" => self.system_tables_parameter(),
+                },
+            )
+            .await?;
+        metrics::PAGES_FETCHED
+            .with_label_values(&[&self.metrics_label, "document_deltas"])
+            .inc();
+        Ok(response)
+    }
+
+    async fn get_tables_and_columns(&self) -> anyhow::Result<HashMap<TableName, Vec<FieldName>>> {
+        let tables_to_columns: HashMap<TableName, Vec<String>> = self
+            .get(
+                "get_tables_and_columns",
+                hashmap! { "systemTables" => self.system_tables_parameter() },
+            )
+            .await?;
+        Ok(with_system_columns(tables_to_columns))
+    }
+
+    async fn get_component_tables_and_columns(
+        &self,
+    ) -> anyhow::Result<HashMap<String, HashMap<TableName, Vec<FieldName>>>> {
+        // The `components` parameter isn’t part of the documented HTTP API
+        // either (see the README). Backends that don’t support it ignore it or
+        // refuse it; any other error fails the call.
+        let response: Option<JsonValue> = match self
+            .get(
+                "get_tables_and_columns",
+                hashmap! { "components" => Some("true".to_string()) },
+            )
+            .await
+        {
+            Ok(response) => Some(response),
+            Err(error) if refuses_components_parameter(&error) => None,
+            Err(error) => return Err(error),
+        };
+        let components = match response {
+            Some(response) => component_tables_and_columns(response)?,
+            None => None,
+        };
+        let Some(components) = components else {
+            log_warning(&format!(
+                "{} doesn’t list the tables of its components, probably because its version of \
+                 Convex doesn’t support components. Only the tables of the root component are \
+                 synced.",
+                self.config.deploy_url
+            ));
+            return Ok(HashMap::new());
+        };
+
+        Ok(components
+            .into_iter()
+            .filter(|(component_path, _)| !component_path.is_empty())
+            .map(|(component_path, tables_to_columns)| {
+                (component_path, with_system_columns(tables_to_columns))
+            })
+            .collect())
+    }
+
+    async fn get_json_schemas(&self) -> anyhow::Result<DatabaseSchema> {
         self.get(
-            "document_deltas",
+            "json_schemas",
             hashmap! {
-                "cursor" => Some(cursor.to_string()),
-                "tableName" => table_name,
-                "format" => Some("convex_encoded_json".to_string()),
+                "deltaSchema" => Some("true".to_string()),
+                "format" => Some(self.export_format.query_parameter().to_string()),
             },
         )
         .await
     }
 
-    async fn get_tables_and_columns(&self) -> anyhow::Result<HashMap<TableName, Vec<FieldName>>> {
-        let tables_to_columns: HashMap<TableName, Vec<String>> =
-            self.get("get_tables_and_columns", hashmap! {}).await?;
+    fn export_format(&self) -> ExportFormat {
+        self.export_format
+    }
+}
 
-        tables_to_columns
-            .into_iter()
-            .map(|(table_name, all_columns)| {
-                let system_columns = ["_id", "_creationTime"].into_iter().map(String::from);
-                let user_columns: Vec<_> = all_columns
-                    .into_iter()
-                    .filter(|key| !key.starts_with('_'))
-                    .collect();
+/// Parses the response of `get_tables_and_columns` with `components=true`,
+/// which lists the columns of every table by component path. Backends that
+/// don’t support components ignore the parameter and list the columns of the
+/// tables of the root component instead, as arrays rather than objects. Their
+/// documents don’t have a `_component` field either, so they are all in the
+/// root component: `None` is returned, since there are no component tables.
+fn component_tables_and_columns(
+    response: JsonValue,
+) -> anyhow::Result<Option<HashMap<String, HashMap<TableName, Vec<String>>>>> {
+    let lists_root_tables = response
+        .as_object()
+        .is_some_and(|object| object.values().any(JsonValue::is_array));
+    if lists_root_tables {
+        return Ok(None);
+    }
+    serde_json::from_value(response)
+        .map(Some)
+        .context("Invalid list of the tables of components")
+}
 
-                let columns = system_columns.chain(user_columns).map(FieldName).collect();
+/// Whether a call to `get_tables_and_columns` with `components=true` failed
+/// because the backend doesn’t know the parameter: it either doesn’t find the
+/// endpoint, or refuses the request as invalid because of the parameter.
+fn refuses_components_parameter(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .filter_map(|cause| cause.downcast_ref::<ApiError>())
+        .any(|api_error| match api_error {
+            ApiError::Backend { status, error, .. } => {
+                *status == StatusCode::NOT_FOUND
+                    || (*status == StatusCode::BAD_REQUEST && error.message.contains("components"))
+            },
+            ApiError::UnsuccessfulResponse { status, body, .. } => {
+                *status == StatusCode::NOT_FOUND
+                    || (*status == StatusCode::BAD_REQUEST && body.contains("components"))
+            },
+            _ => false,
+        })
+}
 
-                Ok((table_name, columns))
-            })
-            .try_collect()
-    }
+/// The tables among `table_names` that aren’t listed in `tables_to_columns`.
+fn missing_tables<'a, T>(
+    table_names: impl IntoIterator<Item = &'a str>,
+    tables_to_columns: &HashMap<TableName, T>,
+) -> Vec<&'a str> {
+    table_names
+        .into_iter()
+        .filter(|table_name| !tables_to_columns.keys().any(|table| table.0 == *table_name))
+        .collect()
+}
+
+/// The columns of every table, keeping the system columns that are synced and
+/// leaving out the other ones.
+fn with_system_columns(
+    tables_to_columns: HashMap<TableName, Vec<String>>,
+) -> HashMap<TableName, Vec<FieldName>> {
+    tables_to_columns
+        .into_iter()
+        .map(|(table_name, all_columns)| {
+            let system_columns = ["_id", "_creationTime"].into_iter().map(String::from);
+            let user_columns: Vec<_> = all_columns
+                .into_iter()
+                .filter(|key| !key.starts_with('_'))
+                .collect();
+
+            let columns = system_columns.chain(user_columns).map(FieldName).collect();
+
+            (table_name, columns)
+        })
+        .collect()
 }
 
 impl Display for ConvexApi {
@@ -196,7 +865,7 @@ pub struct ListSnapshotCursor(pub String);
 #[cfg_attr(test, derive(proptest_derive::Arbitrary))]
 pub struct DocumentDeltasCursor(pub i64);
 
-#[derive(Deserialize, PartialEq, Eq, Hash, Display)]
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash, Display)]
 pub struct TableName(pub String);
 
 #[cfg(test)]
@@ -243,6 +912,11 @@ pub struct SnapshotValue {
     #[serde(rename = "_table")]
     pub table: String,
 
+    /// The path of the component of the table (e.g. `workpool`), or an empty
+    /// string for the tables of the root component.
+    #[serde(rename = "_component", default)]
+    pub component: String,
+
     /// In the document deltas API, this indicates whether the document was
     /// deleted. Will always be `false` in the list snapshot API.
     #[serde(rename = "_deleted", default)]
@@ -256,16 +930,686 @@ pub struct SnapshotValue {
     pub fields: HashMap<String, JsonValue>,
 }
 
-#[derive(Deserialize)]
+/// A structured error returned by the Convex backend
+/// (`{ "code": "…", "message": "…" }`).
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BackendError {
+    pub code: String,
+    pub message: String,
+}
+
+/// The code of the errors returned when reading data at a timestamp the
+/// backend no longer retains (`ErrorMetadata::out_of_retention` in
+/// `crates/errors` of the Convex backend).
+pub const OUT_OF_RETENTION_CODE: &str = "OutOfRetention";
+
+/// The codes of the errors returned when the deploy key is refused (by
+/// `crates/keybroker` of the Convex backend).
+pub const BAD_KEY_CODES: [&str; 2] = ["BadDeployKey", "BadAdminKey"];
+
+/// The categories of backend errors the connector reacts to specifically.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendErrorKind {
+    /// The snapshot passed to `list_snapshot` is no longer retained by the
+    /// backend.
+    SnapshotExpired,
+    /// The cursor passed to `document_deltas` is no longer retained by the
+    /// backend.
+    InvalidCursor,
+    /// The deploy key was refused.
+    Unauthorized,
+    /// Any other error.
+    Other,
+}
+
+impl BackendError {
+    /// The category of the error, when it was returned by the given endpoint.
+    /// The backend returns the same code for expired snapshots and cursors.
+    pub fn kind(&self, endpoint: &str) -> BackendErrorKind {
+        match (self.code.as_str(), endpoint) {
+            (OUT_OF_RETENTION_CODE, "list_snapshot") => BackendErrorKind::SnapshotExpired,
+            (OUT_OF_RETENTION_CODE, "document_deltas") => BackendErrorKind::InvalidCursor,
+            (code, _) if BAD_KEY_CODES.contains(&code) => BackendErrorKind::Unauthorized,
+            _ => BackendErrorKind::Other,
+        }
+    }
+}
+
+impl BackendErrorKind {
+    /// A hint explaining to the user how to get out of this error.
+    fn recovery_hint(self) -> Option<&'static str> {
+        match self {
+            BackendErrorKind::SnapshotExpired => Some(
+                "The initial sync was paused for too long and must be restarted. Please trigger a \
+                 historical resync of the connector.",
+            ),
+            BackendErrorKind::InvalidCursor => Some(
+                "The connector was paused for too long to apply the changes that happened since \
+                 its last sync. Please trigger a historical resync of the connector.",
+            ),
+            BackendErrorKind::Unauthorized => Some(
+                "Please verify that the deploy key or access token matches the deployment URL.",
+            ),
+            BackendErrorKind::Other => None,
+        }
+    }
+}
+
+/// An unsuccessful response returned by a call to the Convex API.
+#[derive(Debug)]
+pub enum ApiError {
+    /// The backend described the error with a [`BackendError`].
+    Backend {
+        endpoint: String,
+        deploy_url: Url,
+        status: StatusCode,
+        error: BackendError,
+    },
+    /// The backend returned a response that isn’t a structured error.
+    UnsuccessfulResponse {
+        endpoint: String,
+        deploy_url: Url,
+        status: StatusCode,
+        body: String,
+    },
+    /// The backend didn’t respond in time.
+    Timeout {
+        endpoint: String,
+        deploy_url: Url,
+        connect_timeout: Duration,
+        request_timeout: Duration,
+    },
+    /// The backend rate limited the connector, asking it to retry after a
+    /// delay (from the `Retry-After` header of the response).
+    RateLimited {
+        endpoint: String,
+        deploy_url: Url,
+        retry_after: Duration,
+    },
+    /// The request couldn’t be sent, or its response couldn’t be received.
+    Transport(reqwest::Error),
+}
+
+impl ApiError {
+    fn from_response(endpoint: &str, deploy_url: &Url, status: StatusCode, body: String) -> Self {
+        match serde_json::from_str::<BackendError>(&body) {
+            Ok(error) => ApiError::Backend {
+                endpoint: endpoint.to_string(),
+                deploy_url: deploy_url.clone(),
+                status,
+                error,
+            },
+            Err(_) => ApiError::UnsuccessfulResponse {
+                endpoint: endpoint.to_string(),
+                deploy_url: deploy_url.clone(),
+                status,
+                body,
+            },
+        }
+    }
+
+    /// The category of the error, if the backend returned a structured error.
+    pub fn kind(&self) -> BackendErrorKind {
+        match self {
+            ApiError::Backend {
+                endpoint, error, ..
+            } => error.kind(endpoint),
+            ApiError::UnsuccessfulResponse { .. }
+            | ApiError::Timeout { .. }
+            | ApiError::RateLimited { .. }
+            | ApiError::Transport(_) => BackendErrorKind::Other,
+        }
+    }
+
+    /// The HTTP status of the response, if the backend responded.
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            ApiError::Backend { status, .. } | ApiError::UnsuccessfulResponse { status, .. } => {
+                Some(*status)
+            },
+            ApiError::RateLimited { .. } => Some(StatusCode::TOO_MANY_REQUESTS),
+            ApiError::Timeout { .. } | ApiError::Transport(_) => None,
+        }
+    }
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiError::Backend {
+                endpoint,
+                deploy_url,
+                status,
+                error,
+            } => {
+                write!(
+                    f,
+                    "Call to {endpoint} on {deploy_url} failed ({status}): {} ({})",
+                    error.message, error.code
+                )?;
+                if let Some(hint) = self.kind().recovery_hint() {
+                    write!(f, ". {hint}")?;
+                }
+                Ok(())
+            },
+            ApiError::UnsuccessfulResponse {
+                endpoint,
+                deploy_url,
+                status,
+                body,
+            } => write!(
+                f,
+                "Call to {endpoint} on {deploy_url} returned an unsuccessful response ({status}): \
+                 {body}"
+            ),
+            ApiError::Timeout {
+                endpoint,
+                deploy_url,
+                connect_timeout,
+                request_timeout,
+            } => write!(
+                f,
+                "Call to {endpoint} on {deploy_url} timed out (connection timeout: {}s, request \
+                 timeout: {}s)",
+                connect_timeout.as_secs(),
+                request_timeout.as_secs(),
+            ),
+            ApiError::RateLimited {
+                endpoint,
+                deploy_url,
+                retry_after,
+            } => write!(
+                f,
+                "Call to {endpoint} on {deploy_url} was rate limited by the Convex backend, which \
+                 asked to retry in {}s",
+                retry_after.as_secs_f64()
+            ),
+            ApiError::Transport(error) => Display::fmt(error, f),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+/// The JSON schema of the documents of each table of a deployment.
+#[derive(Debug, Clone, Default, Deserialize)]
 pub struct DatabaseSchema(pub HashMap<TableName, Schema>);
 
 #[cfg(test)]
 mod tests {
     use core::panic;
+    use std::{
+        convert::Infallible,
+        net::SocketAddr,
+    };
 
     use serde_json::json;
 
     use super::*;
+    use crate::{
+        config::AllowAllHosts,
+        error::is_transient,
+    };
+
+    #[test]
+    fn parses_the_url_of_a_deployment() -> anyhow::Result<()> {
+        let body = json!({ "deploymentUrl": "https://aware-llama-900.convex.cloud" }).to_string();
+        assert_eq!(
+            deployment_url("aware-llama-900", &body)?,
+            Url::parse("https://aware-llama-900.convex.cloud")?
+        );
+
+        let error = deployment_url("aware-llama-900", "{}").unwrap_err();
+        assert!(error.to_string().starts_with(
+            "The management API didn’t return the URL of the deployment aware-llama-900"
+        ));
+        assert!(deployment_url("aware-llama-900", "Not found").is_err());
+        let invalid_url = json!({ "deploymentUrl": "aware-llama-900" }).to_string();
+        assert!(deployment_url("aware-llama-900", &invalid_url).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn finds_the_system_tables_not_listed() {
+        let tables_to_columns: HashMap<TableName, Vec<String>> = hashmap! {
+            TableName::from("messages") => vec![],
+            TableName::from("_storage") => vec![],
+        };
+        assert_eq!(
+            missing_tables(["_scheduled_functions", "_storage"], &tables_to_columns),
+            vec!["_scheduled_functions"]
+        );
+        assert!(missing_tables(["_storage"], &tables_to_columns).is_empty());
+    }
+
+    #[test]
+    fn detects_backends_without_components() -> anyhow::Result<()> {
+        let components = component_tables_and_columns(json!({
+            "": { "messages": ["_id", "body"] },
+            "workpool": { "jobs": ["_id", "status"] },
+        }))?
+        .expect("The components weren’t listed");
+        assert_eq!(
+            components["workpool"][&TableName::from("jobs")],
+            vec!["_id", "status"]
+        );
+
+        // Older backends ignore the parameter and list the root tables
+        let root_tables = json!({ "messages": ["_id", "body"] });
+        assert!(component_tables_and_columns(root_tables)?.is_none());
+
+        assert!(component_tables_and_columns(json!({ "workpool": 42 })).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn falls_back_to_the_root_tables_only_when_components_are_refused() {
+        let deploy_url = Url::parse("https://aware-llama-900.convex.cloud").unwrap();
+        let error = |status: StatusCode, body: &str| -> anyhow::Error {
+            ApiError::from_response(
+                "get_tables_and_columns",
+                &deploy_url,
+                status,
+                body.to_string(),
+            )
+            .into()
+        };
+
+        assert!(refuses_components_parameter(&error(
+            StatusCode::NOT_FOUND,
+            "Not found"
+        )));
+        assert!(refuses_components_parameter(&error(
+            StatusCode::BAD_REQUEST,
+            r#"{"code": "BadRequest", "message": "Unknown query parameter: components"}"#
+        )));
+        assert!(!refuses_components_parameter(&error(
+            StatusCode::BAD_REQUEST,
+            r#"{"code": "BadRequest", "message": "Invalid systemTables"}"#
+        )));
+        assert!(!refuses_components_parameter(&error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Internal server error"
+        )));
+        assert!(!refuses_components_parameter(&error(
+            StatusCode::UNAUTHORIZED,
+            r#"{"code": "BadDeployKey", "message": "Invalid deploy key"}"#
+        )));
+    }
+
+    #[tokio::test]
+    async fn parses_responses_received_in_chunks() -> anyhow::Result<()> {
+        let body = json!({
+            "values": [
+                { "_table": "messages", "_id": "abc", "body": "Hello" },
+                { "_table": "messages", "_id": "def", "_deleted": true },
+            ],
+            "cursor": 42,
+            "hasMore": false,
+        })
+        .to_string();
+        let (sender, receiver) = mpsc::channel(BODY_CHUNKS_BUFFERED);
+        let parser = spawn_json_parser::<DocumentDeltasResponse>(receiver);
+        for chunk in body.as_bytes().chunks(3) {
+            sender.send(chunk.to_vec()).await?;
+        }
+        drop(sender);
+
+        let response = parser.await??;
+        assert_eq!(response.values.len(), 2);
+        assert_eq!(response.values[0].fields["body"], json!("Hello"));
+        assert!(response.values[1].deleted);
+        assert_eq!(response.cursor, 42);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn aborts_responses_exceeding_the_maximum_size() -> anyhow::Result<()> {
+        let response = || {
+            reqwest::Response::from(tonic::codegen::http::Response::new(
+                json!({ "values": [], "cursor": 42, "hasMore": false }).to_string(),
+            ))
+        };
+
+        let parsed: Option<DocumentDeltasResponse> =
+            deserialize_body(response(), None, ApiError::Transport).await?;
+        assert!(parsed.is_some());
+        let parsed: Option<DocumentDeltasResponse> =
+            deserialize_body(response(), Some(1000), ApiError::Transport).await?;
+        assert!(parsed.is_some());
+        let parsed: Option<DocumentDeltasResponse> =
+            deserialize_body(response(), Some(10), ApiError::Transport).await?;
+        assert!(parsed.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn fails_on_truncated_responses() {
+        let (sender, receiver) = mpsc::channel(BODY_CHUNKS_BUFFERED);
+        let parser = spawn_json_parser::<DocumentDeltasResponse>(receiver);
+        sender
+            .send(br#"{"values": [{"_table": "mess"#.to_vec())
+            .await
+            .unwrap();
+        drop(sender);
+
+        assert!(parser.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn bodies_cut_off_mid_stream_are_transient_errors() -> anyhow::Result<()> {
+        let config = Config::from_parameters(
+            hashmap! {
+                "url".to_string() => "https://aware-llama-900.convex.cloud".to_string(),
+                "key".to_string() => "prod:aware-llama-900|secret".to_string(),
+            },
+            AllowAllHosts(false),
+        )?;
+        let api = ConvexApi::new(config, &HttpOptions::default())?;
+        // The connection is lost after the first chunk of the body.
+        let (mut sender, body) = hyper::Body::channel();
+        sender
+            .try_send_data(hyper::body::Bytes::from_static(
+                br#"{"values": [{"_table": "mess"#,
+            ))
+            .unwrap();
+        sender.abort();
+        let response = reqwest::Response::from(tonic::codegen::http::Response::new(body));
+
+        let error = deserialize_body::<DocumentDeltasResponse>(response, None, |e| {
+            api.transport_error("document_deltas", e)
+        })
+        .await
+        .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ApiError>(),
+            Some(ApiError::Transport(_))
+        ));
+        assert!(is_transient(&error));
+
+        Ok(())
+    }
+
+    /// Answers every request with `respond` on a local port, returning the
+    /// URL of the server.
+    fn serve(
+        respond: impl Fn(hyper::Request<hyper::Body>) -> hyper::Response<hyper::Body>
+            + Send
+            + Sync
+            + 'static,
+    ) -> anyhow::Result<Url> {
+        let respond = Arc::new(respond);
+        let make_service = hyper::service::make_service_fn(move |_| {
+            let respond = respond.clone();
+            async move {
+                Ok::<_, Infallible>(hyper::service::service_fn(move |request| {
+                    std::future::ready(Ok::<_, Infallible>(respond(request)))
+                }))
+            }
+        });
+        let server =
+            hyper::Server::try_bind(&SocketAddr::from(([127, 0, 0, 1], 0)))?.serve(make_service);
+        let url = Url::parse(&format!("http://{}", server.local_addr()))?;
+        tokio::spawn(server);
+        Ok(url)
+    }
+
+    #[tokio::test]
+    async fn sends_access_tokens_with_the_convex_scheme() -> anyhow::Result<()> {
+        let authorizations = Arc::new(Mutex::new(vec![]));
+        let deploy_url = serve({
+            let authorizations = authorizations.clone();
+            move |request| {
+                authorizations
+                    .lock()
+                    .unwrap()
+                    .push(request.headers()[hyper::header::AUTHORIZATION].clone());
+                hyper::Response::new(hyper::Body::from("{}"))
+            }
+        })?;
+        let config = Config::from_parameters(
+            hashmap! {
+                "url".to_string() => deploy_url.to_string(),
+                "access_token".to_string() => "eyJhbGciOi.token".to_string(),
+            },
+            AllowAllHosts(true),
+        )?;
+        let api = ConvexApi::new(config, &HttpOptions::default())?;
+        api.get_tables_and_columns().await?;
+
+        assert_eq!(
+            *authorizations.lock().unwrap(),
+            vec!["Convex eyJhbGciOi.token"]
+        );
+
+        Ok(())
+    }
+
+    fn headers_with_retry_after(value: &str) -> reqwest::header::HeaderMap {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(
+            reqwest::header::RETRY_AFTER,
+            reqwest::header::HeaderValue::from_str(value).unwrap(),
+        );
+        headers
+    }
+
+    #[tokio::test]
+    async fn clones_share_the_request_slots() -> anyhow::Result<()> {
+        let config = Config::from_parameters(
+            hashmap! {
+                "url".to_string() => "https://aware-llama-900.convex.cloud".to_string(),
+                "key".to_string() => "prod:aware-llama-900|secret".to_string(),
+                "max_concurrent_requests".to_string() => "2".to_string(),
+            },
+            AllowAllHosts(false),
+        )?;
+        let api = ConvexApi::new(config, &HttpOptions::default())?;
+        let clone = api.clone();
+
+        let slots = api.request_slots.as_ref().unwrap();
+        let _permit = slots.acquire().await?;
+        assert_eq!(clone.request_slots.as_ref().unwrap().available_permits(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn uses_the_secondary_deploy_key_when_requested() -> anyhow::Result<()> {
+        let config = Config::from_parameters(
+            hashmap! {
+                "url".to_string() => "https://aware-llama-900.convex.cloud".to_string(),
+                "key".to_string() => "prod:aware-llama-900|old".to_string(),
+                "secondary_key".to_string() => "prod:aware-llama-900|new".to_string(),
+            },
+            AllowAllHosts(false),
+        )?;
+        let api = ConvexApi::new(config.clone(), &HttpOptions::default())?;
+        assert_eq!(api.authorization(false), "Convex prod:aware-llama-900|old");
+        assert_eq!(api.authorization(true), "Convex prod:aware-llama-900|new");
+
+        let config = Config {
+            secondary_deploy_key: None,
+            ..config
+        };
+        let api = ConvexApi::new(config, &HttpOptions::default())?;
+        assert_eq!(api.authorization(true), "Convex prod:aware-llama-900|old");
+
+        Ok(())
+    }
+
+    #[test]
+    fn requests_the_system_tables_synced() -> anyhow::Result<()> {
+        let api = |system_tables: &str| {
+            let config = Config::from_parameters(
+                hashmap! {
+                    "url".to_string() => "https://aware-llama-900.convex.cloud".to_string(),
+                    "key".to_string() => "prod:aware-llama-900|secret".to_string(),
+                    "system_tables".to_string() => system_tables.to_string(),
+                },
+                AllowAllHosts(false),
+            )?;
+            ConvexApi::new(config, &HttpOptions::default())
+        };
+
+        assert_eq!(api("")?.system_tables_parameter(), None);
+        assert_eq!(
+            api("_storage, _scheduled_functions")?
+                .system_tables_parameter()
+                .as_deref(),
+            Some("_scheduled_functions,_storage")
+        );
+
+        let config = Config::from_parameters(
+            hashmap! {
+                "url".to_string() => "https://aware-llama-900.convex.cloud".to_string(),
+                "key".to_string() => "prod:aware-llama-900|secret".to_string(),
+                "files_table".to_string() => "true".to_string(),
+            },
+            AllowAllHosts(false),
+        )?;
+        assert_eq!(
+            ConvexApi::new(config, &HttpOptions::default())?
+                .system_tables_parameter()
+                .as_deref(),
+            Some("_storage")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn parses_retry_after_seconds() {
+        assert_eq!(
+            retry_after(&headers_with_retry_after("120"), SystemTime::now()),
+            Some(Duration::from_secs(120))
+        );
+    }
+
+    #[test]
+    fn parses_retry_after_dates() {
+        let now = httpdate::parse_http_date("Wed, 21 Oct 2015 07:28:00 GMT").unwrap();
+        assert_eq!(
+            retry_after(
+                &headers_with_retry_after("Wed, 21 Oct 2015 07:28:30 GMT"),
+                now
+            ),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            retry_after(
+                &headers_with_retry_after("Wed, 21 Oct 2015 07:27:00 GMT"),
+                now
+            ),
+            Some(Duration::ZERO)
+        );
+    }
+
+    #[test]
+    fn caps_retry_after() {
+        assert_eq!(
+            retry_after(&headers_with_retry_after("86400"), SystemTime::now()),
+            Some(MAX_RETRY_AFTER)
+        );
+    }
+
+    #[test]
+    fn ignores_invalid_retry_after() {
+        assert_eq!(
+            retry_after(&reqwest::header::HeaderMap::new(), SystemTime::now()),
+            None
+        );
+        assert_eq!(
+            retry_after(&headers_with_retry_after("soon"), SystemTime::now()),
+            None
+        );
+    }
+
+    #[test]
+    fn parses_structured_backend_errors() {
+        let error = ApiError::from_response(
+            "document_deltas",
+            &Url::parse("https://aware-llama-900.convex.cloud").unwrap(),
+            StatusCode::BAD_REQUEST,
+            json!({ "code": OUT_OF_RETENTION_CODE, "message": "Cursor is too old" }).to_string(),
+        );
+
+        assert_eq!(error.kind(), BackendErrorKind::InvalidCursor);
+        let ApiError::Backend { ref error, .. } = error else {
+            panic!("Expected a structured error");
+        };
+        assert_eq!(error.code, OUT_OF_RETENTION_CODE);
+        assert_eq!(error.message, "Cursor is too old");
+    }
+
+    #[test]
+    fn classifies_backend_errors() {
+        for (endpoint, code, kind) in [
+            (
+                "list_snapshot",
+                "OutOfRetention",
+                BackendErrorKind::SnapshotExpired,
+            ),
+            (
+                "document_deltas",
+                "OutOfRetention",
+                BackendErrorKind::InvalidCursor,
+            ),
+            (
+                "test_streaming_export_connection",
+                "OutOfRetention",
+                BackendErrorKind::Other,
+            ),
+            (
+                "list_snapshot",
+                "BadDeployKey",
+                BackendErrorKind::Unauthorized,
+            ),
+            (
+                "list_snapshot",
+                "BadAdminKey",
+                BackendErrorKind::Unauthorized,
+            ),
+            ("list_snapshot", "SomethingElse", BackendErrorKind::Other),
+        ] {
+            let error = BackendError {
+                code: code.to_string(),
+                message: "".to_string(),
+            };
+            assert_eq!(error.kind(endpoint), kind, "{endpoint} {code}");
+        }
+    }
+
+    #[test]
+    fn keeps_unstructured_error_bodies() {
+        let error = ApiError::from_response(
+            "list_snapshot",
+            &Url::parse("https://aware-llama-900.convex.cloud").unwrap(),
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Internal server error".to_string(),
+        );
+
+        assert_eq!(error.kind(), BackendErrorKind::Other);
+        assert!(error.to_string().contains("Internal server error"));
+    }
+
+    #[test]
+    fn can_downcast_api_errors() {
+        let error: anyhow::Error = ApiError::from_response(
+            "list_snapshot",
+            &Url::parse("https://aware-llama-900.convex.cloud").unwrap(),
+            StatusCode::BAD_REQUEST,
+            json!({ "code": "OutOfRetention", "message": "Snapshot is too old" }).to_string(),
+        )
+        .into();
+
+        assert_eq!(
+            error.downcast_ref::<ApiError>().map(ApiError::kind),
+            Some(BackendErrorKind::SnapshotExpired)
+        );
+    }
 
     #[test]
     fn can_deserialize_schema() {
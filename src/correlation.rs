@@ -0,0 +1,96 @@
+//! Sync IDs identifying the RPC that a log line or an error belongs to, so that
+//! the interleaved logs of concurrent syncs can be told apart.
+
+use std::{
+    collections::hash_map::RandomState,
+    future::Future,
+    hash::{
+        BuildHasher,
+        Hasher,
+    },
+    sync::{
+        atomic::{
+            AtomicU64,
+            Ordering,
+        },
+        Arc,
+    },
+};
+
+use futures::{
+    Stream,
+    StreamExt,
+};
+
+tokio::task_local! {
+    static SYNC_ID: Arc<str>;
+}
+
+/// Generates a new random sync ID (12 hexadecimal digits).
+pub fn new_sync_id() -> Arc<str> {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    format!("{:012x}", hasher.finish() & 0xffff_ffff_ffff).into()
+}
+
+/// The sync ID of the RPC currently running, if any.
+pub fn current_sync_id() -> Option<Arc<str>> {
+    SYNC_ID.try_with(Arc::clone).ok()
+}
+
+/// Prefixes a message with the current sync ID, if any.
+pub fn with_sync_id(message: &str) -> String {
+    match current_sync_id() {
+        Some(sync_id) => format!("[sync {sync_id}] {message}"),
+        None => message.to_string(),
+    }
+}
+
+/// Runs a future with the given sync ID.
+pub async fn in_sync<F: Future>(sync_id: Arc<str>, future: F) -> F::Output {
+    SYNC_ID.scope(sync_id, future).await
+}
+
+/// Polls a stream with the given sync ID, so that the sync ID is available
+/// while the stream is consumed by the gRPC server.
+pub fn stream_in_sync<S: Stream + Unpin>(
+    sync_id: Arc<str>,
+    mut stream: S,
+) -> impl Stream<Item = S::Item> {
+    futures::stream::poll_fn(move |cx| {
+        SYNC_ID.sync_scope(sync_id.clone(), || stream.poll_next_unpin(cx))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::stream;
+
+    use super::*;
+
+    #[test]
+    fn generates_distinct_sync_ids() {
+        let (a, b) = (new_sync_id(), new_sync_id());
+        assert_eq!(a.len(), 12);
+        assert_ne!(a, b);
+    }
+
+    #[tokio::test]
+    async fn prefixes_messages_in_a_sync() {
+        assert_eq!(with_sync_id("Hello"), "Hello");
+        let prefixed = in_sync("abc".into(), async { with_sync_id("Hello") }).await;
+        assert_eq!(prefixed, "[sync abc] Hello");
+    }
+
+    #[tokio::test]
+    async fn provides_the_sync_id_to_streams() {
+        let messages: Vec<String> = stream_in_sync(
+            "abc".into(),
+            stream::iter(["a", "b"]).map(with_sync_id).boxed(),
+        )
+        .collect()
+        .await;
+        assert_eq!(messages, vec!["[sync abc] a", "[sync abc] b"]);
+    }
+}
@@ -0,0 +1,125 @@
+//! Detection of the ISO 8601 dates and times stored as strings in Convex
+//! documents, since Convex has no date type.
+
+use std::sync::LazyLock;
+
+use prost_types::Timestamp;
+use regex::Regex;
+
+use crate::fivetran_sdk::value_type::Inner as FivetranValue;
+
+/// A date (`2024-03-01`), optionally followed by a time (`T12:30:00.5`) and
+/// a UTC offset (`Z` or `+01:00`).
+static ISO_8601: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"^(\d{4})-(\d{2})-(\d{2})(?:[T ](\d{2}):(\d{2})(?::(\d{2})(?:\.(\d{1,9}))?)?(Z|[+-]\d{2}:?\d{2})?)?$",
+    )
+    .unwrap()
+});
+
+/// Parses an ISO 8601 date or date and time. Dates are naive dates, and times
+/// are naive date times unless they have a UTC offset, in which case they are
+/// converted to UTC. Returns `None` if the string isn’t a valid date.
+pub fn parse_iso_8601(value: &str) -> Option<FivetranValue> {
+    let captures = ISO_8601.captures(value)?;
+    let number = |index: usize| -> Option<u8> {
+        captures
+            .get(index)
+            .map_or(Some(0), |capture| capture.as_str().parse().ok())
+    };
+    let nanos = match captures.get(7) {
+        // Fractional seconds, padded to nanoseconds
+        Some(fraction) => format!("{:0<9}", fraction.as_str()).parse().ok()?,
+        None => 0,
+    };
+    let timestamp = Timestamp::date_time_nanos(
+        captures[1].parse().ok()?,
+        number(2)?,
+        number(3)?,
+        number(4)?,
+        number(5)?,
+        number(6)?,
+        nanos,
+    )
+    .ok()?;
+
+    if captures.get(4).is_none() {
+        return Some(FivetranValue::NaiveDate(timestamp));
+    }
+    let Some(offset) = captures.get(8) else {
+        return Some(FivetranValue::NaiveDatetime(timestamp));
+    };
+    let offset_seconds = match offset.as_str() {
+        "Z" => 0,
+        offset => {
+            let sign = if offset.starts_with('-') { -1 } else { 1 };
+            let digits = offset[1..].replace(':', "");
+            let hours: i64 = digits[..2].parse().ok()?;
+            let minutes: i64 = digits[2..].parse().ok()?;
+            if hours > 23 || minutes > 59 {
+                return None;
+            }
+            sign * (hours * 3600 + minutes * 60)
+        },
+    };
+    Some(FivetranValue::UtcDatetime(Timestamp {
+        seconds: timestamp.seconds - offset_seconds,
+        nanos: timestamp.nanos,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_dates_and_times() {
+        assert_eq!(
+            parse_iso_8601("2024-02-29"),
+            Some(FivetranValue::NaiveDate(
+                Timestamp::date(2024, 2, 29).unwrap()
+            ))
+        );
+        assert_eq!(
+            parse_iso_8601("2024-03-01T12:30:15.5"),
+            Some(FivetranValue::NaiveDatetime(
+                Timestamp::date_time_nanos(2024, 3, 1, 12, 30, 15, 500_000_000).unwrap()
+            ))
+        );
+        assert_eq!(
+            parse_iso_8601("2024-03-01T12:30Z"),
+            Some(FivetranValue::UtcDatetime(
+                Timestamp::date_time(2024, 3, 1, 12, 30, 0).unwrap()
+            ))
+        );
+        assert_eq!(
+            parse_iso_8601("2024-03-01T00:30:00+01:00"),
+            Some(FivetranValue::UtcDatetime(
+                Timestamp::date_time(2024, 2, 29, 23, 30, 0).unwrap()
+            ))
+        );
+        assert_eq!(
+            parse_iso_8601("2024-03-01 12:00:00-0530"),
+            Some(FivetranValue::UtcDatetime(
+                Timestamp::date_time(2024, 3, 1, 17, 30, 0).unwrap()
+            ))
+        );
+    }
+
+    #[test]
+    fn ignores_other_strings() {
+        for value in [
+            "",
+            "hello",
+            "2023-02-29",
+            "2024-13-01",
+            "2024-03-01T25:00:00Z",
+            "2024-03-01T12:00:00+24:00",
+            "20240301",
+            "2024-03-01 and more",
+            "1234-56",
+        ] {
+            assert_eq!(parse_iso_8601(value), None, "{value}");
+        }
+    }
+}
@@ -0,0 +1,96 @@
+//! Warnings about behaviors that the connector still supports but will drop in
+//! a future version. Each deprecation has a stable code that users can search
+//! for, and is warned about at most once per sync, so that breaking changes
+//! can be rolled out after giving users time to act.
+
+use std::fmt::Display;
+
+use crate::{
+    fivetran_sdk::LogLevel,
+    log_warning,
+    sync::UpdateMessage,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Deprecation {
+    /// The state was emitted by a version of the connector that didn’t track
+    /// the tables synced, so it had to be backfilled.
+    LegacyState,
+    /// The configuration contains a field that isn’t part of the form anymore.
+    UnusedConfigField(String),
+    /// The deployment doesn’t support an endpoint of the streaming export API
+    /// used by the connector, because it runs an old version of Convex.
+    OldBackend { endpoint: String },
+}
+
+impl Deprecation {
+    /// The stable code of the deprecation, included in its warning.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Deprecation::LegacyState => "legacy_state",
+            Deprecation::UnusedConfigField(_) => "unused_config_field",
+            Deprecation::OldBackend { .. } => "old_backend",
+        }
+    }
+
+    /// The message sent to Fivetran (and logged) to warn about the
+    /// deprecation.
+    pub fn warning(&self) -> UpdateMessage {
+        let message = self.to_string();
+        log_warning(&message);
+        UpdateMessage::Log(LogLevel::Warning, message)
+    }
+}
+
+impl Display for Deprecation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Deprecation warning [{}]: ", self.code())?;
+        match self {
+            Deprecation::LegacyState => f.write_str(
+                "The connector state was emitted by an old version of the connector that didn’t \
+                 track the tables synced. It was upgraded for this sync and will be stored in the \
+                 current format at the next checkpoint. Support for such states will be removed \
+                 in a future version.",
+            ),
+            Deprecation::UnusedConfigField(field) => write!(
+                f,
+                "The configuration field “{field}” isn’t used by the connector anymore and is \
+                 ignored. Remove it from the configuration of the connection, since a future \
+                 version will reject it."
+            ),
+            Deprecation::OldBackend { endpoint } => write!(
+                f,
+                "The deployment doesn’t support the {endpoint} endpoint, so it runs an old \
+                 version of Convex. Some features of the connector are disabled, and a future \
+                 version will require it. Upgrade the deployment to remove this warning."
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn warnings_include_the_code() {
+        let deprecation = Deprecation::UnusedConfigField("page_cursor".to_string());
+        let UpdateMessage::Log(LogLevel::Warning, message) = deprecation.warning() else {
+            panic!("Expected a warning");
+        };
+        assert!(message.starts_with("Deprecation warning [unused_config_field]: "));
+        assert!(message.contains("“page_cursor”"));
+    }
+
+    #[test]
+    fn codes_are_stable() {
+        assert_eq!(Deprecation::LegacyState.code(), "legacy_state");
+        assert_eq!(
+            Deprecation::OldBackend {
+                endpoint: "get_table_document_counts".to_string()
+            }
+            .code(),
+            "old_backend"
+        );
+    }
+}
@@ -0,0 +1,242 @@
+//! The errors returned by the connector endpoints, classified by the gRPC
+//! status code sent to Fivetran. Fivetran decides whether to retry a call and
+//! what to show users from this code, so configuration problems and
+//! temporary outages must not all be reported as internal errors.
+
+use std::fmt::Display;
+
+use tonic::{
+    Code,
+    Status,
+};
+
+use crate::{
+    convex_api::{
+        ApiError,
+        BackendErrorKind,
+    },
+    correlation::with_sync_id,
+    redact::redact,
+};
+
+#[derive(Debug)]
+pub enum ConnectorError {
+    /// The configuration of the connection is invalid.
+    InvalidConfiguration(anyhow::Error),
+    /// The state sent by Fivetran can’t be parsed.
+    InvalidState(anyhow::Error),
+    /// The deployment refused the deploy key.
+    Unauthenticated(anyhow::Error),
+    /// The deployment doesn’t allow the connector to export its data (e.g.
+    /// streaming export isn’t enabled).
+    PermissionDenied(anyhow::Error),
+    /// The sync can’t continue without an action from the user (e.g. a
+    /// historical resync).
+    FailedPrecondition(anyhow::Error),
+    /// The deployment is temporarily unreachable or failing, so the call can
+    /// be retried later.
+    Unavailable(anyhow::Error),
+    /// A message exceeds the limits of the gRPC connection.
+    ResourceExhausted(anyhow::Error),
+    /// Any other error.
+    Internal(anyhow::Error),
+}
+
+impl ConnectorError {
+    /// The gRPC status code returned to Fivetran for this error.
+    pub fn code(&self) -> Code {
+        match self {
+            ConnectorError::InvalidConfiguration(_) | ConnectorError::InvalidState(_) => {
+                Code::InvalidArgument
+            },
+            ConnectorError::Unauthenticated(_) => Code::Unauthenticated,
+            ConnectorError::PermissionDenied(_) => Code::PermissionDenied,
+            ConnectorError::FailedPrecondition(_) => Code::FailedPrecondition,
+            ConnectorError::Unavailable(_) => Code::Unavailable,
+            ConnectorError::ResourceExhausted(_) => Code::ResourceExhausted,
+            ConnectorError::Internal(_) => Code::Internal,
+        }
+    }
+
+    fn error(&self) -> &anyhow::Error {
+        match self {
+            ConnectorError::InvalidConfiguration(error)
+            | ConnectorError::InvalidState(error)
+            | ConnectorError::Unauthenticated(error)
+            | ConnectorError::PermissionDenied(error)
+            | ConnectorError::FailedPrecondition(error)
+            | ConnectorError::Unavailable(error)
+            | ConnectorError::ResourceExhausted(error)
+            | ConnectorError::Internal(error) => error,
+        }
+    }
+}
+
+/// Whether an error is transient, so that the call that failed with it can be
+/// retried as is.
+pub fn is_transient(error: &anyhow::Error) -> bool {
+    classify(error) == Code::Unavailable
+}
+
+/// The status code of an error, from its causes: errors created as a
+/// [`ConnectorError`] keep their category, and errors of the Convex API are
+/// classified from the response of the backend.
+fn classify(error: &anyhow::Error) -> Code {
+    if let Some(connector_error) = error.downcast_ref::<ConnectorError>() {
+        return connector_error.code();
+    }
+    let Some(api_error) = error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<ApiError>())
+    else {
+        return Code::Internal;
+    };
+    match api_error.kind() {
+        BackendErrorKind::Unauthorized => Code::Unauthenticated,
+        BackendErrorKind::SnapshotExpired | BackendErrorKind::InvalidCursor => {
+            Code::FailedPrecondition
+        },
+        BackendErrorKind::Other => match api_error.status() {
+            // Timeouts and connection errors
+            None => Code::Unavailable,
+            Some(status) if status.is_server_error() || status.as_u16() == 429 => Code::Unavailable,
+            Some(status) if status.as_u16() == 401 => Code::Unauthenticated,
+            Some(status) if status.as_u16() == 403 => Code::PermissionDenied,
+            Some(_) => Code::Internal,
+        },
+    }
+}
+
+impl From<anyhow::Error> for ConnectorError {
+    fn from(error: anyhow::Error) -> Self {
+        let error = match error.downcast::<ConnectorError>() {
+            Ok(connector_error) => return connector_error,
+            Err(error) => error,
+        };
+        match classify(&error) {
+            Code::Unauthenticated => ConnectorError::Unauthenticated(error),
+            Code::PermissionDenied => ConnectorError::PermissionDenied(error),
+            Code::FailedPrecondition => ConnectorError::FailedPrecondition(error),
+            Code::Unavailable => ConnectorError::Unavailable(error),
+            _ => ConnectorError::Internal(error),
+        }
+    }
+}
+
+impl Display for ConnectorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self.error(), f)
+    }
+}
+
+impl std::error::Error for ConnectorError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.error().source()
+    }
+}
+
+/// The status returned to Fivetran when a call fails, without secrets.
+impl From<ConnectorError> for Status {
+    fn from(error: ConnectorError) -> Self {
+        Status::new(
+            error.code(),
+            redact(&with_sync_id(&error.error().to_string())),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::StatusCode;
+    use url::Url;
+
+    use super::*;
+    use crate::convex_api::BackendError;
+
+    fn backend_error(status: StatusCode, code: &str) -> anyhow::Error {
+        ApiError::Backend {
+            endpoint: "list_snapshot".to_string(),
+            deploy_url: Url::parse("https://aware-llama-900.convex.cloud").unwrap(),
+            status,
+            error: BackendError {
+                code: code.to_string(),
+                message: "Something went wrong".to_string(),
+            },
+        }
+        .into()
+    }
+
+    fn code(error: anyhow::Error) -> Code {
+        ConnectorError::from(error).code()
+    }
+
+    #[test]
+    fn classifies_backend_errors() {
+        assert_eq!(
+            code(backend_error(StatusCode::UNAUTHORIZED, "BadDeployKey")),
+            Code::Unauthenticated
+        );
+        assert_eq!(
+            code(backend_error(StatusCode::FORBIDDEN, "Forbidden")),
+            Code::PermissionDenied
+        );
+        assert_eq!(
+            code(backend_error(StatusCode::BAD_REQUEST, "OutOfRetention")),
+            Code::FailedPrecondition
+        );
+        assert_eq!(
+            code(backend_error(StatusCode::SERVICE_UNAVAILABLE, "Overloaded")),
+            Code::Unavailable
+        );
+        assert_eq!(
+            code(backend_error(StatusCode::BAD_REQUEST, "Unknown")),
+            Code::Internal
+        );
+    }
+
+    #[test]
+    fn classifies_backend_errors_wrapped_in_context() {
+        let error = backend_error(StatusCode::BAD_GATEWAY, "Overloaded")
+            .context("Failed to fetch a page of the initial sync");
+        assert_eq!(code(error), Code::Unavailable);
+    }
+
+    #[test]
+    fn keeps_the_category_of_connector_errors() {
+        let error: anyhow::Error =
+            ConnectorError::FailedPrecondition(anyhow::anyhow!("Resync refused")).into();
+        let error = ConnectorError::from(error);
+        assert_eq!(error.code(), Code::FailedPrecondition);
+        assert_eq!(error.to_string(), "Resync refused");
+    }
+
+    #[test]
+    fn other_errors_are_internal() {
+        assert_eq!(code(anyhow::anyhow!("Unexpected")), Code::Internal);
+    }
+
+    #[test]
+    fn only_unavailable_errors_are_transient() {
+        assert!(is_transient(&backend_error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Overloaded"
+        )));
+        assert!(is_transient(
+            &ConnectorError::Unavailable(anyhow::anyhow!("Stalled")).into()
+        ));
+        assert!(!is_transient(&backend_error(
+            StatusCode::BAD_REQUEST,
+            "OutOfRetention"
+        )));
+        assert!(!is_transient(&anyhow::anyhow!("Unexpected")));
+    }
+
+    #[test]
+    fn statuses_keep_the_message_of_the_error() {
+        let status = Status::from(ConnectorError::InvalidConfiguration(anyhow::anyhow!(
+            "Missing key"
+        )));
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert!(status.message().ends_with("Missing key"));
+    }
+}
@@ -0,0 +1,144 @@
+//! The `_convex_files` table, which describes the files stored in the
+//! deployment, from the documents of the `_storage` system table.
+
+use std::collections::HashMap;
+
+use serde_json::Value as JsonValue;
+use url::Url;
+
+use crate::{
+    convert::{
+        to_fivetran_row,
+        RowOptions,
+    },
+    fivetran_sdk::{
+        value_type::Inner as FivetranValue,
+        Column,
+        DataType,
+        Table,
+    },
+};
+
+pub const FILES_TABLE_NAME: &str = "_convex_files";
+
+/// The system table listing the files stored in a deployment.
+pub const STORAGE_TABLE_NAME: &str = "_storage";
+
+/// The fields of the documents of `_storage` that are synced, with the name
+/// of their column in the files table.
+const FILE_COLUMNS: [(&str, &str); 5] = [
+    ("_id", "_id"),
+    ("_creationTime", "creation_time"),
+    ("contentType", "content_type"),
+    ("size", "size"),
+    ("sha256", "sha256"),
+];
+
+/// The schema of the files table, as declared in the schema endpoint.
+pub fn files_table(download_urls: bool) -> Table {
+    let column = |name: &str, data_type: DataType| Column {
+        name: name.to_string(),
+        r#type: data_type as i32,
+        primary_key: name == "_id",
+        decimal: None,
+    };
+
+    let mut columns = vec![
+        column("_id", DataType::String),
+        column("creation_time", DataType::UtcDatetime),
+        column("content_type", DataType::String),
+        column("size", DataType::Long),
+        column("sha256", DataType::String),
+    ];
+    if download_urls {
+        columns.push(column("download_url", DataType::String));
+    }
+    Table {
+        name: FILES_TABLE_NAME.to_string(),
+        columns,
+    }
+}
+
+/// The row of the files table describing a document of `_storage`. When
+/// `deploy_url` is set, the row has the URL from which the file can be
+/// downloaded.
+pub fn file_row(
+    document: HashMap<String, JsonValue>,
+    row_options: &RowOptions,
+    deploy_url: Option<&Url>,
+) -> anyhow::Result<HashMap<String, FivetranValue>> {
+    // The other options only apply to the tables of the deployment.
+    let mut fields = to_fivetran_row(
+        document,
+        &RowOptions {
+            format: row_options.format,
+            ..RowOptions::default()
+        },
+    )?;
+    let mut row: HashMap<String, FivetranValue> = FILE_COLUMNS
+        .into_iter()
+        .filter_map(|(field_name, column_name)| {
+            Some((column_name.to_string(), fields.remove(field_name)?))
+        })
+        .collect();
+    if let (Some(deploy_url), Some(FivetranValue::String(storage_id))) =
+        (deploy_url, row.get("_id"))
+    {
+        let download_url = deploy_url.join(&format!("api/storage/{storage_id}"))?;
+        row.insert(
+            "download_url".to_string(),
+            FivetranValue::String(download_url.to_string()),
+        );
+    }
+    Ok(row)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use maplit::hashmap;
+    use serde_json::json;
+
+    use super::*;
+    use crate::convex_api::ExportFormat;
+
+    #[test]
+    fn converts_storage_documents_to_file_rows() -> anyhow::Result<()> {
+        let document = || {
+            hashmap! {
+                "_id".to_string() => json!("kg2abc"),
+                "_creationTime".to_string() => json!(1718000000000.0),
+                "contentType".to_string() => json!("image/png"),
+                "size".to_string() => json!(1024),
+                "sha256".to_string() => json!("3q2+7w=="),
+            }
+        };
+        let row_options = RowOptions {
+            format: ExportFormat::Json,
+            presence_columns: true,
+            ..RowOptions::default()
+        };
+
+        let row = file_row(document(), &row_options, None)?;
+        assert_eq!(
+            row.keys().map(String::as_str).collect::<BTreeSet<_>>(),
+            ["_id", "content_type", "creation_time", "sha256", "size"].into()
+        );
+        assert_eq!(
+            row["content_type"],
+            FivetranValue::String("image/png".to_string())
+        );
+
+        let deploy_url = Url::parse("https://aware-llama-900.convex.cloud")?;
+        let row = file_row(document(), &row_options, Some(&deploy_url))?;
+        assert_eq!(
+            row["download_url"],
+            FivetranValue::String(
+                "https://aware-llama-900.convex.cloud/api/storage/kg2abc".to_string()
+            )
+        );
+
+        Ok(())
+    }
+}
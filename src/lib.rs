@@ -0,0 +1,123 @@
+//! The Convex source connector for Fivetran, as a library.
+//!
+//! The connector binary serves the sync over gRPC, but the sync itself can be
+//! driven directly, for instance to export a deployment with a custom tool.
+//! [`sync::sync`] returns the stream of rows and checkpoints of a sync: passing
+//! no state starts an initial sync, and passing the state of the last
+//! checkpoint resumes the initial sync or continues with the changes made
+//! since (delta sync).
+//!
+//! ```no_run
+//! use convex_fivetran_source::{
+//!     config::{
+//!         AllowAllHosts,
+//!         Config,
+//!     },
+//!     convex_api::{
+//!         ConvexApi,
+//!         HttpOptions,
+//!     },
+//!     schema_cache::SchemaCache,
+//!     sync::{
+//!         sync,
+//!         State,
+//!         SyncOptions,
+//!         UpdateMessage,
+//!     },
+//! };
+//! use futures::StreamExt;
+//!
+//! # async fn run(previous_state: Option<State>) -> anyhow::Result<()> {
+//! let config = Config::from_credentials(
+//!     "https://aware-llama-900.convex.cloud".to_string(),
+//!     "prod:aware-llama-900|secret".to_string(),
+//!     AllowAllHosts(false),
+//! )?;
+//! let source = ConvexApi::new(config, &HttpOptions::default())?;
+//!
+//! let mut updates = sync(
+//!     source,
+//!     previous_state,
+//!     SyncOptions::default(),
+//!     SchemaCache::default(),
+//! );
+//! while let Some(message) = updates.next().await {
+//!     match message? {
+//!         UpdateMessage::Update {
+//!             table_name, row, ..
+//!         } => println!("{table_name}: {row:?}"),
+//!         // Everything before the checkpoint must be stored before the state
+//!         // is, so that the sync can be resumed from it.
+//!         UpdateMessage::Checkpoint(state) => println!("{}", serde_json::to_string(&state)?),
+//!         UpdateMessage::Log(..) | UpdateMessage::Heartbeat(_) => {},
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod clock;
+pub mod config;
+pub mod convert;
+pub mod convex_api;
+pub mod deprecation;
+pub mod error;
+pub mod row_filter;
+pub mod schema_cache;
+pub mod sync;
+pub mod table_filter;
+#[cfg(any(test, feature = "test-util"))]
+pub mod testing;
+pub mod throttle;
+
+pub mod fivetran_sdk {
+    #![allow(clippy::enum_variant_names)]
+    tonic::include_proto!("fivetran_sdk");
+}
+
+// Used by the connector binary, but not part of the public API.
+#[doc(hidden)]
+pub mod allowlist;
+#[doc(hidden)]
+pub mod checkpoint_sink;
+#[doc(hidden)]
+pub mod connector;
+#[doc(hidden)]
+pub mod logging;
+#[doc(hidden)]
+pub mod metrics;
+#[doc(hidden)]
+pub mod panic;
+#[doc(hidden)]
+pub mod schema;
+#[doc(hidden)]
+pub mod settings;
+#[doc(hidden)]
+pub mod status;
+#[doc(hidden)]
+pub mod telemetry;
+#[doc(hidden)]
+pub mod verify;
+
+mod budget;
+mod correlation;
+mod dates;
+mod files;
+mod metadata;
+mod page_sizing;
+mod progress;
+mod redact;
+mod restart;
+mod table_stats;
+mod watchdog;
+
+#[cfg(test)]
+mod tests;
+
+#[doc(hidden)]
+pub use logging::{
+    log,
+    log_debug,
+    log_severe,
+    log_warning,
+};
@@ -0,0 +1,196 @@
+//! Leveled logging, following the Fivetran logging format: every line printed
+//! on stdout is a JSON object with a level, a message and a message origin.
+
+use std::sync::atomic::{
+    AtomicU8,
+    Ordering,
+};
+
+use clap::ValueEnum;
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::{
+    correlation::with_sync_id,
+    fivetran_sdk::LogLevel,
+    redact::redact,
+};
+
+/// The severity of a log line.
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    /// Details that are only useful to debug the connector. They are not sent
+    /// to Fivetran, which doesn’t support this level.
+    Debug,
+    Info,
+    Warning,
+    Severe,
+}
+
+impl Level {
+    fn as_str(self) -> &'static str {
+        match self {
+            Level::Debug => "DEBUG",
+            Level::Info => "INFO",
+            Level::Warning => "WARNING",
+            Level::Severe => "SEVERE",
+        }
+    }
+}
+
+impl From<LogLevel> for Level {
+    fn from(value: LogLevel) -> Self {
+        match value {
+            LogLevel::Info => Level::Info,
+            LogLevel::Warning => Level::Warning,
+            LogLevel::Severe => Level::Severe,
+        }
+    }
+}
+
+/// The minimum level of the lines logged, set with `--log-level` or with the
+/// `logLevel` of the settings file.
+static MIN_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+pub fn set_min_level(level: Level) {
+    MIN_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Returns true if messages of the given level are logged.
+pub fn enabled(level: Level) -> bool {
+    level as u8 >= MIN_LEVEL.load(Ordering::Relaxed)
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct LogLine<'a> {
+    level: &'a str,
+    message: &'a str,
+    message_origin: &'a str,
+}
+
+pub fn log_debug(message: &str) {
+    log_with_level(Level::Debug, message);
+}
+
+pub fn log(message: &str) {
+    log_with_level(Level::Info, message);
+}
+
+pub fn log_warning(message: &str) {
+    log_with_level(Level::Warning, message);
+}
+
+pub fn log_severe(message: &str) {
+    log_with_level(Level::Severe, message);
+}
+
+/// The maximum size (in bytes) of a message logged on stdout or sent to
+/// Fivetran. Fivetran truncates or rejects longer messages.
+const MAX_MESSAGE_BYTES: usize = 4096;
+
+/// Prepares a message to be logged: prefixes it with the sync ID, redacts its
+/// secrets, and shortens it if it is too long.
+pub fn format_message(message: &str) -> String {
+    truncate_message(redact(&with_sync_id(message)), MAX_MESSAGE_BYTES)
+}
+
+/// Shortens a message longer than `max_bytes` by removing its middle, so that
+/// both its beginning (usually what failed) and its end (usually the error
+/// code and how to recover) are kept.
+fn truncate_message(message: String, max_bytes: usize) -> String {
+    if message.len() <= max_bytes {
+        return message;
+    }
+    // The marker takes some of the budget too, so account for its size.
+    let marker_len = format!(" … [{} bytes omitted] … ", message.len()).len();
+    let budget = max_bytes.saturating_sub(marker_len);
+    let head_end = floor_char_boundary(&message, budget / 2);
+    let tail_start = ceil_char_boundary(&message, message.len() - (budget - budget / 2));
+    let omitted_bytes = tail_start - head_end;
+    format!(
+        "{} … [{omitted_bytes} bytes omitted] … {}",
+        &message[..head_end],
+        &message[tail_start..]
+    )
+}
+
+fn floor_char_boundary(s: &str, mut index: usize) -> usize {
+    while !s.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+fn ceil_char_boundary(s: &str, mut index: usize) -> usize {
+    while !s.is_char_boundary(index) {
+        index += 1;
+    }
+    index
+}
+
+pub fn log_with_level(level: Level, message: &str) {
+    if !enabled(level) {
+        return;
+    }
+    let message = &format_message(message);
+
+    let result = serde_json::to_string(&LogLine {
+        level: level.as_str(),
+        message,
+        message_origin: "sdk_connector",
+    });
+    match result {
+        Ok(msg) => println!("{msg}"),
+        Err(e) => println!("Unable to serialize to json: {message}: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_are_ordered_by_severity() {
+        assert!(Level::Debug < Level::Info);
+        assert!(Level::Info < Level::Warning);
+        assert!(Level::Warning < Level::Severe);
+    }
+
+    #[test]
+    fn maps_fivetran_levels() {
+        assert_eq!(Level::from(LogLevel::Info), Level::Info);
+        assert_eq!(Level::from(LogLevel::Warning), Level::Warning);
+        assert_eq!(Level::from(LogLevel::Severe), Level::Severe);
+    }
+
+    #[test]
+    fn keeps_short_messages() {
+        assert_eq!(truncate_message("Hello".to_string(), 100), "Hello");
+    }
+
+    #[test]
+    fn truncates_the_middle_of_long_messages() {
+        let message = format!(
+            "Call to list_snapshot failed (400): {} (InvalidCursor)",
+            "x".repeat(10_000)
+        );
+
+        let truncated = truncate_message(message, 200);
+
+        assert!(truncated.len() <= 200, "{}", truncated.len());
+        assert!(truncated.starts_with("Call to list_snapshot failed (400): xxx"));
+        assert!(truncated.contains("bytes omitted"));
+        assert!(truncated.ends_with("xxx (InvalidCursor)"));
+    }
+
+    #[test]
+    fn truncates_on_character_boundaries() {
+        let truncated = truncate_message("é".repeat(1000), 101);
+        assert!(truncated.len() <= 101);
+        assert!(truncated.starts_with('é') && truncated.ends_with('é'));
+    }
+}
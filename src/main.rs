@@ -1,32 +1,64 @@
-#![feature(coroutines)]
-#![feature(iterator_try_collect)]
-#![feature(lazy_cell)]
-
-mod config;
-mod connector;
-mod convert;
-mod convex_api;
-mod sync;
-
-mod fivetran_sdk {
-    #![allow(clippy::enum_variant_names)]
-    tonic::include_proto!("fivetran_sdk");
-}
-
-#[cfg(test)]
-mod tests;
-
-use std::net::{
-    IpAddr,
-    Ipv4Addr,
-    SocketAddr,
+use std::{
+    future::Future,
+    net::{
+        IpAddr,
+        Ipv4Addr,
+        SocketAddr,
+    },
+    path::PathBuf,
+    pin::pin,
+    process::ExitCode,
+    sync::Arc,
+    time::Duration,
 };
 
-use clap::Parser;
-use config::AllowAllHosts;
-use connector::ConvexConnector;
-use fivetran_sdk::connector_server::ConnectorServer;
-use serde::Serialize;
+use clap::{
+    Parser,
+    Subcommand,
+    ValueEnum,
+};
+use convex_fivetran_source::{
+    allowlist::HostAllowlist,
+    checkpoint_sink::{
+        CheckpointSink,
+        FileCheckpointSink,
+    },
+    config::{
+        AllowAllHosts,
+        Config,
+    },
+    connector::ConvexConnector,
+    convex_api::{
+        ConvexApi,
+        DeployUrls,
+        HttpOptions,
+        Source,
+    },
+    fivetran_sdk::connector_server::ConnectorServer,
+    log,
+    log_severe,
+    logging::{
+        self,
+        Level,
+    },
+    metrics,
+    panic,
+    schema,
+    schema_cache::SchemaCaches,
+    settings::LiveSettings,
+    status::{
+        self,
+        ActiveSyncs,
+    },
+    telemetry,
+    verify,
+};
+use futures::{
+    future,
+    stream,
+};
+use serde_json::json;
+use tokio::net::TcpListener;
 use tonic::{
     codec::CompressionEncoding,
     transport::Server,
@@ -40,49 +72,378 @@ struct Args {
     #[arg(long, default_value_t = 50051)]
     port: u16,
 
+    /// The minimum level of the messages logged. DEBUG messages are only
+    /// printed on stdout, since Fivetran doesn’t support this level.
+    #[arg(long, value_enum, default_value_t = Level::Info)]
+    log_level: Level,
+
+    /// The compression used for the responses sent to Fivetran. Requests
+    /// compressed with any of the supported encodings are accepted.
+    #[arg(long, value_enum, default_value_t = Compression::Gzip)]
+    compression: Compression,
+
+    /// The maximum size (in bytes) of a gRPC message sent to Fivetran.
+    /// Documents larger than this limit make the sync fail with an explicit
+    /// error.
+    #[arg(long)]
+    max_encoding_message_size: Option<usize>,
+
+    /// The maximum size (in bytes) of a gRPC message received from Fivetran.
+    #[arg(long)]
+    max_decoding_message_size: Option<usize>,
+
+    /// Aborts syncs that make no progress (no row, log or checkpoint emitted)
+    /// for this many seconds, so that Fivetran can retry them. Set to 0 to
+    /// disable.
+    #[arg(long, default_value_t = 30 * 60)]
+    stall_timeout_secs: u64,
+
+    /// Sends Fivetran a heartbeat log message every time a call to the Convex
+    /// API has been pending for this many seconds, so that slow calls aren’t
+    /// mistaken for a stuck connector. Heartbeats don’t count as progress for
+    /// `--stall-timeout-secs`. Set to 0 to disable.
+    #[arg(long, default_value_t = 60)]
+    heartbeat_interval_secs: u64,
+
+    /// Restarts syncs failing with a transient error (timeouts, unavailable
+    /// deployment, stalls) from their last checkpoint up to this many times,
+    /// instead of waiting for Fivetran to retry them.
+    #[arg(long, default_value_t = 0)]
+    max_sync_restarts: u32,
+
+    /// A directory where the last checkpoint of every connection is mirrored
+    /// (as `<host>_<configuration hash>.json`), to restore the state of
+    /// connections that are recreated.
+    #[arg(long)]
+    checkpoint_mirror_dir: Option<PathBuf>,
+
     /// Whether the connector is allowed to use any host as deployment URL,
     /// instead of only Convex cloud deployments.
     #[arg(long)]
     allow_all_hosts: bool,
+
+    /// A file listing the origins of deployments (e.g. "http://localhost:3210",
+    /// one per line) that are allowed even when `--allow-all-hosts` is not set.
+    /// The file can be edited while the connector is running.
+    #[arg(long)]
+    allowed_hosts_file: Option<PathBuf>,
+
+    /// A JSON file containing settings that can be changed while the connector
+    /// is running (see the README). Changes are picked up within a few seconds.
+    #[arg(long)]
+    settings_file: Option<PathBuf>,
+
+    /// The maximum time (in seconds) allowed to establish a connection to a
+    /// Convex deployment.
+    #[arg(long, default_value_t = HttpOptions::default().connect_timeout.as_secs())]
+    connect_timeout_secs: u64,
+
+    /// The maximum time (in seconds) allowed for a single call to the Convex
+    /// API, including reading the response.
+    #[arg(long, default_value_t = HttpOptions::default().request_timeout.as_secs())]
+    request_timeout_secs: u64,
+
+    /// The maximum size (in MB, after decompression) of a response of the
+    /// Convex API. Larger responses make the sync fail with an explicit error
+    /// rather than using gigabytes of memory.
+    #[arg(long)]
+    max_response_size_mb: Option<u64>,
+
+    /// If set, spans describing the RPCs, the pages fetched and the
+    /// checkpoints emitted are exported to this OpenTelemetry collector
+    /// (OTLP over gRPC, e.g. "http://localhost:4317").
+    #[arg(long)]
+    otlp_endpoint: Option<String>,
+
+    /// If set, Prometheus metrics are served on `/metrics` on this port.
+    #[arg(long)]
+    metrics_port: Option<u16>,
+
+    /// Runs the connector under the lifecycle of the Fivetran agent of hybrid
+    /// deployments: the port is announced on stdout once requests are
+    /// accepted (use `--port 0` to pick any free port), SIGTERM stops the
+    /// connector gracefully, and fatal errors are logged before exiting with
+    /// a non-zero code.
+    #[arg(long)]
+    fivetran_managed: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// The compression encodings supported by the gRPC server.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl From<Compression> for CompressionEncoding {
+    fn from(value: Compression) -> Self {
+        match value {
+            Compression::Gzip => CompressionEncoding::Gzip,
+            Compression::Zstd => CompressionEncoding::Zstd,
+        }
+    }
+}
+
+/// Commands that can be run instead of starting the gRPC server.
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Prints the JSON payload creating a connection to the given deployment
+    /// with the Fivetran REST API (or Terraform provider), without starting the
+    /// connector.
+    ConfigPayload {
+        /// The deployment URL (e.g. "https://aware-llama-900.convex.cloud")
+        #[arg(long)]
+        url: String,
+
+        /// The deploy key of the deployment. Prefer setting it in the
+        /// `CONVEX_DEPLOY_KEY` environment variable, so that it doesn’t appear
+        /// in the process list or in the shell history.
+        #[arg(long, env = "CONVEX_DEPLOY_KEY", hide_env_values = true)]
+        key: String,
+    },
+
+    /// Prints the tables and columns of a deployment, as seen by the
+    /// connector, as a generic JSON catalog.
+    Catalog {
+        /// The deployment URL (e.g. "https://aware-llama-900.convex.cloud")
+        #[arg(long)]
+        url: String,
+
+        /// The deploy key of the deployment. Prefer setting it in the
+        /// `CONVEX_DEPLOY_KEY` environment variable, so that it doesn’t appear
+        /// in the process list or in the shell history.
+        #[arg(long, env = "CONVEX_DEPLOY_KEY", hide_env_values = true)]
+        key: String,
+    },
+
+    /// Compares the row counts of the destination tables with the number of
+    /// documents of every table of a deployment at a given snapshot, and fails
+    /// if any of them differ.
+    Verify {
+        /// The deployment URL (e.g. "https://aware-llama-900.convex.cloud")
+        #[arg(long)]
+        url: String,
+
+        /// The deploy key of the deployment. Prefer setting it in the
+        /// `CONVEX_DEPLOY_KEY` environment variable, so that it doesn’t appear
+        /// in the process list or in the shell history.
+        #[arg(long, env = "CONVEX_DEPLOY_KEY", hide_env_values = true)]
+        key: String,
+
+        /// The snapshot timestamp at which the documents are counted (e.g. the
+        /// cursor of the last checkpoint of the connector). The deployment
+        /// must still retain it.
+        #[arg(long)]
+        snapshot: i64,
+
+        /// A file containing the row counts of the destination tables, either
+        /// as a JSON object of table names to counts, or as CSV lines of
+        /// `table,count`
+        #[arg(long)]
+        counts_file: PathBuf,
+    },
 }
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+async fn main() -> ExitCode {
     let args = Args::parse();
+    let fivetran_managed = args.fivetran_managed;
+    match run(args).await {
+        Ok(()) => ExitCode::SUCCESS,
+        // The agent only reads stdout, in the Fivetran logging format.
+        Err(error) if fivetran_managed => {
+            log_severe(&format!(
+                "The connector stopped because of an error: {error}"
+            ));
+            ExitCode::FAILURE
+        },
+        Err(error) => {
+            eprintln!("Error: {error:?}");
+            ExitCode::FAILURE
+        },
+    }
+}
+
+async fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    logging::set_min_level(args.log_level);
+    panic::install_panic_hook();
+    let allow_all_hosts = AllowAllHosts(args.allow_all_hosts);
+    let http_options = HttpOptions {
+        connect_timeout: Duration::from_secs(args.connect_timeout_secs),
+        request_timeout: Duration::from_secs(args.request_timeout_secs),
+        max_response_size: args
+            .max_response_size_mb
+            .map(|megabytes| megabytes.saturating_mul(1_000_000)),
+    };
+
+    if let Some(command) = args.command {
+        return run_command(command, allow_all_hosts, &http_options).await;
+    }
+
     let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), args.port);
+    telemetry::init(args.otlp_endpoint)?;
+
+    if let Some(metrics_port) = args.metrics_port {
+        let metrics_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), metrics_port);
+        tokio::spawn(async move {
+            if let Err(error) = metrics::serve(metrics_addr).await {
+                log_severe(&format!("The metrics server stopped: {error:#}"));
+            }
+        });
+    }
+
+    let settings = match args.settings_file {
+        Some(path) => LiveSettings::watch(path, args.log_level)?,
+        None => LiveSettings::default(),
+    };
+
+    let active_syncs = ActiveSyncs::default();
+    #[cfg(unix)]
+    status::dump_on_sigusr1(active_syncs.clone())?;
 
     let connector = ConvexConnector {
-        allow_all_hosts: AllowAllHosts(args.allow_all_hosts),
+        allow_all_hosts,
+        host_allowlist: args.allowed_hosts_file.map(HostAllowlist::new),
+        http_options,
+        settings,
+        schema_caches: SchemaCaches::default(),
+        active_syncs,
+        deploy_urls: DeployUrls::default(),
+        max_encoding_message_size: args.max_encoding_message_size,
+        stall_timeout: (args.stall_timeout_secs > 0)
+            .then(|| Duration::from_secs(args.stall_timeout_secs)),
+        heartbeat_interval: (args.heartbeat_interval_secs > 0)
+            .then(|| Duration::from_secs(args.heartbeat_interval_secs)),
+        max_sync_restarts: args.max_sync_restarts,
+        checkpoint_sink: args.checkpoint_mirror_dir.map(|directory| {
+            Arc::new(FileCheckpointSink::new(directory)) as Arc<dyn CheckpointSink>
+        }),
     };
 
-    log(&format!("Starting the connector on {}", addr));
-    Server::builder()
-        .add_service(
-            ConnectorServer::new(connector)
-                .accept_compressed(CompressionEncoding::Gzip)
-                .send_compressed(CompressionEncoding::Gzip),
-        )
-        .serve(addr)
-        .await?;
+    let mut connector_server = ConnectorServer::new(connector)
+        .accept_compressed(CompressionEncoding::Gzip)
+        .accept_compressed(CompressionEncoding::Zstd)
+        .send_compressed(args.compression.into());
+    if let Some(limit) = args.max_encoding_message_size {
+        connector_server = connector_server.max_encoding_message_size(limit);
+    }
+    if let Some(limit) = args.max_decoding_message_size {
+        connector_server = connector_server.max_decoding_message_size(limit);
+    }
+
+    // Expose the standard gRPC health checking service, so that orchestrators
+    // can probe the connector without calling the connector endpoints.
+    let (mut health_reporter, health_service) = tonic_health::server::health_reporter();
+    health_reporter
+        .set_serving::<ConnectorServer<ConvexConnector>>()
+        .await;
 
+    let router = Server::builder()
+        .add_service(health_service)
+        .add_service(connector_server);
+    if args.fivetran_managed {
+        // Bind before announcing the port, so that the agent can connect as soon
+        // as it reads it, and so that `--port 0` can be resolved.
+        let listener = TcpListener::bind(addr).await?;
+        let port = listener.local_addr()?.port();
+        let incoming = stream::unfold(listener, |listener| async {
+            let connection = listener.accept().await.map(|(stream, _)| stream);
+            Some((connection, listener))
+        });
+        let shutdown = shutdown_signal()?;
+        log(&format!("Starting the connector on port {port}"));
+        announce_ready(port);
+        router
+            .serve_with_incoming_shutdown(pin!(incoming), shutdown)
+            .await?;
+        log("The connector stopped gracefully");
+    } else {
+        log(&format!("Starting the connector on {}", addr));
+        router.serve(addr).await?;
+    }
+
+    telemetry::shutdown();
     Ok(())
 }
 
-#[derive(Serialize)]
-#[serde(rename_all = "kebab-case")]
-struct LogLine<'a> {
-    level: &'a str,
-    message: &'a str,
-    message_origin: &'a str,
-}
-pub fn log(message: &str) {
-    let result = serde_json::to_string(&LogLine {
-        level: "INFO",
-        message,
-        message_origin: "sdk_connector",
+/// Prints the handshake line telling the Fivetran agent that the connector
+/// accepts requests on `port`. Like every other line printed on stdout, it
+/// follows the Fivetran logging format, and it is printed regardless of
+/// `--log-level`.
+fn announce_ready(port: u16) {
+    let line = json!({
+        "level": "INFO",
+        "message": format!("The connector is ready on port {port}"),
+        "message-origin": "sdk_connector",
+        "event": "ready",
+        "port": port,
     });
-    match result {
-        Ok(msg) => println!("{msg}"),
-        Err(e) => println!("Unable to serialize to json: {message}: {e}"),
+    println!("{line}");
+}
+
+/// Resolves when the agent asks the connector to stop, with SIGTERM (or with
+/// Ctrl-C when the connector runs in a terminal). Requests in progress are
+/// completed before the connector exits.
+fn shutdown_signal() -> std::io::Result<impl Future<Output = ()>> {
+    #[cfg(unix)]
+    let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+    Ok(async move {
+        #[cfg(unix)]
+        let terminate = terminate.recv();
+        #[cfg(not(unix))]
+        let terminate = future::pending::<Option<()>>();
+        future::select(pin!(terminate), pin!(tokio::signal::ctrl_c())).await;
+    })
+}
+
+async fn run_command(
+    command: Command,
+    allow_all_hosts: AllowAllHosts,
+    http_options: &HttpOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match command {
+        Command::ConfigPayload { url, key } => {
+            let payload = Config::fivetran_connection_payload(url, key, allow_all_hosts)?;
+            println!("{}", serde_json::to_string_pretty(&payload)?);
+        },
+        Command::Catalog { url, key } => {
+            let config = Config::from_credentials(url, key, allow_all_hosts)?;
+            let source = ConvexApi::new(config, http_options)?;
+            let mut columns = source.get_tables_and_columns().await?;
+            let mut json_schemas = schema::fetch_json_schemas(&source, None).await;
+            schema::sample_missing_schemas(&source, &mut columns, &mut json_schemas).await;
+            let tables =
+                schema::fivetran_tables(columns, &json_schemas, &source.config.schema_options());
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&schema::catalog(&tables, &json_schemas))?
+            );
+        },
+        Command::Verify {
+            url,
+            key,
+            snapshot,
+            counts_file,
+        } => {
+            let destination_counts =
+                verify::parse_destination_counts(&std::fs::read_to_string(&counts_file)?)?;
+            let config = Config::from_credentials(url, key, allow_all_hosts)?;
+            let source = ConvexApi::new(config, http_options)?;
+            let source_counts = verify::snapshot_counts(&source, snapshot).await?;
+            let reports = verify::compare(&source_counts, &destination_counts);
+            for report in &reports {
+                println!("{report}");
+            }
+            let mismatches = reports.iter().filter(|report| !report.matches()).count();
+            if mismatches > 0 {
+                return Err(
+                    format!("{mismatches} tables don’t match the snapshot {snapshot}").into(),
+                );
+            }
+        },
     }
+    Ok(())
 }
@@ -0,0 +1,129 @@
+//! The `_convex_sync_metadata` table, in which the connector records
+//! information about the sync itself rather than data from the deployment.
+
+use std::{
+    collections::HashMap,
+    time::SystemTime,
+};
+
+use maplit::hashmap;
+use prost_types::Timestamp;
+
+use crate::fivetran_sdk::{
+    value_type::Inner as FivetranValue,
+    Column,
+    DataType,
+    Table,
+};
+
+pub const METADATA_TABLE_NAME: &str = "_convex_sync_metadata";
+
+/// Why the connector truncated a destination table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TruncateReason {
+    /// The table was seen for the first time during an initial sync, which
+    /// replaces any data previously synced to the destination.
+    InitialSync,
+    /// The table was created in the deployment after the initial sync.
+    NewTable,
+    /// The changes since the last sync couldn’t be fetched because the
+    /// connector was paused for too long, so all data is synced again.
+    CursorExpired,
+    /// The initial sync couldn’t be resumed because its snapshot expired, so
+    /// it started again from scratch.
+    SnapshotExpired,
+}
+
+impl TruncateReason {
+    fn as_str(self) -> &'static str {
+        match self {
+            TruncateReason::InitialSync => "initial_sync",
+            TruncateReason::NewTable => "new_table",
+            TruncateReason::CursorExpired => "cursor_expired",
+            TruncateReason::SnapshotExpired => "snapshot_expired",
+        }
+    }
+}
+
+/// The schema of the metadata table, as declared in the schema endpoint.
+pub fn metadata_table() -> Table {
+    let column = |name: &str, data_type: DataType| Column {
+        name: name.to_string(),
+        r#type: data_type as i32,
+        primary_key: name == "_id",
+        decimal: None,
+    };
+
+    Table {
+        name: METADATA_TABLE_NAME.to_string(),
+        columns: vec![
+            column("_id", DataType::String),
+            column("event", DataType::String),
+            column("table_name", DataType::String),
+            column("reason", DataType::String),
+            column("timestamp", DataType::UtcDatetime),
+            column("connector_version", DataType::String),
+        ],
+    }
+}
+
+/// A row of the metadata table recording that the given table was truncated.
+pub fn truncate_audit_row(
+    table_name: &str,
+    reason: TruncateReason,
+    now: SystemTime,
+) -> HashMap<String, FivetranValue> {
+    let timestamp = Timestamp::from(now);
+    hashmap! {
+        "_id".to_string() => FivetranValue::String(format!(
+            "truncate:{table_name}:{}.{:09}",
+            timestamp.seconds, timestamp.nanos
+        )),
+        "event".to_string() => FivetranValue::String("truncate".to_string()),
+        "table_name".to_string() => FivetranValue::String(table_name.to_string()),
+        "reason".to_string() => FivetranValue::String(reason.as_str().to_string()),
+        "timestamp".to_string() => FivetranValue::UtcDatetime(timestamp),
+        "connector_version".to_string() =>
+            FivetranValue::String(env!("CARGO_PKG_VERSION").to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn truncate_audit_rows_match_the_declared_schema() {
+        let row = truncate_audit_row(
+            "messages",
+            TruncateReason::InitialSync,
+            SystemTime::UNIX_EPOCH + Duration::from_millis(1500),
+        );
+
+        let mut row_columns: Vec<&String> = row.keys().collect();
+        row_columns.sort();
+        let mut declared_columns: Vec<String> = metadata_table()
+            .columns
+            .into_iter()
+            .map(|column| column.name)
+            .collect();
+        declared_columns.sort();
+        assert_eq!(
+            row_columns,
+            declared_columns.iter().collect::<Vec<&String>>()
+        );
+
+        assert_eq!(
+            row.get("_id"),
+            Some(&FivetranValue::String(
+                "truncate:messages:1.500000000".to_string()
+            ))
+        );
+        assert_eq!(
+            row.get("reason"),
+            Some(&FivetranValue::String("initial_sync".to_string()))
+        );
+    }
+}
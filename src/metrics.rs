@@ -0,0 +1,283 @@
+//! Prometheus metrics describing the activity of the connector, served on
+//! `/metrics` when the connector is started with `--metrics-port`. Every
+//! series has a `connection` label (see [`connection_label`]), so that the
+//! connections served by the same process can be told apart.
+
+use std::{
+    convert::Infallible,
+    net::SocketAddr,
+    sync::LazyLock,
+    time::Duration,
+};
+
+use hyper::{
+    service::{
+        make_service_fn,
+        service_fn,
+    },
+    Body,
+    Method,
+    Request,
+    Response,
+    Server,
+    StatusCode,
+};
+use prometheus::{
+    register_histogram_vec,
+    register_int_counter_vec,
+    Encoder,
+    HistogramVec,
+    IntCounterVec,
+    TextEncoder,
+};
+use url::Url;
+
+use crate::{
+    fivetran_sdk::OpType,
+    log,
+    sync::UpdateMessage,
+    telemetry,
+};
+
+/// Pages of documents fetched from the Convex API, by endpoint.
+pub static PAGES_FETCHED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "convex_fivetran_pages_fetched_total",
+        "Pages of documents fetched from the Convex API",
+        &["connection", "endpoint"]
+    )
+    .unwrap()
+});
+
+/// Operations sent to Fivetran, by destination table and operation.
+pub static ROWS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "convex_fivetran_rows_total",
+        "Rows sent to Fivetran",
+        &["connection", "table", "operation"]
+    )
+    .unwrap()
+});
+
+/// Calls to the Convex API that were retried, by endpoint and reason.
+pub static RETRIES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "convex_fivetran_retries_total",
+        "Calls to the Convex API that were retried",
+        &["connection", "endpoint", "reason"]
+    )
+    .unwrap()
+});
+
+/// Duration of the HTTP calls to the Convex API, by endpoint and status code.
+pub static HTTP_LATENCY: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "convex_fivetran_http_request_duration_seconds",
+        "Duration of the HTTP calls to the Convex API",
+        &["connection", "endpoint", "status"],
+        vec![0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0]
+    )
+    .unwrap()
+});
+
+/// Checkpoints sent to Fivetran.
+pub static CHECKPOINTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "convex_fivetran_checkpoints_total",
+        "Checkpoints sent to Fivetran",
+        &["connection"]
+    )
+    .unwrap()
+});
+
+/// A stable identifier of the connection to a deployment, used as the
+/// `connection` label of the metrics. It is a hash of the deployment URL, so
+/// that the URL isn’t exposed to whoever can read the metrics.
+pub fn connection_label(deploy_url: &Url) -> String {
+    // FNV-1a rather than the standard library hasher, whose output may change
+    // between Rust versions and would break dashboards.
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in deploy_url.as_str().bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    format!("{hash:016x}")
+}
+
+/// API calls taking longer than this are logged, so that latency spikes seen
+/// in the metrics can be traced back to the logs of the sync that caused them.
+const SLOW_CALL_THRESHOLD: Duration = Duration::from_secs(10);
+
+/// Records the latency of a call to the Convex API, and logs the call if it
+/// was slow.
+pub fn record_http_latency(connection: &str, endpoint: &str, status: &str, elapsed: Duration) {
+    HTTP_LATENCY
+        .with_label_values(&[connection, endpoint, status])
+        .observe(elapsed.as_secs_f64());
+    if let Some(message) =
+        slow_call_message(endpoint, status, elapsed, telemetry::current_trace_id())
+    {
+        log(&message);
+    }
+}
+
+fn slow_call_message(
+    endpoint: &str,
+    status: &str,
+    elapsed: Duration,
+    trace_id: Option<String>,
+) -> Option<String> {
+    if elapsed < SLOW_CALL_THRESHOLD {
+        return None;
+    }
+    let trace = trace_id
+        .map(|trace_id| format!(", trace ID {trace_id}"))
+        .unwrap_or_default();
+    Some(format!(
+        "Slow call to {endpoint}: {:.1}s (status {status}{trace})",
+        elapsed.as_secs_f64()
+    ))
+}
+
+/// Updates the metrics for a message sent to Fivetran.
+pub fn record_update_message(connection: &str, message: &UpdateMessage) {
+    match message {
+        UpdateMessage::Log(..) | UpdateMessage::Heartbeat(_) => {},
+        UpdateMessage::Update {
+            table_name,
+            op_type,
+            ..
+        } => {
+            let operation = match op_type {
+                OpType::Upsert => "upsert",
+                OpType::Update => "update",
+                OpType::Delete => "delete",
+                OpType::Truncate => "truncate",
+            };
+            ROWS.with_label_values(&[connection, table_name, operation])
+                .inc();
+        },
+        UpdateMessage::Checkpoint(_) => CHECKPOINTS.with_label_values(&[connection]).inc(),
+    }
+}
+
+/// Serves the metrics in the Prometheus text format on `/metrics`.
+pub async fn serve(addr: SocketAddr) -> anyhow::Result<()> {
+    let make_service =
+        make_service_fn(|_| async { Ok::<_, Infallible>(service_fn(handle_request)) });
+
+    log(&format!("Serving metrics on {addr}"));
+    Server::try_bind(&addr)?.serve(make_service).await?;
+    Ok(())
+}
+
+async fn handle_request(request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    if request.method() != Method::GET || request.uri().path() != "/metrics" {
+        return Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())
+            .unwrap());
+    }
+
+    let encoder = TextEncoder::new();
+    let mut buffer = vec![];
+    if let Err(error) = encoder.encode(&prometheus::gather(), &mut buffer) {
+        return Ok(Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Body::from(error.to_string()))
+            .unwrap());
+    }
+
+    Ok(Response::builder()
+        .header(hyper::header::CONTENT_TYPE, encoder.format_type())
+        .body(Body::from(buffer))
+        .unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn counts_rows_by_table_and_operation() {
+        let upserts = ROWS.with_label_values(&["metrics_test", "metrics_test_table", "upsert"]);
+        let deletes = ROWS.with_label_values(&["metrics_test", "metrics_test_table", "delete"]);
+        let other_connection =
+            ROWS.with_label_values(&["metrics_test_other", "metrics_test_table", "upsert"]);
+        let (initial_upserts, initial_deletes) = (upserts.get(), deletes.get());
+
+        for op_type in [OpType::Upsert, OpType::Upsert, OpType::Delete] {
+            record_update_message(
+                "metrics_test",
+                &UpdateMessage::Update {
+                    schema_name: None,
+                    table_name: "metrics_test_table".to_string(),
+                    op_type,
+                    row: HashMap::new(),
+                },
+            );
+        }
+
+        assert_eq!(upserts.get() - initial_upserts, 2);
+        assert_eq!(deletes.get() - initial_deletes, 1);
+        assert_eq!(other_connection.get(), 0);
+    }
+
+    #[test]
+    fn labels_connections_without_their_url() {
+        let url = Url::parse("https://aware-llama-900.convex.cloud").unwrap();
+        let label = connection_label(&url);
+
+        assert_eq!(label.len(), 16);
+        assert!(!label.contains("llama"));
+        assert_eq!(label, connection_label(&url));
+        assert_ne!(
+            label,
+            connection_label(&Url::parse("https://tidy-zebra-123.convex.cloud").unwrap())
+        );
+    }
+
+    #[test]
+    fn describes_slow_calls_with_their_trace() {
+        assert_eq!(
+            slow_call_message("list_snapshot", "200", Duration::from_secs(1), None),
+            None
+        );
+        assert_eq!(
+            slow_call_message("list_snapshot", "200", Duration::from_millis(12_345), None),
+            Some("Slow call to list_snapshot: 12.3s (status 200)".to_string())
+        );
+        assert_eq!(
+            slow_call_message(
+                "document_deltas",
+                "error",
+                Duration::from_secs(30),
+                Some("4bf92f3577b34da6a3ce929d0e0e4736".to_string())
+            ),
+            Some(
+                "Slow call to document_deltas: 30.0s (status error, trace ID \
+                 4bf92f3577b34da6a3ce929d0e0e4736)"
+                    .to_string()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn serves_metrics_on_the_metrics_path() {
+        CHECKPOINTS.with_label_values(&["metrics_test"]).inc();
+
+        let response = handle_request(Request::get("/metrics").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("convex_fivetran_checkpoints_total"));
+
+        let response = handle_request(Request::get("/other").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,96 @@
+use std::collections::VecDeque;
+
+/// The page size used before any page was received, when none is configured.
+const DEFAULT_PAGE_SIZE: u32 = 100;
+
+/// The bounds of the page sizes requested, whatever the size of documents.
+const MIN_PAGE_SIZE: u32 = 1;
+const MAX_PAGE_SIZE: u32 = 10_000;
+
+/// The number of recent pages whose average document size is used.
+const WINDOW: usize = 5;
+
+/// Picks the number of documents requested per page of the initial sync so
+/// that pages are about `target_bytes` large. Tables of small documents are
+/// synced with large pages, and tables of huge documents with small ones that
+/// fit in memory.
+#[derive(Debug)]
+pub struct AdaptivePageSize {
+    target_bytes: u64,
+    page_size: u32,
+    /// The average size of the documents (in bytes) of the recent pages.
+    recent_document_sizes: VecDeque<f64>,
+}
+
+impl AdaptivePageSize {
+    pub fn new(target_bytes: u64, initial_page_size: Option<u32>) -> Self {
+        AdaptivePageSize {
+            target_bytes,
+            page_size: initial_page_size
+                .unwrap_or(DEFAULT_PAGE_SIZE)
+                .clamp(MIN_PAGE_SIZE, MAX_PAGE_SIZE),
+            recent_document_sizes: VecDeque::with_capacity(WINDOW),
+        }
+    }
+
+    /// The number of documents to request in the next page.
+    pub fn page_size(&self) -> u32 {
+        self.page_size
+    }
+
+    /// Adjusts the page size after receiving a page of `documents` documents
+    /// weighing `bytes` in total. The page size at most doubles from one page
+    /// to the next, but shrinks right away when documents get larger.
+    pub fn record(&mut self, documents: usize, bytes: u64) {
+        if documents == 0 {
+            return;
+        }
+        if self.recent_document_sizes.len() == WINDOW {
+            self.recent_document_sizes.pop_front();
+        }
+        self.recent_document_sizes
+            .push_back(bytes as f64 / documents as f64);
+
+        let average_document_size = self.recent_document_sizes.iter().sum::<f64>()
+            / self.recent_document_sizes.len() as f64;
+        let ideal_page_size = (self.target_bytes as f64 / average_document_size.max(1.0)) as u32;
+        self.page_size = ideal_page_size
+            .min(self.page_size.saturating_mul(2))
+            .clamp(MIN_PAGE_SIZE, MAX_PAGE_SIZE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grows_pages_of_small_documents_gradually() {
+        let mut page_size = AdaptivePageSize::new(100_000, None);
+        assert_eq!(page_size.page_size(), 100);
+
+        page_size.record(100, 10_000);
+        assert_eq!(page_size.page_size(), 200);
+        page_size.record(200, 20_000);
+        assert_eq!(page_size.page_size(), 400);
+        for _ in 0..10 {
+            let documents = page_size.page_size() as usize;
+            page_size.record(documents, documents as u64 * 100);
+        }
+        assert_eq!(page_size.page_size(), 1_000);
+    }
+
+    #[test]
+    fn shrinks_pages_of_large_documents_right_away() {
+        let mut page_size = AdaptivePageSize::new(1_000_000, Some(1_000));
+
+        page_size.record(1_000, 100_000_000);
+        assert_eq!(page_size.page_size(), 10);
+        page_size.record(10, 100_000_000);
+        assert_eq!(page_size.page_size(), MIN_PAGE_SIZE);
+
+        // Empty pages don’t change anything.
+        page_size.record(0, 0);
+        assert_eq!(page_size.page_size(), MIN_PAGE_SIZE);
+    }
+}
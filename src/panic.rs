@@ -0,0 +1,164 @@
+use std::{
+    any::Any,
+    backtrace::Backtrace,
+    cell::RefCell,
+    collections::hash_map::DefaultHasher,
+    future::Future,
+    hash::{
+        Hash,
+        Hasher,
+    },
+    panic::AssertUnwindSafe,
+};
+
+use futures::{
+    stream::BoxStream,
+    FutureExt,
+    StreamExt,
+};
+use tonic::Status;
+
+use crate::{
+    correlation::with_sync_id,
+    log_severe,
+    redact::redact,
+};
+
+thread_local! {
+    /// The location of the last panic on this thread, recorded by the panic
+    /// hook so that it can be included in the error returned to Fivetran once
+    /// the panic is caught.
+    static PANIC_LOCATION: RefCell<Option<String>> = RefCell::new(None);
+}
+
+/// Installs a panic hook logging panics as SEVERE log lines, with their
+/// location and backtrace, so that they are visible in the Fivetran logs.
+pub fn install_panic_hook() {
+    std::panic::set_hook(Box::new(|info| {
+        let message = panic_message(info.payload());
+        let location = info
+            .location()
+            .map(|location| location.to_string())
+            .unwrap_or_else(|| "unknown location".to_string());
+        PANIC_LOCATION.with(|last_location| *last_location.borrow_mut() = Some(location.clone()));
+
+        let backtrace = Backtrace::force_capture();
+        log_severe(&format!(
+            "The connector panicked at {location} (fingerprint {}): \
+             {message}\nBacktrace:\n{backtrace}",
+            fingerprint(&message)
+        ));
+    }));
+}
+
+/// Returns an error instead of unwinding through the gRPC server if the future
+/// panics.
+pub async fn catch_panics_in<T>(
+    future: impl Future<Output = Result<T, Status>>,
+) -> Result<T, Status> {
+    match AssertUnwindSafe(future).catch_unwind().await {
+        Ok(result) => result,
+        Err(payload) => Err(panic_status(payload.as_ref())),
+    }
+}
+
+/// Ends the stream with an error instead of dropping it if polling it panics.
+pub fn catch_panics<T: Send + 'static>(
+    stream: BoxStream<'static, Result<T, Status>>,
+) -> BoxStream<'static, Result<T, Status>> {
+    AssertUnwindSafe(stream)
+        .catch_unwind()
+        .map(|result| match result {
+            Ok(item) => item,
+            Err(payload) => Err(panic_status(payload.as_ref())),
+        })
+        .boxed()
+}
+
+/// The error returned to Fivetran when the connector panics.
+fn panic_status(payload: &(dyn Any + Send)) -> Status {
+    let message = panic_message(payload);
+    let location = PANIC_LOCATION
+        .with(|location| location.borrow_mut().take())
+        .map(|location| format!(" at {location}"))
+        .unwrap_or_default();
+    Status::internal(redact(&with_sync_id(&format!(
+        "The connector encountered an unexpected error{location} (fingerprint {}): {message}. See \
+         the connector logs for the backtrace.",
+        fingerprint(&message)
+    ))))
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+/// A short identifier of a panic message, making it easy to find the log lines
+/// of a panic from the error shown in Fivetran.
+fn fingerprint(message: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    message.hash(&mut hasher);
+    format!("{:08x}", hasher.finish() as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::stream;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn converts_panics_to_errors() {
+        let stream = stream::iter([1, 2, 3])
+            .map(|i| {
+                if i == 2 {
+                    panic!("Unexpected value");
+                }
+                Ok(i)
+            })
+            .boxed();
+
+        let results: Vec<Result<i32, Status>> = catch_panics(stream).collect().await;
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].as_ref().ok(), Some(&1));
+        let error = results[1].as_ref().unwrap_err();
+        assert_eq!(error.code(), tonic::Code::Internal);
+        assert!(error.message().contains("Unexpected value"));
+        assert!(error.message().contains(&fingerprint("Unexpected value")));
+    }
+
+    #[tokio::test]
+    async fn converts_panics_of_futures_to_errors() {
+        let result: Result<(), Status> = catch_panics_in(async {
+            panic!("Unexpected schema");
+        })
+        .await;
+
+        let error = result.unwrap_err();
+        assert_eq!(error.code(), tonic::Code::Internal);
+        assert!(error.message().contains("Unexpected schema"));
+    }
+
+    #[tokio::test]
+    async fn passes_through_results_of_futures() {
+        let result = catch_panics_in(async { Ok::<_, Status>(42) }).await;
+        assert_eq!(result.ok(), Some(42));
+    }
+
+    #[test]
+    fn reads_panic_messages() {
+        assert_eq!(panic_message(&"static message"), "static message");
+        assert_eq!(
+            panic_message(&"formatted message".to_string()),
+            "formatted message"
+        );
+        assert_eq!(panic_message(&42), "unknown panic");
+    }
+}
@@ -0,0 +1,164 @@
+//! Throughput statistics about an update stream, reported periodically to
+//! Fivetran so that long syncs show signs of life in the dashboard.
+
+use std::{
+    collections::{
+        BTreeMap,
+        HashMap,
+    },
+    io,
+    time::{
+        Duration,
+        SystemTime,
+    },
+};
+
+use serde_json::Value as JsonValue;
+
+/// How often progress is reported during a sync.
+const REPORT_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// The documents received by an update stream since it started.
+pub struct SyncStats {
+    started_at: SystemTime,
+    last_report_at: SystemTime,
+    rows_by_table: BTreeMap<String, u64>,
+    /// The size of the documents received, as JSON.
+    bytes: u64,
+}
+
+impl SyncStats {
+    pub fn new(now: SystemTime) -> Self {
+        SyncStats {
+            started_at: now,
+            last_report_at: now,
+            rows_by_table: BTreeMap::new(),
+            bytes: 0,
+        }
+    }
+
+    /// Counts a document received from the source, and returns its size as
+    /// JSON.
+    pub fn record(&mut self, table: &str, fields: &HashMap<String, JsonValue>) -> u64 {
+        *self.rows_by_table.entry(table.to_string()).or_default() += 1;
+
+        let mut counter = ByteCounter(0);
+        serde_json::to_writer(&mut counter, fields).expect("Couldn’t serialize a document");
+        self.bytes += counter.0;
+        counter.0
+    }
+
+    /// Describes the progress made so far, if the last report is old enough.
+    pub fn report_if_due(&mut self, now: SystemTime) -> Option<String> {
+        let since_last_report = now.duration_since(self.last_report_at).unwrap_or_default();
+        if since_last_report < REPORT_INTERVAL {
+            return None;
+        }
+        self.last_report_at = now;
+        Some(format!("Progress: {}", self.describe(now)))
+    }
+
+    /// Describes everything received since the stream started.
+    pub fn summary(&self, now: SystemTime) -> String {
+        format!("Summary: {}", self.describe(now))
+    }
+
+    fn describe(&self, now: SystemTime) -> String {
+        let elapsed = now.duration_since(self.started_at).unwrap_or_default();
+        let rows: u64 = self.rows_by_table.values().sum();
+        let rows_per_second = if elapsed.is_zero() {
+            0.0
+        } else {
+            rows as f64 / elapsed.as_secs_f64()
+        };
+        let mut description = format!(
+            "{rows} rows in {}s ({rows_per_second:.1} rows/s), {} downloaded",
+            elapsed.as_secs(),
+            format_bytes(self.bytes),
+        );
+        if !self.rows_by_table.is_empty() {
+            let tables: Vec<String> = self
+                .rows_by_table
+                .iter()
+                .map(|(table, rows)| format!("{table}: {rows}"))
+                .collect();
+            description += &format!(" ({})", tables.join(", "));
+        }
+        description
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1000 {
+        return format!("{bytes} B");
+    }
+    let mut value = bytes as f64 / 1000.0;
+    let mut unit = 0;
+    while value >= 1000.0 && unit < UNITS.len() - 1 {
+        value /= 1000.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+/// Counts the bytes written without storing them.
+struct ByteCounter(u64);
+
+impl io::Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use maplit::hashmap;
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn reports_progress_periodically() {
+        let start = SystemTime::UNIX_EPOCH;
+        let mut stats = SyncStats::new(start);
+        let document = hashmap! { "name".to_string() => json!("Ada") };
+        for _ in 0..600 {
+            stats.record("users", &document);
+        }
+        stats.record("messages", &hashmap! {});
+
+        assert_eq!(stats.report_if_due(start + Duration::from_secs(60)), None);
+        assert_eq!(
+            stats.report_if_due(start + Duration::from_secs(300)),
+            Some(
+                "Progress: 601 rows in 300s (2.0 rows/s), 8.4 KB downloaded (messages: 1, users: \
+                 600)"
+                    .to_string()
+            )
+        );
+        assert_eq!(stats.report_if_due(start + Duration::from_secs(360)), None);
+    }
+
+    #[test]
+    fn summarizes_empty_syncs() {
+        let stats = SyncStats::new(SystemTime::UNIX_EPOCH);
+        assert_eq!(
+            stats.summary(SystemTime::UNIX_EPOCH),
+            "Summary: 0 rows in 0s (0.0 rows/s), 0 B downloaded"
+        );
+    }
+
+    #[test]
+    fn formats_sizes() {
+        assert_eq!(format_bytes(999), "999 B");
+        assert_eq!(format_bytes(1_500), "1.5 KB");
+        assert_eq!(format_bytes(2_000_000), "2.0 MB");
+        assert_eq!(format_bytes(3_210_000_000_000_000), "3210.0 TB");
+    }
+}
@@ -0,0 +1,131 @@
+//! Removal of secrets (deploy keys) from the messages leaving the process:
+//! log lines, log entries sent to Fivetran and gRPC error statuses.
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        LazyLock,
+        RwLock,
+    },
+};
+
+use regex::Regex;
+
+const REDACTED: &str = "[REDACTED]";
+
+/// Deploy keys of Convex deployments, e.g. `prod:aware-llama-900|016b…`.
+static DEPLOY_KEY: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\b(?:prod|dev|preview|project):[A-Za-z0-9_-]+\|[A-Za-z0-9+/=_-]+").unwrap()
+});
+
+/// Credentials in (debug representations of) `Authorization` headers.
+static AUTHORIZATION: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?i)(authorization\W{0,4})(?:convex|bearer)\s+[^\s"',}]+"#).unwrap()
+});
+
+/// The keys used by the connections of the connector, which may not look like
+/// Convex cloud deploy keys (e.g. admin keys of self-hosted deployments), with
+/// the number of live [`SecretRegistration`]s of each key.
+static SECRETS: LazyLock<RwLock<BTreeMap<String, usize>>> = LazyLock::new(Default::default);
+
+/// Keeps secrets removed from every message until it is dropped, so that the
+/// secrets of the connections that are no longer used are forgotten.
+#[must_use]
+#[derive(Debug)]
+pub struct SecretRegistration(Vec<String>);
+
+impl Drop for SecretRegistration {
+    fn drop(&mut self) {
+        let mut secrets = SECRETS.write().unwrap();
+        for secret in &self.0 {
+            if let Some(count) = secrets.get_mut(secret) {
+                *count -= 1;
+                if *count == 0 {
+                    secrets.remove(secret);
+                }
+            }
+        }
+    }
+}
+
+/// Makes sure that the given secrets are removed from every message, until
+/// the registration returned is dropped.
+pub fn register_secrets<'a>(secrets: impl IntoIterator<Item = &'a str>) -> SecretRegistration {
+    let secrets: Vec<String> = secrets
+        .into_iter()
+        .filter(|secret| !secret.is_empty())
+        .map(String::from)
+        .collect();
+    let mut registered = SECRETS.write().unwrap();
+    for secret in &secrets {
+        *registered.entry(secret.clone()).or_default() += 1;
+    }
+    SecretRegistration(secrets)
+}
+
+/// Replaces the secrets contained in a message with a placeholder.
+pub fn redact(message: &str) -> String {
+    let mut message = message.to_string();
+    for secret in SECRETS.read().unwrap().keys() {
+        if message.contains(secret.as_str()) {
+            message = message.replace(secret.as_str(), REDACTED);
+        }
+    }
+    let message = AUTHORIZATION.replace_all(&message, format!("${{1}}{REDACTED}"));
+    DEPLOY_KEY.replace_all(&message, REDACTED).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_deploy_keys() {
+        assert_eq!(
+            redact(
+                "Invalid key prod:aware-llama-900|016b26d3900d5e482f1780969c2fa608a773140fb221db21785a9b2775b50263da6a258301b6374ef72b4c120e237c20ac50 for the deployment"
+            ),
+            "Invalid key [REDACTED] for the deployment"
+        );
+    }
+
+    #[test]
+    fn redacts_authorization_headers() {
+        assert_eq!(
+            redact(r#"headers: {"authorization": "Convex abc123", "accept": "*/*"}"#),
+            r#"headers: {"authorization": "[REDACTED]", "accept": "*/*"}"#
+        );
+        assert_eq!(
+            redact("Authorization: Bearer abc123"),
+            "Authorization: [REDACTED]"
+        );
+    }
+
+    #[test]
+    fn redacts_registered_secrets() {
+        let registration = register_secrets(["self-hosted-admin-key-7f3a"]);
+        assert_eq!(
+            redact("Call failed with key self-hosted-admin-key-7f3a"),
+            "Call failed with key [REDACTED]"
+        );
+
+        // Secrets are kept until their last registration is dropped
+        let other_registration = register_secrets(["self-hosted-admin-key-7f3a"]);
+        drop(registration);
+        assert_eq!(
+            redact("Call failed with key self-hosted-admin-key-7f3a"),
+            "Call failed with key [REDACTED]"
+        );
+        drop(other_registration);
+        assert!(!SECRETS
+            .read()
+            .unwrap()
+            .contains_key("self-hosted-admin-key-7f3a"));
+    }
+
+    #[test]
+    fn keeps_other_messages_unchanged() {
+        let message = "Call to list_snapshot on https://aware-llama-900.convex.cloud/ timed out";
+        assert_eq!(redact(message), message);
+    }
+}
@@ -0,0 +1,175 @@
+//! Restarts of update streams failing with a transient error, from the last
+//! checkpoint they emitted.
+
+use std::{
+    pin::pin,
+    sync::Arc,
+};
+
+use async_stream::try_stream;
+use futures::{
+    Stream,
+    StreamExt,
+};
+
+use crate::{
+    clock::Clock,
+    error::is_transient,
+    fivetran_sdk::LogLevel,
+    log_warning,
+    sync::{
+        transient_retry_delay,
+        State,
+        UpdateMessage,
+    },
+};
+
+/// Runs the update stream returned by `start` for `state`, and starts it again
+/// from the last checkpoint emitted every time it fails with a transient
+/// error, up to `max_restarts` times before surfacing the error.
+///
+/// Fivetran retries failed syncs on its own schedule, which can take hours, so
+/// restarting them in the process recovers from short outages much faster.
+/// The rows emitted after the last checkpoint are sent again, exactly like
+/// when Fivetran retries a sync.
+pub fn restart_on_transient_errors<S>(
+    mut state: Option<State>,
+    max_restarts: u32,
+    clock: Arc<dyn Clock>,
+    mut start: impl FnMut(Option<State>) -> S,
+) -> impl Stream<Item = anyhow::Result<UpdateMessage>>
+where
+    S: Stream<Item = anyhow::Result<UpdateMessage>>,
+{
+    try_stream! {
+        let mut restarts = 0;
+        loop {
+            let mut stream = pin!(start(state.clone()));
+            let error = loop {
+                match stream.next().await {
+                    Some(Ok(message)) => {
+                        if let UpdateMessage::Checkpoint(ref checkpoint) = message {
+                            state = Some(checkpoint.clone());
+                        }
+                        yield message;
+                    },
+                    Some(Err(error)) => break Some(error),
+                    None => break None,
+                }
+            };
+            let Some(error) = error else {
+                break;
+            };
+            if restarts >= max_restarts || !is_transient(&error) {
+                Err(error)?;
+            } else {
+                restarts += 1;
+                let delay = transient_retry_delay(restarts);
+                let log_msg = format!(
+                    "The sync failed with a transient error, restarting it from the last \
+                     checkpoint in {}s (restart {restarts}/{max_restarts}): {error}",
+                    delay.as_secs()
+                );
+                log_warning(&log_msg);
+                yield UpdateMessage::Log(LogLevel::Warning, log_msg);
+                clock.sleep(delay).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        time::{
+            Duration,
+            SystemTime,
+        },
+    };
+
+    use futures::stream;
+
+    use super::*;
+    use crate::{
+        clock::FakeClock,
+        error::ConnectorError,
+        sync::Checkpoint,
+    };
+
+    fn checkpoint(cursor: i64) -> State {
+        State::create(
+            Checkpoint::DeltaUpdates {
+                cursor: cursor.into(),
+            },
+            HashSet::new(),
+        )
+    }
+
+    fn unavailable() -> anyhow::Error {
+        ConnectorError::Unavailable(anyhow::anyhow!("The deployment is overloaded")).into()
+    }
+
+    #[tokio::test]
+    async fn restarts_from_the_last_checkpoint() {
+        let clock = Arc::new(FakeClock::new(SystemTime::UNIX_EPOCH));
+        let mut starts = vec![];
+        let results: Vec<_> = restart_on_transient_errors(None, 3, clock.clone(), |state| {
+            starts.push(state.clone());
+            if state.is_none() {
+                stream::iter(vec![
+                    Ok(UpdateMessage::Checkpoint(checkpoint(1))),
+                    Err(unavailable()),
+                ])
+            } else {
+                stream::iter(vec![Ok(UpdateMessage::Checkpoint(checkpoint(2)))])
+            }
+        })
+        .collect()
+        .await;
+
+        assert_eq!(starts, vec![None, Some(checkpoint(1))]);
+        assert_eq!(results.len(), 3);
+        assert!(results.iter().all(|result| result.is_ok()));
+        assert!(matches!(
+            results[1],
+            Ok(UpdateMessage::Log(LogLevel::Warning, _))
+        ));
+        assert_eq!(clock.sleeps(), vec![Duration::from_secs(1)]);
+    }
+
+    #[tokio::test]
+    async fn surfaces_the_error_after_too_many_restarts() {
+        let clock = Arc::new(FakeClock::new(SystemTime::UNIX_EPOCH));
+        let mut starts = 0;
+        let results: Vec<_> = restart_on_transient_errors(None, 2, clock.clone(), |_| {
+            starts += 1;
+            stream::iter(vec![Err(unavailable())])
+        })
+        .collect()
+        .await;
+
+        assert_eq!(starts, 3);
+        assert!(matches!(results.last(), Some(Err(_))));
+        assert_eq!(
+            clock.sleeps(),
+            vec![Duration::from_secs(1), Duration::from_secs(2)]
+        );
+    }
+
+    #[tokio::test]
+    async fn doesnt_restart_after_other_errors() {
+        let clock = Arc::new(FakeClock::new(SystemTime::UNIX_EPOCH));
+        let mut starts = 0;
+        let results: Vec<_> = restart_on_transient_errors(None, 3, clock.clone(), |_| {
+            starts += 1;
+            stream::iter(vec![Err(anyhow::anyhow!("Unexpected"))])
+        })
+        .collect()
+        .await;
+
+        assert_eq!(starts, 1);
+        assert_eq!(results.len(), 1);
+        assert!(clock.sleeps().is_empty());
+    }
+}
@@ -0,0 +1,392 @@
+//! The filters selecting the documents of a table that are synced, so that
+//! test or noise rows can be kept out of the destination without changing
+//! the Convex app.
+//!
+//! A filter is an expression over the fields of a document, like
+//! `status != 'draft' && !(author.name == "test")`. Comparisons (`==`, `!=`,
+//! `<`, `<=`, `>`, `>=`) compare a field, or a nested field with a dotted
+//! path, with a string (in single or double quotes), a number, `true`,
+//! `false` or `null`. They can be combined with `&&`, `||`, `!` and
+//! parentheses. Missing fields are `null`, and ordering comparisons are only
+//! true between two numbers or two strings.
+
+use std::{
+    cmp::Ordering,
+    collections::{
+        BTreeMap,
+        HashMap,
+    },
+    fmt,
+    str::FromStr,
+};
+
+use serde_json::Value as JsonValue;
+
+/// The row filters of the tables that have one, by table name.
+#[derive(Debug, Clone, Default)]
+pub struct RowFilters(BTreeMap<String, RowFilter>);
+
+impl RowFilters {
+    pub fn new(filters: BTreeMap<String, RowFilter>) -> Self {
+        RowFilters(filters)
+    }
+
+    /// Whether the document of the given table is synced.
+    pub fn includes(&self, table_name: &str, document: &HashMap<String, JsonValue>) -> bool {
+        self.0
+            .get(table_name)
+            .map_or(true, |filter| filter.matches(document))
+    }
+
+    /// Whether every document is synced.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// A parsed filter expression.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RowFilter(Expression);
+
+impl RowFilter {
+    /// Whether the document matches the filter.
+    pub fn matches(&self, document: &HashMap<String, JsonValue>) -> bool {
+        self.0.evaluate(document)
+    }
+}
+
+impl FromStr for RowFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(source: &str) -> anyhow::Result<Self> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+        };
+        let expression = parser.or()?;
+        if let Some(token) = parser.peek() {
+            anyhow::bail!("unexpected {token}");
+        }
+        Ok(RowFilter(expression))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expression {
+    Comparison {
+        path: Vec<String>,
+        operator: Operator,
+        value: JsonValue,
+    },
+    Not(Box<Expression>),
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+}
+
+impl Expression {
+    fn evaluate(&self, document: &HashMap<String, JsonValue>) -> bool {
+        match self {
+            Expression::Comparison {
+                path,
+                operator,
+                value,
+            } => {
+                let field = path[1..]
+                    .iter()
+                    .try_fold(document.get(&path[0]), |field, name| Some(field?.get(name)))
+                    .flatten()
+                    .unwrap_or(&JsonValue::Null);
+                operator.compare(field, value)
+            },
+            Expression::Not(expression) => !expression.evaluate(document),
+            Expression::And(left, right) => left.evaluate(document) && right.evaluate(document),
+            Expression::Or(left, right) => left.evaluate(document) || right.evaluate(document),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Operator {
+    fn compare(self, field: &JsonValue, value: &JsonValue) -> bool {
+        let ordering = match (field, value) {
+            (JsonValue::Number(a), JsonValue::Number(b)) => a
+                .as_f64()
+                .zip(b.as_f64())
+                .and_then(|(a, b)| a.partial_cmp(&b)),
+            (JsonValue::String(a), JsonValue::String(b)) => Some(a.cmp(b)),
+            _ => None,
+        };
+        match self {
+            Operator::Equal => ordering.map_or(field == value, Ordering::is_eq),
+            Operator::NotEqual => !ordering.map_or(field == value, Ordering::is_eq),
+            Operator::Less => ordering.is_some_and(Ordering::is_lt),
+            Operator::LessOrEqual => ordering.is_some_and(Ordering::is_le),
+            Operator::Greater => ordering.is_some_and(Ordering::is_gt),
+            Operator::GreaterOrEqual => ordering.is_some_and(Ordering::is_ge),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Path(String),
+    Value(JsonValue),
+    Operator(Operator),
+    And,
+    Or,
+    Not,
+    OpenParenthesis,
+    CloseParenthesis,
+}
+
+const SYMBOLS: [(&str, Token); 11] = [
+    ("==", Token::Operator(Operator::Equal)),
+    ("!=", Token::Operator(Operator::NotEqual)),
+    ("<=", Token::Operator(Operator::LessOrEqual)),
+    (">=", Token::Operator(Operator::GreaterOrEqual)),
+    ("<", Token::Operator(Operator::Less)),
+    (">", Token::Operator(Operator::Greater)),
+    ("&&", Token::And),
+    ("||", Token::Or),
+    ("!", Token::Not),
+    ("(", Token::OpenParenthesis),
+    (")", Token::CloseParenthesis),
+];
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Path(path) => write!(f, "field {path}"),
+            Token::Value(value) => write!(f, "value {value}"),
+            token => {
+                let (symbol, _) = SYMBOLS
+                    .iter()
+                    .find(|(_, symbol_token)| symbol_token == token)
+                    .expect("every other token is a symbol");
+                write!(f, "“{symbol}”")
+            },
+        }
+    }
+}
+
+fn tokenize(source: &str) -> anyhow::Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut chars = source.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        let rest = &source[start..];
+        let symbol = SYMBOLS.iter().find(|(symbol, _)| rest.starts_with(symbol));
+        if let Some((symbol, token)) = symbol {
+            tokens.push(token.clone());
+            for _ in 0..symbol.len() {
+                chars.next();
+            }
+        } else if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' || c == '\'' {
+            chars.next();
+            let mut string = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, 'n')) => string.push('\n'),
+                        Some((_, 't')) => string.push('\t'),
+                        Some((_, escaped)) => string.push(escaped),
+                        None => anyhow::bail!("unterminated string"),
+                    },
+                    Some((_, quote)) if quote == c => break,
+                    Some((_, other)) => string.push(other),
+                    None => anyhow::bail!("unterminated string"),
+                }
+            }
+            tokens.push(Token::Value(JsonValue::String(string)));
+        } else if c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.' {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || "_-+.".contains(c)))
+                .unwrap_or(rest.len());
+            let word = &rest[..end];
+            for _ in word.chars() {
+                chars.next();
+            }
+            tokens.push(match word {
+                "true" => Token::Value(JsonValue::Bool(true)),
+                "false" => Token::Value(JsonValue::Bool(false)),
+                "null" => Token::Value(JsonValue::Null),
+                _ if word.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '.') => {
+                    let number: f64 = word
+                        .parse()
+                        .map_err(|_| anyhow::anyhow!("invalid number {word}"))?;
+                    Token::Value(
+                        serde_json::Number::from_f64(number)
+                            .map(JsonValue::Number)
+                            .ok_or_else(|| anyhow::anyhow!("invalid number {word}"))?,
+                    )
+                },
+                _ if word.split('.').all(is_identifier) => Token::Path(word.to_string()),
+                _ => anyhow::bail!("invalid field {word}"),
+            });
+        } else {
+            anyhow::bail!("unexpected character {c}");
+        }
+    }
+    Ok(tokens)
+}
+
+fn is_identifier(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// A recursive descent parser, where `!` binds tighter than `&&`, which
+/// binds tighter than `||`.
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> anyhow::Result<Token> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("unexpected end of filter"))?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn or(&mut self) -> anyhow::Result<Expression> {
+        let mut expression = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.position += 1;
+            expression = Expression::Or(Box::new(expression), Box::new(self.and()?));
+        }
+        Ok(expression)
+    }
+
+    fn and(&mut self) -> anyhow::Result<Expression> {
+        let mut expression = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.position += 1;
+            expression = Expression::And(Box::new(expression), Box::new(self.unary()?));
+        }
+        Ok(expression)
+    }
+
+    fn unary(&mut self) -> anyhow::Result<Expression> {
+        match self.next()? {
+            Token::Not => Ok(Expression::Not(Box::new(self.unary()?))),
+            Token::OpenParenthesis => {
+                let expression = self.or()?;
+                match self.next()? {
+                    Token::CloseParenthesis => Ok(expression),
+                    token => anyhow::bail!("expected {}, found {token}", Token::CloseParenthesis),
+                }
+            },
+            Token::Path(path) => {
+                let operator = match self.next()? {
+                    Token::Operator(operator) => operator,
+                    token => anyhow::bail!("expected a comparison after {path}, found {token}"),
+                };
+                let value = match self.next()? {
+                    Token::Value(value) => value,
+                    token => anyhow::bail!("expected a value after {path}, found {token}"),
+                };
+                Ok(Expression::Comparison {
+                    path: path.split('.').map(String::from).collect(),
+                    operator,
+                    value,
+                })
+            },
+            token => anyhow::bail!("expected a field, found {token}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use maplit::hashmap;
+    use serde_json::json;
+
+    use super::*;
+
+    fn matches(filter: &str, document: &HashMap<String, JsonValue>) -> bool {
+        filter.parse::<RowFilter>().unwrap().matches(document)
+    }
+
+    #[test]
+    fn evaluates_filters() {
+        let document = hashmap! {
+            "status".to_string() => json!("published"),
+            "score".to_string() => json!(42),
+            "pinned".to_string() => json!(false),
+            "author".to_string() => json!({ "name": "Alice", "isTest": true }),
+        };
+
+        assert!(matches("status != 'draft'", &document));
+        assert!(!matches(r#"status == "draft""#, &document));
+        assert!(matches("score >= 42 && score < 100.5", &document));
+        assert!(matches("score == 42.0", &document));
+        assert!(!matches("score > 'a'", &document));
+        assert!(matches("pinned == false || score < 0", &document));
+        assert!(!matches("author.isTest == true", &document));
+        assert!(matches(
+            "!(author.name == 'Bob') && author.name >= 'A'",
+            &document
+        ));
+        assert!(matches(
+            "deletedAt == null && author.email == null",
+            &document
+        ));
+        assert!(!matches("deletedAt != null", &document));
+        assert!(matches(
+            "score < 0 || status == 'published' && !pinned == true",
+            &document
+        ));
+    }
+
+    #[test]
+    fn refuses_invalid_filters() {
+        for filter in [
+            "",
+            "status",
+            "status = 'draft'",
+            "status == 'draft",
+            "status == draft",
+            "'draft' == status",
+            "(score > 1",
+            "score > 1)",
+            "score > 1 &&",
+            "1field == 2",
+            "score > 1e999",
+            "status == 'a' ; drop",
+        ] {
+            assert!(filter.parse::<RowFilter>().is_err(), "{filter}");
+        }
+    }
+
+    #[test]
+    fn includes_the_documents_of_tables_without_filters() {
+        let filters = RowFilters::new(BTreeMap::from([(
+            "posts".to_string(),
+            "status != 'draft'".parse().unwrap(),
+        )]));
+        let draft = hashmap! { "status".to_string() => json!("draft") };
+
+        assert!(!filters.includes("posts", &draft));
+        assert!(filters.includes("comments", &draft));
+    }
+}
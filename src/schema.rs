@@ -0,0 +1,1812 @@
+//! The mapping from the tables and columns of a Convex deployment to the schema
+//! exposed by the connector.
+
+use std::{
+    borrow::Cow,
+    collections::{
+        BTreeMap,
+        BTreeSet,
+        HashMap,
+    },
+};
+
+use schemars::schema::{
+    InstanceType,
+    Schema,
+    SchemaObject,
+    SingleOrVec,
+};
+use serde_json::{
+    json,
+    Value as JsonValue,
+};
+
+use crate::{
+    convert::{
+        array_length_column,
+        child_table_name,
+        flattened_column,
+        is_excludable_system_field,
+        presence_column,
+        sanitize_column_name,
+        sanitized_column_names,
+        ColumnMasking,
+        ColumnTypes,
+        DecimalColumns,
+        HashedColumns,
+        NestedObjects,
+        NonFiniteFloats,
+        CHILD_INDEX_COLUMN,
+        CHILD_PARENT_ID_COLUMN,
+        CHILD_VALUE_COLUMN,
+        OVERFLOW_COLUMN,
+        TIMESTAMP_COLUMN,
+    },
+    convex_api::{
+        DatabaseSchema,
+        FieldName,
+        Source,
+        TableName,
+    },
+    files::files_table,
+    fivetran_sdk::{
+        Column,
+        DataType,
+        DecimalParams,
+        Schema as FivetranSchema,
+        SchemaList,
+        Table,
+    },
+    log_warning,
+    metadata::metadata_table,
+    schema_cache::SchemaCache,
+    table_filter::TableFilter,
+    table_stats::table_stats_table,
+};
+
+/// The options of a connection that change the schema exposed to Fivetran.
+#[derive(Debug, Clone, Default)]
+pub struct SchemaOptions {
+    /// If set, only the first user columns (in alphabetical order) of each
+    /// table are declared, next to an overflow column.
+    pub max_fields: Option<usize>,
+
+    /// Tables that aren’t exposed, since only statistics about them are
+    /// synced.
+    pub stats_only_tables: BTreeSet<String>,
+
+    /// The tables exposed. Excluded tables aren’t synced at all.
+    pub table_filter: TableFilter,
+
+    /// A prefix added to the name of every table.
+    pub table_prefix: Option<String>,
+
+    /// If set, every user column has a companion boolean column marking
+    /// whether documents have the field (see [`presence_column`]).
+    pub presence_columns: bool,
+
+    /// If set, every table has a column containing the timestamp of the
+    /// revision of each document synced (see [`TIMESTAMP_COLUMN`]).
+    pub timestamp_column: bool,
+
+    /// If set, the system columns other than `_id` (e.g. `_creationTime`)
+    /// aren’t declared.
+    pub exclude_system_columns: bool,
+
+    /// The user columns declared as decimal numbers, with their precision and
+    /// scale, rather than with the type inferred by Fivetran.
+    pub decimal_columns: DecimalColumns,
+
+    /// The user columns whose data type is forced rather than inferred.
+    pub column_types: ColumnTypes,
+
+    /// The user columns whose values are replaced by their hash, which are
+    /// strings.
+    pub hashed_columns: HashedColumns,
+
+    /// The user columns whose values are masked. They are strings when they
+    /// are masked by a string, and keep their type when they are nulled.
+    pub column_masking: ColumnMasking,
+
+    /// How NaN and infinite numbers are synced. Number columns can contain
+    /// strings when they are synced as strings.
+    pub non_finite_floats: NonFiniteFloats,
+
+    /// If set, the strings containing dates are synced as dates, so string
+    /// columns can contain dates.
+    pub detect_dates: bool,
+
+    /// If set, the array columns are declared as their length, and their
+    /// elements as child tables (see [`child_table_name`]).
+    pub child_tables: bool,
+
+    /// How the object columns are declared. When they are flattened, the
+    /// fields of their JSON schema are declared as columns (see
+    /// [`flattened_column`]).
+    pub nested_objects: NestedObjects,
+
+    /// If set, the columns of the deployment tables are declared with their
+    /// sanitized names (see [`sanitize_column_name`]).
+    pub sanitize_column_names: bool,
+
+    /// If set, the files stored in the deployment are described in the files
+    /// table (see [`files_table`]).
+    pub files_table: bool,
+
+    /// If set, the files table has the download URL of every file.
+    pub file_download_urls: bool,
+}
+
+/// The tables exposed to Fivetran for the given deployment tables, including
+/// the connector metadata table. When the number of fields per document is
+/// limited, every table has an overflow column and only the first user columns
+/// (in alphabetical order) are declared. The types of user columns are taken
+/// from the JSON schemas of the tables when they are known. When child tables
+/// are enabled, the array columns of the JSON schemas are declared as their
+/// length and as a child table. When objects are flattened, the object columns
+/// of the JSON schemas are declared as the columns of their fields.
+pub fn fivetran_tables(
+    columns: HashMap<TableName, Vec<FieldName>>,
+    json_schemas: &DatabaseSchema,
+    options: &SchemaOptions,
+) -> Vec<Table> {
+    let max_fields = options.max_fields;
+    let columns: Vec<(TableName, Vec<FieldName>)> = columns
+        .into_iter()
+        .filter(|(table_name, _)| {
+            options.table_filter.includes(&table_name.0)
+                && !options.stats_only_tables.contains(&table_name.0)
+        })
+        .collect();
+    let table_renames = table_renames(columns.iter().map(|(table_name, _)| table_name.0.as_str()));
+    for (table_name, destination_name) in &table_renames {
+        log_warning(&table_rename_warning(table_name, destination_name));
+    }
+    columns
+        .into_iter()
+        .flat_map(|(table_name, column_names)| {
+            let destination_name = destination_table_name(&table_renames, &table_name.0);
+            let table_schema = json_schemas.0.get(&table_name);
+            let decimal_columns = options.decimal_columns.get(&table_name.0);
+            let column_types = options.column_types.get(&table_name.0);
+            let hashed_columns = options.hashed_columns.get(&table_name.0);
+            let masked_columns = options.column_masking.columns.get(&table_name.0);
+
+            // Like rows, the array columns are taken out before the columns are
+            // limited. Nullable arrays are kept, since null values aren’t arrays.
+            // Masked arrays aren’t arrays anymore, so they don’t have child tables.
+            let mut array_columns: Vec<(String, DataType)> = vec![];
+            let column_names: Vec<FieldName> = column_names
+                .into_iter()
+                .filter(|column_name| {
+                    let Some(column_schema) =
+                        column_schema(table_schema, &column_name.0).filter(|_| {
+                            options.child_tables
+                                && !column_name.0.starts_with('_')
+                                && !masked_columns
+                                    .is_some_and(|columns| columns.contains(&column_name.0))
+                        })
+                    else {
+                        return true;
+                    };
+                    let Some(element_type) = array_element_type(column_schema, options) else {
+                        return true;
+                    };
+                    array_columns.push((column_name.0.clone(), element_type));
+                    accepts_null(column_schema)
+                })
+                .collect();
+
+            // The object columns are taken out too, and masked objects aren’t
+            // objects anymore either.
+            let mut object_columns: Vec<(String, DataType)> = vec![];
+            let column_names: Vec<FieldName> = column_names
+                .into_iter()
+                .filter(|column_name| {
+                    let Some(column_schema) =
+                        column_schema(table_schema, &column_name.0).filter(|_| {
+                            options.nested_objects == NestedObjects::Columns
+                                && !column_name.0.starts_with('_')
+                                && !masked_columns
+                                    .is_some_and(|columns| columns.contains(&column_name.0))
+                        })
+                    else {
+                        return true;
+                    };
+                    let Some(properties) = object_properties(column_schema) else {
+                        return true;
+                    };
+                    flattened_columns(&column_name.0, properties, options, &mut object_columns);
+                    accepts_null(column_schema)
+                })
+                .collect();
+
+            let columns = limit_columns(column_names, max_fields)
+                .into_iter()
+                .filter(|column_name| {
+                    !(options.exclude_system_columns && is_excludable_system_field(&column_name.0))
+                })
+                .flat_map(|column_name| {
+                    let column_name: String = column_name.to_string();
+                    let presence = (options.presence_columns && !column_name.starts_with('_'))
+                        .then(|| Column {
+                            name: presence_column(&column_name),
+                            r#type: DataType::Boolean as i32,
+                            primary_key: false,
+                            decimal: None,
+                        });
+                    let decimal_type = decimal_columns
+                        .and_then(|columns| columns.get(&column_name))
+                        .filter(|_| !column_name.starts_with('_'));
+                    let column_type = column_types
+                        .and_then(|columns| columns.get(&column_name))
+                        .filter(|_| !column_name.starts_with('_'));
+                    let is_hashed =
+                        hashed_columns.is_some_and(|columns| columns.contains(&column_name));
+                    let is_masked_by_string = options.column_masking.mask.is_some()
+                        && masked_columns.is_some_and(|columns| columns.contains(&column_name));
+                    std::iter::once(Column {
+                        name: column_name.clone(),
+                        r#type: match column_name.as_str() {
+                            "_id" => DataType::String,
+                            "_creationTime" => DataType::UtcDatetime,
+                            _ if is_hashed || is_masked_by_string => DataType::String,
+                            _ if decimal_type.is_some() => DataType::Decimal,
+                            _ => column_type.copied().unwrap_or_else(|| {
+                                user_column_type(table_schema, &column_name, options)
+                            }),
+                        } as i32,
+                        primary_key: column_name == "_id",
+                        decimal: decimal_type.map(|decimal_type| DecimalParams {
+                            precision: decimal_type.precision,
+                            scale: decimal_type.scale,
+                        }),
+                    })
+                    .chain(presence)
+                })
+                .chain(
+                    object_columns
+                        .into_iter()
+                        .map(|(column_name, data_type)| Column {
+                            name: column_name,
+                            r#type: data_type as i32,
+                            primary_key: false,
+                            decimal: None,
+                        }),
+                )
+                .chain(array_columns.iter().map(|(column_name, _)| Column {
+                    name: array_length_column(column_name),
+                    r#type: DataType::Long as i32,
+                    primary_key: false,
+                    decimal: None,
+                }))
+                .chain(options.timestamp_column.then(|| Column {
+                    name: TIMESTAMP_COLUMN.to_string(),
+                    r#type: DataType::Long as i32,
+                    primary_key: false,
+                    decimal: None,
+                }))
+                .chain(max_fields.map(|_| Column {
+                    name: OVERFLOW_COLUMN.to_string(),
+                    r#type: DataType::Json as i32,
+                    primary_key: false,
+                    decimal: None,
+                }))
+                .collect();
+            let columns = if options.sanitize_column_names {
+                sanitize_columns(&table_name.0, columns)
+            } else {
+                columns
+            };
+
+            let child_tables = array_columns
+                .into_iter()
+                .map(|(column_name, element_type)| Table {
+                    name: child_table_name(&destination_name, &column_name),
+                    columns: vec![
+                        Column {
+                            name: CHILD_PARENT_ID_COLUMN.to_string(),
+                            r#type: DataType::String as i32,
+                            primary_key: true,
+                            decimal: None,
+                        },
+                        Column {
+                            name: CHILD_INDEX_COLUMN.to_string(),
+                            r#type: DataType::Long as i32,
+                            primary_key: true,
+                            decimal: None,
+                        },
+                        Column {
+                            name: CHILD_VALUE_COLUMN.to_string(),
+                            r#type: element_type as i32,
+                            primary_key: false,
+                            decimal: None,
+                        },
+                    ],
+                })
+                .collect::<Vec<_>>();
+            std::iter::once(Table {
+                name: destination_name.into_owned(),
+                columns,
+            })
+            .chain(child_tables)
+        })
+        .chain(std::iter::once(metadata_table()))
+        .chain((!options.stats_only_tables.is_empty()).then(table_stats_table))
+        .chain(
+            options
+                .files_table
+                .then(|| files_table(options.file_download_urls)),
+        )
+        .map(|mut table| {
+            if let Some(ref table_prefix) = options.table_prefix {
+                let (component_path, table_name) = split_component_table_name(&table.name);
+                table.name =
+                    component_table_name(component_path, &format!("{table_prefix}{table_name}"));
+            }
+            table
+        })
+        .collect()
+}
+
+/// The Fivetran schema of the tables of the root component of a deployment,
+/// when components are synced.
+pub const ROOT_COMPONENT_SCHEMA_NAME: &str = "app";
+
+/// The name of a table of a component, as used in the configuration and the
+/// state of the connector: `<component path>/<table name>`. The tables of the
+/// root component (whose path is empty) keep their name.
+pub fn component_table_name(component_path: &str, table_name: &str) -> String {
+    if component_path.is_empty() {
+        table_name.to_string()
+    } else {
+        format!("{component_path}/{table_name}")
+    }
+}
+
+/// The component path and the name of a table named by
+/// [`component_table_name`]. Table names can’t contain slashes, so the path is
+/// everything before the last one.
+pub fn split_component_table_name(name: &str) -> (&str, &str) {
+    name.rsplit_once('/').unwrap_or(("", name))
+}
+
+/// The Fivetran schema of the tables of a component: its path, with slashes
+/// replaced by underscores (`workpool`, `aggregate_btree`).
+pub fn component_schema_name(component_path: &str) -> String {
+    if component_path.is_empty() {
+        ROOT_COMPONENT_SCHEMA_NAME.to_string()
+    } else {
+        component_path.replace('/', "_")
+    }
+}
+
+/// The tables and columns of a deployment, including the tables of its
+/// components (named by [`component_table_name`]) if they are synced.
+pub async fn tables_and_columns(
+    source: &impl Source,
+    sync_components: bool,
+) -> anyhow::Result<HashMap<TableName, Vec<FieldName>>> {
+    let mut tables = source.get_tables_and_columns().await?;
+    if sync_components {
+        for (component_path, component_tables) in source.get_component_tables_and_columns().await? {
+            tables.extend(component_tables.into_iter().map(|(table_name, columns)| {
+                (
+                    TableName(component_table_name(&component_path, &table_name.0)),
+                    columns,
+                )
+            }));
+        }
+    }
+    Ok(tables)
+}
+
+/// Groups the tables exposed to Fivetran by the schema of their component.
+/// Tables of the root component (including the connector metadata tables) are
+/// in the root schema.
+pub fn component_schemas(tables: Vec<Table>, root_schema_name: &str) -> SchemaList {
+    let mut schemas: BTreeMap<String, Vec<Table>> = BTreeMap::new();
+    for mut table in tables {
+        let (component_path, table_name) = split_component_table_name(&table.name);
+        let schema_name = if component_path.is_empty() {
+            root_schema_name.to_string()
+        } else {
+            component_schema_name(component_path)
+        };
+        table.name = table_name.to_string();
+        schemas.entry(schema_name).or_default().push(table);
+    }
+    SchemaList {
+        schemas: schemas
+            .into_iter()
+            .map(|(name, tables)| FivetranSchema { name, tables })
+            .collect(),
+    }
+}
+
+/// The destination names of the tables whose names only differ by case from
+/// another table, by table name. Most destinations ignore the case of table
+/// names, and would merge the rows of such tables. In every group of colliding
+/// tables, the table whose name is in lowercase (or else the first one in
+/// alphabetical order) keeps its name, and the others get a `_2`, `_3`…
+/// suffix, in alphabetical order.
+pub fn table_renames<'a>(
+    table_names: impl IntoIterator<Item = &'a str>,
+) -> BTreeMap<String, String> {
+    let mut groups: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+    for table_name in table_names {
+        groups
+            .entry(table_name.to_lowercase())
+            .or_default()
+            .push(table_name);
+    }
+
+    let mut taken: BTreeSet<String> = groups.keys().cloned().collect();
+    let mut renames = BTreeMap::new();
+    for (lowercase_name, mut table_names) in groups {
+        table_names.sort_by_key(|table_name| (*table_name != lowercase_name, *table_name));
+        for table_name in table_names.into_iter().skip(1) {
+            let mut suffix = 2;
+            let destination_name = loop {
+                let candidate = format!("{table_name}_{suffix}");
+                if taken.insert(candidate.to_lowercase()) {
+                    break candidate;
+                }
+                suffix += 1;
+            };
+            renames.insert(table_name.to_string(), destination_name);
+        }
+    }
+    renames
+}
+
+/// The destination name of a table or of one of its child tables, given the
+/// renames of [`table_renames`].
+pub fn destination_table_name<'a>(
+    table_renames: &BTreeMap<String, String>,
+    table_name: &'a str,
+) -> Cow<'a, str> {
+    if let Some(destination_name) = table_renames.get(table_name) {
+        return Cow::Owned(destination_name.clone());
+    }
+    // Child tables are named after their parent table (see
+    // `child_table_name`), so they follow its rename.
+    table_renames
+        .iter()
+        .find_map(|(renamed_table, destination_name)| {
+            let field_name = table_name
+                .strip_prefix(renamed_table.as_str())?
+                .strip_prefix("__")?;
+            Some(Cow::Owned(child_table_name(destination_name, field_name)))
+        })
+        .unwrap_or(Cow::Borrowed(table_name))
+}
+
+/// The warning logged about a table renamed by [`table_renames`].
+pub fn table_rename_warning(table_name: &str, destination_name: &str) -> String {
+    format!(
+        "The name of the table {table_name} only differs by case from another table, which most \
+         destinations would merge with it, so it is synced as {destination_name}"
+    )
+}
+
+/// Renames the columns of a table to their sanitized names, warning about the
+/// columns whose sanitized names collide.
+fn sanitize_columns(table_name: &str, mut columns: Vec<Column>) -> Vec<Column> {
+    let sanitized_names: BTreeMap<String, String> =
+        sanitized_column_names(columns.iter().map(|column| column.name.as_str()))
+            .into_iter()
+            .map(|(column_name, sanitized)| (column_name.to_string(), sanitized))
+            .collect();
+    for column in &mut columns {
+        let sanitized = &sanitized_names[&column.name];
+        if *sanitized != sanitize_column_name(&column.name) {
+            log_warning(&format!(
+                "The sanitized name of {table_name}.{} collides with another column, so it is \
+                 synced as {sanitized}",
+                column.name
+            ));
+        }
+        column.name = sanitized.clone();
+    }
+    columns
+}
+
+/// Fetches the JSON schemas of the tables of a deployment, unless the cache of
+/// the deployment has recent ones. They only refine the types of the columns
+/// declared, so when they can’t be fetched, the types of user columns are
+/// inferred from the data instead.
+pub async fn fetch_json_schemas(
+    source: &impl Source,
+    cache: Option<&SchemaCache>,
+) -> DatabaseSchema {
+    if let Some(json_schemas) = cache.and_then(SchemaCache::json_schemas) {
+        return json_schemas;
+    }
+    match source.get_json_schemas().await {
+        Ok(json_schemas) => {
+            if let Some(cache) = cache {
+                cache.set_json_schemas(json_schemas.clone());
+            }
+            json_schemas
+        },
+        Err(error) => {
+            log_warning(&format!(
+                "Couldn’t fetch the JSON schemas of {source}, so the types of its columns will be \
+                 inferred from the data: {error}"
+            ));
+            DatabaseSchema::default()
+        },
+    }
+}
+
+/// The columns declared by the JSON schemas of the tables of a deployment.
+pub fn json_schema_columns(json_schemas: &DatabaseSchema) -> HashMap<TableName, Vec<FieldName>> {
+    json_schemas
+        .0
+        .iter()
+        .map(|(table_name, table_schema)| {
+            let column_names = match table_schema {
+                Schema::Object(SchemaObject {
+                    object: Some(table_object),
+                    ..
+                }) => table_object
+                    .properties
+                    .keys()
+                    .map(|column_name| FieldName(column_name.clone()))
+                    .collect(),
+                _ => vec![],
+            };
+            (table_name.clone(), column_names)
+        })
+        .collect()
+}
+
+/// The number of documents sampled to infer the schema of a table.
+const SAMPLE_SIZE: u32 = 100;
+
+/// Infers the schema of the tables whose JSON schema accepts any document
+/// (e.g. in deployments without a `schema.ts` file) from a sample of their
+/// first documents, so that their columns and types are declared rather than
+/// discovered by Fivetran from the data it receives. The columns sampled are
+/// added to `columns`.
+pub async fn sample_missing_schemas(
+    source: &impl Source,
+    columns: &mut HashMap<TableName, Vec<FieldName>>,
+    json_schemas: &mut DatabaseSchema,
+) {
+    // Snapshots can only be listed by table in the root component.
+    let table_names: Vec<String> = columns
+        .keys()
+        .filter(|table_name| split_component_table_name(&table_name.0).0.is_empty())
+        .filter(|table_name| !has_properties(json_schemas.0.get(*table_name)))
+        .map(|table_name| table_name.0.clone())
+        .collect();
+    for table_name in table_names {
+        let table_schema = match sample_table_schema(source, &table_name).await {
+            Ok(table_schema) => table_schema,
+            Err(error) => {
+                log_warning(&format!(
+                    "Couldn’t sample the documents of {table_name}, so the types of its columns \
+                     will be inferred from the data: {error}"
+                ));
+                continue;
+            },
+        };
+        if let Schema::Object(SchemaObject {
+            object: Some(ref table_object),
+            ..
+        }) = table_schema
+        {
+            let table_columns = columns.entry(TableName(table_name.clone())).or_default();
+            for column_name in table_object.properties.keys() {
+                if !table_columns.iter().any(|column| column.0 == *column_name) {
+                    table_columns.push(FieldName(column_name.clone()));
+                }
+            }
+        }
+        json_schemas.0.insert(TableName(table_name), table_schema);
+    }
+}
+
+/// Whether the JSON schema of a table declares fields.
+fn has_properties(table_schema: Option<&Schema>) -> bool {
+    matches!(
+        table_schema,
+        Some(Schema::Object(SchemaObject {
+            object: Some(table_object),
+            ..
+        })) if !table_object.properties.is_empty()
+    )
+}
+
+/// Infers the JSON schema of a table from its first documents. Fields with
+/// values of different types accept any value, and fields that are null in
+/// some documents are nullable.
+async fn sample_table_schema(source: &impl Source, table_name: &str) -> anyhow::Result<Schema> {
+    let documents = source
+        .list_snapshot(None, None, Some(table_name.to_string()), Some(SAMPLE_SIZE))
+        .await?
+        .values;
+
+    let mut field_schemas: BTreeMap<&String, (Vec<JsonValue>, bool)> = BTreeMap::new();
+    for (field_name, value) in documents.iter().flat_map(|document| &document.fields) {
+        if field_name.starts_with('_') && field_name != "_id" && field_name != "_creationTime" {
+            continue;
+        }
+        let (schemas, nullable) = field_schemas.entry(field_name).or_default();
+        match value_schema(value) {
+            None => *nullable = true,
+            Some(schema) if !schemas.contains(&schema) => schemas.push(schema),
+            Some(_) => {},
+        }
+    }
+
+    let properties: BTreeMap<&String, JsonValue> = field_schemas
+        .into_iter()
+        .map(|(field_name, (mut schemas, nullable))| {
+            let schema = match (schemas.len(), nullable) {
+                (0, _) => json!({ "type": "null" }),
+                (1, false) => schemas.remove(0),
+                (1, true) => json!({ "anyOf": [schemas.remove(0), { "type": "null" }] }),
+                _ => json!({}),
+            };
+            (field_name, schema)
+        })
+        .collect();
+    Ok(serde_json::from_value(json!({
+        "type": "object",
+        "properties": properties,
+    }))?)
+}
+
+/// The JSON schema of a sampled value, or `None` for null values. The schemas
+/// of objects list their fields, so that the values encoded by the export
+/// format (like `{"$integer": "…"}`) can be recognized.
+fn value_schema(value: &JsonValue) -> Option<JsonValue> {
+    let instance_type = match value {
+        JsonValue::Null => return None,
+        JsonValue::Bool(_) => "boolean",
+        JsonValue::Number(_) => "number",
+        JsonValue::String(_) => "string",
+        JsonValue::Array(_) => "array",
+        JsonValue::Object(fields) => {
+            let properties: serde_json::Map<String, JsonValue> = fields
+                .keys()
+                .map(|field_name| (field_name.clone(), json!({})))
+                .collect();
+            return Some(json!({ "type": "object", "properties": properties }));
+        },
+    };
+    Some(json!({ "type": instance_type }))
+}
+
+/// The data type of a user column according to the JSON schema of its table.
+/// Arrays and objects are declared as JSON, so that destinations supporting
+/// semi-structured data store them natively. Int64 and Bytes values are
+/// declared as longs and binary values. Columns without a schema, or of
+/// several types, have the “unspecified” data type, letting Fivetran infer
+/// their type from the data it receives.
+fn user_column_type(
+    table_schema: Option<&Schema>,
+    column_name: &str,
+    options: &SchemaOptions,
+) -> DataType {
+    column_schema(table_schema, column_name).map_or(DataType::Unspecified, |column_schema| {
+        schema_type(column_schema, options)
+    })
+}
+
+/// The JSON schema of a column, if the JSON schema of its table is known.
+fn column_schema<'a>(
+    table_schema: Option<&'a Schema>,
+    column_name: &str,
+) -> Option<&'a SchemaObject> {
+    let Some(Schema::Object(SchemaObject {
+        object: Some(table_object),
+        ..
+    })) = table_schema
+    else {
+        return None;
+    };
+    match table_object.properties.get(column_name) {
+        Some(Schema::Object(column_schema)) => Some(column_schema),
+        _ => None,
+    }
+}
+
+/// The data type of the elements of a column, if it is an array column that
+/// is synced to a child table.
+fn array_element_type(column_schema: &SchemaObject, options: &SchemaOptions) -> Option<DataType> {
+    let (array_schema, InstanceType::Array) = non_null_type(column_schema)? else {
+        return None;
+    };
+    let items = array_schema
+        .array
+        .as_ref()
+        .and_then(|array| array.items.as_ref());
+    Some(match items {
+        Some(SingleOrVec::Single(items)) => match **items {
+            Schema::Object(ref items) => schema_type(items, options),
+            Schema::Bool(_) => DataType::Unspecified,
+        },
+        _ => DataType::Unspecified,
+    })
+}
+
+/// The fields of the objects described by the JSON schema of a column, if it
+/// is an object column that can be flattened. Values encoded by the Convex
+/// export format and empty objects can’t be.
+fn object_properties(column_schema: &SchemaObject) -> Option<&schemars::Map<String, Schema>> {
+    let (object_schema, InstanceType::Object) = non_null_type(column_schema)? else {
+        return None;
+    };
+    if encoded_type(object_schema).is_some() {
+        return None;
+    }
+    object_schema
+        .object
+        .as_ref()
+        .map(|object| &object.properties)
+        .filter(|properties| !properties.is_empty())
+}
+
+/// Adds the columns of the fields of a flattened object column to `columns`,
+/// with their data type. Nested objects are flattened too, and keep their own
+/// column when they are nullable, for their null values.
+fn flattened_columns(
+    column_name: &str,
+    properties: &schemars::Map<String, Schema>,
+    options: &SchemaOptions,
+    columns: &mut Vec<(String, DataType)>,
+) {
+    for (field_name, field_schema) in properties {
+        let column_name = flattened_column(column_name, field_name);
+        let Schema::Object(field_schema) = field_schema else {
+            columns.push((column_name, DataType::Unspecified));
+            continue;
+        };
+        match object_properties(field_schema) {
+            Some(properties) => {
+                if accepts_null(field_schema) {
+                    columns.push((column_name.clone(), DataType::Json));
+                }
+                flattened_columns(&column_name, properties, options, columns);
+            },
+            None => columns.push((column_name, schema_type(field_schema, options))),
+        }
+    }
+}
+
+/// The data type of the values described by the JSON schema of a column, or
+/// `DataType::Unspecified` if the column can contain values of different
+/// types. Nullable columns have the type of their non-null values.
+fn schema_type(schema: &SchemaObject, options: &SchemaOptions) -> DataType {
+    let Some((schema, instance_type)) = non_null_type(schema) else {
+        return DataType::Unspecified;
+    };
+    match instance_type {
+        InstanceType::Null => DataType::Unspecified,
+        InstanceType::Boolean => DataType::Boolean,
+        InstanceType::Number => match options.non_finite_floats {
+            NonFiniteFloats::String => DataType::Unspecified,
+            NonFiniteFloats::Double | NonFiniteFloats::Null => DataType::Double,
+        },
+        // IDs never contain dates
+        InstanceType::String if !options.detect_dates || is_id(schema) => DataType::String,
+        InstanceType::String => DataType::Unspecified,
+        InstanceType::Array => DataType::Json,
+        InstanceType::Integer => DataType::Long,
+        InstanceType::Object => match encoded_type(schema) {
+            None => DataType::Json,
+            Some("$integer") => DataType::Long,
+            Some("$bytes") => DataType::Binary,
+            Some(_) => DataType::Unspecified,
+        },
+    }
+}
+
+/// The type of the non-null values described by a JSON schema, with the schema
+/// describing them, or `None` if they can have different types.
+fn non_null_type(schema: &SchemaObject) -> Option<(&SchemaObject, InstanceType)> {
+    if let Some(any_of) = schema
+        .subschemas
+        .as_ref()
+        .and_then(|subschemas| subschemas.any_of.as_ref())
+    {
+        let mut non_null_schemas = any_of.iter().filter(|schema| {
+            !matches!(
+                schema,
+                Schema::Object(SchemaObject {
+                    instance_type: Some(SingleOrVec::Single(instance_type)),
+                    ..
+                }) if **instance_type == InstanceType::Null
+            )
+        });
+        return match (non_null_schemas.next(), non_null_schemas.next()) {
+            (Some(Schema::Object(schema)), None) => non_null_type(schema),
+            _ => None,
+        };
+    }
+
+    match schema.instance_type {
+        Some(SingleOrVec::Single(ref instance_type)) => Some((schema, **instance_type)),
+        Some(SingleOrVec::Vec(ref instance_types)) => {
+            let mut non_null_types = instance_types
+                .iter()
+                .filter(|instance_type| **instance_type != InstanceType::Null);
+            match (non_null_types.next(), non_null_types.next()) {
+                (Some(instance_type), None) => Some((schema, *instance_type)),
+                _ => None,
+            }
+        },
+        None => None,
+    }
+}
+
+/// Whether the JSON schema describes document IDs, which Convex marks with a
+/// description like `Id(messages)`.
+fn is_id(schema: &SchemaObject) -> bool {
+    schema
+        .extensions
+        .get("$description")
+        .and_then(JsonValue::as_str)
+        .is_some_and(|description| description.starts_with("Id("))
+}
+
+/// If the schema of an object describes a value encoded by the Convex export
+/// format (e.g. `{"$integer": "…"}` for Int64 values) rather than a user
+/// object, the field encoding the value. User objects can’t have fields
+/// starting with `$`.
+fn encoded_type(schema: &SchemaObject) -> Option<&str> {
+    let object = schema.object.as_ref()?;
+    let mut field_names = object.properties.keys();
+    match (field_names.next(), field_names.next()) {
+        (Some(field_name), None) if field_name.starts_with('$') => Some(field_name),
+        _ => None,
+    }
+}
+
+/// Keeps the system columns and the first `max_fields` user columns, in
+/// alphabetical order, like [`crate::convert::to_fivetran_row`] does.
+fn limit_columns(column_names: Vec<FieldName>, max_fields: Option<usize>) -> Vec<FieldName> {
+    let Some(max_fields) = max_fields else {
+        return column_names;
+    };
+
+    let (system_columns, mut user_columns): (Vec<FieldName>, Vec<FieldName>) = column_names
+        .into_iter()
+        .partition(|column_name| column_name.0.starts_with('_'));
+    user_columns.sort_by(|a, b| a.0.cmp(&b.0));
+    user_columns.truncate(max_fields);
+    system_columns.into_iter().chain(user_columns).collect()
+}
+
+/// Renders tables as a catalog in a generic JSON format, so that tools other
+/// than Fivetran can inspect the schema seen by the connector. Columns whose
+/// type is inferred by the destination from the data have a `null` type.
+pub fn catalog(tables: &[Table], json_schemas: &DatabaseSchema) -> JsonValue {
+    let mut tables: Vec<&Table> = tables.iter().collect();
+    tables.sort_by(|a, b| a.name.cmp(&b.name));
+
+    json!({
+        "connector": "convex",
+        "connectorVersion": env!("CARGO_PKG_VERSION"),
+        "tables": tables
+            .into_iter()
+            .map(|table| {
+                let table_schema = json_schemas.0.get(&TableName(table.name.clone()));
+                json!({
+                    "name": table.name,
+                    "primaryKey": table
+                        .columns
+                        .iter()
+                        .filter(|column| column.primary_key)
+                        .map(|column| column.name.as_str())
+                        .collect::<Vec<_>>(),
+                    "columns": table
+                        .columns
+                        .iter()
+                        .map(|column| {
+                            json!({
+                                "name": column.name,
+                                "type": catalog_type(column.r#type),
+                                "primaryKey": column.primary_key,
+                                "nullable": !is_not_null(table_schema, &column.name),
+                            })
+                        })
+                        .collect::<Vec<_>>(),
+                })
+            })
+            .collect::<Vec<_>>(),
+    })
+}
+
+/// Whether a column never contains NULL values: the system columns, and the
+/// fields that the JSON schema of their table requires and that don’t accept
+/// null. Fivetran schemas can’t declare this constraint, so it is only exposed
+/// in the catalog.
+fn is_not_null(table_schema: Option<&Schema>, column_name: &str) -> bool {
+    if column_name == "_id" || column_name == "_creationTime" {
+        return true;
+    }
+    let Some(Schema::Object(SchemaObject {
+        object: Some(table_object),
+        ..
+    })) = table_schema
+    else {
+        return false;
+    };
+    table_object.required.contains(column_name)
+        && matches!(
+            table_object.properties.get(column_name),
+            Some(Schema::Object(column_schema)) if !accepts_null(column_schema)
+        )
+}
+
+/// Whether the JSON schema accepts null values.
+fn accepts_null(schema: &SchemaObject) -> bool {
+    if let Some(any_of) = schema
+        .subschemas
+        .as_ref()
+        .and_then(|subschemas| subschemas.any_of.as_ref())
+    {
+        return any_of.iter().any(|schema| match schema {
+            Schema::Bool(accepts_anything) => *accepts_anything,
+            Schema::Object(schema) => accepts_null(schema),
+        });
+    }
+    match schema.instance_type {
+        Some(SingleOrVec::Single(ref instance_type)) => **instance_type == InstanceType::Null,
+        Some(SingleOrVec::Vec(ref instance_types)) => instance_types.contains(&InstanceType::Null),
+        None => true,
+    }
+}
+
+fn catalog_type(data_type: i32) -> Option<String> {
+    match DataType::try_from(data_type) {
+        Ok(DataType::Unspecified) | Err(_) => None,
+        Ok(data_type) => Some(data_type.as_str_name().to_lowercase()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use maplit::hashmap;
+
+    use super::*;
+    use crate::{
+        convert::DecimalType,
+        metadata::METADATA_TABLE_NAME,
+        table_stats::TABLE_STATS_TABLE_NAME,
+        testing::FakeSource,
+    };
+
+    #[test]
+    fn exposes_system_columns_with_their_types() {
+        let tables = fivetran_tables(
+            hashmap! {
+                TableName::from("messages") => vec![
+                    FieldName("_id".to_string()),
+                    FieldName("_creationTime".to_string()),
+                    FieldName("body".to_string()),
+                ],
+            },
+            &DatabaseSchema::default(),
+            &SchemaOptions::default(),
+        );
+
+        assert_eq!(tables.len(), 2);
+        let messages = tables.iter().find(|t| t.name == "messages").unwrap();
+        assert_eq!(messages.columns[0].r#type, DataType::String as i32);
+        assert!(messages.columns[0].primary_key);
+        assert_eq!(messages.columns[1].r#type, DataType::UtcDatetime as i32);
+        assert_eq!(messages.columns[2].r#type, DataType::Unspecified as i32);
+        assert!(tables.iter().any(|t| t.name == METADATA_TABLE_NAME));
+    }
+
+    #[test]
+    fn declares_nested_columns_as_json() -> anyhow::Result<()> {
+        let json_schemas: DatabaseSchema = serde_json::from_value(json!({
+            "messages": {
+                "type": "object",
+                "properties": {
+                    "_id": { "type": "string" },
+                    "body": { "type": "string" },
+                    "tags": { "type": "array", "items": { "type": "string" } },
+                    "author": {
+                        "type": "object",
+                        "properties": { "name": { "type": "string" } },
+                    },
+                    "count": {
+                        "type": "object",
+                        "properties": { "$integer": { "type": "string" } },
+                    },
+                    "avatar": {
+                        "type": "object",
+                        "properties": { "$bytes": { "type": "string" } },
+                    },
+                },
+            },
+            "untyped": true,
+        }))?;
+        let columns = |table_name: &str| {
+            (
+                TableName::from(table_name),
+                ["_id", "body", "tags", "author", "count", "avatar"]
+                    .into_iter()
+                    .map(|column_name| FieldName(column_name.to_string()))
+                    .collect(),
+            )
+        };
+
+        let tables = fivetran_tables(
+            HashMap::from([columns("messages"), columns("untyped")]),
+            &json_schemas,
+            &SchemaOptions::default(),
+        );
+
+        let column_types = |table_name: &str| -> Vec<(String, i32)> {
+            tables
+                .iter()
+                .find(|t| t.name == table_name)
+                .unwrap()
+                .columns
+                .iter()
+                .map(|column| (column.name.clone(), column.r#type))
+                .collect()
+        };
+        let unspecified = DataType::Unspecified as i32;
+        assert_eq!(
+            column_types("messages"),
+            vec![
+                ("_id".to_string(), DataType::String as i32),
+                ("body".to_string(), DataType::String as i32),
+                ("tags".to_string(), DataType::Json as i32),
+                ("author".to_string(), DataType::Json as i32),
+                ("count".to_string(), DataType::Long as i32),
+                ("avatar".to_string(), DataType::Binary as i32),
+            ]
+        );
+        assert_eq!(
+            column_types("untyped"),
+            vec![
+                ("_id".to_string(), DataType::String as i32),
+                ("body".to_string(), unspecified),
+                ("tags".to_string(), unspecified),
+                ("author".to_string(), unspecified),
+                ("count".to_string(), unspecified),
+                ("avatar".to_string(), unspecified),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn declares_the_types_of_the_json_schema() -> anyhow::Result<()> {
+        let json_schemas: DatabaseSchema = serde_json::from_value(json!({
+            "messages": {
+                "type": "object",
+                "properties": {
+                    "body": { "type": "string" },
+                    "author": { "type": "string", "$description": "Id(users)" },
+                    "score": { "type": "number" },
+                    "pinned": { "type": "boolean" },
+                    "editedBy": {
+                        "anyOf": [
+                            { "type": "string", "$description": "Id(users)" },
+                            { "type": "null" },
+                        ],
+                    },
+                    "rating": { "type": ["number", "null"] },
+                    "value": { "anyOf": [{ "type": "number" }, { "type": "string" }] },
+                },
+            },
+        }))?;
+        let column_types = |options: &SchemaOptions| -> Vec<(String, i32)> {
+            let tables = fivetran_tables(
+                hashmap! {
+                    TableName::from("messages") => [
+                        "body", "author", "score", "pinned", "editedBy", "rating", "value",
+                    ]
+                    .into_iter()
+                    .map(|column_name| FieldName(column_name.to_string()))
+                    .collect(),
+                },
+                &json_schemas,
+                options,
+            );
+            let messages = tables.into_iter().find(|t| t.name == "messages").unwrap();
+            messages
+                .columns
+                .into_iter()
+                .map(|column| (column.name, column.r#type))
+                .collect()
+        };
+
+        assert_eq!(
+            column_types(&SchemaOptions::default()),
+            vec![
+                ("body".to_string(), DataType::String as i32),
+                ("author".to_string(), DataType::String as i32),
+                ("score".to_string(), DataType::Double as i32),
+                ("pinned".to_string(), DataType::Boolean as i32),
+                ("editedBy".to_string(), DataType::String as i32),
+                ("rating".to_string(), DataType::Double as i32),
+                ("value".to_string(), DataType::Unspecified as i32),
+            ]
+        );
+
+        // Strings can be synced as dates, and NaN as a string
+        let unspecified = DataType::Unspecified as i32;
+        assert_eq!(
+            column_types(&SchemaOptions {
+                detect_dates: true,
+                non_finite_floats: NonFiniteFloats::String,
+                ..SchemaOptions::default()
+            }),
+            vec![
+                ("body".to_string(), unspecified),
+                ("author".to_string(), DataType::String as i32),
+                ("score".to_string(), unspecified),
+                ("pinned".to_string(), DataType::Boolean as i32),
+                ("editedBy".to_string(), DataType::String as i32),
+                ("rating".to_string(), unspecified),
+                ("value".to_string(), unspecified),
+            ]
+        );
+
+        // Hashed columns are strings, whatever the type of their values
+        assert_eq!(
+            column_types(&SchemaOptions {
+                hashed_columns: BTreeMap::from([(
+                    "messages".to_string(),
+                    BTreeSet::from(["score".to_string(), "pinned".to_string()]),
+                )]),
+                ..SchemaOptions::default()
+            })[2..4],
+            [
+                ("score".to_string(), DataType::String as i32),
+                ("pinned".to_string(), DataType::String as i32),
+            ]
+        );
+
+        // Masked columns are strings when they are masked by a string
+        let masked = |mask: Option<&str>| SchemaOptions {
+            column_masking: ColumnMasking {
+                columns: BTreeMap::from([(
+                    "messages".to_string(),
+                    BTreeSet::from(["pinned".to_string()]),
+                )]),
+                mask: mask.map(String::from),
+            },
+            ..SchemaOptions::default()
+        };
+        assert_eq!(
+            column_types(&masked(Some("***")))[3],
+            ("pinned".to_string(), DataType::String as i32)
+        );
+        assert_eq!(
+            column_types(&masked(None))[3],
+            ("pinned".to_string(), DataType::Boolean as i32)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn declares_child_tables_for_array_columns() -> anyhow::Result<()> {
+        let json_schemas: DatabaseSchema = serde_json::from_value(json!({
+            "people": {
+                "type": "object",
+                "properties": {
+                    "name": { "type": "string" },
+                    "scores": { "type": "array", "items": { "type": "number" } },
+                    "tags": {
+                        "anyOf": [
+                            { "type": "array", "items": { "type": "string" } },
+                            { "type": "null" },
+                        ],
+                    },
+                },
+            },
+        }))?;
+        let tables = fivetran_tables(
+            hashmap! {
+                TableName::from("people") => ["_id", "name", "scores", "tags"]
+                    .into_iter()
+                    .map(|column_name| FieldName(column_name.to_string()))
+                    .collect(),
+            },
+            &json_schemas,
+            &SchemaOptions {
+                child_tables: true,
+                ..SchemaOptions::default()
+            },
+        );
+
+        let columns = |table_name: &str| -> Vec<(String, i32, bool)> {
+            tables
+                .iter()
+                .find(|t| t.name == table_name)
+                .unwrap()
+                .columns
+                .iter()
+                .map(|column| (column.name.clone(), column.r#type, column.primary_key))
+                .collect()
+        };
+        assert_eq!(
+            columns("people"),
+            vec![
+                ("_id".to_string(), DataType::String as i32, true),
+                ("name".to_string(), DataType::String as i32, false),
+                // Null values of nullable arrays stay in the row of the document
+                ("tags".to_string(), DataType::Json as i32, false),
+                ("scores__length".to_string(), DataType::Long as i32, false),
+                ("tags__length".to_string(), DataType::Long as i32, false),
+            ]
+        );
+        assert_eq!(
+            columns("people__scores"),
+            vec![
+                ("_parent_id".to_string(), DataType::String as i32, true),
+                ("_index".to_string(), DataType::Long as i32, true),
+                ("value".to_string(), DataType::Double as i32, false),
+            ]
+        );
+        assert_eq!(
+            columns("people__tags"),
+            vec![
+                ("_parent_id".to_string(), DataType::String as i32, true),
+                ("_index".to_string(), DataType::Long as i32, true),
+                ("value".to_string(), DataType::String as i32, false),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn declares_the_fields_of_flattened_object_columns() -> anyhow::Result<()> {
+        let json_schemas: DatabaseSchema = serde_json::from_value(json!({
+            "people": {
+                "type": "object",
+                "properties": {
+                    "address": {
+                        "type": "object",
+                        "properties": {
+                            "city": {
+                                "anyOf": [
+                                    {
+                                        "type": "object",
+                                        "properties": { "name": { "type": "string" } },
+                                    },
+                                    { "type": "null" },
+                                ],
+                            },
+                            "zip": { "type": "string" },
+                        },
+                    },
+                    "count": {
+                        "type": "object",
+                        "properties": { "$integer": { "type": "string" } },
+                    },
+                },
+            },
+        }))?;
+        let tables = fivetran_tables(
+            hashmap! {
+                TableName::from("people") => ["_id", "address", "count"]
+                    .into_iter()
+                    .map(|column_name| FieldName(column_name.to_string()))
+                    .collect(),
+            },
+            &json_schemas,
+            &SchemaOptions {
+                nested_objects: NestedObjects::Columns,
+                ..SchemaOptions::default()
+            },
+        );
+
+        let people = tables.iter().find(|t| t.name == "people").unwrap();
+        assert_eq!(
+            people
+                .columns
+                .iter()
+                .map(|column| (column.name.as_str(), column.r#type))
+                .collect::<Vec<_>>(),
+            vec![
+                ("_id", DataType::String as i32),
+                // Encoded values aren’t objects
+                ("count", DataType::Long as i32),
+                // Null values of nullable objects stay in their own column
+                ("address__city", DataType::Json as i32),
+                ("address__city__name", DataType::String as i32),
+                ("address__zip", DataType::String as i32),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn samples_the_tables_without_schema() -> anyhow::Result<()> {
+        let mut source = FakeSource::default();
+        source.insert(
+            "messages",
+            hashmap! {
+                "body".to_string() => json!("Hello"),
+                "count".to_string() => json!({ "$integer": "KgAAAAAAAAA=" }),
+                "rating".to_string() => json!(null),
+            },
+        );
+        source.insert(
+            "messages",
+            hashmap! {
+                "body".to_string() => json!(42),
+                "rating".to_string() => json!(4.5),
+            },
+        );
+        source.insert("users", hashmap! { "name".to_string() => json!("Ada") });
+        let mut columns = hashmap! {
+            TableName::from("messages") => vec![
+                FieldName("_id".to_string()),
+                FieldName("_creationTime".to_string()),
+            ],
+            TableName::from("users") => vec![FieldName("_id".to_string())],
+        };
+        let mut json_schemas: DatabaseSchema = serde_json::from_value(json!({
+            "messages": true,
+            "users": {
+                "type": "object",
+                "properties": { "name": { "type": ["string", "null"] } },
+            },
+        }))?;
+
+        sample_missing_schemas(&source, &mut columns, &mut json_schemas).await;
+        let tables = fivetran_tables(columns, &json_schemas, &SchemaOptions::default());
+
+        let column_types = |table_name: &str| -> Vec<(String, i32)> {
+            tables
+                .iter()
+                .find(|t| t.name == table_name)
+                .unwrap()
+                .columns
+                .iter()
+                .map(|column| (column.name.clone(), column.r#type))
+                .collect()
+        };
+        assert_eq!(
+            column_types("messages"),
+            vec![
+                ("_id".to_string(), DataType::String as i32),
+                ("_creationTime".to_string(), DataType::UtcDatetime as i32),
+                ("body".to_string(), DataType::Unspecified as i32),
+                ("count".to_string(), DataType::Long as i32),
+                ("rating".to_string(), DataType::Double as i32),
+            ]
+        );
+        // Tables with a schema aren’t sampled
+        assert_eq!(
+            column_types("users"),
+            vec![("_id".to_string(), DataType::String as i32)]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn renders_the_catalog() -> anyhow::Result<()> {
+        let json_schemas: DatabaseSchema = serde_json::from_value(json!({
+            "messages": {
+                "type": "object",
+                "properties": {
+                    "body": { "type": "string" },
+                    "note": { "type": "string" },
+                    "editedBy": { "type": ["string", "null"] },
+                },
+                "required": ["body", "editedBy"],
+            },
+        }))?;
+        let tables = fivetran_tables(
+            hashmap! {
+                TableName::from("messages") => vec![
+                    FieldName("_id".to_string()),
+                    FieldName("body".to_string()),
+                    FieldName("note".to_string()),
+                    FieldName("editedBy".to_string()),
+                    FieldName("tags".to_string()),
+                ],
+            },
+            &json_schemas,
+            &SchemaOptions::default(),
+        );
+
+        let catalog = catalog(&tables, &json_schemas);
+
+        assert_eq!(catalog["tables"][0]["name"], json!(METADATA_TABLE_NAME));
+        assert_eq!(
+            catalog["tables"][1],
+            json!({
+                "name": "messages",
+                "primaryKey": ["_id"],
+                "columns": [
+                    { "name": "_id", "type": "string", "primaryKey": true, "nullable": false },
+                    { "name": "body", "type": "string", "primaryKey": false, "nullable": false },
+                    { "name": "note", "type": "string", "primaryKey": false, "nullable": true },
+                    {
+                        "name": "editedBy",
+                        "type": "string",
+                        "primaryKey": false,
+                        "nullable": true,
+                    },
+                    { "name": "tags", "type": null, "primaryKey": false, "nullable": true },
+                ],
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn declares_the_overflow_column_when_fields_are_limited() {
+        let tables = fivetran_tables(
+            hashmap! {
+                TableName::from("messages") => vec![
+                    FieldName("_id".to_string()),
+                    FieldName("_creationTime".to_string()),
+                    FieldName("c".to_string()),
+                    FieldName("a".to_string()),
+                    FieldName("b".to_string()),
+                ],
+            },
+            &DatabaseSchema::default(),
+            &SchemaOptions {
+                max_fields: Some(2),
+                ..SchemaOptions::default()
+            },
+        );
+
+        let messages = tables.iter().find(|t| t.name == "messages").unwrap();
+        let columns: Vec<(&str, i32)> = messages
+            .columns
+            .iter()
+            .map(|column| (column.name.as_str(), column.r#type))
+            .collect();
+        assert_eq!(
+            columns,
+            vec![
+                ("_id", DataType::String as i32),
+                ("_creationTime", DataType::UtcDatetime as i32),
+                ("a", DataType::Unspecified as i32),
+                ("b", DataType::Unspecified as i32),
+                (OVERFLOW_COLUMN, DataType::Json as i32),
+            ]
+        );
+    }
+
+    #[test]
+    fn declares_presence_columns_when_requested() {
+        let tables = fivetran_tables(
+            hashmap! {
+                TableName::from("messages") => vec![
+                    FieldName("_id".to_string()),
+                    FieldName("body".to_string()),
+                ],
+            },
+            &DatabaseSchema::default(),
+            &SchemaOptions {
+                presence_columns: true,
+                ..SchemaOptions::default()
+            },
+        );
+
+        let messages = tables.iter().find(|t| t.name == "messages").unwrap();
+        let columns: Vec<(&str, i32)> = messages
+            .columns
+            .iter()
+            .map(|column| (column.name.as_str(), column.r#type))
+            .collect();
+        assert_eq!(
+            columns,
+            vec![
+                ("_id", DataType::String as i32),
+                ("body", DataType::Unspecified as i32),
+                ("body__present", DataType::Boolean as i32),
+            ]
+        );
+    }
+
+    #[test]
+    fn excludes_system_columns_when_requested() {
+        let tables = fivetran_tables(
+            hashmap! {
+                TableName::from("messages") => vec![
+                    FieldName("_id".to_string()),
+                    FieldName("_creationTime".to_string()),
+                    FieldName("body".to_string()),
+                ],
+            },
+            &DatabaseSchema::default(),
+            &SchemaOptions {
+                exclude_system_columns: true,
+                ..SchemaOptions::default()
+            },
+        );
+
+        let messages = tables.iter().find(|t| t.name == "messages").unwrap();
+        let column_names: Vec<&str> = messages
+            .columns
+            .iter()
+            .map(|column| column.name.as_str())
+            .collect();
+        assert_eq!(column_names, vec!["_id", "body"]);
+    }
+
+    #[test]
+    fn declares_the_timestamp_column_when_requested() {
+        let tables = fivetran_tables(
+            hashmap! {
+                TableName::from("messages") => vec![FieldName("_id".to_string())],
+            },
+            &DatabaseSchema::default(),
+            &SchemaOptions {
+                timestamp_column: true,
+                ..SchemaOptions::default()
+            },
+        );
+
+        let messages = tables.iter().find(|t| t.name == "messages").unwrap();
+        let timestamp_column = messages
+            .columns
+            .iter()
+            .find(|column| column.name == "_convex_ts")
+            .expect("Missing timestamp column");
+        assert_eq!(timestamp_column.r#type, DataType::Long as i32);
+        assert!(!timestamp_column.primary_key);
+    }
+
+    #[test]
+    fn declares_decimal_columns() {
+        let tables = fivetran_tables(
+            hashmap! {
+                TableName::from("orders") => vec![
+                    FieldName("_id".to_string()),
+                    FieldName("total".to_string()),
+                    FieldName("note".to_string()),
+                ],
+            },
+            &DatabaseSchema::default(),
+            &SchemaOptions {
+                decimal_columns: [(
+                    "orders".to_string(),
+                    [(
+                        "total".to_string(),
+                        DecimalType {
+                            precision: 10,
+                            scale: 2,
+                        },
+                    )]
+                    .into(),
+                )]
+                .into(),
+                ..SchemaOptions::default()
+            },
+        );
+
+        let orders = tables.iter().find(|t| t.name == "orders").unwrap();
+        assert_eq!(orders.columns[1].r#type, DataType::Decimal as i32);
+        assert_eq!(
+            orders.columns[1].decimal,
+            Some(DecimalParams {
+                precision: 10,
+                scale: 2,
+            })
+        );
+        assert_eq!(orders.columns[2].r#type, DataType::Unspecified as i32);
+        assert_eq!(orders.columns[2].decimal, None);
+    }
+
+    #[test]
+    fn declares_the_forced_types_of_columns() {
+        let tables = fivetran_tables(
+            hashmap! {
+                TableName::from("orders") => vec![
+                    FieldName("_id".to_string()),
+                    FieldName("count".to_string()),
+                    FieldName("note".to_string()),
+                ],
+            },
+            &DatabaseSchema::default(),
+            &SchemaOptions {
+                column_types: [(
+                    "orders".to_string(),
+                    [
+                        ("_id".to_string(), DataType::Long),
+                        ("count".to_string(), DataType::Long),
+                    ]
+                    .into(),
+                )]
+                .into(),
+                ..SchemaOptions::default()
+            },
+        );
+
+        let orders = tables.iter().find(|t| t.name == "orders").unwrap();
+        assert_eq!(orders.columns[0].r#type, DataType::String as i32);
+        assert_eq!(orders.columns[1].r#type, DataType::Long as i32);
+        assert_eq!(orders.columns[2].r#type, DataType::Unspecified as i32);
+    }
+
+    #[test]
+    fn replaces_stats_only_tables_with_the_table_stats_table() {
+        let tables = fivetran_tables(
+            hashmap! {
+                TableName::from("messages") => vec![FieldName("_id".to_string())],
+                TableName::from("events") => vec![FieldName("_id".to_string())],
+            },
+            &DatabaseSchema::default(),
+            &SchemaOptions {
+                stats_only_tables: BTreeSet::from(["events".to_string()]),
+                ..SchemaOptions::default()
+            },
+        );
+
+        let mut table_names: Vec<&str> = tables.iter().map(|t| t.name.as_str()).collect();
+        table_names.sort();
+        assert_eq!(
+            table_names,
+            vec![METADATA_TABLE_NAME, TABLE_STATS_TABLE_NAME, "messages"]
+        );
+    }
+
+    #[test]
+    fn prefixes_every_table() {
+        let tables = fivetran_tables(
+            hashmap! {
+                TableName::from("messages") => vec![FieldName("_id".to_string())],
+            },
+            &DatabaseSchema::default(),
+            &SchemaOptions {
+                table_prefix: Some("prod_".to_string()),
+                ..SchemaOptions::default()
+            },
+        );
+
+        let mut table_names: Vec<String> = tables.into_iter().map(|t| t.name).collect();
+        table_names.sort();
+        assert_eq!(
+            table_names,
+            vec![
+                format!("prod_{METADATA_TABLE_NAME}"),
+                "prod_messages".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn groups_the_tables_of_components_by_schema() {
+        let tables = fivetran_tables(
+            hashmap! {
+                TableName::from("jobs") => vec![FieldName("_id".to_string())],
+                TableName::from("workpool/jobs") => vec![FieldName("_id".to_string())],
+                TableName::from("aggregate/btree/nodes") => vec![FieldName("_id".to_string())],
+            },
+            &DatabaseSchema::default(),
+            &SchemaOptions {
+                table_prefix: Some("prod_".to_string()),
+                ..SchemaOptions::default()
+            },
+        );
+
+        let schemas: Vec<(String, BTreeSet<String>)> =
+            component_schemas(tables, ROOT_COMPONENT_SCHEMA_NAME)
+                .schemas
+                .into_iter()
+                .map(|schema| {
+                    let table_names = schema.tables.into_iter().map(|t| t.name).collect();
+                    (schema.name, table_names)
+                })
+                .collect();
+        assert_eq!(
+            schemas,
+            vec![
+                (
+                    "aggregate_btree".to_string(),
+                    BTreeSet::from(["prod_nodes".to_string()])
+                ),
+                (
+                    "app".to_string(),
+                    BTreeSet::from([
+                        format!("prod_{METADATA_TABLE_NAME}"),
+                        "prod_jobs".to_string()
+                    ])
+                ),
+                (
+                    "workpool".to_string(),
+                    BTreeSet::from(["prod_jobs".to_string()])
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn renames_tables_differing_by_case() {
+        assert_eq!(
+            table_renames(["Users", "users", "USERS", "Users_2", "messages"]),
+            BTreeMap::from([
+                ("USERS".to_string(), "USERS_3".to_string()),
+                ("Users".to_string(), "Users_4".to_string()),
+            ])
+        );
+
+        let table_renames = table_renames(["Users", "users"]);
+        assert_eq!(destination_table_name(&table_renames, "users"), "users");
+        assert_eq!(destination_table_name(&table_renames, "Users"), "Users_2");
+        assert_eq!(
+            destination_table_name(&table_renames, "Users__tags"),
+            "Users_2__tags"
+        );
+        assert_eq!(
+            destination_table_name(&table_renames, "UsersArchive"),
+            "UsersArchive"
+        );
+
+        let tables = fivetran_tables(
+            hashmap! {
+                TableName::from("Users") => vec![FieldName("_id".to_string())],
+                TableName::from("users") => vec![FieldName("_id".to_string())],
+            },
+            &DatabaseSchema::default(),
+            &SchemaOptions::default(),
+        );
+        let mut table_names: Vec<String> = tables.into_iter().map(|t| t.name).collect();
+        table_names.sort();
+        assert_eq!(
+            table_names,
+            vec![
+                "Users_2".to_string(),
+                METADATA_TABLE_NAME.to_string(),
+                "users".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn sanitizes_column_names() {
+        let tables = fivetran_tables(
+            hashmap! {
+                TableName::from("users") => ["_id", "createdAt", "user_name", "userName"]
+                    .into_iter()
+                    .map(|column_name| FieldName(column_name.to_string()))
+                    .collect(),
+            },
+            &DatabaseSchema::default(),
+            &SchemaOptions {
+                presence_columns: true,
+                sanitize_column_names: true,
+                ..SchemaOptions::default()
+            },
+        );
+
+        let users = tables.iter().find(|t| t.name == "users").unwrap();
+        let column_names: BTreeSet<&str> = users
+            .columns
+            .iter()
+            .map(|column| column.name.as_str())
+            .collect();
+        assert_eq!(
+            column_names,
+            BTreeSet::from([
+                "_id",
+                "created_at",
+                "created_at__present",
+                "user_name",
+                "user_name__present",
+                "user_name__present_2",
+                "user_name_2",
+            ])
+        );
+    }
+}
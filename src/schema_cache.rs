@@ -0,0 +1,251 @@
+use std::{
+    collections::{
+        HashMap,
+        HashSet,
+    },
+    sync::{
+        Arc,
+        Mutex,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use url::Url;
+
+use crate::convex_api::{
+    DatabaseSchema,
+    FieldName,
+    TableName,
+};
+
+/// How long the JSON schemas fetched from a deployment are reused.
+const JSON_SCHEMAS_TTL: Duration = Duration::from_secs(60);
+
+/// The columns of a deployment known by the connector, kept across `update`
+/// calls so that columns appearing between two Fivetran schema refreshes can
+/// be detected.
+#[derive(Debug, Clone, Default)]
+pub struct SchemaCache {
+    /// The known columns of each table, or `None` if the cache was never
+    /// filled.
+    tables: Arc<Mutex<Option<HashMap<String, HashSet<String>>>>>,
+
+    /// The JSON schemas last fetched from the deployment, and when, so that
+    /// Fivetran polling the schema frequently doesn’t fetch them every time.
+    json_schemas: Arc<Mutex<Option<(Instant, DatabaseSchema)>>>,
+}
+
+impl SchemaCache {
+    /// The JSON schemas of the deployment, if they were fetched recently.
+    pub fn json_schemas(&self) -> Option<DatabaseSchema> {
+        self.json_schemas_at(Instant::now())
+    }
+
+    fn json_schemas_at(&self, now: Instant) -> Option<DatabaseSchema> {
+        let cached = self.json_schemas.lock().unwrap();
+        let (fetched_at, json_schemas) = cached.as_ref()?;
+        (now.saturating_duration_since(*fetched_at) < JSON_SCHEMAS_TTL)
+            .then(|| json_schemas.clone())
+    }
+
+    /// Stores the JSON schemas just fetched from the deployment.
+    pub fn set_json_schemas(&self, json_schemas: DatabaseSchema) {
+        *self.json_schemas.lock().unwrap() = Some((Instant::now(), json_schemas));
+    }
+
+    /// Forgets the JSON schemas of the deployment, e.g. after its schema
+    /// changed, so that they are fetched again on the next schema request.
+    pub fn invalidate_json_schemas(&self) {
+        *self.json_schemas.lock().unwrap() = None;
+    }
+
+    /// Returns true if all the given columns of the given table are known.
+    pub fn contains<'a>(&self, table: &str, mut columns: impl Iterator<Item = &'a String>) -> bool {
+        let tables = self.tables.lock().unwrap();
+        let Some(ref tables) = *tables else {
+            return false;
+        };
+        let Some(known_columns) = tables.get(table) else {
+            return false;
+        };
+        columns.all(|column| known_columns.contains(column))
+    }
+
+    /// The known columns of the given table, in no particular order.
+    pub fn columns(&self, table: &str) -> Vec<String> {
+        let tables = self.tables.lock().unwrap();
+        tables
+            .as_ref()
+            .and_then(|tables| tables.get(table))
+            .map(|columns| columns.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns true if the cache was ever filled.
+    pub fn is_filled(&self) -> bool {
+        self.tables.lock().unwrap().is_some()
+    }
+
+    /// Replaces the contents of the cache with a fresh copy of the deployment
+    /// schema. Returns the columns that were not known before, by table. If
+    /// the cache was never filled before, no column is considered new.
+    pub fn refresh(
+        &self,
+        new_tables: HashMap<TableName, Vec<FieldName>>,
+    ) -> Vec<(String, Vec<String>)> {
+        let new_tables: HashMap<String, HashSet<String>> = new_tables
+            .into_iter()
+            .map(|(table_name, columns)| {
+                (
+                    table_name.0,
+                    columns.into_iter().map(|column| column.0).collect(),
+                )
+            })
+            .collect();
+
+        let mut tables = self.tables.lock().unwrap();
+        let mut discovered: Vec<(String, Vec<String>)> = match *tables {
+            None => vec![],
+            Some(ref old_tables) => new_tables
+                .iter()
+                .filter_map(|(table_name, columns)| {
+                    let mut new_columns: Vec<String> = columns
+                        .iter()
+                        .filter(|column| {
+                            !old_tables
+                                .get(table_name)
+                                .is_some_and(|old_columns| old_columns.contains(*column))
+                        })
+                        .cloned()
+                        .collect();
+                    new_columns.sort();
+                    (!new_columns.is_empty()).then(|| (table_name.clone(), new_columns))
+                })
+                .collect(),
+        };
+        discovered.sort();
+
+        // Keep the columns we already know about in case the schema returned by
+        // the deployment lags behind the documents we have seen.
+        let mut merged = tables.take().unwrap_or_default();
+        for (table_name, columns) in new_tables {
+            merged.entry(table_name).or_default().extend(columns);
+        }
+        *tables = Some(merged);
+
+        discovered
+    }
+
+    /// Marks the given columns as known.
+    pub fn insert<'a>(&self, table: &str, columns: impl Iterator<Item = &'a String>) {
+        let mut tables = self.tables.lock().unwrap();
+        tables
+            .get_or_insert_with(HashMap::new)
+            .entry(table.to_string())
+            .or_default()
+            .extend(columns.cloned());
+    }
+}
+
+/// The schema caches of every deployment the connector has synced from.
+#[derive(Debug, Default)]
+pub struct SchemaCaches(Mutex<HashMap<Url, SchemaCache>>);
+
+impl SchemaCaches {
+    pub fn for_deployment(&self, deploy_url: &Url) -> SchemaCache {
+        self.0
+            .lock()
+            .unwrap()
+            .entry(deploy_url.clone())
+            .or_default()
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use maplit::hashmap;
+
+    use super::*;
+
+    fn schema(tables: HashMap<&str, Vec<&str>>) -> HashMap<TableName, Vec<FieldName>> {
+        tables
+            .into_iter()
+            .map(|(table_name, columns)| {
+                (
+                    TableName::from(table_name),
+                    columns
+                        .into_iter()
+                        .map(|column| FieldName(column.to_string()))
+                        .collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn first_refresh_discovers_nothing() {
+        let cache = SchemaCache::default();
+        assert!(!cache.contains("messages", [].iter()));
+
+        let discovered = cache.refresh(schema(hashmap! {
+            "messages" => vec!["_id", "_creationTime", "body"],
+        }));
+
+        assert_eq!(discovered, vec![]);
+        assert!(cache.contains("messages", ["_id".to_string(), "body".to_string()].iter()));
+    }
+
+    #[test]
+    fn refresh_discovers_new_tables_and_columns() {
+        let cache = SchemaCache::default();
+        cache.refresh(schema(hashmap! {
+            "messages" => vec!["_id", "_creationTime", "body"],
+        }));
+
+        let discovered = cache.refresh(schema(hashmap! {
+            "messages" => vec!["_id", "_creationTime", "body", "author"],
+            "users" => vec!["_id", "_creationTime"],
+        }));
+
+        assert_eq!(
+            discovered,
+            vec![
+                ("messages".to_string(), vec!["author".to_string()]),
+                (
+                    "users".to_string(),
+                    vec!["_creationTime".to_string(), "_id".to_string()]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn reuses_json_schemas_until_they_expire_or_are_invalidated() {
+        let cache = SchemaCache::default();
+        assert!(cache.json_schemas().is_none());
+
+        cache.set_json_schemas(DatabaseSchema::default());
+        assert!(cache.json_schemas().is_some());
+        assert!(cache
+            .json_schemas_at(Instant::now() + JSON_SCHEMAS_TTL)
+            .is_none());
+
+        cache.invalidate_json_schemas();
+        assert!(cache.json_schemas().is_none());
+    }
+
+    #[test]
+    fn refresh_keeps_inserted_columns() {
+        let cache = SchemaCache::default();
+        cache.insert("messages", ["author".to_string()].iter());
+        cache.refresh(schema(hashmap! {
+            "messages" => vec!["_id"],
+        }));
+
+        assert!(cache.contains("messages", ["_id".to_string(), "author".to_string()].iter()));
+    }
+}
@@ -0,0 +1,225 @@
+use std::{
+    collections::HashSet,
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::{
+        Arc,
+        RwLock,
+    },
+    time::Duration,
+};
+
+use anyhow::Context;
+use serde::Deserialize;
+use url::Url;
+
+use crate::{
+    allowlist::parse_origin,
+    convex_api::HttpOptions,
+    log,
+    log_warning,
+    logging::{
+        self,
+        Level,
+    },
+};
+
+/// How often the settings file is checked for changes.
+const SETTINGS_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Server-side settings that operators can change while the connector is
+/// running, read from the JSON file given with `--settings-file`.
+///
+/// Changes only apply to requests received after the file is reloaded: update
+/// streams that are already running keep the settings they started with.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    /// Origins of deployments (e.g. `http://localhost:3210`) that are allowed
+    /// even when the connector doesn’t allow all hosts.
+    #[serde(default)]
+    allowed_hosts: HashSet<String>,
+
+    /// Overrides `--connect-timeout-secs`.
+    connect_timeout_secs: Option<u64>,
+
+    /// Overrides `--request-timeout-secs`.
+    request_timeout_secs: Option<u64>,
+
+    /// Overrides `--log-level`.
+    log_level: Option<Level>,
+
+    /// If set, update requests received while this many syncs are running are
+    /// refused with a retryable error, to limit the load on the connector and
+    /// on the deployments.
+    max_concurrent_syncs: Option<usize>,
+}
+
+impl Settings {
+    fn parse(contents: &str) -> anyhow::Result<Self> {
+        let mut settings: Settings = serde_json::from_str(contents)?;
+        settings.allowed_hosts = settings
+            .allowed_hosts
+            .iter()
+            .map(|origin| parse_origin(origin))
+            .collect::<anyhow::Result<_>>()?;
+        Ok(settings)
+    }
+
+    fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Couldn’t read the settings file at {}", path.display()))?;
+        Settings::parse(&contents)
+            .with_context(|| format!("Invalid settings file at {}", path.display()))
+    }
+
+    /// Returns true if the origin of the given deployment URL is allowed by the
+    /// settings.
+    pub fn allows_host(&self, deploy_url: &Url) -> bool {
+        self.allowed_hosts
+            .contains(&deploy_url.origin().ascii_serialization())
+    }
+
+    /// Applies the overrides of the settings to the given HTTP options.
+    pub fn http_options(&self, defaults: &HttpOptions) -> HttpOptions {
+        HttpOptions {
+            connect_timeout: self
+                .connect_timeout_secs
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.connect_timeout),
+            request_timeout: self
+                .request_timeout_secs
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.request_timeout),
+            max_response_size: defaults.max_response_size,
+        }
+    }
+
+    /// The maximum number of syncs running at the same time, if limited.
+    pub fn max_concurrent_syncs(&self) -> Option<usize> {
+        self.max_concurrent_syncs
+    }
+}
+
+/// The latest version of the [`Settings`], shared between the connector and
+/// the task reloading the settings file.
+#[derive(Debug, Clone, Default)]
+pub struct LiveSettings(Arc<RwLock<Settings>>);
+
+impl LiveSettings {
+    /// Loads the settings from the given file, and starts a task reloading them
+    /// whenever the file changes. `log_level` is the level set with
+    /// `--log-level`, which applies when the settings don’t override it.
+    pub fn watch(path: PathBuf, log_level: Level) -> anyhow::Result<Self> {
+        let settings = LiveSettings(Arc::new(RwLock::new(Settings::load(&path)?)));
+        settings.apply_log_level(log_level);
+
+        let watched_settings = settings.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SETTINGS_POLL_INTERVAL);
+            loop {
+                interval.tick().await;
+                match watched_settings.reload(&path) {
+                    Ok(true) => watched_settings.apply_log_level(log_level),
+                    Ok(false) => {},
+                    Err(error) => {
+                        log_warning(&format!("{error:#}. Keeping the previous settings."))
+                    },
+                }
+            }
+        });
+
+        Ok(settings)
+    }
+
+    /// Sets the minimum level of the lines logged to the `logLevel` of the
+    /// current settings, or to the given default level.
+    fn apply_log_level(&self, default: Level) {
+        logging::set_min_level(self.current().log_level.unwrap_or(default));
+    }
+
+    /// Reloads the settings from the given file. Returns true if the settings
+    /// changed.
+    fn reload(&self, path: &Path) -> anyhow::Result<bool> {
+        let new_settings = Settings::load(path)?;
+        let mut settings = self.0.write().unwrap();
+        if *settings == new_settings {
+            return Ok(false);
+        }
+
+        *settings = new_settings;
+        log(&format!("Reloaded the settings from {}", path.display()));
+        Ok(true)
+    }
+
+    pub fn current(&self) -> Settings {
+        self.0.read().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[test]
+    fn parses_settings() -> anyhow::Result<()> {
+        let settings = Settings::parse(
+            r#"{
+                "allowedHosts": ["http://localhost:3210/"],
+                "requestTimeoutSecs": 60,
+                "logLevel": "debug",
+                "maxConcurrentSyncs": 4
+            }"#,
+        )?;
+
+        assert!(settings.allows_host(&Url::parse("http://localhost:3210")?));
+        assert!(!settings.allows_host(&Url::parse("http://localhost:8000")?));
+
+        let http_options = settings.http_options(&HttpOptions::default());
+        assert_eq!(http_options.request_timeout, Duration::from_secs(60));
+        assert_eq!(
+            http_options.connect_timeout,
+            HttpOptions::default().connect_timeout
+        );
+        assert_eq!(settings.log_level, Some(Level::Debug));
+        assert_eq!(settings.max_concurrent_syncs(), Some(4));
+        Ok(())
+    }
+
+    #[test]
+    fn refuses_invalid_settings() {
+        assert!(Settings::parse(r#"{ "unknownSetting": true }"#).is_err());
+        assert!(Settings::parse(r#"{ "allowedHosts": ["localhost"] }"#).is_err());
+        assert!(Settings::parse(r#"{ "logLevel": "verbose" }"#).is_err());
+        assert!(Settings::parse("").is_err());
+    }
+
+    #[test]
+    fn reloads_changed_settings() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!("settings-{}.json", Uuid::new_v4()));
+        std::fs::write(&path, "{}")?;
+        let settings = LiveSettings::default();
+        assert!(!settings.reload(&path)?);
+
+        std::fs::write(&path, r#"{ "allowedHosts": ["http://localhost:3210"] }"#)?;
+        assert!(settings.reload(&path)?);
+        assert!(settings
+            .current()
+            .allows_host(&Url::parse("http://localhost:3210")?));
+
+        // Invalid files are refused and the previous settings are kept
+        std::fs::write(&path, "{")?;
+        assert!(settings.reload(&path).is_err());
+        assert!(settings
+            .current()
+            .allows_host(&Url::parse("http://localhost:3210")?));
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,232 @@
+//! Tracks the progress of the update streams currently running, so that it can
+//! be dumped to the log to diagnose stuck syncs while the connector runs.
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{
+            AtomicU64,
+            Ordering,
+        },
+        Arc,
+        Mutex,
+    },
+    time::SystemTime,
+};
+
+use serde::Serialize;
+
+use crate::{
+    correlation::current_sync_id,
+    fivetran_sdk::OpType,
+    log,
+    sync::{
+        Checkpoint,
+        UpdateMessage,
+    },
+};
+
+/// The progress of an update stream.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncStatus {
+    /// The sync ID prefixing the log lines of the stream.
+    pub sync_id: Option<String>,
+    pub deploy_url: String,
+    pub started_at: SystemTime,
+    pub last_message_at: Option<SystemTime>,
+    /// The checkpoint the stream started from, until it emits a new one.
+    pub last_checkpoint: Option<Checkpoint>,
+    pub upserts: u64,
+    pub deletes: u64,
+    pub truncates: u64,
+    pub checkpoints: u64,
+}
+
+impl SyncStatus {
+    /// The phase of the sync, derived from the last checkpoint.
+    fn phase(&self) -> &'static str {
+        match self.last_checkpoint {
+            None => "starting",
+            Some(Checkpoint::InitialSync { .. }) => "initial_sync",
+            Some(Checkpoint::DeltaUpdates { .. }) => "delta_sync",
+        }
+    }
+
+    fn record(&mut self, message: &UpdateMessage) {
+        self.last_message_at = Some(SystemTime::now());
+        match message {
+            UpdateMessage::Log(..) | UpdateMessage::Heartbeat(_) => {},
+            UpdateMessage::Update { op_type, .. } => match op_type {
+                OpType::Delete => self.deletes += 1,
+                OpType::Truncate => self.truncates += 1,
+                _ => self.upserts += 1,
+            },
+            UpdateMessage::Checkpoint(state) => {
+                self.checkpoints += 1;
+                self.last_checkpoint = Some(state.checkpoint.clone());
+            },
+        }
+    }
+}
+
+/// The update streams currently running.
+#[derive(Debug, Clone, Default)]
+pub struct ActiveSyncs {
+    next_id: Arc<AtomicU64>,
+    syncs: Arc<Mutex<BTreeMap<u64, SyncStatus>>>,
+}
+
+impl ActiveSyncs {
+    /// Starts tracking a new stream. It stops being tracked when the returned
+    /// handle is dropped.
+    pub fn register(&self, deploy_url: String, checkpoint: Option<Checkpoint>) -> SyncHandle {
+        self.try_register(deploy_url, checkpoint, None)
+            .expect("Unlimited registrations can’t fail")
+    }
+
+    /// Like [`ActiveSyncs::register`], but returns `None` instead when
+    /// `max_active` streams are already tracked.
+    pub fn try_register(
+        &self,
+        deploy_url: String,
+        checkpoint: Option<Checkpoint>,
+        max_active: Option<usize>,
+    ) -> Option<SyncHandle> {
+        let mut syncs = self.syncs.lock().unwrap();
+        if max_active.is_some_and(|max_active| syncs.len() >= max_active) {
+            return None;
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        syncs.insert(
+            id,
+            SyncStatus {
+                sync_id: current_sync_id().map(|sync_id| sync_id.to_string()),
+                deploy_url,
+                started_at: SystemTime::now(),
+                last_message_at: None,
+                last_checkpoint: checkpoint,
+                upserts: 0,
+                deletes: 0,
+                truncates: 0,
+                checkpoints: 0,
+            },
+        );
+        Some(SyncHandle {
+            id,
+            syncs: self.clone(),
+        })
+    }
+
+    pub fn snapshot(&self) -> Vec<SyncStatus> {
+        self.syncs.lock().unwrap().values().cloned().collect()
+    }
+
+    /// Logs the status of every active stream as a JSON object.
+    pub fn dump(&self) {
+        let syncs: Vec<_> = self
+            .snapshot()
+            .into_iter()
+            .map(|status| {
+                let phase = status.phase();
+                let mut json = serde_json::to_value(status).expect("Couldn’t serialize a status");
+                json["phase"] = phase.into();
+                json
+            })
+            .collect();
+        log(&serde_json::json!({ "activeSyncs": syncs }).to_string());
+    }
+}
+
+/// Updates the status of a tracked stream.
+pub struct SyncHandle {
+    id: u64,
+    syncs: ActiveSyncs,
+}
+
+impl SyncHandle {
+    pub fn record(&self, message: &UpdateMessage) {
+        if let Some(status) = self.syncs.syncs.lock().unwrap().get_mut(&self.id) {
+            status.record(message);
+        }
+    }
+}
+
+impl Drop for SyncHandle {
+    fn drop(&mut self) {
+        self.syncs.syncs.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// Dumps the status of the active streams every time the process receives
+/// SIGUSR1.
+#[cfg(unix)]
+pub fn dump_on_sigusr1(active_syncs: ActiveSyncs) -> anyhow::Result<()> {
+    use tokio::signal::unix::{
+        signal,
+        SignalKind,
+    };
+
+    let mut signals = signal(SignalKind::user_defined1())?;
+    tokio::spawn(async move {
+        while signals.recv().await.is_some() {
+            active_syncs.dump();
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{
+        HashMap,
+        HashSet,
+    };
+
+    use super::*;
+    use crate::sync::State;
+
+    #[test]
+    fn tracks_streams_until_their_handle_is_dropped() {
+        let active_syncs = ActiveSyncs::default();
+        let handle = active_syncs.register("https://a.convex.cloud/".to_string(), None);
+
+        handle.record(&UpdateMessage::Update {
+            schema_name: None,
+            table_name: "messages".to_string(),
+            op_type: OpType::Upsert,
+            row: HashMap::new(),
+        });
+        handle.record(&UpdateMessage::Checkpoint(State::create(
+            Checkpoint::DeltaUpdates { cursor: 42.into() },
+            HashSet::new(),
+        )));
+
+        let statuses = active_syncs.snapshot();
+        assert_eq!(statuses.len(), 1);
+        assert_eq!(statuses[0].upserts, 1);
+        assert_eq!(statuses[0].checkpoints, 1);
+        assert_eq!(statuses[0].phase(), "delta_sync");
+        assert_eq!(
+            statuses[0].last_checkpoint,
+            Some(Checkpoint::DeltaUpdates { cursor: 42.into() })
+        );
+
+        drop(handle);
+        assert!(active_syncs.snapshot().is_empty());
+    }
+
+    #[test]
+    fn refuses_streams_beyond_the_limit() {
+        let active_syncs = ActiveSyncs::default();
+        let register =
+            || active_syncs.try_register("https://a.convex.cloud/".to_string(), None, Some(1));
+
+        let handle = register();
+        assert!(handle.is_some());
+        assert!(register().is_none());
+
+        drop(handle);
+        assert!(register().is_some());
+    }
+}
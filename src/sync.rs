@@ -1,27 +1,88 @@
-use std::collections::{
-    HashMap,
-    HashSet,
+//! The synchronization of a Convex deployment, as a stream of rows and
+//! checkpoints (see [`sync`]).
+
+use std::{
+    collections::{
+        BTreeMap,
+        BTreeSet,
+        HashMap,
+        HashSet,
+    },
+    future::Future,
+    pin::{
+        pin,
+        Pin,
+    },
+    sync::Arc,
+    time::Duration,
 };
 
 use anyhow::Context;
+use async_stream::try_stream;
 use futures::{
+    future::{
+        self,
+        BoxFuture,
+        Either,
+        RemoteHandle,
+    },
     stream::BoxStream,
+    FutureExt,
+    Stream,
     StreamExt,
 };
-use futures_async_stream::try_stream;
 use serde::{
     Deserialize,
     Serialize,
 };
+use serde_json::Value as JsonValue;
+use tracing::Instrument;
+use url::Url;
 use value_type::Inner as FivetranValue;
 
 use crate::{
-    convert::to_fivetran_row,
+    budget::with_time_budget,
+    clock::{
+        Clock,
+        SystemClock,
+    },
+    convert::{
+        child_rows,
+        convert_column_types,
+        convert_decimal_columns,
+        hash_columns,
+        mask_fields,
+        sanitize_column_names,
+        sanitized_column_names,
+        to_fivetran_row,
+        ColumnHashing,
+        ColumnMasking,
+        ColumnNames,
+        ColumnTypes,
+        DecimalColumns,
+        NestedObjects,
+        NonFiniteFloats,
+        RowOptions,
+    },
     convex_api::{
+        ApiError,
+        BackendErrorKind,
         DocumentDeltasCursor,
         ListSnapshotCursor,
+        ListSnapshotResponse,
+        SnapshotValue,
         Source,
     },
+    deprecation::Deprecation,
+    error::{
+        is_transient,
+        ConnectorError,
+    },
+    files::{
+        file_row,
+        FILES_TABLE_NAME,
+        STORAGE_TABLE_NAME,
+    },
     fivetran_sdk::{
         self,
         operation::Op,
@@ -36,10 +97,95 @@ use crate::{
         ValueType,
     },
     log,
+    log_debug,
+    log_warning,
+    logging::format_message,
+    metadata::{
+        truncate_audit_row,
+        TruncateReason,
+        METADATA_TABLE_NAME,
+    },
+    page_sizing::AdaptivePageSize,
+    progress::SyncStats,
+    row_filter::RowFilters,
+    schema::{
+        component_schema_name,
+        component_table_name,
+        destination_table_name,
+        json_schema_columns,
+        split_component_table_name,
+        table_rename_warning,
+        table_renames,
+        tables_and_columns,
+        ROOT_COMPONENT_SCHEMA_NAME,
+    },
+    schema_cache::SchemaCache,
+    table_filter::TableFilter,
+    table_stats::{
+        TableStats,
+        TABLE_STATS_TABLE_NAME,
+    },
+    throttle::BackfillThrottle,
 };
 
+/// How often delta syncs refresh the JSON schemas of the deployment by default.
+const DEFAULT_SCHEMA_REFRESH_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// The column marking the rows of deleted documents when soft deletes are
+/// enabled. Fivetran adds it to every destination table.
+const SOFT_DELETE_COLUMN_NAME: &str = "_fivetran_deleted";
+
 /// The value currently used for the `version` field of [`State`].
-const CURSOR_VERSION: i64 = 1;
+const CURSOR_VERSION: i64 = 3;
+
+/// A migration upgrading the JSON of a state from one version of its layout to
+/// the next.
+type Migration = fn(JsonValue) -> anyhow::Result<JsonValue>;
+
+/// The migrations applied to the states emitted by older versions of the
+/// connector, in order: `MIGRATIONS[i]` upgrades a state of version `i + 1`.
+/// Changing the layout of [`State`] requires bumping [`CURSOR_VERSION`] and
+/// adding a migration here, so that existing connections keep syncing.
+const MIGRATIONS: [Migration; (CURSOR_VERSION - 1) as usize] = [migrate_v1_to_v2, migrate_v2_to_v3];
+
+/// Version 2 names the fields of checkpoints in camelCase, like the other
+/// fields of the state.
+fn migrate_v1_to_v2(mut state: JsonValue) -> anyhow::Result<JsonValue> {
+    if let Some(initial_sync) = state
+        .pointer_mut("/checkpoint/InitialSync")
+        .and_then(JsonValue::as_object_mut)
+    {
+        for (old_name, new_name) in [
+            ("page_size", "pageSize"),
+            ("documents_synced", "documentsSynced"),
+        ] {
+            if let Some(value) = initial_sync.remove(old_name) {
+                initial_sync.insert(new_name.to_string(), value);
+            }
+        }
+    }
+    state["version"] = JsonValue::from(2);
+    Ok(state)
+}
+
+/// Version 3 always has `tablesSeen`, which older versions of the connector
+/// didn’t track. It can’t be derived from the state itself, so the states
+/// lacking it must be backfilled by [`State::upgrade`].
+fn migrate_v2_to_v3(mut state: JsonValue) -> anyhow::Result<JsonValue> {
+    anyhow::ensure!(
+        has_tables_seen(&state),
+        "The state doesn’t track the tables synced, so it must be upgraded with the tables of the \
+         deployment"
+    );
+    state["version"] = JsonValue::from(3);
+    Ok(state)
+}
+
+fn has_tables_seen(state: &JsonValue) -> bool {
+    state
+        .get("tablesSeen")
+        .is_some_and(|tables_seen| !tables_seen.is_null())
+}
 
 /// Stores the current synchronization state of a destination. A state will be
 /// send (as JSON) to Fivetran every time we perform a checkpoint, and will be
@@ -50,29 +196,107 @@ const CURSOR_VERSION: i64 = 1;
 #[serde(deny_unknown_fields)]
 #[cfg_attr(test, derive(proptest_derive::Arbitrary))]
 pub struct State {
-    /// The version of the connector that emitted this checkpoint. Could be used
-    /// in the future to support backward compatibility with older state
-    /// formats.
+    /// The version of the layout of the state. States emitted with an older
+    /// layout are upgraded by [`State::migrate`].
     pub version: i64,
 
     pub checkpoint: Checkpoint,
 
-    /// If set, then we are tracking the full set of tables that the connector
-    /// has every seen, so we are able to issue truncates the first time we
-    /// see a table.
+    /// The tables that the connector has ever seen, so that it can issue
+    /// truncates the first time it sees a table.
     ///
-    /// Older versions of state.json do not have this field set. Once all
-    /// state.json have this field, we can make this non-optional.
-    pub tables_seen: Option<HashSet<String>>,
+    /// Older versions of the connector didn’t track them, in which case they
+    /// are backfilled with the tables of the deployment (see
+    /// [`State::upgrade`]).
+    pub tables_seen: HashSet<String>,
+
+    /// The states of the additional deployments synced by the same
+    /// connection, by schema name (see [`sync_deployments`]).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    #[cfg_attr(test, proptest(value = "BTreeMap::new()"))]
+    pub deployments: BTreeMap<String, State>,
+
+    /// The sanitized names given to the columns of each table when
+    /// [`SyncOptions::sanitize_column_names`] is set, so that a column keeps
+    /// its name when a colliding column appears in a later sync.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    #[cfg_attr(test, proptest(value = "BTreeMap::new()"))]
+    pub column_names: BTreeMap<String, ColumnNames>,
+
+    /// The destination names of the tables whose names only differ by case
+    /// from a table synced before them, so that the tables keep their
+    /// destination when a colliding table appears (see [`TableRenames`]).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    #[cfg_attr(test, proptest(value = "BTreeMap::new()"))]
+    pub table_renames: BTreeMap<String, String>,
 }
 
 impl State {
-    pub fn create(checkpoint: Checkpoint, tables_seen: Option<HashSet<String>>) -> Self {
+    pub fn create(checkpoint: Checkpoint, tables_seen: HashSet<String>) -> Self {
         Self {
             version: CURSOR_VERSION,
             checkpoint,
             tables_seen,
+            deployments: BTreeMap::new(),
+            column_names: BTreeMap::new(),
+            table_renames: BTreeMap::new(),
+        }
+    }
+
+    /// Parses a state emitted by any version of the connector, upgrading it to
+    /// the current layout.
+    pub fn migrate(mut state: JsonValue) -> anyhow::Result<State> {
+        let version = state
+            .get("version")
+            .and_then(JsonValue::as_i64)
+            .context("The state has no version")?;
+        if !(1..=CURSOR_VERSION).contains(&version) {
+            anyhow::bail!(
+                "The state has the unsupported version {version}, probably because it was emitted \
+                 by a newer version of the connector (this one supports versions up to \
+                 {CURSOR_VERSION})"
+            );
+        }
+        for migration in &MIGRATIONS[(version - 1) as usize..] {
+            state = migration(state)?;
+        }
+        if let Some(JsonValue::Object(deployments)) = state.get_mut("deployments") {
+            for deployment_state in deployments.values_mut() {
+                *deployment_state = serde_json::to_value(State::migrate(deployment_state.take())?)?;
+            }
         }
+        serde_json::from_value(state).context("Invalid state")
+    }
+
+    /// Whether a state was emitted by a version of the connector that didn’t
+    /// track the tables seen, which is deprecated (see [`State::upgrade`]).
+    pub fn is_legacy(state: &JsonValue) -> bool {
+        state.is_object() && !has_tables_seen(state)
+    }
+
+    /// Like [`State::migrate`], but also upgrades the states emitted by older
+    /// versions of the connector that didn’t track the tables seen. They are
+    /// backfilled with the tables of the deployment: these tables have been
+    /// synced already, and the tables created later are truncated when they
+    /// are first seen.
+    pub async fn upgrade(mut state: JsonValue, source: &impl Source) -> anyhow::Result<State> {
+        if Self::is_legacy(&state) {
+            let mut tables: Vec<String> = source
+                .get_tables_and_columns()
+                .await
+                .context("Failed to list the tables of the deployment to upgrade the state")?
+                .into_keys()
+                .map(|table_name| table_name.0)
+                .collect();
+            tables.sort();
+            log(&format!(
+                "Upgrading a legacy state that doesn’t track the tables synced, assuming that the \
+                 tables of {source} were synced already: {}",
+                tables.join(", ")
+            ));
+            state["tablesSeen"] = JsonValue::from(tables);
+        }
+        Self::migrate(state).map_err(|error| ConnectorError::InvalidState(error).into())
     }
 }
 
@@ -84,14 +308,31 @@ pub enum Checkpoint {
     InitialSync {
         snapshot: i64,
         cursor: ListSnapshotCursor,
+        /// The page size used when the checkpoint was emitted. Cursors don’t
+        /// depend on the page size, so it is only kept to report changes.
+        #[serde(rename = "pageSize", default, skip_serializing_if = "Option::is_none")]
+        page_size: Option<u32>,
+        /// The number of documents synced before the checkpoint, used to
+        /// estimate the progress of the initial sync. Missing in checkpoints
+        /// emitted by older versions.
+        #[serde(
+            rename = "documentsSynced",
+            default,
+            skip_serializing_if = "Option::is_none"
+        )]
+        documents_synced: Option<u64>,
     },
     /// A checkpoint emitted after an initial synchronzation has been completed.
     DeltaUpdates { cursor: DocumentDeltasCursor },
 }
 
 /// A simplification of the messages sent to Fivetran in the `update` endpoint.
+#[derive(Debug, Clone)]
 pub enum UpdateMessage {
     Log(LogLevel, String),
+    /// A message logged while waiting on the Convex API, so that Fivetran
+    /// doesn’t consider the connector stuck. It doesn’t count as progress.
+    Heartbeat(String),
     Update {
         schema_name: Option<String>,
         table_name: String,
@@ -101,6 +342,42 @@ pub enum UpdateMessage {
     Checkpoint(State),
 }
 
+impl UpdateMessage {
+    /// The message, with the destination of its table if it is an update:
+    /// the name given by the table renames, with the table prefix, and, when
+    /// components are synced, the schema of the component of the table.
+    fn with_destination(
+        self,
+        table_renames: &BTreeMap<String, String>,
+        prefix: &str,
+        sync_components: bool,
+    ) -> Self {
+        match self {
+            UpdateMessage::Update {
+                schema_name,
+                table_name,
+                op_type,
+                row,
+            } => {
+                let table_name = destination_table_name(table_renames, &table_name);
+                let (component_path, table_name) = split_component_table_name(&table_name);
+                let schema_name = match component_path {
+                    _ if !sync_components => schema_name,
+                    "" => schema_name.or_else(|| Some(ROOT_COMPONENT_SCHEMA_NAME.to_string())),
+                    component_path => Some(component_schema_name(component_path)),
+                };
+                UpdateMessage::Update {
+                    schema_name,
+                    table_name: format!("{prefix}{table_name}"),
+                    op_type,
+                    row,
+                }
+            },
+            message => message,
+        }
+    }
+}
+
 /// Conversion of the simplified update message type to the actual gRPC type.
 impl From<UpdateMessage> for FivetranUpdateResponse {
     fn from(value: UpdateMessage) -> Self {
@@ -109,7 +386,13 @@ impl From<UpdateMessage> for FivetranUpdateResponse {
                 UpdateMessage::Log(level, message) => {
                     update_response::Response::LogEntry(LogEntry {
                         level: level as i32,
-                        message,
+                        message: format_message(&message),
+                    })
+                },
+                UpdateMessage::Heartbeat(message) => {
+                    update_response::Response::LogEntry(LogEntry {
+                        level: LogLevel::Info as i32,
+                        message: format_message(&message),
                     })
                 },
                 UpdateMessage::Update {
@@ -147,171 +430,1639 @@ impl From<UpdateMessage> for FivetranUpdateResponse {
     }
 }
 
+/// Options changing how a synchronization is performed.
+#[derive(Clone)]
+pub struct SyncOptions {
+    /// A delay to wait for between two pages of the initial synchronization,
+    /// in order to spread the load on the deployment over time.
+    pub page_pacing: Option<Duration>,
+
+    /// The number of documents requested per page of the initial
+    /// synchronization, or `None` to use the default of the deployment.
+    pub page_size: Option<u32>,
+
+    /// If set, the page size is adjusted after every page of the initial
+    /// synchronization so that pages weigh about this many bytes, starting
+    /// from `page_size`.
+    pub target_page_bytes: Option<u64>,
+
+    /// If set, the fields of documents having more fields than this are
+    /// moved to a single JSON column.
+    pub max_fields: Option<usize>,
+
+    /// If set, the arrays and objects nested deeper than this in a field are
+    /// stored as JSON strings.
+    pub max_nesting_depth: Option<usize>,
+
+    /// If set, every user column has a companion boolean column marking
+    /// whether documents have the field, so that missing fields and `null`
+    /// values can be told apart.
+    pub presence_columns: bool,
+
+    /// If set, deleted documents are emitted as updates setting the
+    /// `_fivetran_deleted` column, so that the destination keeps their rows.
+    pub soft_deletes: bool,
+
+    /// If set, rows have a `_convex_ts` column containing the timestamp of the
+    /// revision of the document synced.
+    pub timestamp_column: bool,
+
+    /// If set, the system fields of documents other than `_id` aren’t synced.
+    pub exclude_system_columns: bool,
+
+    /// If set, the elements of array fields are synced to child tables named
+    /// `<table>__<field>`.
+    pub child_tables: bool,
+
+    /// How the object fields of documents are synced.
+    pub nested_objects: NestedObjects,
+
+    /// How NaN and infinite numbers are synced.
+    pub non_finite_floats: NonFiniteFloats,
+
+    /// The columns synced as decimal numbers, by table.
+    pub decimal_columns: DecimalColumns,
+
+    /// The columns whose values are converted to a forced data type, by table.
+    pub column_types: ColumnTypes,
+
+    /// The columns whose values are replaced by their hash, by table.
+    pub column_hashing: ColumnHashing,
+
+    /// The columns whose values are replaced by NULL or by a mask, by table.
+    pub column_masking: ColumnMasking,
+
+    /// If set, the strings containing ISO 8601 dates are synced as dates.
+    pub detect_dates: bool,
+
+    /// If set, the columns of the rows synced are renamed to their sanitized
+    /// names, which are kept in [`State::column_names`].
+    pub sanitize_column_names: bool,
+
+    /// Limits on the throughput of the initial synchronization.
+    pub backfill_throttle: BackfillThrottle,
+
+    /// If set, the connector refuses to start an initial synchronization from
+    /// scratch, since it would truncate the destination tables.
+    pub refuse_resync: bool,
+
+    /// The destination schema of every row emitted, including truncates and
+    /// metadata rows, or `None` to use the schema of the connection.
+    pub schema_name: Option<String>,
+
+    /// A prefix added to the name of every destination table, including the
+    /// metadata tables, so that deployments syncing to the same schema don’t
+    /// collide.
+    pub table_prefix: Option<String>,
+
+    /// The destination names of the tables whose names only differ by case
+    /// from another table when a new connection starts syncing, which are the
+    /// names declared by its schema (see [`crate::schema::table_renames`]).
+    /// Connections resuming from a state use the renames stored in it.
+    pub table_renames: BTreeMap<String, String>,
+
+    /// If set, the tables of the components of the deployment are synced too,
+    /// to one destination schema per component (see [`component_schema_name`]).
+    /// The rows of the root component go to the schema given by `schema_name`
+    /// or to the [`ROOT_COMPONENT_SCHEMA_NAME`] schema.
+    pub sync_components: bool,
+
+    /// Tables whose documents aren’t synced. Only aggregate statistics about
+    /// them are, in the table statistics table.
+    pub stats_only_tables: BTreeSet<String>,
+
+    /// The tables synced. The documents of other tables are skipped.
+    pub table_filter: TableFilter,
+
+    /// The filters selecting the documents synced in some tables. Documents
+    /// that stop matching their filter are deleted from the destination.
+    pub row_filters: RowFilters,
+
+    /// If set, the documents of the `_storage` system table are synced to
+    /// the files table, whether or not `_storage` itself is synced.
+    pub files_table: bool,
+
+    /// The URL of the deployment, if the rows of the files table have the
+    /// download URL of their file.
+    pub file_download_urls: Option<Url>,
+
+    /// If set, delta syncs refresh the JSON schemas of the deployment this
+    /// often, to warn about the tables and columns added during long syncs.
+    /// They are also refreshed after every page of changes having columns
+    /// missing from the schema cache.
+    pub schema_refresh_interval: Option<Duration>,
+
+    /// If set, a heartbeat is emitted every time a call to the Convex API has
+    /// been pending for this long.
+    pub heartbeat_interval: Option<Duration>,
+
+    /// The deprecated behaviors detected before the sync (e.g. while parsing
+    /// the configuration and the state), warned about when it starts.
+    pub deprecations: BTreeSet<Deprecation>,
+
+    /// The system tables synced that the deployment didn’t list before the
+    /// sync, warned about when it starts.
+    pub missing_system_tables: Vec<String>,
+
+    /// If set, the sync ends at its first checkpoint after running for this
+    /// long, and the next sync continues from there.
+    pub time_budget: Option<Duration>,
+
+    pub clock: Arc<dyn Clock>,
+}
+
+impl Default for SyncOptions {
+    fn default() -> Self {
+        SyncOptions {
+            page_pacing: None,
+            page_size: None,
+            target_page_bytes: None,
+            max_fields: None,
+            max_nesting_depth: None,
+            presence_columns: false,
+            soft_deletes: false,
+            timestamp_column: false,
+            exclude_system_columns: false,
+            child_tables: false,
+            nested_objects: NestedObjects::Json,
+            non_finite_floats: NonFiniteFloats::Double,
+            decimal_columns: DecimalColumns::new(),
+            column_types: ColumnTypes::new(),
+            column_hashing: ColumnHashing::default(),
+            column_masking: ColumnMasking::default(),
+            detect_dates: false,
+            sanitize_column_names: false,
+            backfill_throttle: BackfillThrottle::default(),
+            refuse_resync: false,
+            schema_name: None,
+            table_prefix: None,
+            table_renames: BTreeMap::new(),
+            sync_components: false,
+            stats_only_tables: BTreeSet::new(),
+            table_filter: TableFilter::default(),
+            row_filters: RowFilters::default(),
+            files_table: false,
+            file_download_urls: None,
+            schema_refresh_interval: Some(DEFAULT_SCHEMA_REFRESH_INTERVAL),
+            heartbeat_interval: None,
+            deprecations: BTreeSet::new(),
+            missing_system_tables: vec![],
+            time_budget: None,
+            clock: Arc::new(SystemClock),
+        }
+    }
+}
+
+/// Refreshes the schema cache of a deployment from its JSON schemas. Returns
+/// the warnings about the new columns found, or about the failed refresh.
+async fn refresh_schema_cache(
+    source: &impl Source,
+    schema_cache: &SchemaCache,
+    options: &SyncOptions,
+) -> Vec<UpdateMessage> {
+    match source.get_json_schemas().await {
+        Ok(json_schemas) => {
+            let discovered = schema_cache.refresh(json_schema_columns(&json_schemas));
+            schema_cache.set_json_schemas(json_schemas);
+            discovered_column_warnings(discovered, options)
+        },
+        Err(error) => {
+            let message = format!("Couldn’t refresh the schema from {source}: {error}");
+            log_warning(&message);
+            vec![UpdateMessage::Log(LogLevel::Warning, message)]
+        },
+    }
+}
+
+/// The warnings about the new columns found by a refresh of the schema cache,
+/// in the tables that are synced.
+fn discovered_column_warnings(
+    discovered: Vec<(String, Vec<String>)>,
+    options: &SyncOptions,
+) -> Vec<UpdateMessage> {
+    discovered
+        .into_iter()
+        .filter(|(table_name, _)| options.table_filter.includes(table_name))
+        .map(|(table_name, columns)| {
+            let message = format!(
+                "Discovered new columns in table {table_name}: {}",
+                columns.join(", ")
+            );
+            log_warning(&message);
+            UpdateMessage::Log(LogLevel::Warning, message)
+        })
+        .collect()
+}
+
+/// The messages truncating a destination table, and recording why in the
+/// metadata table.
+fn truncate_table(
+    table_name: &str,
+    reason: TruncateReason,
+    options: &SyncOptions,
+) -> [UpdateMessage; 2] {
+    [
+        UpdateMessage::Update {
+            schema_name: options.schema_name.clone(),
+            table_name: table_name.to_string(),
+            op_type: OpType::Truncate,
+            row: HashMap::new(),
+        },
+        UpdateMessage::Update {
+            schema_name: options.schema_name.clone(),
+            table_name: METADATA_TABLE_NAME.to_string(),
+            op_type: OpType::Upsert,
+            row: truncate_audit_row(table_name, reason, options.clock.now()),
+        },
+    ]
+}
+
+/// The messages upserting the rows of the child tables of a document (see
+/// [`RowOptions::child_tables`]). Child tables seen for the first time are
+/// truncated like their parent table would be.
+fn child_table_messages(
+    table_name: &str,
+    fields: &HashMap<String, JsonValue>,
+    tables_seen: &mut HashSet<String>,
+    reason: TruncateReason,
+    row_options: &RowOptions,
+    options: &SyncOptions,
+) -> anyhow::Result<Vec<UpdateMessage>> {
+    let mut messages = vec![];
+    for (child_table_name, row) in child_rows(table_name, fields, row_options)? {
+        if tables_seen.insert(child_table_name.clone()) {
+            messages.extend(truncate_table(&child_table_name, reason, options));
+        }
+        messages.push(UpdateMessage::Update {
+            schema_name: options.schema_name.clone(),
+            table_name: child_table_name,
+            op_type: OpType::Upsert,
+            row,
+        });
+    }
+    Ok(messages)
+}
+
+/// The messages syncing a document of the `_storage` system table to the
+/// files table, if it is enabled. The files table is truncated the first time
+/// it is seen, like the other tables.
+fn file_messages(
+    value: &SnapshotValue,
+    tables_seen: &mut HashSet<String>,
+    reason: TruncateReason,
+    row_options: &RowOptions,
+    options: &SyncOptions,
+) -> anyhow::Result<Vec<UpdateMessage>> {
+    if !options.files_table || value.table != STORAGE_TABLE_NAME {
+        return Ok(vec![]);
+    }
+    let mut messages = vec![];
+    if tables_seen.insert(FILES_TABLE_NAME.to_string()) {
+        messages.extend(truncate_table(FILES_TABLE_NAME, reason, options));
+    }
+    let deploy_url = options
+        .file_download_urls
+        .as_ref()
+        .filter(|_| !value.deleted);
+    messages.push(UpdateMessage::Update {
+        schema_name: options.schema_name.clone(),
+        table_name: FILES_TABLE_NAME.to_string(),
+        op_type: if value.deleted {
+            OpType::Delete
+        } else {
+            OpType::Upsert
+        },
+        row: file_row(value.fields.clone(), row_options, deploy_url)?,
+    });
+    Ok(messages)
+}
+
+/// The rows of the table statistics table describing the documents of the
+/// stats-only tables received since the last call.
+fn table_stats_rows(
+    table_stats: &mut TableStats,
+    phase: &str,
+    cursor: &str,
+    options: &SyncOptions,
+) -> Vec<UpdateMessage> {
+    table_stats
+        .take_rows(phase, cursor, options.clock.now())
+        .into_iter()
+        .map(|row| UpdateMessage::Update {
+            schema_name: options.schema_name.clone(),
+            table_name: TABLE_STATS_TABLE_NAME.to_string(),
+            op_type: OpType::Upsert,
+            row,
+        })
+        .collect()
+}
+
+/// Waits for a call to the Convex API. Returns `None` every time
+/// `heartbeat_interval` elapses while the call is still pending, so that the
+/// caller can emit a heartbeat before waiting again.
+async fn wait_or_heartbeat<F: Future>(
+    call: Pin<&mut F>,
+    options: &SyncOptions,
+) -> Option<F::Output> {
+    let Some(heartbeat_interval) = options.heartbeat_interval else {
+        return Some(call.await);
+    };
+    match future::select(call, options.clock.sleep(heartbeat_interval)).await {
+        Either::Left((output, _)) => Some(output),
+        Either::Right(_) => None,
+    }
+}
+
+/// Whether the Convex API refused a call with the given kind of error.
+fn is_backend_error(error: &anyhow::Error, kind: BackendErrorKind) -> bool {
+    error
+        .chain()
+        .filter_map(|cause| cause.downcast_ref::<ApiError>())
+        .any(|api_error| api_error.kind() == kind)
+}
+
+/// How many times a page of changes failing with a transient error is retried
+/// before the sync fails.
+const MAX_TRANSIENT_RETRIES: u32 = 5;
+
+/// How many times in a row a page rate limited by the Convex backend is
+/// retried before the sync fails.
+const MAX_RATE_LIMITED_ATTEMPTS: u32 = 10;
+
+/// The delay the Convex backend asked to wait for before retrying, if it rate
+/// limited the call that failed with this error.
+fn rate_limit_delay(error: &anyhow::Error) -> Option<Duration> {
+    error
+        .chain()
+        .find_map(|cause| match cause.downcast_ref::<ApiError>() {
+            Some(ApiError::RateLimited { retry_after, .. }) => Some(*retry_after),
+            _ => None,
+        })
+}
+
+/// The delay before retrying a page after its `attempt`-th transient failure,
+/// doubling from 1 second.
+pub(crate) fn transient_retry_delay(attempt: u32) -> Duration {
+    Duration::from_secs(1 << attempt.saturating_sub(1).min(6))
+}
+
+/// Records a checkpoint as an event of the span of the sync (the `update`
+/// span of the connector), right before the checkpoint is emitted.
+fn record_checkpoint(cursor: &str) {
+    tracing::info!(cursor, "checkpoint");
+}
+
+/// The sanitized names of the columns of a table synced for the first time,
+/// which are the names its schema declares (see [`sanitized_column_names`]).
+fn schema_column_names(schema_cache: &SchemaCache, table: &str) -> ColumnNames {
+    let columns = schema_cache.columns(table);
+    sanitized_column_names(
+        columns
+            .iter()
+            .map(String::as_str)
+            .filter(|column_name| !column_name.starts_with('_')),
+    )
+    .into_iter()
+    .map(|(column_name, sanitized)| (column_name.to_string(), sanitized))
+    .collect()
+}
+
 /// Returns the stream that the `update` endpoint emits.
 pub fn sync(
     source: impl Source + 'static,
     state: Option<State>,
+    options: SyncOptions,
+    schema_cache: SchemaCache,
+) -> BoxStream<'static, anyhow::Result<UpdateMessage>> {
+    let system_tables_warning = (!options.missing_system_tables.is_empty()).then(|| {
+        format!(
+            "{source} doesn’t list the system tables {}, probably because its version of Convex \
+             doesn’t support syncing system tables, or because they are empty. They are only \
+             synced once the deployment returns them.",
+            options.missing_system_tables.join(", ")
+        )
+    });
+    let warnings = futures::stream::iter(options.deprecations.clone())
+        .map(|deprecation| Ok(deprecation.warning()))
+        .chain(futures::stream::iter(system_tables_warning).map(|message| {
+            log_warning(&message);
+            Ok(UpdateMessage::Log(LogLevel::Warning, message))
+        }));
+    let time_budget = options.time_budget;
+    let clock = options.clock.clone();
+    let table_prefix = options.table_prefix.clone();
+    let mut table_renames = TableRenames::new(state.as_ref(), &options.table_renames);
+    let sync_components = options.sync_components;
+    let updates = warnings
+        .chain(sync_from_state(source, state, options, schema_cache))
+        .flat_map(move |message| {
+            let mut rename_warning = None;
+            let message = message.map(|message| match message {
+                UpdateMessage::Update { ref table_name, .. } => {
+                    rename_warning = table_renames.see(table_name);
+                    let table_prefix = table_prefix.as_deref().unwrap_or_default();
+                    message.with_destination(&table_renames.renames, table_prefix, sync_components)
+                },
+                UpdateMessage::Checkpoint(state) => UpdateMessage::Checkpoint(State {
+                    table_renames: table_renames.renames.clone(),
+                    ..state
+                }),
+                message => message,
+            });
+            let rename_warning = rename_warning.map(|message| {
+                log_warning(&message);
+                Ok(UpdateMessage::Log(LogLevel::Warning, message))
+            });
+            futures::stream::iter(rename_warning.into_iter().chain([message]))
+        });
+    match time_budget {
+        Some(time_budget) => with_time_budget(updates, time_budget, clock).boxed(),
+        None => updates.boxed(),
+    }
+}
+
+/// The destination names of the tables whose names only differ by case from
+/// another table, which most destinations would merge. A table keeps its name
+/// unless it collides with a table seen before it, in which case it gets the
+/// first free `_2`, `_3`… suffix. The renames are stored in the state, so
+/// that a table synced before never changes destinations.
+struct TableRenames {
+    renames: BTreeMap<String, String>,
+    tables_seen: HashSet<String>,
+    /// The lowercase destination names of the tables seen.
+    taken: HashSet<String>,
+}
+
+impl TableRenames {
+    /// The renames of a sync resuming from `state`, or of a new connection
+    /// starting with the renames declared by its schema. The states emitted
+    /// before the renames were stored get the renames computed from the tables
+    /// seen, which are the ones their tables were synced with.
+    fn new(state: Option<&State>, new_connection_renames: &BTreeMap<String, String>) -> Self {
+        let Some(state) = state else {
+            return Self {
+                renames: new_connection_renames.clone(),
+                tables_seen: HashSet::new(),
+                taken: HashSet::new(),
+            };
+        };
+        let parent_tables: Vec<&str> = state
+            .tables_seen
+            .iter()
+            .map(String::as_str)
+            .filter(|table_name| !is_child_table(table_name, &state.tables_seen))
+            .filter(|table_name| !table_name.starts_with('_'))
+            .collect();
+        let renames = if state.table_renames.is_empty() {
+            table_renames(parent_tables.iter().copied())
+        } else {
+            state.table_renames.clone()
+        };
+        let taken = parent_tables
+            .iter()
+            .map(|table_name| destination_table_name(&renames, table_name).to_lowercase())
+            .collect();
+        Self {
+            renames,
+            tables_seen: state.tables_seen.clone(),
+            taken,
+        }
+    }
+
+    /// Records that a table is synced, renaming it if it is seen for the first
+    /// time and collides with a table seen before. Returns the warning to log
+    /// if the table is renamed.
+    fn see(&mut self, table_name: &str) -> Option<String> {
+        if table_name.starts_with('_') || self.tables_seen.contains(table_name) {
+            return None;
+        }
+        // Child tables follow the rename of their parent table.
+        let is_child_table = is_child_table(table_name, &self.tables_seen);
+        self.tables_seen.insert(table_name.to_string());
+        if is_child_table {
+            return None;
+        }
+        let destination_name = match self.renames.get(table_name) {
+            Some(destination_name) => destination_name.clone(),
+            None if self.taken.contains(&table_name.to_lowercase()) => {
+                let mut suffix = 2;
+                let destination_name = loop {
+                    let candidate = format!("{table_name}_{suffix}");
+                    if !self.taken.contains(&candidate.to_lowercase()) {
+                        break candidate;
+                    }
+                    suffix += 1;
+                };
+                self.renames
+                    .insert(table_name.to_string(), destination_name.clone());
+                destination_name
+            },
+            None => {
+                self.taken.insert(table_name.to_lowercase());
+                return None;
+            },
+        };
+        self.taken.insert(destination_name.to_lowercase());
+        Some(table_rename_warning(table_name, &destination_name))
+    }
+}
+
+/// Whether a table is the child table of one of the tables given (see
+/// [`crate::convert::child_table_name`]).
+fn is_child_table(table_name: &str, tables: &HashSet<String>) -> bool {
+    tables.iter().any(|parent_table| {
+        table_name
+            .strip_prefix(parent_table.as_str())
+            .is_some_and(|field_name| field_name.starts_with("__"))
+    })
+}
+
+/// A deployment synced by the same connection as the main deployment, to its
+/// own schema (set in its options).
+#[derive(Clone)]
+pub struct AdditionalDeployment<S> {
+    pub schema_name: String,
+    pub source: S,
+    pub options: SyncOptions,
+    pub schema_cache: SchemaCache,
+}
+
+/// Like [`sync`], but also syncs the additional deployments of the connection
+/// one after the other once the main deployment is synced. Their states are
+/// stored in [`State::deployments`], so that every checkpoint covers the
+/// progress of all the deployments.
+pub fn sync_deployments<S: Source + 'static>(
+    source: S,
+    state: Option<State>,
+    options: SyncOptions,
+    schema_cache: SchemaCache,
+    additional_deployments: Vec<AdditionalDeployment<S>>,
+) -> BoxStream<'static, anyhow::Result<UpdateMessage>> {
+    if additional_deployments.is_empty() {
+        return sync(source, state, options, schema_cache);
+    }
+    let (state, mut deployment_states) = match state {
+        Some(mut state) => {
+            let deployment_states = std::mem::take(&mut state.deployments);
+            (Some(state), deployment_states)
+        },
+        None => (None, BTreeMap::new()),
+    };
+    let is_new_connection = state.is_none();
+    let incoming_main_state = state.clone();
+    try_stream! {
+        let mut main_state = None;
+        let mut updates = sync(source, state, options, schema_cache);
+        while let Some(message) = updates.next().await {
+            match message? {
+                UpdateMessage::Checkpoint(state) => {
+                    main_state = Some(state.clone());
+                    yield UpdateMessage::Checkpoint(State {
+                        deployments: deployment_states.clone(),
+                        ..state
+                    });
+                },
+                message => yield message,
+            }
+        }
+        // Idle delta syncs and paused initial syncs end without a checkpoint:
+        // the main deployment is then still at its incoming state.
+        let Some(main_state) = main_state.or(incoming_main_state) else {
+            // The checkpoints of the other deployments are stored in the state
+            // of the main deployment, so they are only synced once the main
+            // deployment has a checkpoint.
+            return;
+        };
+
+        for deployment in additional_deployments {
+            let AdditionalDeployment {
+                schema_name,
+                source,
+                mut options,
+                schema_cache,
+            } = deployment;
+            let state = deployment_states.get(&schema_name).cloned();
+            // A deployment added to an existing connection has never been
+            // synced, so starting from scratch isn’t an accidental resync.
+            if state.is_none() && !is_new_connection {
+                options.refuse_resync = false;
+            }
+            let mut updates = sync(source, state, options, schema_cache);
+            while let Some(message) = updates.next().await {
+                match message? {
+                    UpdateMessage::Checkpoint(state) => {
+                        deployment_states.insert(schema_name.clone(), state);
+                        yield UpdateMessage::Checkpoint(State {
+                            deployments: deployment_states.clone(),
+                            ..main_state.clone()
+                        });
+                    },
+                    message => yield message,
+                }
+            }
+        }
+    }
+    .boxed()
+}
+
+/// The updates of [`sync`], after the warnings about the deprecations and
+/// the missing system tables.
+fn sync_from_state(
+    source: impl Source + 'static,
+    state: Option<State>,
+    options: SyncOptions,
+    schema_cache: SchemaCache,
 ) -> BoxStream<'static, anyhow::Result<UpdateMessage>> {
     let Some(state) = state else {
-        return initial_sync(source, None, Some(HashSet::new())).boxed();
+        if options.refuse_resync {
+            return futures::stream::once(async {
+                Err(ConnectorError::FailedPrecondition(anyhow::anyhow!(
+                    "The connector state is empty, which would start a new sync of all your data \
+                     from scratch and clear the destination tables. This was refused because the \
+                     connector is protected from accidental resyncs. If you want to resync all \
+                     your data, enable “Allow a full resync” in the connector configuration."
+                ))
+                .into())
+            })
+            .boxed();
+        }
+        return initial_sync(
+            source,
+            None,
+            None,
+            Some(0),
+            HashSet::new(),
+            BTreeMap::new(),
+            options,
+            schema_cache,
+        )
+        .boxed();
     };
 
     let State {
         version: _version,
         checkpoint,
         tables_seen,
+        deployments: _deployments,
+        column_names,
+        table_renames: _table_renames,
     } = state;
     match checkpoint {
-        Checkpoint::InitialSync { snapshot, cursor } => {
-            initial_sync(source, Some((snapshot, cursor)), tables_seen).boxed()
-        },
-        Checkpoint::DeltaUpdates { cursor } => delta_sync(source, cursor, tables_seen).boxed(),
+        Checkpoint::InitialSync {
+            snapshot,
+            cursor,
+            page_size,
+            documents_synced,
+        } => initial_sync(
+            source,
+            Some((snapshot, cursor)),
+            page_size,
+            documents_synced,
+            tables_seen,
+            column_names,
+            options,
+            schema_cache,
+        )
+        .boxed(),
+        Checkpoint::DeltaUpdates { cursor } => delta_sync(
+            source,
+            cursor,
+            tables_seen,
+            column_names,
+            options,
+            schema_cache,
+        )
+        .boxed(),
     }
 }
 
+/// Fetches a page of the initial synchronization.
+fn fetch_snapshot_page<S: Source + 'static>(
+    source: Arc<S>,
+    snapshot: Option<i64>,
+    cursor: Option<ListSnapshotCursor>,
+    page_size: Option<u32>,
+) -> BoxFuture<'static, anyhow::Result<ListSnapshotResponse>> {
+    let span = tracing::info_span!(
+        "list_snapshot_page",
+        snapshot,
+        cursor = cursor.as_ref().map(|cursor| cursor.0.as_str()),
+    );
+    async move {
+        source
+            .list_snapshot(snapshot, cursor, None, page_size)
+            .await
+    }
+    .instrument(span)
+    .boxed()
+}
+
+/// Starts fetching a page in the background, so that the request is in flight
+/// while the rows of the previous page are streamed. The request is cancelled
+/// if the handle is dropped.
+fn prefetch<T: Send + 'static>(
+    fetch: BoxFuture<'static, anyhow::Result<T>>,
+) -> RemoteHandle<anyhow::Result<T>> {
+    let (remote, handle) = fetch.remote_handle();
+    tokio::spawn(remote);
+    handle
+}
+
 /// Performs (or resume) an initial synchronization.
-#[try_stream(ok = UpdateMessage, error = anyhow::Error)]
-async fn initial_sync(
-    source: impl Source,
+fn initial_sync(
+    source: impl Source + 'static,
     mut checkpoint: Option<(i64, ListSnapshotCursor)>,
-    mut tables_seen: Option<HashSet<String>>,
-) {
-    let log_msg = if let Some((snapshot, _)) = checkpoint {
-        format!("Resuming an initial sync from {source} at {snapshot}")
-    } else {
-        format!("Starting an initial sync from {source}")
-    };
-    log(&log_msg);
-    yield UpdateMessage::Log(LogLevel::Info, log_msg);
+    previous_page_size: Option<u32>,
+    documents_synced: Option<u64>,
+    mut tables_seen: HashSet<String>,
+    mut column_names: BTreeMap<String, ColumnNames>,
+    options: SyncOptions,
+    schema_cache: SchemaCache,
+) -> impl Stream<Item = anyhow::Result<UpdateMessage>> {
+    try_stream! {
+        let source = Arc::new(source);
+        let log_msg = if let Some((snapshot, _)) = checkpoint {
+            format!("Resuming an initial sync from {source} at {snapshot}")
+        } else {
+            format!("Starting an initial sync from {source}")
+        };
+        log(&log_msg);
+        yield UpdateMessage::Log(LogLevel::Info, log_msg);
+
+        // The cursor identifies the last document synced rather than a page
+        // number, so it stays valid when the page size changes.
+        if checkpoint.is_some() && previous_page_size != options.page_size {
+            let describe = |page_size: Option<u32>| match page_size {
+                Some(page_size) => format!("{page_size} documents"),
+                None => "the default".to_string(),
+            };
+            let log_msg = format!(
+                "The page size changed from {} to {} since the last checkpoint. Resuming after the \
+                 last document synced.",
+                describe(previous_page_size),
+                describe(options.page_size),
+            );
+            log(&log_msg);
+            yield UpdateMessage::Log(LogLevel::Info, log_msg);
+        }
 
-    let mut has_more = true;
+        // The snapshot goes through the tables of the deployment one after the
+        // other, so the progress is estimated from the number of tables it
+        // reached. The columns of a table synced for the first time are also
+        // named like the schema names them, which requires knowing all the
+        // columns of the table before its first row is renamed.
+        let deployment_tables: Option<BTreeSet<String>> =
+            match tables_and_columns(&*source, options.sync_components).await {
+                Ok(tables) => {
+                    let table_names =
+                        tables.keys().map(|table_name| table_name.0.clone()).collect();
+                    if options.sanitize_column_names && !schema_cache.is_filled() {
+                        schema_cache.refresh(tables);
+                    }
+                    Some(table_names)
+                },
+                Err(error) if options.sanitize_column_names => {
+                    let message = format!("Couldn’t fetch the schema of {source}: {error}");
+                    log_warning(&message);
+                    yield UpdateMessage::Log(LogLevel::Warning, message);
+                    None
+                },
+                Err(error) => {
+                    log_debug(&format!(
+                        "Couldn’t fetch the tables of {source}, so the progress of the initial \
+                         sync won’t be reported: {error:#}"
+                    ));
+                    None
+                },
+            };
+        // A resumed sync already went through the tables synced before the
+        // checkpoint.
+        let mut tables_reached: BTreeSet<String> = match checkpoint {
+            Some(_) => tables_seen
+                .iter()
+                .filter(|table_name| {
+                    deployment_tables
+                        .as_ref()
+                        .is_some_and(|tables| tables.contains(*table_name))
+                })
+                .cloned()
+                .collect(),
+            None => BTreeSet::new(),
+        };
+        let mut documents_synced = documents_synced;
 
-    while has_more {
-        let snapshot = checkpoint.as_ref().map(|c| c.0);
-        let cursor = checkpoint.as_ref().map(|c| c.1.clone());
-        let res = source.list_snapshot(snapshot, cursor.clone(), None).await?;
+        // The snapshot of the last page received, from which the changes are
+        // synced once the initial sync is done. Single-page deployments (including
+        // empty ones) never emit an initial sync checkpoint.
+        let resumed = checkpoint.is_some();
+        let mut last_snapshot = checkpoint.as_ref().map(|(snapshot, _)| *snapshot);
+        let mut has_more = true;
+        let mut paused = false;
+        let started_at = options.clock.now();
+        let mut rows_synced: u64 = 0;
+        let mut stats = SyncStats::new(started_at);
+        let mut table_stats = TableStats::default();
+        let row_options = RowOptions {
+            format: source.export_format(),
+            max_fields: options.max_fields,
+            max_nesting_depth: options.max_nesting_depth,
+            presence_columns: options.presence_columns,
+            timestamp_column: options.timestamp_column,
+            exclude_system_columns: options.exclude_system_columns,
+            child_tables: options.child_tables,
+            nested_objects: options.nested_objects,
+            non_finite_floats: options.non_finite_floats,
+            detect_dates: options.detect_dates,
+        };
+        // The next page is requested as soon as a page is received, unless the
+        // sync is paced or throttled, in which case the requests must wait.
+        let prefetch_pages = options.page_pacing.is_none()
+            && options.backfill_throttle.max_rows_per_minute.is_none();
+        let mut prefetched = None;
+        let mut rate_limited_attempts = 0;
+        // Prefetched pages are requested before the rows of the current page
+        // are measured, so a new page size applies from the page after next.
+        let mut adaptive_page_size = options
+            .target_page_bytes
+            .map(|target_bytes| AdaptivePageSize::new(target_bytes, options.page_size));
+        let page_size = |adaptive_page_size: &Option<AdaptivePageSize>| match adaptive_page_size {
+            Some(adaptive_page_size) => Some(adaptive_page_size.page_size()),
+            None => options.page_size,
+        };
 
-        for value in res.values {
-            if let Some(ref mut tables_seen) = tables_seen {
-                // Issue truncates if we see a table for the first time.
-                // Skip the behavior for legacy state.json - where tables_seen wasn't tracked.
-                if !tables_seen.contains(&value.table) {
-                    tables_seen.insert(value.table.clone());
-                    yield UpdateMessage::Update {
-                        schema_name: None,
-                        table_name: value.table.clone(),
-                        op_type: OpType::Truncate,
-                        row: HashMap::new(),
-                    };
+        while has_more {
+            // Stop at the last checkpoint during quiet hours rather than waiting, so
+            // that the sync resumes on the first sync scheduled after them.
+            if let Some(quiet_hours) = options.backfill_throttle.quiet_hours {
+                if quiet_hours.remaining(options.clock.now()).is_some() {
+                    let log_msg = format!(
+                        "Pausing the initial sync during the quiet hours ({quiet_hours}). It will \
+                         resume on the next sync after them."
+                    );
+                    log(&log_msg);
+                    yield UpdateMessage::Log(LogLevel::Info, log_msg);
+                    paused = true;
+                    break;
                 }
             }
-            yield UpdateMessage::Update {
-                schema_name: None,
-                table_name: value.table,
-                op_type: OpType::Upsert,
-                row: to_fivetran_row(value.fields)?,
+
+            let snapshot = checkpoint.as_ref().map(|c| c.0);
+            let cursor = checkpoint.as_ref().map(|c| c.1.clone());
+            let mut call = pin!(match prefetched.take() {
+                Some(handle) => FutureExt::boxed(handle),
+                None => fetch_snapshot_page(
+                    source.clone(),
+                    snapshot,
+                    cursor,
+                    page_size(&adaptive_page_size),
+                ),
+            });
+            let res = loop {
+                if let Some(res) = wait_or_heartbeat(call.as_mut(), &options).await {
+                    break res;
+                }
+                yield UpdateMessage::Heartbeat(format!(
+                    "Still waiting for a page of the initial sync from {source}"
+                ));
             };
-        }
+            let res = match res {
+                Ok(mut res) => {
+                    rate_limited_attempts = 0;
+                    qualify_component_tables(&mut res.values, options.sync_components);
+                    res
+                },
+                // The page is requested again from the last checkpoint once the
+                // delay asked for by the backend has elapsed.
+                Err(error)
+                    if rate_limited_attempts < MAX_RATE_LIMITED_ATTEMPTS
+                        && rate_limit_delay(&error).is_some() =>
+                {
+                    rate_limited_attempts += 1;
+                    let delay = rate_limit_delay(&error).unwrap_or_default();
+                    let log_msg = format!(
+                        "The initial sync of {source} was rate limited by the Convex backend, \
+                         retrying in {}s (attempt \
+                         {rate_limited_attempts}/{MAX_RATE_LIMITED_ATTEMPTS})",
+                        delay.as_secs_f64()
+                    );
+                    log_warning(&log_msg);
+                    yield UpdateMessage::Log(LogLevel::Warning, log_msg);
+                    options.clock.sleep(delay).await;
+                    continue;
+                },
+                // The deployment only retains snapshots for a while, so an initial
+                // sync paused for too long can’t be resumed. The tables synced so
+                // far are cleared and the sync starts again from a new snapshot.
+                // This is allowed even when resyncs are refused, since the
+                // destination is incomplete until the initial sync is done.
+                Err(error) if is_backend_error(&error, BackendErrorKind::SnapshotExpired) => {
+                    let log_msg = format!(
+                        "The snapshot {} of {source} has expired, so the initial sync can’t be \
+                         resumed. Starting it again from scratch.",
+                        snapshot.unwrap_or_default()
+                    );
+                    log_warning(&log_msg);
+                    yield UpdateMessage::Log(LogLevel::Warning, log_msg);
 
-        has_more = res.has_more;
-        if has_more {
-            let cursor = ListSnapshotCursor::from(
-                res.cursor.context("Missing cursor when has_more was set")?,
-            );
-            yield UpdateMessage::Checkpoint(State::create(
-                Checkpoint::InitialSync {
-                    snapshot: res.snapshot,
-                    cursor: cursor.clone(),
+                    for table_name in tables_seen.iter().collect::<BTreeSet<_>>() {
+                        for message in
+                            truncate_table(table_name, TruncateReason::SnapshotExpired, &options)
+                        {
+                            yield message;
+                        }
+                    }
+                    checkpoint = None;
+                    last_snapshot = None;
+                    documents_synced = documents_synced.map(|_| 0);
+                    tables_reached.clear();
+                    continue;
                 },
-                tables_seen.clone(),
+                Err(error) => Err(error)?,
+            };
+            log_debug(&format!(
+                "Fetched {} documents from the snapshot {} of {source}",
+                res.values.len(),
+                res.snapshot
+            ));
+            last_snapshot = Some(res.snapshot);
+            if prefetch_pages && res.has_more {
+                if let Some(ref next_cursor) = res.cursor {
+                    prefetched = Some(prefetch(fetch_snapshot_page(
+                        source.clone(),
+                        Some(res.snapshot),
+                        Some(ListSnapshotCursor::from(next_cursor.clone())),
+                        page_size(&adaptive_page_size),
+                    )));
+                }
+            }
+
+            rows_synced += res.values.len() as u64;
+            if let Some(ref mut documents_synced) = documents_synced {
+                *documents_synced += res.values.len() as u64;
+            }
+            for value in &res.values {
+                if !tables_reached.contains(&value.table) {
+                    tables_reached.insert(value.table.clone());
+                }
+            }
+            let page_documents = res.values.len();
+            let mut page_bytes = 0;
+            for mut value in res.values {
+                page_bytes += stats.record(&value.table, &value.fields);
+                for message in file_messages(
+                    &value,
+                    &mut tables_seen,
+                    TruncateReason::InitialSync,
+                    &row_options,
+                    &options,
+                )? {
+                    yield message;
+                }
+                if !options.table_filter.includes(&value.table) {
+                    continue;
+                }
+                if options.stats_only_tables.contains(&value.table) {
+                    table_stats.record(&value.table, value.deleted, &value.fields);
+                    continue;
+                }
+                // Issue truncates if we see a table for the first time.
+                if tables_seen.insert(value.table.clone()) {
+                    for message in
+                        truncate_table(&value.table, TruncateReason::InitialSync, &options)
+                    {
+                        yield message;
+                    }
+                }
+                if !options.row_filters.includes(&value.table, &value.fields) {
+                    continue;
+                }
+                mask_fields(&value.table, &mut value.fields, &options.column_masking);
+                let child_messages = child_table_messages(
+                    &value.table,
+                    &value.fields,
+                    &mut tables_seen,
+                    TruncateReason::InitialSync,
+                    &row_options,
+                    &options,
+                )?;
+                let mut row = to_fivetran_row(value.fields, &row_options)?;
+                convert_decimal_columns(&value.table, &mut row, &options.decimal_columns);
+                convert_column_types(&value.table, &mut row, &options.column_types);
+                hash_columns(&value.table, &mut row, &options.column_hashing);
+                if options.sanitize_column_names {
+                    let column_names = column_names
+                        .entry(value.table.clone())
+                        .or_insert_with(|| schema_column_names(&schema_cache, &value.table));
+                    sanitize_column_names(&mut row, column_names);
+                }
+                yield UpdateMessage::Update {
+                    schema_name: options.schema_name.clone(),
+                    table_name: value.table,
+                    op_type: OpType::Upsert,
+                    row,
+                };
+                for message in child_messages {
+                    yield message;
+                }
+            }
+
+            if let Some(ref mut adaptive_page_size) = adaptive_page_size {
+                let previous_page_size = adaptive_page_size.page_size();
+                adaptive_page_size.record(page_documents, page_bytes);
+                if adaptive_page_size.page_size() != previous_page_size {
+                    log_debug(&format!(
+                        "Adjusted the page size from {previous_page_size} to {} documents after \
+                         a page of {page_bytes} bytes",
+                        adaptive_page_size.page_size()
+                    ));
+                }
+            }
+
+            has_more = res.has_more;
+            if has_more {
+                let cursor = ListSnapshotCursor::from(
+                    res.cursor.context("Missing cursor when has_more was set")?,
+                );
+                let stats_rows =
+                    table_stats_rows(&mut table_stats, "initial_sync", &cursor.0, &options);
+                for message in stats_rows {
+                    yield message;
+                }
+                record_checkpoint(&cursor.0);
+                yield UpdateMessage::Checkpoint(State {
+                    column_names: column_names.clone(),
+                    ..State::create(
+                        Checkpoint::InitialSync {
+                            snapshot: res.snapshot,
+                            cursor: cursor.clone(),
+                            page_size: options.page_size,
+                            documents_synced,
+                        },
+                        tables_seen.clone(),
+                    )
+                });
+                checkpoint = Some((res.snapshot, cursor));
+
+                if let Some(mut log_msg) = stats.report_if_due(options.clock.now()) {
+                    if let Some(ref deployment_tables) = deployment_tables {
+                        log_msg += &format!(
+                            ". {}",
+                            estimated_progress(
+                                tables_reached.len(),
+                                deployment_tables.len(),
+                                documents_synced
+                            )
+                        );
+                    }
+                    log(&log_msg);
+                    yield UpdateMessage::Log(LogLevel::Info, log_msg);
+                }
+
+                if let Some(page_pacing) = options.page_pacing {
+                    options.clock.sleep(page_pacing).await;
+                }
+
+                let elapsed = options
+                    .clock
+                    .now()
+                    .duration_since(started_at)
+                    .unwrap_or_default();
+                if let Some(delay) = options.backfill_throttle.rate_delay(rows_synced, elapsed) {
+                    options.clock.sleep(delay).await;
+                }
+            }
+        }
+
+        let log_msg = stats.summary(options.clock.now());
+        log(&log_msg);
+        yield UpdateMessage::Log(LogLevel::Info, log_msg);
+
+        if !paused {
+            if !resumed && rows_synced == 0 {
+                let log_msg = format!(
+                    "The deployment {source} has no documents yet. Its changes will be synced \
+                     from now on."
+                );
+                log(&log_msg);
+                yield UpdateMessage::Log(LogLevel::Info, log_msg);
+            }
+            let snapshot =
+                last_snapshot.context("list_snapshot lacking a snapshot for checkpoint")?;
+            let cursor = DocumentDeltasCursor::from(snapshot);
+            let position = cursor.to_string();
+            for message in table_stats_rows(&mut table_stats, "initial_sync", &position, &options) {
+                yield message;
+            }
+            record_checkpoint(&position);
+            yield UpdateMessage::Checkpoint(State {
+                column_names,
+                ..State::create(Checkpoint::DeltaUpdates { cursor }, tables_seen)
+            });
+
+            yield UpdateMessage::Log(LogLevel::Info, "Initial sync successful".to_string());
+            log(&format!(
+                "Initial sync from {source} successful at cursor {cursor}."
             ));
-            checkpoint = Some((res.snapshot, cursor));
         }
     }
+}
 
-    let (snapshot, _) = checkpoint.context("list_snapshot lacking a snapshot for checkpoint")?;
-    let cursor = DocumentDeltasCursor::from(snapshot);
-    yield UpdateMessage::Checkpoint(State::create(
-        Checkpoint::DeltaUpdates { cursor },
-        tables_seen,
-    ));
+/// Drops the documents of the tables of components, unless components are
+/// synced, in which case their tables are named like [`component_table_name`]
+/// does, so that they can’t be mistaken for the tables of the root component.
+fn qualify_component_tables(values: &mut Vec<SnapshotValue>, sync_components: bool) {
+    values.retain(|value| sync_components || value.component.is_empty());
+    for value in values {
+        if !value.component.is_empty() {
+            value.table = component_table_name(&value.component, &value.table);
+        }
+    }
+}
 
-    yield UpdateMessage::Log(LogLevel::Info, "Initial sync successful".to_string());
-    log(&format!(
-        "Initial sync from {source} successful at cursor {cursor}."
-    ));
+/// The positions of the changes of a page that are superseded by a later
+/// revision of the same document in the page. Only the last revision of each
+/// document is emitted, since the destination would overwrite the previous
+/// ones anyway.
+fn superseded_revisions(values: &[SnapshotValue]) -> HashSet<usize> {
+    let mut last_revisions: HashMap<(&str, &str), usize> = HashMap::new();
+    let mut superseded = HashSet::new();
+    for (index, value) in values.iter().enumerate() {
+        let Some(id) = value.fields.get("_id").and_then(JsonValue::as_str) else {
+            continue;
+        };
+        if let Some(previous_index) = last_revisions.insert((&value.table, id), index) {
+            superseded.insert(previous_index);
+        }
+    }
+    superseded
+}
+
+/// Describes how far along the initial sync is, given the number of tables
+/// that the snapshot reached. The last table reached is still being synced,
+/// and tables may be created during the sync, so this never claims the sync
+/// is complete.
+fn estimated_progress(
+    tables_reached: usize,
+    total_tables: usize,
+    documents_synced: Option<u64>,
+) -> String {
+    let tables_synced = tables_reached.saturating_sub(1);
+    let percent = match total_tables {
+        0 => 99,
+        total => (tables_synced * 100 / total).min(99),
+    };
+    let documents = match documents_synced {
+        Some(documents_synced) => format!(", {documents_synced} documents"),
+        None => String::new(),
+    };
+    format!(
+        "The initial sync is about {percent}% done ({tables_synced} of {total_tables} tables \
+         synced{documents})"
+    )
 }
 
 /// Synchronizes the changes that happened after an initial synchronization or
 /// delta synchronization has been completed.
-#[try_stream(ok = UpdateMessage, error = anyhow::Error)]
-async fn delta_sync(
-    source: impl Source,
+fn delta_sync(
+    source: impl Source + 'static,
     cursor: DocumentDeltasCursor,
-    mut tables_seen: Option<HashSet<String>>,
-) {
-    yield UpdateMessage::Log(
-        LogLevel::Info,
-        format!("Starting to apply changes from {source} starting at {cursor}"),
-    );
-    log(&format!("Delta sync from {source} starting at {cursor}."));
+    mut tables_seen: HashSet<String>,
+    mut column_names: BTreeMap<String, ColumnNames>,
+    options: SyncOptions,
+    schema_cache: SchemaCache,
+) -> impl Stream<Item = anyhow::Result<UpdateMessage>> {
+    try_stream! {
+        log(&format!("Delta sync from {source} starting at {cursor}."));
 
-    let mut cursor = cursor;
-    let mut has_more = true;
-    while has_more {
-        let response = source.document_deltas(cursor, None).await?;
+        let start_cursor = cursor;
+        let mut cursor = cursor;
+        // Syncs finding no changes, which are most of the syncs of connections
+        // scheduled every few minutes, only emit the checkpoint of the new
+        // cursor (if it moved), without logs or redundant checkpoints, so they
+        // end as soon as the deployment responds.
+        let mut has_changes = false;
+        let mut checkpointed_cursor = cursor;
+        let mut has_more = true;
+        let mut cursor_expired = false;
+        let mut stats = SyncStats::new(options.clock.now());
+        let mut table_stats = TableStats::default();
+        let row_options = RowOptions {
+            format: source.export_format(),
+            max_fields: options.max_fields,
+            max_nesting_depth: options.max_nesting_depth,
+            presence_columns: options.presence_columns,
+            timestamp_column: options.timestamp_column,
+            exclude_system_columns: options.exclude_system_columns,
+            child_tables: options.child_tables,
+            nested_objects: options.nested_objects,
+            non_finite_floats: options.non_finite_floats,
+            detect_dates: options.detect_dates,
+        };
+        let mut last_schema_refresh = options.clock.now();
+        while has_more {
+            // Transient errors are retried from the last checkpoint (emitted
+            // after the previous page) rather than failing the whole sync.
+            let mut failed_attempts = 0;
+            let mut rate_limited_attempts = 0;
+            let response = loop {
+                let mut call = pin!(source
+                    .document_deltas(cursor, None)
+                    .instrument(tracing::info_span!(
+                        "document_deltas_page",
+                        cursor = i64::from(cursor)
+                    )));
+                let result = loop {
+                    if let Some(result) = wait_or_heartbeat(call.as_mut(), &options).await {
+                        break result;
+                    }
+                    yield UpdateMessage::Heartbeat(format!(
+                        "Still waiting for changes from {source} after {cursor}"
+                    ));
+                };
+                match result {
+                    Ok(response) => break Some(response),
+                    // Unless resyncs are refused, in which case the error
+                    // explains how to trigger one.
+                    Err(error)
+                        if is_backend_error(&error, BackendErrorKind::InvalidCursor)
+                            && !options.refuse_resync =>
+                    {
+                        break None;
+                    },
+                    // Rate limiting isn’t counted as a failure: the backend says
+                    // how long to wait for.
+                    Err(error)
+                        if rate_limited_attempts < MAX_RATE_LIMITED_ATTEMPTS
+                            && rate_limit_delay(&error).is_some() =>
+                    {
+                        rate_limited_attempts += 1;
+                        let delay = rate_limit_delay(&error).unwrap_or_default();
+                        let log_msg = format!(
+                            "Fetching changes from {source} after {cursor} was rate limited by \
+                             the Convex backend, retrying in {}s (attempt \
+                             {rate_limited_attempts}/{MAX_RATE_LIMITED_ATTEMPTS})",
+                            delay.as_secs_f64()
+                        );
+                        log_warning(&log_msg);
+                        yield UpdateMessage::Log(LogLevel::Warning, log_msg);
+                        options.clock.sleep(delay).await;
+                    },
+                    Err(error) if failed_attempts < MAX_TRANSIENT_RETRIES
+                        && is_transient(&error) =>
+                    {
+                        failed_attempts += 1;
+                        let delay = transient_retry_delay(failed_attempts);
+                        let log_msg = format!(
+                            "Fetching changes from {source} after {cursor} failed with a \
+                             transient error, retrying in {}s (attempt \
+                             {failed_attempts}/{MAX_TRANSIENT_RETRIES}): {error}",
+                            delay.as_secs()
+                        );
+                        log_warning(&log_msg);
+                        yield UpdateMessage::Log(LogLevel::Warning, log_msg);
+                        options.clock.sleep(delay).await;
+                    },
+                    Err(error) => Err(error)?,
+                }
+            };
+            let Some(mut response) = response else {
+                cursor_expired = true;
+                break;
+            };
+            qualify_component_tables(&mut response.values, options.sync_components);
+            log_debug(&format!(
+                "Fetched {} changes from {source} after {cursor}",
+                response.values.len()
+            ));
+            // The columns of the page missing from the schema cache, by table.
+            let mut unknown_columns: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+            if !has_changes && !response.values.is_empty() {
+                has_changes = true;
+                yield UpdateMessage::Log(
+                    LogLevel::Info,
+                    format!("Starting to apply changes from {source} starting at {start_cursor}"),
+                );
+            }
 
-        for value in response.values {
-            if let Some(ref mut tables_seen) = tables_seen {
+            let superseded = superseded_revisions(&response.values);
+            if !superseded.is_empty() {
+                log_debug(&format!(
+                    "Skipping {} revisions superseded by a later revision in the same page",
+                    superseded.len()
+                ));
+            }
+            for (index, mut value) in response.values.into_iter().enumerate() {
+                stats.record(&value.table, &value.fields);
+                if !superseded.contains(&index) {
+                    for message in file_messages(
+                        &value,
+                        &mut tables_seen,
+                        TruncateReason::NewTable,
+                        &row_options,
+                        &options,
+                    )? {
+                        yield message;
+                    }
+                }
+                if !options.table_filter.includes(&value.table) {
+                    continue;
+                }
+                if options.stats_only_tables.contains(&value.table) {
+                    table_stats.record(&value.table, value.deleted, &value.fields);
+                    continue;
+                }
+                if superseded.contains(&index) {
+                    continue;
+                }
                 // Issue truncates if we see a table for the first time.
-                // Skip the behavior for legacy state.json - where tables_seen wasn't tracked.
-                if !tables_seen.contains(&value.table) {
-                    tables_seen.insert(value.table.clone());
-                    yield UpdateMessage::Update {
-                        schema_name: None,
-                        table_name: value.table.clone(),
-                        op_type: OpType::Truncate,
-                        row: HashMap::new(),
-                    };
+                if tables_seen.insert(value.table.clone()) {
+                    for message in
+                        truncate_table(&value.table, TruncateReason::NewTable, &options)
+                    {
+                        yield message;
+                    }
+                }
+
+                // The document may have matched its filter before this
+                // change, so documents that don’t are deleted rather than
+                // skipped.
+                if !value.deleted && !options.row_filters.includes(&value.table, &value.fields) {
+                    value
+                        .fields
+                        .retain(|field_name, _| field_name == "_id" || field_name == "_ts");
+                    value.deleted = true;
+                }
+                mask_fields(&value.table, &mut value.fields, &options.column_masking);
+                let child_messages = child_table_messages(
+                    &value.table,
+                    &value.fields,
+                    &mut tables_seen,
+                    TruncateReason::NewTable,
+                    &row_options,
+                    &options,
+                )?;
+                let mut row = to_fivetran_row(value.fields, &row_options)?;
+                convert_decimal_columns(&value.table, &mut row, &options.decimal_columns);
+                convert_column_types(&value.table, &mut row, &options.column_types);
+                hash_columns(&value.table, &mut row, &options.column_hashing);
+
+                // Columns we have never seen before are added to the schema
+                // cache after the page, once the schema has been refreshed.
+                if !value.deleted && !schema_cache.contains(&value.table, row.keys()) {
+                    unknown_columns
+                        .entry(value.table.clone())
+                        .or_default()
+                        .extend(row.keys().cloned());
+                }
+                if options.sanitize_column_names {
+                    let column_names = column_names
+                        .entry(value.table.clone())
+                        .or_insert_with(|| schema_column_names(&schema_cache, &value.table));
+                    sanitize_column_names(&mut row, column_names);
+                }
+
+                // Soft deletes are partial updates, so that the row keeps the
+                // last values of the document rather than only its ID.
+                let op_type = match (value.deleted, options.soft_deletes) {
+                    (false, _) => OpType::Upsert,
+                    (true, false) => OpType::Delete,
+                    (true, true) => {
+                        row.insert(
+                            SOFT_DELETE_COLUMN_NAME.to_string(),
+                            FivetranValue::Bool(true),
+                        );
+                        OpType::Update
+                    },
+                };
+                yield UpdateMessage::Update {
+                    schema_name: options.schema_name.clone(),
+                    table_name: value.table,
+                    op_type,
+                    row,
+                };
+                for message in child_messages {
+                    yield message;
                 }
             }
 
-            yield UpdateMessage::Update {
-                schema_name: None,
-                table_name: value.table,
-                op_type: if value.deleted {
-                    OpType::Delete
-                } else {
-                    OpType::Upsert
-                },
-                row: to_fivetran_row(value.fields)?,
-            };
+            cursor = DocumentDeltasCursor::from(response.cursor);
+            has_more = response.has_more;
+
+            // The schema is refreshed after the pages with columns we have
+            // never seen before, and regularly during long syncs, since the
+            // documents of tables and fields created during the sync are
+            // synced right away.
+            let now = options.clock.now();
+            let refresh_due = options.schema_refresh_interval.is_some_and(|interval| {
+                now.duration_since(last_schema_refresh).unwrap_or_default() >= interval
+            });
+            if refresh_due || !unknown_columns.is_empty() {
+                last_schema_refresh = now;
+                for message in refresh_schema_cache(&source, &schema_cache, &options).await {
+                    yield message;
+                }
+                for (table_name, columns) in &unknown_columns {
+                    schema_cache.insert(table_name, columns.iter());
+                }
+            }
+
+            let position = cursor.to_string();
+            for message in table_stats_rows(&mut table_stats, "delta_sync", &position, &options) {
+                yield message;
+            }
+
+            // It is safe to take a snapshot here, because document_deltas
+            // guarantees that the state given by one call is consistent.
+            if cursor != checkpointed_cursor {
+                checkpointed_cursor = cursor;
+                record_checkpoint(&position);
+                yield UpdateMessage::Checkpoint(State {
+                    column_names: column_names.clone(),
+                    ..State::create(Checkpoint::DeltaUpdates { cursor }, tables_seen.clone())
+                });
+            }
+
+            if let Some(log_msg) = stats.report_if_due(options.clock.now()) {
+                log(&log_msg);
+                yield UpdateMessage::Log(LogLevel::Info, log_msg);
+            }
+        }
+
+        if cursor_expired {
+            // The changes since the cursor are lost, so the destination can only
+            // be brought back in sync by syncing everything again. The tables
+            // known so far are cleared first, since some of them may no longer
+            // exist in the deployment.
+            let log_msg = format!(
+                "The cursor {cursor} is too old to fetch the changes from {source} since the \
+                 last sync. Syncing all data again from scratch."
+            );
+            log_warning(&log_msg);
+            yield UpdateMessage::Log(LogLevel::Warning, log_msg);
+
+            for table_name in tables_seen.iter().collect::<BTreeSet<_>>() {
+                for message in truncate_table(table_name, TruncateReason::CursorExpired, &options) {
+                    yield message;
+                }
+            }
+
+            let mut resync = pin!(initial_sync(
+                source,
+                None,
+                None,
+                Some(0),
+                tables_seen,
+                column_names,
+                options,
+                schema_cache.clone(),
+            ));
+            while let Some(message) = resync.next().await {
+                yield message?;
+            }
+        } else if !has_changes {
+            log_debug(&format!("No changes in {source} since {start_cursor}"));
+        } else {
+            let log_msg = stats.summary(options.clock.now());
+            log(&log_msg);
+            yield UpdateMessage::Log(LogLevel::Info, log_msg);
+            yield UpdateMessage::Log(LogLevel::Info, "Changes applied".to_string());
+            log(&format!(
+                "Delta sync changes applied from {source}. Final cursor {cursor}"
+            ));
         }
+    }
+}
+
+#[cfg(test)]
+mod update_message_encoding_tests {
+    use std::collections::BTreeMap;
 
-        cursor = DocumentDeltasCursor::from(response.cursor);
-        has_more = response.has_more;
+    use convex::Value as ConvexValue;
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::redact::redact;
+
+    fn log_level() -> impl Strategy<Value = LogLevel> {
+        prop_oneof![
+            Just(LogLevel::Info),
+            Just(LogLevel::Warning),
+            Just(LogLevel::Severe),
+        ]
+    }
+
+    fn op_type() -> impl Strategy<Value = OpType> {
+        prop_oneof![
+            Just(OpType::Upsert),
+            Just(OpType::Update),
+            Just(OpType::Delete),
+            Just(OpType::Truncate),
+        ]
+    }
 
-        // It is safe to take a snapshot here, because document_deltas
-        // guarantees that the state given by one call is consistent.
-        yield UpdateMessage::Checkpoint(State::create(
-            Checkpoint::DeltaUpdates { cursor },
-            tables_seen.clone(),
-        ));
+    fn update_message() -> impl Strategy<Value = UpdateMessage> {
+        prop_oneof![
+            (log_level(), any::<String>())
+                .prop_map(|(level, message)| UpdateMessage::Log(level, message)),
+            any::<String>().prop_map(UpdateMessage::Heartbeat),
+            (
+                any::<Option<String>>(),
+                any::<String>(),
+                op_type(),
+                prop::collection::hash_map(
+                    any::<String>(),
+                    any::<ConvexValue>().prop_map(FivetranValue::from),
+                    0..8
+                ),
+            )
+                .prop_map(|(schema_name, table_name, op_type, row)| {
+                    UpdateMessage::Update {
+                        schema_name,
+                        table_name,
+                        op_type,
+                        row,
+                    }
+                }),
+            any::<State>().prop_map(UpdateMessage::Checkpoint),
+        ]
     }
 
-    yield UpdateMessage::Log(LogLevel::Info, "Changes applied".to_string());
-    log(&format!(
-        "Delta sync changes applied from {source}. Final cursor {cursor}"
-    ));
+    proptest! {
+        #![proptest_config(ProptestConfig {
+            failure_persistence: None, ..ProptestConfig::default()
+        })]
+        #[test]
+        fn update_messages_encode_faithfully(message in update_message()) {
+            let response = FivetranUpdateResponse::from(message.clone()).response;
+            match (message, response) {
+                (
+                    UpdateMessage::Log(level, _),
+                    Some(update_response::Response::LogEntry(entry)),
+                ) => {
+                    prop_assert_eq!(entry.level, level as i32);
+                    prop_assert_eq!(redact(&entry.message), entry.message);
+                },
+                (
+                    UpdateMessage::Heartbeat(_),
+                    Some(update_response::Response::LogEntry(entry)),
+                ) => {
+                    prop_assert_eq!(entry.level, LogLevel::Info as i32);
+                    prop_assert_eq!(redact(&entry.message), entry.message);
+                },
+                (
+                    UpdateMessage::Update {
+                        schema_name,
+                        table_name,
+                        op_type,
+                        row,
+                    },
+                    Some(update_response::Response::Operation(Operation {
+                        op: Some(Op::Record(record)),
+                    })),
+                ) => {
+                    prop_assert_eq!(record.schema_name, schema_name);
+                    prop_assert_eq!(record.table_name, table_name);
+                    prop_assert_eq!(record.r#type, op_type as i32);
+                    // Compared through their debug representation since NaN != NaN
+                    let data: BTreeMap<String, FivetranValue> = record
+                        .data
+                        .into_iter()
+                        .map(|(field_name, value)| (field_name, value.inner.expect("Empty value")))
+                        .collect();
+                    let row: BTreeMap<String, FivetranValue> = row.into_iter().collect();
+                    prop_assert_eq!(format!("{data:?}"), format!("{row:?}"));
+                },
+                (
+                    UpdateMessage::Checkpoint(state),
+                    Some(update_response::Response::Operation(Operation {
+                        op: Some(Op::Checkpoint(checkpoint)),
+                    })),
+                ) => {
+                    let decoded: State = serde_json::from_str(&checkpoint.state_json).unwrap();
+                    prop_assert_eq!(decoded, state);
+                },
+                (message, response) => {
+                    prop_assert!(false, "{message:?} was encoded as {response:?}");
+                },
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod state_serialization_tests {
+    use std::collections::HashSet;
+
     use proptest::prelude::*;
+    use serde_json::json;
 
-    use crate::sync::{
-        Checkpoint,
-        State,
+    use crate::{
+        sync::{
+            Checkpoint,
+            State,
+            CURSOR_VERSION,
+        },
+        testing::FakeSource,
     };
 
     proptest! {
@@ -323,6 +2074,13 @@ mod state_serialization_tests {
             let json = serde_json::to_string(&value).unwrap();
             prop_assert_eq!(value, serde_json::from_str(&json).unwrap());
         }
+
+        #[test]
+        fn current_states_are_unchanged_by_migrations(checkpoint in any::<Checkpoint>()) {
+            let state = State::create(checkpoint, HashSet::new());
+            let json = serde_json::to_value(&state).unwrap();
+            prop_assert_eq!(State::migrate(json).unwrap(), state);
+        }
     }
 
     #[test]
@@ -339,36 +2097,178 @@ mod state_serialization_tests {
     }
 
     #[test]
-    fn deserializes_v1_initial_sync_checkpoints() {
+    fn migrates_v1_initial_sync_checkpoints() {
         assert_eq!(
-            serde_json::from_str::<State>(
-                "{ \"version\": 1, \"checkpoint\": { \"InitialSync\": { \"snapshot\": 42, \
-                 \"cursor\": \"abc123\" } } }"
-            )
+            State::migrate(json!({
+                "version": 1,
+                "checkpoint": { "InitialSync": { "snapshot": 42, "cursor": "abc123" } },
+                "tablesSeen": [],
+            }))
             .unwrap(),
-            State {
-                version: 1,
-                checkpoint: Checkpoint::InitialSync {
+            State::create(
+                Checkpoint::InitialSync {
                     snapshot: 42,
                     cursor: String::from("abc123").into(),
+                    page_size: None,
+                    documents_synced: None,
                 },
-                tables_seen: None,
-            },
+                HashSet::new(),
+            ),
+        );
+        assert_eq!(
+            State::migrate(json!({
+                "version": 1,
+                "checkpoint": {
+                    "InitialSync": {
+                        "snapshot": 42,
+                        "cursor": "abc123",
+                        "page_size": 100,
+                        "documents_synced": 1000,
+                    },
+                },
+                "tablesSeen": ["messages"],
+            }))
+            .unwrap(),
+            State::create(
+                Checkpoint::InitialSync {
+                    snapshot: 42,
+                    cursor: String::from("abc123").into(),
+                    page_size: Some(100),
+                    documents_synced: Some(1000),
+                },
+                ["messages".to_string()].into(),
+            ),
         );
     }
 
     #[test]
-    fn deserializes_v1_delta_update_checkpoints() {
+    fn migrates_v1_delta_update_checkpoints() {
         assert_eq!(
-            serde_json::from_str::<State>(
-                "{ \"version\": 1, \"checkpoint\": { \"DeltaUpdates\": { \"cursor\": 42 } } }"
-            )
+            State::migrate(json!({
+                "version": 1,
+                "checkpoint": { "DeltaUpdates": { "cursor": 42 } },
+                "tablesSeen": ["messages"],
+            }))
+            .unwrap(),
+            State::create(
+                Checkpoint::DeltaUpdates { cursor: 42.into() },
+                ["messages".to_string()].into(),
+            ),
+        );
+    }
+
+    #[test]
+    fn migrates_the_states_of_additional_deployments() {
+        let mut expected_state = State::create(
+            Checkpoint::DeltaUpdates { cursor: 42.into() },
+            ["messages".to_string()].into(),
+        );
+        expected_state.deployments.insert(
+            "staging".to_string(),
+            State::create(
+                Checkpoint::DeltaUpdates { cursor: 7.into() },
+                ["jobs".to_string()].into(),
+            ),
+        );
+        assert_eq!(
+            State::migrate(json!({
+                "version": 1,
+                "checkpoint": { "DeltaUpdates": { "cursor": 42 } },
+                "tablesSeen": ["messages"],
+                "deployments": {
+                    "staging": {
+                        "version": 1,
+                        "checkpoint": { "DeltaUpdates": { "cursor": 7 } },
+                        "tablesSeen": ["jobs"],
+                    },
+                },
+            }))
             .unwrap(),
-            State {
-                version: 1,
-                checkpoint: Checkpoint::DeltaUpdates { cursor: 42.into() },
-                tables_seen: None,
+            expected_state,
+        );
+    }
+
+    #[tokio::test]
+    async fn backfills_the_tables_seen_of_legacy_states() {
+        for legacy_state in [
+            json!({
+                "version": 1,
+                "checkpoint": { "DeltaUpdates": { "cursor": 42 } },
+            }),
+            json!({
+                "version": 2,
+                "checkpoint": { "DeltaUpdates": { "cursor": 42 } },
+                "tablesSeen": null,
+            }),
+        ] {
+            assert!(State::migrate(legacy_state.clone()).is_err());
+            assert_eq!(
+                State::upgrade(legacy_state, &FakeSource::seeded())
+                    .await
+                    .unwrap(),
+                State::create(
+                    Checkpoint::DeltaUpdates { cursor: 42.into() },
+                    ["table1", "table2", "table3"].map(String::from).into(),
+                ),
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn doesnt_backfill_the_tables_seen_of_current_states() {
+        let state = State::create(
+            Checkpoint::DeltaUpdates { cursor: 42.into() },
+            ["messages".to_string()].into(),
+        );
+        assert_eq!(
+            State::upgrade(serde_json::to_value(&state).unwrap(), &FakeSource::seeded())
+                .await
+                .unwrap(),
+            state,
+        );
+    }
+
+    #[test]
+    fn serializes_initial_sync_checkpoints_in_camel_case() {
+        let state = State::create(
+            Checkpoint::InitialSync {
+                snapshot: 42,
+                cursor: String::from("abc123").into(),
+                page_size: Some(100),
+                documents_synced: Some(1000),
             },
+            HashSet::new(),
+        );
+        assert_eq!(
+            serde_json::to_value(&state).unwrap(),
+            json!({
+                "version": 3,
+                "checkpoint": {
+                    "InitialSync": {
+                        "snapshot": 42,
+                        "cursor": "abc123",
+                        "pageSize": 100,
+                        "documentsSynced": 1000,
+                    },
+                },
+                "tablesSeen": [],
+            }),
         );
     }
+
+    #[test]
+    fn refuses_states_of_unsupported_versions() {
+        for version in [0, CURSOR_VERSION + 1] {
+            assert!(State::migrate(json!({
+                "version": version,
+                "checkpoint": { "DeltaUpdates": { "cursor": 42 } },
+                "tablesSeen": [],
+            }))
+            .is_err());
+        }
+        assert!(State::migrate(json!({
+            "checkpoint": { "DeltaUpdates": { "cursor": 42 } },
+        }))
+        .is_err());
+    }
 }
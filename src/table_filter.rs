@@ -0,0 +1,107 @@
+//! The selection of the tables of a deployment that are synced, so that
+//! internal or scratch tables never reach the destination.
+
+use regex::Regex;
+
+/// Include and exclude patterns matched against table names. A pattern is
+/// either a glob (`scratch_*`, where `*` matches any characters and `?` a
+/// single one) or a regular expression between slashes (`/^tmp_\d+$/`).
+#[derive(Debug, Clone, Default)]
+pub struct TableFilter {
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+}
+
+impl TableFilter {
+    /// A filter keeping the tables matching one of the include patterns (or
+    /// every table if there is none), except those matching one of the
+    /// exclude patterns.
+    pub fn new(include: Vec<Regex>, exclude: Vec<Regex>) -> Self {
+        TableFilter { include, exclude }
+    }
+
+    /// Whether the table is synced.
+    pub fn includes(&self, table_name: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|regex| regex.is_match(table_name)))
+            && !self.exclude.iter().any(|regex| regex.is_match(table_name))
+    }
+
+    /// Whether every table is synced.
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+}
+
+/// Parses a table pattern, returning `None` if it is invalid.
+pub fn parse_table_pattern(pattern: &str) -> Option<Regex> {
+    if let Some(regex) = pattern
+        .strip_prefix('/')
+        .and_then(|pattern| pattern.strip_suffix('/'))
+    {
+        return Regex::new(regex).ok();
+    }
+
+    let mut regex = String::from("^");
+    for c in pattern.chars() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            // Slashes separate the path of a component from its tables.
+            c if c.is_ascii_alphanumeric() || c == '_' || c == '/' => regex.push(c),
+            _ => return None,
+        }
+    }
+    regex.push('$');
+    Regex::new(&regex).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(include: &[&str], exclude: &[&str]) -> TableFilter {
+        let patterns = |patterns: &[&str]| {
+            patterns
+                .iter()
+                .map(|pattern| parse_table_pattern(pattern).unwrap())
+                .collect()
+        };
+        TableFilter::new(patterns(include), patterns(exclude))
+    }
+
+    #[test]
+    fn matches_globs_and_regular_expressions() {
+        let filter = filter(&["messages", "user*"], &["*_tmp", r"/^users_\d+$/"]);
+
+        assert!(filter.includes("messages"));
+        assert!(filter.includes("users"));
+        assert!(filter.includes("user_profiles"));
+        assert!(!filter.includes("messages_archive"));
+        assert!(!filter.includes("users_tmp"));
+        assert!(!filter.includes("users_2024"));
+    }
+
+    #[test]
+    fn includes_every_table_by_default() {
+        let filter = filter(&[], &["scratch?"]);
+
+        assert!(filter.includes("messages"));
+        assert!(filter.includes("scratch"));
+        assert!(!filter.includes("scratch1"));
+        assert!(TableFilter::default().is_empty());
+    }
+
+    #[test]
+    fn matches_the_tables_of_components() {
+        let filter = filter(&["workpool/*"], &[]);
+
+        assert!(filter.includes("workpool/jobs"));
+        assert!(!filter.includes("jobs"));
+    }
+
+    #[test]
+    fn refuses_invalid_patterns() {
+        assert!(parse_table_pattern("messages.body").is_none());
+        assert!(parse_table_pattern("/(/").is_none());
+    }
+}
@@ -0,0 +1,193 @@
+//! The `_convex_table_stats` table, which receives aggregate statistics about
+//! the tables synced as statistics only instead of their documents.
+
+use std::{
+    collections::{
+        BTreeMap,
+        HashMap,
+    },
+    time::SystemTime,
+};
+
+use maplit::hashmap;
+use prost_types::Timestamp;
+use serde_json::Value as JsonValue;
+
+use crate::{
+    convert::timestamp_from_ms,
+    fivetran_sdk::{
+        value_type::Inner as FivetranValue,
+        Column,
+        DataType,
+        Table,
+    },
+};
+
+pub const TABLE_STATS_TABLE_NAME: &str = "_convex_table_stats";
+
+/// The schema of the table statistics table, as declared in the schema
+/// endpoint.
+pub fn table_stats_table() -> Table {
+    let column = |name: &str, data_type: DataType| Column {
+        name: name.to_string(),
+        r#type: data_type as i32,
+        primary_key: name == "_id",
+        decimal: None,
+    };
+
+    Table {
+        name: TABLE_STATS_TABLE_NAME.to_string(),
+        columns: vec![
+            column("_id", DataType::String),
+            column("table_name", DataType::String),
+            column("phase", DataType::String),
+            column("upserts", DataType::Long),
+            column("deletes", DataType::Long),
+            column("min_creation_time", DataType::UtcDatetime),
+            column("max_creation_time", DataType::UtcDatetime),
+            column("timestamp", DataType::UtcDatetime),
+        ],
+    }
+}
+
+/// The documents of a table received since the last statistics row.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct Aggregate {
+    upserts: u64,
+    deletes: u64,
+    /// The range of `_creationTime` of the upserted documents, in milliseconds.
+    creation_time_range: Option<(f64, f64)>,
+}
+
+/// Aggregates the documents of the tables synced as statistics only. Every
+/// time rows are taken, a new row per table (which can be summed with the
+/// previous ones) describes the documents received since the last rows.
+#[derive(Debug, Default)]
+pub struct TableStats {
+    tables: BTreeMap<String, Aggregate>,
+}
+
+impl TableStats {
+    pub fn record(&mut self, table: &str, deleted: bool, fields: &HashMap<String, JsonValue>) {
+        let aggregate = self.tables.entry(table.to_string()).or_default();
+        if deleted {
+            aggregate.deletes += 1;
+            return;
+        }
+        aggregate.upserts += 1;
+        if let Some(creation_time) = fields.get("_creationTime").and_then(JsonValue::as_f64) {
+            aggregate.creation_time_range = Some(match aggregate.creation_time_range {
+                None => (creation_time, creation_time),
+                Some((min, max)) => (min.min(creation_time), max.max(creation_time)),
+            });
+        }
+    }
+
+    /// The rows describing the documents received since the last call, for
+    /// the given phase of the sync (e.g. `initial_sync`). The rows are
+    /// identified by the position of the sync (the cursor of the checkpoint
+    /// they precede), so that rows sent again after a sync is retried from
+    /// the same checkpoint replace the previous ones.
+    pub fn take_rows(
+        &mut self,
+        phase: &str,
+        position: &str,
+        now: SystemTime,
+    ) -> Vec<HashMap<String, FivetranValue>> {
+        let timestamp = Timestamp::from(now);
+        std::mem::take(&mut self.tables)
+            .into_iter()
+            .map(|(table_name, aggregate)| {
+                let creation_time = |milliseconds: Option<f64>| match milliseconds {
+                    Some(milliseconds) => {
+                        FivetranValue::UtcDatetime(timestamp_from_ms(milliseconds))
+                    },
+                    None => FivetranValue::Null(true),
+                };
+                let range = aggregate.creation_time_range;
+                hashmap! {
+                    "_id".to_string() => FivetranValue::String(format!(
+                        "{table_name}:{phase}:{position}"
+                    )),
+                    "table_name".to_string() => FivetranValue::String(table_name),
+                    "phase".to_string() => FivetranValue::String(phase.to_string()),
+                    "upserts".to_string() => FivetranValue::Long(aggregate.upserts as i64),
+                    "deletes".to_string() => FivetranValue::Long(aggregate.deletes as i64),
+                    "min_creation_time".to_string() => creation_time(range.map(|(min, _)| min)),
+                    "max_creation_time".to_string() => creation_time(range.map(|(_, max)| max)),
+                    "timestamp".to_string() => FivetranValue::UtcDatetime(timestamp.clone()),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn aggregates_documents_by_table() {
+        let mut stats = TableStats::default();
+        for creation_time in [2000.0, 1000.0, 3000.0] {
+            stats.record(
+                "events",
+                false,
+                &hashmap! { "_creationTime".to_string() => json!(creation_time) },
+            );
+        }
+        stats.record("events", true, &hashmap! {});
+        stats.record("logs", true, &hashmap! {});
+
+        let rows = stats.take_rows("delta_sync", "42", SystemTime::UNIX_EPOCH);
+
+        assert_eq!(rows.len(), 2);
+        let events = &rows[0];
+        assert_eq!(
+            events.get("_id"),
+            Some(&FivetranValue::String("events:delta_sync:42".to_string()))
+        );
+        assert_eq!(events.get("upserts"), Some(&FivetranValue::Long(3)));
+        assert_eq!(events.get("deletes"), Some(&FivetranValue::Long(1)));
+        assert_eq!(
+            events.get("min_creation_time"),
+            Some(&FivetranValue::UtcDatetime(timestamp_from_ms(1000.0)))
+        );
+        assert_eq!(
+            events.get("max_creation_time"),
+            Some(&FivetranValue::UtcDatetime(timestamp_from_ms(3000.0)))
+        );
+        assert_eq!(
+            rows[1].get("min_creation_time"),
+            Some(&FivetranValue::Null(true))
+        );
+
+        assert!(stats
+            .take_rows("delta_sync", "43", SystemTime::now())
+            .is_empty());
+    }
+
+    #[test]
+    fn rows_match_the_declared_schema() {
+        let mut stats = TableStats::default();
+        stats.record("events", false, &hashmap! {});
+        let row = stats
+            .take_rows("initial_sync", "abc", SystemTime::UNIX_EPOCH)
+            .remove(0);
+
+        let mut row_columns: Vec<&String> = row.keys().collect();
+        row_columns.sort();
+        let mut declared_columns: Vec<String> = table_stats_table()
+            .columns
+            .into_iter()
+            .map(|column| column.name)
+            .collect();
+        declared_columns.sort();
+        assert_eq!(
+            row_columns,
+            declared_columns.iter().collect::<Vec<&String>>()
+        );
+    }
+}
@@ -0,0 +1,58 @@
+//! Export of the `tracing` spans of the connector to an OpenTelemetry
+//! collector, enabled with `--otlp-endpoint`.
+
+use anyhow::Context;
+use opentelemetry::{
+    trace::TraceContextExt,
+    KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{
+    runtime,
+    trace,
+    Resource,
+};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::layer::SubscriberExt;
+
+/// Sends the spans of the connector to the OTLP (gRPC) endpoint, if any.
+/// Without an endpoint, spans are not recorded.
+pub fn init(otlp_endpoint: Option<String>) -> anyhow::Result<()> {
+    let Some(otlp_endpoint) = otlp_endpoint else {
+        return Ok(());
+    };
+
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(otlp_endpoint),
+        )
+        .with_trace_config(trace::config().with_resource(Resource::new(vec![
+            KeyValue::new("service.name", env!("CARGO_PKG_NAME")),
+            KeyValue::new("service.version", env!("CARGO_PKG_VERSION")),
+        ])))
+        .install_batch(runtime::Tokio)
+        .context("Couldn’t set up the OTLP exporter")?;
+
+    let subscriber =
+        tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+    tracing::subscriber::set_global_default(subscriber)
+        .context("Couldn’t install the tracing subscriber")?;
+    Ok(())
+}
+
+/// The ID of the trace the current span belongs to, if spans are exported.
+pub fn current_trace_id() -> Option<String> {
+    let context = tracing::Span::current().context();
+    let span_context = context.span().span_context().clone();
+    span_context
+        .is_valid()
+        .then(|| span_context.trace_id().to_string())
+}
+
+/// Sends the spans that were not exported yet.
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
@@ -0,0 +1,530 @@
+//! An in-memory [`Source`], for testing code that drives the sync without a
+//! Convex deployment, and a wrapper injecting faults in the calls of a source.
+//! Only available with the `test-util` feature.
+
+use std::{
+    collections::{
+        BTreeMap,
+        HashMap,
+        HashSet,
+    },
+    fmt::Display,
+    sync::{
+        Arc,
+        Mutex,
+    },
+};
+
+use async_trait::async_trait;
+use maplit::hashmap;
+use serde_json::{
+    json,
+    Value as JsonValue,
+};
+use uuid::Uuid;
+
+use crate::{
+    convex_api::{
+        DatabaseSchema,
+        DocumentDeltasCursor,
+        DocumentDeltasResponse,
+        ExportFormat,
+        FieldName,
+        ListSnapshotCursor,
+        ListSnapshotResponse,
+        SnapshotValue,
+        Source,
+        TableName,
+    },
+    schema::{
+        component_table_name,
+        split_component_table_name,
+    },
+};
+
+/// The fields of a document, including its system fields.
+pub type JsonDocument = HashMap<String, JsonValue>;
+
+/// The number of documents returned per page of `list_snapshot` when no page
+/// size is requested.
+const DEFAULT_PAGE_SIZE: usize = 10;
+
+/// The number of changes returned per page of `document_deltas`.
+const DELTAS_PAGE_SIZE: usize = 5;
+
+/// A deployment kept in memory. Every write is appended to a changelog, whose
+/// length serves as the timestamp of the snapshots and deltas returned, so the
+/// changes made after a sync started are picked up by the next delta sync.
+/// Like the real API, older snapshots can still be listed, so an interrupted
+/// initial sync can resume after the deployment changed.
+///
+/// Clones are independent: a clone can be synced while the original keeps
+/// changing.
+///
+/// Tables named `<component path>/<table name>` (e.g. `workpool/jobs`) are
+/// tables of components.
+#[derive(Debug, Clone, Default)]
+pub struct FakeSource {
+    tables: HashMap<String, Vec<JsonDocument>>,
+    changelog: Vec<SnapshotValue>,
+}
+
+impl FakeSource {
+    /// A deployment with three tables (`table1`, `table2` and `table3`) of 25
+    /// documents each, having a `name` and an `index` field.
+    pub fn seeded() -> Self {
+        let mut source = Self::default();
+        for table_name in ["table1", "table2", "table3"] {
+            for i in 0..25 {
+                source.insert(
+                    table_name,
+                    hashmap! {
+                        "name".to_string() => json!(format!("Document {} of {}", i, table_name)),
+                        "index".to_string() => json!(i),
+                    },
+                );
+            }
+        }
+
+        source
+    }
+
+    /// The documents of every table, in insertion order.
+    pub fn tables(&self) -> &HashMap<String, Vec<JsonDocument>> {
+        &self.tables
+    }
+
+    /// Inserts a document, generating its system fields, and returns its ID.
+    ///
+    /// # Panics
+    ///
+    /// If the document already has an `_id`.
+    pub fn insert(&mut self, table_name: &str, mut value: JsonDocument) -> String {
+        if value.contains_key("_id") {
+            panic!("ID specified while inserting a new row");
+        }
+        let id = Uuid::new_v4().to_string();
+        value.insert("_id".to_string(), JsonValue::String(id.clone()));
+        value.insert("_creationTime".to_string(), json!(0));
+
+        self.tables
+            .entry(table_name.to_string())
+            .or_default()
+            .push(value.clone());
+
+        self.changelog
+            .push(snapshot_value(table_name, false, value));
+        id
+    }
+
+    /// Sets the given fields of the `index`-th document of a table.
+    ///
+    /// # Panics
+    ///
+    /// If the document doesn’t exist, if `changed_fields` isn’t a JSON object,
+    /// or if it contains a system field.
+    pub fn patch(&mut self, table_name: &str, index: usize, changed_fields: JsonValue) {
+        let table = self.tables.get_mut(table_name).unwrap();
+        let element = table.get_mut(index).unwrap();
+        for (key, value) in changed_fields.as_object().unwrap().iter() {
+            if key.starts_with('_') {
+                panic!("Trying to set a system field");
+            }
+
+            element.insert(key.clone(), value.clone());
+        }
+
+        self.changelog
+            .push(snapshot_value(table_name, false, element.clone()));
+    }
+
+    /// Deletes the `index`-th document of a table.
+    ///
+    /// # Panics
+    ///
+    /// If the document doesn’t exist.
+    pub fn delete(&mut self, table_name: &str, index: usize) {
+        let table = self.tables.get_mut(table_name).unwrap();
+        let id = table
+            .get(index)
+            .unwrap()
+            .get("_id")
+            .unwrap()
+            .as_str()
+            .unwrap()
+            .to_string();
+        table.remove(index);
+        self.changelog.push(snapshot_value(
+            table_name,
+            true,
+            hashmap! { "_id".to_string() => json!(id) },
+        ))
+    }
+
+    /// The documents of every table at a snapshot (a length of the changelog),
+    /// replayed from the changelog. The tables are ordered by name, so that
+    /// every page of a snapshot agrees on the position of the documents.
+    fn tables_at(&self, snapshot: usize) -> BTreeMap<String, Vec<JsonDocument>> {
+        let mut tables: BTreeMap<String, Vec<JsonDocument>> = BTreeMap::new();
+        for value in &self.changelog[..snapshot] {
+            let table = tables
+                .entry(component_table_name(&value.component, &value.table))
+                .or_default();
+            let id = &value.fields["_id"];
+            let position = table.iter().position(|document| &document["_id"] == id);
+            match (value.deleted, position) {
+                (true, Some(position)) => {
+                    table.remove(position);
+                },
+                (false, Some(position)) => table[position] = value.fields.clone(),
+                (false, None) => table.push(value.fields.clone()),
+                (true, None) => {},
+            }
+        }
+        tables
+    }
+}
+
+/// A document of a table, which is in a component if its name is
+/// `<component path>/<table name>`.
+fn snapshot_value(table_name: &str, deleted: bool, fields: JsonDocument) -> SnapshotValue {
+    let (component_path, table_name) = split_component_table_name(table_name);
+    SnapshotValue {
+        table: table_name.to_string(),
+        component: component_path.to_string(),
+        deleted,
+        fields,
+    }
+}
+
+/// The columns of a table, as the union of the fields of its documents.
+fn table_columns(documents: &[JsonDocument]) -> Vec<FieldName> {
+    documents
+        .iter()
+        .flat_map(|row| row.keys())
+        .map(|f| FieldName(f.to_string()))
+        .collect()
+}
+
+/// The JSON schema type of a value.
+fn json_type(value: &JsonValue) -> &'static str {
+    match value {
+        JsonValue::Null => "null",
+        JsonValue::Bool(_) => "boolean",
+        JsonValue::Number(_) => "number",
+        JsonValue::String(_) => "string",
+        JsonValue::Array(_) => "array",
+        JsonValue::Object(_) => "object",
+    }
+}
+
+impl Display for FakeSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("fake_source")
+    }
+}
+
+#[async_trait]
+impl Source for FakeSource {
+    async fn test_streaming_export_connection(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn get_tables_and_columns(&self) -> anyhow::Result<HashMap<TableName, Vec<FieldName>>> {
+        let result = self
+            .tables
+            .iter()
+            .filter(|(table_name, _)| split_component_table_name(table_name).0.is_empty())
+            .map(|(table_name, rows)| (TableName(table_name.to_string()), table_columns(rows)))
+            .collect();
+        Ok(result)
+    }
+
+    async fn get_component_tables_and_columns(
+        &self,
+    ) -> anyhow::Result<HashMap<String, HashMap<TableName, Vec<FieldName>>>> {
+        let mut components: HashMap<String, HashMap<TableName, Vec<FieldName>>> = HashMap::new();
+        for (name, rows) in &self.tables {
+            let (component_path, table_name) = split_component_table_name(name);
+            if !component_path.is_empty() {
+                components
+                    .entry(component_path.to_string())
+                    .or_default()
+                    .insert(TableName(table_name.to_string()), table_columns(rows));
+            }
+        }
+        Ok(components)
+    }
+
+    /// Like deployments without schema validation, infers the schema of every
+    /// table from its documents. Fields of different types in different
+    /// documents accept any value.
+    async fn get_json_schemas(&self) -> anyhow::Result<DatabaseSchema> {
+        let schemas: BTreeMap<&String, JsonValue> = self
+            .tables
+            .iter()
+            .map(|(table_name, documents)| {
+                let mut properties: BTreeMap<&String, JsonValue> = BTreeMap::new();
+                for (field_name, value) in documents.iter().flatten() {
+                    let schema = json!({ "type": json_type(value) });
+                    properties
+                        .entry(field_name)
+                        .and_modify(|existing| {
+                            if *existing != schema {
+                                *existing = json!({});
+                            }
+                        })
+                        .or_insert(schema);
+                }
+                (
+                    table_name,
+                    json!({ "type": "object", "properties": properties }),
+                )
+            })
+            .collect();
+        Ok(serde_json::from_value(json!(schemas))?)
+    }
+
+    async fn list_snapshot(
+        &self,
+        snapshot: Option<i64>,
+        cursor: Option<ListSnapshotCursor>,
+        table_name: Option<String>,
+        page_size: Option<u32>,
+    ) -> anyhow::Result<ListSnapshotResponse> {
+        let snapshot = snapshot.map_or(self.changelog.len(), |snapshot| {
+            usize::try_from(snapshot)
+                .ok()
+                .filter(|snapshot| *snapshot <= self.changelog.len())
+                .expect("Unexpected snapshot value")
+        });
+
+        // Like the real API, the cursor is the position after the last document
+        // returned rather than a page number.
+        let cursor: usize = cursor.map(|c| c.0.parse().unwrap()).unwrap_or(0);
+        let values_per_call = page_size.map_or(DEFAULT_PAGE_SIZE, |size| size as usize);
+        let tables_at_snapshot = self.tables_at(snapshot);
+        let tables = tables_at_snapshot
+            .iter()
+            .filter(|(table, _)| table_name.as_ref().map_or(true, |name| name == *table));
+        let documents_count: usize = tables.clone().map(|(_, docs)| docs.len()).sum();
+        let values: Vec<SnapshotValue> = tables
+            .flat_map(|(table, docs)| {
+                docs.iter()
+                    .map(|fields| snapshot_value(table, false, fields.clone()))
+                    .collect::<Vec<_>>()
+            })
+            .skip(cursor)
+            .take(values_per_call)
+            .collect();
+        let next_cursor = cursor + values.len();
+
+        Ok(ListSnapshotResponse {
+            has_more: next_cursor < documents_count,
+            values,
+            snapshot: snapshot as i64,
+            cursor: Some(next_cursor.to_string()),
+        })
+    }
+
+    async fn document_deltas(
+        &self,
+        cursor: DocumentDeltasCursor,
+        table_name: Option<String>,
+    ) -> anyhow::Result<DocumentDeltasResponse> {
+        if table_name.is_some() {
+            panic!("Per-table log not supported in fake");
+        }
+
+        let values: Vec<SnapshotValue> = self
+            .changelog
+            .iter()
+            .skip(i64::from(cursor) as usize)
+            .take(DELTAS_PAGE_SIZE)
+            .cloned()
+            .collect();
+        let values_len = values.len();
+
+        Ok(DocumentDeltasResponse {
+            values,
+            cursor: i64::from(cursor) + values_len as i64,
+            has_more: values_len == DELTAS_PAGE_SIZE,
+        })
+    }
+}
+
+/// The methods of [`Source`], to which [`FaultySource`] attaches faults.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SourceMethod {
+    TestStreamingExportConnection,
+    ListSnapshot,
+    DocumentDeltas,
+    GetTablesAndColumns,
+    GetComponentTablesAndColumns,
+    GetJsonSchemas,
+}
+
+impl SourceMethod {
+    /// Every method calling the Convex API.
+    pub const ALL: [SourceMethod; 6] = [
+        SourceMethod::TestStreamingExportConnection,
+        SourceMethod::ListSnapshot,
+        SourceMethod::DocumentDeltas,
+        SourceMethod::GetTablesAndColumns,
+        SourceMethod::GetComponentTablesAndColumns,
+        SourceMethod::GetJsonSchemas,
+    ];
+}
+
+/// A call to a [`FaultySource`], as given to its faults.
+#[derive(Debug, Clone, Copy)]
+pub struct SourceCall {
+    pub method: SourceMethod,
+    /// How many calls to the same method were made before this one.
+    pub index: u32,
+    /// The snapshot requested, for the calls to `list_snapshot`.
+    pub snapshot: Option<i64>,
+}
+
+/// Decides whether a call to a [`FaultySource`] fails, and with which error.
+pub type Fault = Arc<dyn Fn(&SourceCall) -> anyhow::Result<()> + Send + Sync>;
+
+/// The number of calls made to a [`FaultySource`] by method, shared with the
+/// source so that it can be read while the source is syncing.
+#[derive(Debug, Clone, Default)]
+pub struct CallCounts(Arc<Mutex<HashMap<SourceMethod, u32>>>);
+
+impl CallCounts {
+    /// The number of calls made to a method so far.
+    pub fn get(&self, method: SourceMethod) -> u32 {
+        self.0.lock().unwrap().get(&method).copied().unwrap_or(0)
+    }
+
+    /// Records a call to a method, returning its index.
+    fn record(&self, method: SourceMethod) -> u32 {
+        let mut counts = self.0.lock().unwrap();
+        let count = counts.entry(method).or_default();
+        *count += 1;
+        *count - 1
+    }
+}
+
+/// Wrapper around a source injecting faults in its calls: every call is
+/// counted, and the calls of some methods can be slowed down or fail.
+#[derive(Clone)]
+pub struct FaultySource<S = FakeSource> {
+    source: S,
+    faults: HashMap<SourceMethod, Fault>,
+    slow_methods: HashSet<SourceMethod>,
+    call_counts: CallCounts,
+}
+
+impl<S: Source> FaultySource<S> {
+    pub fn new(source: S) -> Self {
+        FaultySource {
+            source,
+            faults: HashMap::new(),
+            slow_methods: HashSet::new(),
+            call_counts: CallCounts::default(),
+        }
+    }
+
+    /// Makes the calls to a method fail with the error returned by `fault`,
+    /// if any, instead of calling the wrapped source.
+    pub fn fail(
+        mut self,
+        method: SourceMethod,
+        fault: impl Fn(&SourceCall) -> anyhow::Result<()> + Send + Sync + 'static,
+    ) -> Self {
+        self.faults.insert(method, Arc::new(fault));
+        self
+    }
+
+    /// Makes the calls to a method pending the first time they are polled, as
+    /// if they were slow.
+    pub fn slow(mut self, method: SourceMethod) -> Self {
+        self.slow_methods.insert(method);
+        self
+    }
+
+    /// The number of calls made to the source, which keeps counting after the
+    /// source is moved into a sync.
+    pub fn call_counts(&self) -> CallCounts {
+        self.call_counts.clone()
+    }
+
+    /// Records a call, delaying it or failing it as configured.
+    async fn call(&self, method: SourceMethod, snapshot: Option<i64>) -> anyhow::Result<()> {
+        let index = self.call_counts.record(method);
+        if self.slow_methods.contains(&method) {
+            tokio::task::yield_now().await;
+        }
+        match self.faults.get(&method) {
+            Some(fault) => fault(&SourceCall {
+                method,
+                index,
+                snapshot,
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<S: Display> Display for FaultySource<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.source, f)
+    }
+}
+
+#[async_trait]
+impl<S: Source> Source for FaultySource<S> {
+    async fn test_streaming_export_connection(&self) -> anyhow::Result<()> {
+        self.call(SourceMethod::TestStreamingExportConnection, None)
+            .await?;
+        self.source.test_streaming_export_connection().await
+    }
+
+    async fn list_snapshot(
+        &self,
+        snapshot: Option<i64>,
+        cursor: Option<ListSnapshotCursor>,
+        table_name: Option<String>,
+        page_size: Option<u32>,
+    ) -> anyhow::Result<ListSnapshotResponse> {
+        self.call(SourceMethod::ListSnapshot, snapshot).await?;
+        self.source
+            .list_snapshot(snapshot, cursor, table_name, page_size)
+            .await
+    }
+
+    async fn document_deltas(
+        &self,
+        cursor: DocumentDeltasCursor,
+        table_name: Option<String>,
+    ) -> anyhow::Result<DocumentDeltasResponse> {
+        self.call(SourceMethod::DocumentDeltas, None).await?;
+        self.source.document_deltas(cursor, table_name).await
+    }
+
+    async fn get_tables_and_columns(&self) -> anyhow::Result<HashMap<TableName, Vec<FieldName>>> {
+        self.call(SourceMethod::GetTablesAndColumns, None).await?;
+        self.source.get_tables_and_columns().await
+    }
+
+    async fn get_component_tables_and_columns(
+        &self,
+    ) -> anyhow::Result<HashMap<String, HashMap<TableName, Vec<FieldName>>>> {
+        self.call(SourceMethod::GetComponentTablesAndColumns, None)
+            .await?;
+        self.source.get_component_tables_and_columns().await
+    }
+
+    async fn get_json_schemas(&self) -> anyhow::Result<DatabaseSchema> {
+        self.call(SourceMethod::GetJsonSchemas, None).await?;
+        self.source.get_json_schemas().await
+    }
+
+    fn export_format(&self) -> ExportFormat {
+        self.source.export_format()
+    }
+}
@@ -1,249 +1,97 @@
 use std::{
-    collections::HashMap,
-    fmt::Display,
+    collections::{
+        BTreeMap,
+        BTreeSet,
+        HashMap,
+    },
     panic,
+    sync::Arc,
+    time::{
+        Duration,
+        SystemTime,
+    },
     vec,
 };
 
 use anyhow::Ok;
-use async_trait::async_trait;
-use derive_more::From;
 use futures::{
+    future,
     Stream,
     StreamExt,
+    TryStreamExt,
 };
 use maplit::hashmap;
 use rand::Rng;
-use serde_json::{
-    json,
-    Value as JsonValue,
-};
-use uuid::Uuid;
+use reqwest::StatusCode;
+use serde_json::json;
+use url::Url;
 use value_type::Inner as FivetranValue;
 
 use crate::{
+    clock::FakeClock,
     convex_api::{
-        DocumentDeltasCursor,
-        DocumentDeltasResponse,
-        FieldName,
+        ApiError,
+        BackendError,
         ListSnapshotCursor,
-        ListSnapshotResponse,
-        SnapshotValue,
         Source,
-        TableName,
+    },
+    deprecation::Deprecation,
+    error::ConnectorError,
+    files::{
+        FILES_TABLE_NAME,
+        STORAGE_TABLE_NAME,
     },
     fivetran_sdk::{
         value_type,
         LogLevel,
         OpType,
     },
+    metadata::METADATA_TABLE_NAME,
+    restart::restart_on_transient_errors,
+    row_filter::RowFilters,
+    schema::table_renames,
+    schema_cache::SchemaCache,
     sync::{
         sync,
+        sync_deployments,
+        AdditionalDeployment,
+        Checkpoint,
         State,
+        SyncOptions,
         UpdateMessage,
     },
+    table_filter::{
+        parse_table_pattern,
+        TableFilter,
+    },
+    table_stats::TABLE_STATS_TABLE_NAME,
+    testing::{
+        FakeSource,
+        FaultySource,
+        SourceMethod,
+    },
+    throttle::BackfillThrottle,
 };
 
-type JsonDocument = HashMap<String, JsonValue>;
-
-#[derive(Debug, Clone)]
-struct FakeSource {
-    tables: HashMap<String, Vec<JsonDocument>>,
-    changelog: Vec<SnapshotValue>,
-}
-
-impl Default for FakeSource {
-    fn default() -> Self {
-        FakeSource {
-            tables: hashmap! {},
-            changelog: vec![],
-        }
-    }
-}
-
-impl FakeSource {
-    fn seeded() -> Self {
-        let mut source = Self::default();
-        for table_name in ["table1", "table2", "table3"] {
-            for i in 0..25 {
-                source.insert(
-                    table_name,
-                    hashmap! {
-                        "name".to_string() => json!(format!("Document {} of {}", i, table_name)),
-                        "index".to_string() => json!(i),
-                    },
-                )
-            }
-        }
-
-        source
-    }
-
-    fn insert(&mut self, table_name: &str, mut value: JsonDocument) {
-        if value.contains_key("_id") {
-            panic!("ID specified while inserting a new row");
-        }
-        value.insert(
-            "_id".to_string(),
-            JsonValue::String(Uuid::new_v4().to_string()),
-        );
-        value.insert("_creationTime".to_string(), json!(0));
-
-        self.tables
-            .entry(table_name.to_string())
-            .or_default()
-            .push(value.clone().into_iter().collect());
-
-        self.changelog.push(SnapshotValue {
-            table: table_name.to_string(),
-            deleted: false,
-            fields: value,
-        });
-    }
-
-    fn patch(&mut self, table_name: &str, index: usize, changed_fields: JsonValue) {
-        let table = self.tables.get_mut(table_name).unwrap();
-        let element = table.get_mut(index).unwrap();
-        for (key, value) in changed_fields.as_object().unwrap().iter() {
-            if key.starts_with('_') {
-                panic!("Trying to set a system field");
-            }
-
-            element.insert(key.clone(), value.clone());
-        }
-
-        self.changelog.push(SnapshotValue {
-            table: table_name.to_string(),
-            deleted: false,
-            fields: element.clone(),
-        });
-    }
-
-    fn delete(&mut self, table_name: &str, index: usize) {
-        let table = self.tables.get_mut(table_name).unwrap();
-        let id = table
-            .get(index)
-            .unwrap()
-            .get("_id")
-            .unwrap()
-            .as_str()
-            .unwrap()
-            .to_string();
-        table.remove(index);
-        self.changelog.push(SnapshotValue {
-            table: table_name.to_string(),
-            deleted: true,
-            fields: hashmap! { "_id".to_string() => json!(id) },
-        })
-    }
-}
-
-impl Display for FakeSource {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str("fake_source")
-    }
-}
-
-#[async_trait]
-impl Source for FakeSource {
-    async fn test_streaming_export_connection(&self) -> anyhow::Result<()> {
-        Ok(())
-    }
-
-    async fn get_tables_and_columns(&self) -> anyhow::Result<HashMap<TableName, Vec<FieldName>>> {
-        let result = self
-            .tables
-            .iter()
-            .map(|(table_name, rows)| {
-                let field_names = rows
-                    .iter()
-                    .flat_map(|row| row.keys())
-                    .map(|f| FieldName(f.to_string()))
-                    .collect();
-                (TableName(table_name.to_string()), field_names)
-            })
-            .collect();
-        Ok(result)
-    }
-
-    async fn list_snapshot(
-        &self,
-        snapshot: Option<i64>,
-        cursor: Option<ListSnapshotCursor>,
-        table_name: Option<String>,
-    ) -> anyhow::Result<ListSnapshotResponse> {
-        if table_name.is_some() {
-            panic!("Query by table is not supported by the fake");
-        }
-
-        if snapshot.is_some() && snapshot != Some(self.changelog.len() as i64) {
-            panic!("Unexpected snapshot value");
-        }
-
-        let cursor: usize = cursor.map(|c| c.0.parse().unwrap()).unwrap_or(0);
-        let values_per_call = 10;
-        let values: Vec<SnapshotValue> = self
-            .tables
-            .iter()
-            .flat_map(|(table, docs)| {
-                docs.iter()
-                    .map(|fields| SnapshotValue {
-                        table: table.to_string(),
-                        deleted: false,
-                        fields: fields.clone(),
-                    })
-                    .collect::<Vec<_>>()
-            })
-            .skip(cursor * values_per_call)
-            .take(values_per_call)
-            .collect();
-
-        Ok(ListSnapshotResponse {
-            has_more: values.len() == values_per_call,
-            values,
-            snapshot: self.changelog.len() as i64,
-            cursor: Some((cursor + 1).to_string()),
-        })
-    }
-
-    async fn document_deltas(
-        &self,
-        cursor: DocumentDeltasCursor,
-        table_name: Option<String>,
-    ) -> anyhow::Result<DocumentDeltasResponse> {
-        if table_name.is_some() {
-            panic!("Per-table log not supported in fake");
-        }
-
-        let results_per_page = 5;
-        let values: Vec<SnapshotValue> = self
-            .changelog
-            .iter()
-            .skip(i64::from(cursor) as usize)
-            .take(results_per_page as usize)
-            .cloned()
-            .collect();
-        let values_len = values.len() as i64;
-
-        Ok(DocumentDeltasResponse {
-            values,
-            cursor: i64::from(cursor) + values_len,
-            has_more: values_len == results_per_page,
-        })
-    }
-}
-
 #[derive(Default, Debug, PartialEq)]
 struct FakeDestination {
     current_data: FakeDestinationData,
     checkpointed_data: FakeDestinationData,
     state: Option<State>,
+    /// The schema that every row received must be attributed to.
+    schema_name: Option<String>,
+    /// If set, rows can be attributed to any schema instead, and their tables
+    /// are named `<schema>.<table>`.
+    multiple_schemas: bool,
 }
 
 #[derive(Default, Debug, PartialEq, Clone)]
 struct FakeDestinationData {
     logs: Vec<(LogLevel, String)>,
     tables: HashMap<String, Vec<HashMap<String, FivetranValue>>>,
+    /// The rows of the metadata table, which are kept separately since they
+    /// aren’t part of the source data.
+    metadata: Vec<HashMap<String, FivetranValue>>,
 }
 
 impl FakeDestination {
@@ -271,14 +119,30 @@ impl FakeDestination {
                 UpdateMessage::Log(level, message) => {
                     self.current_data.logs.push((level, message));
                 },
+                UpdateMessage::Heartbeat(message) => {
+                    self.current_data.logs.push((LogLevel::Info, message));
+                },
                 UpdateMessage::Update {
                     schema_name,
-                    table_name,
+                    mut table_name,
                     op_type,
                     row,
                 } => {
-                    if schema_name.is_some() {
-                        panic!("Schemas not supported by the fake");
+                    if !self.multiple_schemas {
+                        assert_eq!(
+                            schema_name, self.schema_name,
+                            "Row of {table_name} attributed to the wrong schema"
+                        );
+                    }
+                    if table_name == METADATA_TABLE_NAME {
+                        assert_eq!(op_type, OpType::Upsert);
+                        self.current_data.metadata.push(row);
+                        continue;
+                    }
+                    if self.multiple_schemas {
+                        let schema_name = schema_name
+                            .unwrap_or_else(|| panic!("Row of {table_name} without a schema"));
+                        table_name = format!("{schema_name}.{table_name}");
                     }
                     if !self.current_data.tables.contains_key(&table_name) {
                         self.current_data.tables.insert(table_name.clone(), vec![]);
@@ -294,8 +158,8 @@ impl FakeDestination {
                         .tables
                         .get_mut(&table_name)
                         .expect("Unknown table name");
-                    let id = row.get("_id").unwrap();
-                    let position = table.iter().position(|row| row.get("_id").unwrap() == id);
+                    let key = primary_key(&row);
+                    let position = table.iter().position(|other| primary_key(other) == key);
 
                     match op_type {
                         OpType::Upsert => {
@@ -304,6 +168,10 @@ impl FakeDestination {
                                 None => table.push(row),
                             };
                         },
+                        OpType::Update => {
+                            let index = position.expect("Could not find the row to update");
+                            table[index].extend(row);
+                        },
                         OpType::Delete => {
                             table.remove(position.expect("Could not find the row to delete"));
                         },
@@ -321,23 +189,37 @@ impl FakeDestination {
     }
 }
 
+/// The primary key of a row: its `_id`, or the parent ID and index of the rows
+/// of child tables.
+fn primary_key(row: &HashMap<String, FivetranValue>) -> Vec<&FivetranValue> {
+    match row.get("_id") {
+        Some(id) => vec![id],
+        None => vec![&row["_parent_id"], &row["_index"]],
+    }
+}
+
 #[tokio::test]
 async fn initial_sync_copies_documents_from_source_to_destination() -> anyhow::Result<()> {
     let source = FakeSource::seeded();
     let mut destination = FakeDestination::default();
 
     destination
-        .receive(sync(source.clone(), destination.latest_state()))
+        .receive(sync(
+            source.clone(),
+            destination.latest_state(),
+            SyncOptions::default(),
+            SchemaCache::default(),
+        ))
         .await?;
 
     assert!(destination.has_log("Initial sync successful"));
 
     assert_eq!(
-        source.tables.len(),
+        source.tables().len(),
         destination.checkpointed_data.tables.len()
     );
     assert_eq!(
-        source.tables.get("table1").unwrap().len(),
+        source.tables().get("table1").unwrap().len(),
         destination
             .checkpointed_data
             .tables
@@ -380,7 +262,12 @@ async fn initial_sync_copies_documents_from_source_to_destination() -> anyhow::R
 async fn assert_in_sync(source: impl Source + 'static, destination: &FakeDestination) {
     let mut parallel_destination = FakeDestination::default();
     parallel_destination
-        .receive(sync(source, parallel_destination.latest_state()))
+        .receive(sync(
+            source,
+            parallel_destination.latest_state(),
+            SyncOptions::default(),
+            SchemaCache::default(),
+        ))
         .await
         .expect("Unexpected error during parallel synchronization");
     assert_eq!(
@@ -392,7 +279,12 @@ async fn assert_in_sync(source: impl Source + 'static, destination: &FakeDestina
 async fn assert_not_in_sync(source: impl Source + 'static, destination: &FakeDestination) {
     let mut parallel_destination = FakeDestination::default();
     parallel_destination
-        .receive(sync(source, parallel_destination.latest_state()))
+        .receive(sync(
+            source,
+            parallel_destination.latest_state(),
+            SyncOptions::default(),
+            SchemaCache::default(),
+        ))
         .await
         .expect("Unexpected error during parallel synchronization");
     assert_ne!(
@@ -409,7 +301,12 @@ async fn initial_sync_synchronizes_the_destination_with_the_source() -> anyhow::
     assert_not_in_sync(source.clone(), &destination).await;
 
     destination
-        .receive(sync(source.clone(), destination.latest_state()))
+        .receive(sync(
+            source.clone(),
+            destination.latest_state(),
+            SyncOptions::default(),
+            SchemaCache::default(),
+        ))
         .await?;
 
     assert_in_sync(source, &destination).await;
@@ -418,62 +315,182 @@ async fn initial_sync_synchronizes_the_destination_with_the_source() -> anyhow::
 }
 
 #[tokio::test]
-async fn sync_after_adding_a_document() -> anyhow::Result<()> {
-    let mut source = FakeSource::seeded();
+async fn initial_sync_of_an_empty_deployment_moves_to_delta_syncs() -> anyhow::Result<()> {
+    let mut source = FakeSource::default();
     let mut destination = FakeDestination::default();
 
     destination
-        .receive(sync(source.clone(), destination.latest_state()))
+        .receive(sync(
+            source.clone(),
+            destination.latest_state(),
+            SyncOptions::default(),
+            SchemaCache::default(),
+        ))
         .await?;
-    let state = destination.latest_state();
+
+    assert!(destination.has_log("has no documents yet"));
+    assert!(destination.has_log("Initial sync successful"));
+    assert!(destination.checkpointed_data.tables.is_empty());
+    assert!(matches!(
+        destination.latest_state().unwrap().checkpoint,
+        Checkpoint::DeltaUpdates { .. }
+    ));
 
     source.insert(
         "table1",
         hashmap! {
-            "name".to_string() => json!("New document"),
+            "name".to_string() => json!("First document"),
         },
     );
-    destination.receive(sync(source.clone(), state)).await?;
+    destination
+        .receive(sync(
+            source.clone(),
+            destination.latest_state(),
+            SyncOptions::default(),
+            SchemaCache::default(),
+        ))
+        .await?;
+    assert_eq!(destination.checkpointed_data.tables["table1"].len(), 1);
     assert_in_sync(source, &destination).await;
 
     Ok(())
 }
 
 #[tokio::test]
-async fn sync_after_modifying_a_document() -> anyhow::Result<()> {
+async fn initial_sync_of_a_single_page_deployment() -> anyhow::Result<()> {
+    let mut source = FakeSource::default();
+    for i in 0..3 {
+        source.insert("table1", hashmap! { "index".to_string() => json!(i) });
+    }
+    let mut destination = FakeDestination::default();
+
+    destination
+        .receive(sync(
+            source.clone(),
+            destination.latest_state(),
+            SyncOptions::default(),
+            SchemaCache::default(),
+        ))
+        .await?;
+
+    assert!(destination.has_log("Initial sync successful"));
+    assert!(!destination.has_log("has no documents yet"));
+    assert!(matches!(
+        destination.latest_state().unwrap().checkpoint,
+        Checkpoint::DeltaUpdates { .. }
+    ));
+    assert_in_sync(source, &destination).await;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn sync_after_adding_a_document() -> anyhow::Result<()> {
     let mut source = FakeSource::seeded();
     let mut destination = FakeDestination::default();
 
     destination
-        .receive(sync(source.clone(), destination.latest_state()))
+        .receive(sync(
+            source.clone(),
+            destination.latest_state(),
+            SyncOptions::default(),
+            SchemaCache::default(),
+        ))
         .await?;
     let state = destination.latest_state();
 
-    source.patch(
+    source.insert(
         "table1",
-        13,
-        json!({
-            "name": "New name",
-        }),
+        hashmap! {
+            "name".to_string() => json!("New document"),
+        },
     );
-    destination.receive(sync(source.clone(), state)).await?;
+    destination
+        .receive(sync(
+            source.clone(),
+            state,
+            SyncOptions::default(),
+            SchemaCache::default(),
+        ))
+        .await?;
     assert_in_sync(source, &destination).await;
 
     Ok(())
 }
 
 #[tokio::test]
-async fn sync_after_deleting_a_document() -> anyhow::Result<()> {
-    let mut source = FakeSource::seeded();
+async fn delta_sync_without_changes_emits_nothing() -> anyhow::Result<()> {
+    let source = FakeSource::seeded();
     let mut destination = FakeDestination::default();
+    destination
+        .receive(sync(
+            source.clone(),
+            None,
+            SyncOptions::default(),
+            SchemaCache::default(),
+        ))
+        .await?;
+
+    let messages: Vec<UpdateMessage> = sync(
+        source,
+        destination.latest_state(),
+        SyncOptions::default(),
+        SchemaCache::default(),
+    )
+    .try_collect()
+    .await?;
+    assert!(messages.is_empty(), "Unexpected messages: {messages:?}");
 
+    Ok(())
+}
+
+#[tokio::test]
+async fn delta_sync_emits_the_last_revision_of_each_document() -> anyhow::Result<()> {
+    let mut source = FakeSource::seeded();
+    let mut destination = FakeDestination::default();
     destination
-        .receive(sync(source.clone(), destination.latest_state()))
+        .receive(sync(
+            source.clone(),
+            None,
+            SyncOptions::default(),
+            SchemaCache::default(),
+        ))
         .await?;
 
-    source.delete("table1", 8);
+    for name in ["First", "Second", "Third"] {
+        source.patch("table1", 3, json!({ "name": name }));
+    }
+    source.patch("table1", 4, json!({ "name": "Deleted" }));
+    source.delete("table1", 4);
+
+    let messages: Vec<UpdateMessage> = sync(
+        source.clone(),
+        destination.latest_state(),
+        SyncOptions::default(),
+        SchemaCache::default(),
+    )
+    .try_collect()
+    .await?;
+    let operations: Vec<(OpType, Option<&FivetranValue>)> = messages
+        .iter()
+        .filter_map(|message| match message {
+            UpdateMessage::Update { op_type, row, .. } => Some((*op_type, row.get("name"))),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        operations,
+        vec![
+            (
+                OpType::Upsert,
+                Some(&FivetranValue::String("Third".to_string()))
+            ),
+            (OpType::Delete, None),
+        ]
+    );
+
     destination
-        .receive(sync(source.clone(), destination.latest_state()))
+        .receive(futures::stream::iter(messages.into_iter().map(Ok)))
         .await?;
     assert_in_sync(source, &destination).await;
 
@@ -481,92 +498,2204 @@ async fn sync_after_deleting_a_document() -> anyhow::Result<()> {
 }
 
 #[tokio::test]
-async fn resync_after_sync_and_delete() -> anyhow::Result<()> {
+async fn soft_deletes_keep_the_rows_of_deleted_documents() -> anyhow::Result<()> {
     let mut source = FakeSource::seeded();
     let mut destination = FakeDestination::default();
+    let options = SyncOptions {
+        soft_deletes: true,
+        ..SyncOptions::default()
+    };
+    destination
+        .receive(sync(
+            source.clone(),
+            None,
+            options.clone(),
+            SchemaCache::default(),
+        ))
+        .await?;
+    let deleted_id = source.tables()["table1"][5]["_id"].clone();
 
-    destination.receive(sync(source.clone(), None)).await?;
-    source.delete("table1", 8);
+    source.delete("table1", 5);
+    destination
+        .receive(sync(
+            source.clone(),
+            destination.latest_state(),
+            options,
+            SchemaCache::default(),
+        ))
+        .await?;
 
-    // The sync + delete + resync tests to ensure that the connector
-    // correctly truncates the destination before a resync.
-    destination.receive(sync(source.clone(), None)).await?;
-    assert_in_sync(source, &destination).await;
+    let rows = &destination.checkpointed_data.tables["table1"];
+    assert_eq!(rows.len(), 25);
+    let deleted_row = rows
+        .iter()
+        .find(|row| row["_id"] == FivetranValue::String(deleted_id.as_str().unwrap().to_string()))
+        .expect("The row of the deleted document was removed");
+    assert_eq!(deleted_row["_fivetran_deleted"], FivetranValue::Bool(true));
+    assert!(deleted_row.contains_key("name"));
 
     Ok(())
 }
 
-/// Wrapper around a source that fails half of its calls.
-#[derive(From)]
-struct UnreliableSource {
-    source: FakeSource,
-}
+#[tokio::test]
+async fn child_tables_contain_the_elements_of_arrays() -> anyhow::Result<()> {
+    let mut source = FakeSource::seeded();
+    let id = source.insert(
+        "table1",
+        hashmap! {
+            "name".to_string() => json!("With tags"),
+            "tags".to_string() => json!(["a", "b", "c"]),
+        },
+    );
+    let mut destination = FakeDestination::default();
+    let options = SyncOptions {
+        child_tables: true,
+        ..SyncOptions::default()
+    };
+    destination
+        .receive(sync(
+            source.clone(),
+            None,
+            options.clone(),
+            SchemaCache::default(),
+        ))
+        .await?;
 
-impl Display for UnreliableSource {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        Display::fmt(&self.source, f)
-    }
-}
+    let parent_row = |destination: &FakeDestination| {
+        destination.checkpointed_data.tables["table1"]
+            .iter()
+            .find(|row| row["_id"] == FivetranValue::String(id.clone()))
+            .cloned()
+            .expect("The document wasn’t synced")
+    };
+    let child_values = |destination: &FakeDestination| {
+        let mut rows: Vec<(i64, FivetranValue)> = destination.checkpointed_data.tables
+            ["table1__tags"]
+            .iter()
+            .map(|row| {
+                assert_eq!(row["_parent_id"], FivetranValue::String(id.clone()));
+                let FivetranValue::Long(index) = row["_index"] else {
+                    panic!("Invalid index: {:?}", row["_index"]);
+                };
+                (index, row["value"].clone())
+            })
+            .collect();
+        rows.sort_by_key(|(index, _)| *index);
+        rows
+    };
+    assert_eq!(
+        parent_row(&destination)["tags__length"],
+        FivetranValue::Long(3)
+    );
+    assert!(!parent_row(&destination).contains_key("tags"));
+    assert_eq!(
+        child_values(&destination),
+        vec![
+            (0, FivetranValue::String("a".to_string())),
+            (1, FivetranValue::String("b".to_string())),
+            (2, FivetranValue::String("c".to_string())),
+        ]
+    );
 
-impl UnreliableSource {
-    fn maybe_fail(&self) -> anyhow::Result<()> {
-        if rand::thread_rng().gen_bool(0.5) {
-            anyhow::bail!("Unreliable source error");
-        }
+    // Rows beyond the new length of an array are left over, and can be told
+    // apart with the length column.
+    source.patch("table1", 25, json!({ "tags": ["d"] }));
+    destination
+        .receive(sync(
+            source.clone(),
+            destination.latest_state(),
+            options,
+            SchemaCache::default(),
+        ))
+        .await?;
+    assert_eq!(
+        parent_row(&destination)["tags__length"],
+        FivetranValue::Long(1)
+    );
+    assert_eq!(
+        child_values(&destination)[0],
+        (0, FivetranValue::String("d".to_string()))
+    );
 
-        Ok(())
-    }
+    Ok(())
 }
 
-#[async_trait]
-impl Source for UnreliableSource {
-    async fn test_streaming_export_connection(&self) -> anyhow::Result<()> {
-        self.maybe_fail()?;
-        self.source.test_streaming_export_connection().await
-    }
+#[tokio::test]
+async fn sync_after_modifying_a_document() -> anyhow::Result<()> {
+    let mut source = FakeSource::seeded();
+    let mut destination = FakeDestination::default();
 
-    async fn list_snapshot(
-        &self,
-        snapshot: Option<i64>,
-        cursor: Option<ListSnapshotCursor>,
-        table_name: Option<String>,
-    ) -> anyhow::Result<ListSnapshotResponse> {
-        self.maybe_fail()?;
-        self.source
-            .list_snapshot(snapshot, cursor, table_name)
-            .await
-    }
+    destination
+        .receive(sync(
+            source.clone(),
+            destination.latest_state(),
+            SyncOptions::default(),
+            SchemaCache::default(),
+        ))
+        .await?;
+    let state = destination.latest_state();
 
-    async fn document_deltas(
-        &self,
-        cursor: DocumentDeltasCursor,
-        table_name: Option<String>,
-    ) -> anyhow::Result<DocumentDeltasResponse> {
-        self.maybe_fail()?;
-        self.source.document_deltas(cursor, table_name).await
-    }
+    source.patch(
+        "table1",
+        13,
+        json!({
+            "name": "New name",
+        }),
+    );
+    destination
+        .receive(sync(
+            source.clone(),
+            state,
+            SyncOptions::default(),
+            SchemaCache::default(),
+        ))
+        .await?;
+    assert_in_sync(source, &destination).await;
 
-    async fn get_tables_and_columns(&self) -> anyhow::Result<HashMap<TableName, Vec<FieldName>>> {
-        self.maybe_fail()?;
-        self.source.get_tables_and_columns().await
-    }
+    Ok(())
 }
 
 #[tokio::test]
-async fn can_perform_an_initial_sync_from_an_unreliable_source() -> anyhow::Result<()> {
-    let source = FakeSource::seeded();
+async fn sync_after_deleting_a_document() -> anyhow::Result<()> {
+    let mut source = FakeSource::seeded();
     let mut destination = FakeDestination::default();
 
-    while destination
+    destination
         .receive(sync(
-            UnreliableSource::from(source.clone()),
+            source.clone(),
             destination.latest_state(),
+            SyncOptions::default(),
+            SchemaCache::default(),
         ))
-        .await
-        .is_err()
-    {}
+        .await?;
 
+    source.delete("table1", 8);
+    destination
+        .receive(sync(
+            source.clone(),
+            destination.latest_state(),
+            SyncOptions::default(),
+            SchemaCache::default(),
+        ))
+        .await?;
     assert_in_sync(source, &destination).await;
 
     Ok(())
 }
+
+#[tokio::test]
+async fn resync_after_sync_and_delete() -> anyhow::Result<()> {
+    let mut source = FakeSource::seeded();
+    let mut destination = FakeDestination::default();
+
+    destination
+        .receive(sync(
+            source.clone(),
+            None,
+            SyncOptions::default(),
+            SchemaCache::default(),
+        ))
+        .await?;
+    source.delete("table1", 8);
+
+    // The sync + delete + resync tests to ensure that the connector
+    // correctly truncates the destination before a resync.
+    destination
+        .receive(sync(
+            source.clone(),
+            None,
+            SyncOptions::default(),
+            SchemaCache::default(),
+        ))
+        .await?;
+    assert_in_sync(source, &destination).await;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn initial_sync_waits_between_pages_when_paced() -> anyhow::Result<()> {
+    let source = FakeSource::seeded();
+    let mut destination = FakeDestination::default();
+    let clock = Arc::new(FakeClock::new(SystemTime::UNIX_EPOCH));
+
+    destination
+        .receive(sync(
+            source.clone(),
+            None,
+            SyncOptions {
+                page_pacing: Some(Duration::from_secs(2)),
+                clock: clock.clone(),
+                ..SyncOptions::default()
+            },
+            SchemaCache::default(),
+        ))
+        .await?;
+
+    // 75 documents are fetched in 8 pages, so there are 7 pauses between pages.
+    assert_eq!(clock.sleeps(), vec![Duration::from_secs(2); 7]);
+    assert_in_sync(source, &destination).await;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn initial_sync_does_not_wait_without_pacing() -> anyhow::Result<()> {
+    let source = FakeSource::seeded();
+    let mut destination = FakeDestination::default();
+    let clock = Arc::new(FakeClock::new(SystemTime::UNIX_EPOCH));
+
+    destination
+        .receive(sync(
+            source,
+            None,
+            SyncOptions {
+                clock: clock.clone(),
+                ..SyncOptions::default()
+            },
+            SchemaCache::default(),
+        ))
+        .await?;
+
+    assert_eq!(clock.sleeps(), vec![]);
+
+    Ok(())
+}
+
+/// Stops a sync stream after its first checkpoint, as if the connector had
+/// been interrupted.
+fn until_first_checkpoint(
+    stream: impl Stream<Item = anyhow::Result<UpdateMessage>>,
+) -> impl Stream<Item = anyhow::Result<UpdateMessage>> {
+    let mut checkpointed = false;
+    stream.take_while(move |message| {
+        let keep_going = !checkpointed;
+        checkpointed |= matches!(message, Result::Ok(UpdateMessage::Checkpoint(_)));
+        future::ready(keep_going)
+    })
+}
+
+#[tokio::test]
+async fn resumes_initial_sync_with_a_different_page_size() -> anyhow::Result<()> {
+    for (previous_page_size, new_page_size) in [(10, 7), (10, 25), (7, 10)] {
+        let source = FakeSource::seeded();
+        let mut destination = FakeDestination::default();
+
+        destination
+            .receive(until_first_checkpoint(sync(
+                source.clone(),
+                None,
+                SyncOptions {
+                    page_size: Some(previous_page_size),
+                    ..SyncOptions::default()
+                },
+                SchemaCache::default(),
+            )))
+            .await?;
+        assert_eq!(
+            destination
+                .checkpointed_data
+                .tables
+                .values()
+                .map(Vec::len)
+                .sum::<usize>(),
+            previous_page_size as usize
+        );
+
+        destination
+            .receive(sync(
+                source.clone(),
+                destination.latest_state(),
+                SyncOptions {
+                    page_size: Some(new_page_size),
+                    ..SyncOptions::default()
+                },
+                SchemaCache::default(),
+            ))
+            .await?;
+
+        assert!(destination.has_log(&format!(
+            "The page size changed from {previous_page_size} documents to {new_page_size} \
+             documents"
+        )));
+        assert!(destination.has_log("Initial sync successful"));
+        assert_in_sync(source, &destination).await;
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn resumes_initial_sync_with_the_same_page_size_silently() -> anyhow::Result<()> {
+    let source = FakeSource::seeded();
+    let mut destination = FakeDestination::default();
+    let options = SyncOptions {
+        page_size: Some(10),
+        ..SyncOptions::default()
+    };
+
+    destination
+        .receive(until_first_checkpoint(sync(
+            source.clone(),
+            None,
+            options.clone(),
+            SchemaCache::default(),
+        )))
+        .await?;
+    destination
+        .receive(sync(
+            source.clone(),
+            destination.latest_state(),
+            options,
+            SchemaCache::default(),
+        ))
+        .await?;
+
+    assert!(!destination.has_log("The page size changed"));
+    assert_in_sync(source, &destination).await;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn initial_sync_respects_the_throughput_cap() -> anyhow::Result<()> {
+    let source = FakeSource::seeded();
+    let mut destination = FakeDestination::default();
+    let clock = Arc::new(FakeClock::new(SystemTime::UNIX_EPOCH));
+
+    destination
+        .receive(sync(
+            source.clone(),
+            None,
+            SyncOptions {
+                backfill_throttle: BackfillThrottle {
+                    max_rows_per_minute: Some(600),
+                    quiet_hours: None,
+                },
+                clock: clock.clone(),
+                ..SyncOptions::default()
+            },
+            SchemaCache::default(),
+        ))
+        .await?;
+
+    // Pages of 10 documents at 10 documents per second
+    assert_eq!(clock.sleeps(), vec![Duration::from_secs(1); 7]);
+    assert_in_sync(source, &destination).await;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn initial_sync_reports_its_progress() -> anyhow::Result<()> {
+    let source = FakeSource::seeded();
+    let mut destination = FakeDestination::default();
+
+    destination
+        .receive(sync(
+            source.clone(),
+            None,
+            SyncOptions {
+                page_pacing: Some(Duration::from_secs(60)),
+                clock: Arc::new(FakeClock::new(SystemTime::UNIX_EPOCH)),
+                ..SyncOptions::default()
+            },
+            SchemaCache::default(),
+        ))
+        .await?;
+
+    assert!(destination.has_log("Progress: 60 rows in 300s (0.2 rows/s)"));
+    assert!(destination
+        .has_log("The initial sync is about 66% done (2 of 3 tables synced, 60 documents)"));
+    assert!(destination.has_log("Summary: 75 rows in 420s"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn initial_sync_pauses_during_quiet_hours() -> anyhow::Result<()> {
+    let source = FakeSource::seeded();
+    let mut destination = FakeDestination::default();
+    let day = SystemTime::UNIX_EPOCH + Duration::from_secs(19_000 * 24 * 3600);
+    // One second before the quiet hours, with one second between pages
+    let clock = Arc::new(FakeClock::new(day + Duration::from_secs(9 * 3600 - 1)));
+    let options = SyncOptions {
+        page_pacing: Some(Duration::from_secs(1)),
+        backfill_throttle: BackfillThrottle {
+            max_rows_per_minute: None,
+            quiet_hours: Some("09:00-17:00".parse()?),
+        },
+        clock: clock.clone(),
+        ..SyncOptions::default()
+    };
+
+    destination
+        .receive(sync(
+            source.clone(),
+            None,
+            options.clone(),
+            SchemaCache::default(),
+        ))
+        .await?;
+    assert!(destination.has_log("Pausing the initial sync during the quiet hours"));
+    assert!(!destination.has_log("Initial sync successful"));
+    assert!(matches!(
+        destination.latest_state().map(|state| state.checkpoint),
+        Some(Checkpoint::InitialSync { .. })
+    ));
+
+    // The initial sync resumes once the quiet hours are over
+    clock.advance(Duration::from_secs(8 * 3600));
+    destination
+        .receive(sync(
+            source.clone(),
+            destination.latest_state(),
+            options,
+            SchemaCache::default(),
+        ))
+        .await?;
+    assert!(destination.has_log("Initial sync successful"));
+    assert_in_sync(source, &destination).await;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn every_row_is_attributed_to_the_configured_schema() -> anyhow::Result<()> {
+    let mut source = FakeSource::seeded();
+    let mut destination = FakeDestination {
+        schema_name: Some("convex".to_string()),
+        ..FakeDestination::default()
+    };
+    let options = || SyncOptions {
+        schema_name: Some("convex".to_string()),
+        ..SyncOptions::default()
+    };
+
+    // Initial sync: truncates, metadata rows and upserts
+    destination
+        .receive(sync(
+            source.clone(),
+            None,
+            options(),
+            SchemaCache::default(),
+        ))
+        .await?;
+
+    // Delta sync: upserts, deletes, and truncates of new tables
+    source.patch("table1", 0, json!({ "name": "Renamed" }));
+    source.delete("table2", 3);
+    source.insert(
+        "table4",
+        hashmap! {
+            "name".to_string() => json!("Document of a new table"),
+        },
+    );
+    destination
+        .receive(sync(
+            source.clone(),
+            destination.latest_state(),
+            options(),
+            SchemaCache::default(),
+        ))
+        .await?;
+
+    assert_eq!(destination.checkpointed_data.metadata.len(), 4);
+    assert_in_sync(source, &destination).await;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn table_names_are_prefixed() -> anyhow::Result<()> {
+    let source = FakeSource::seeded();
+    let mut destination = FakeDestination::default();
+
+    destination
+        .receive(sync(
+            source,
+            None,
+            SyncOptions {
+                table_prefix: Some("prod_".to_string()),
+                ..SyncOptions::default()
+            },
+            SchemaCache::default(),
+        ))
+        .await?;
+
+    let mut table_names: Vec<&str> = destination
+        .checkpointed_data
+        .tables
+        .keys()
+        .map(String::as_str)
+        .collect();
+    table_names.sort();
+    let metadata_table_name = format!("prod_{METADATA_TABLE_NAME}");
+    assert_eq!(
+        table_names,
+        vec![
+            metadata_table_name.as_str(),
+            "prod_table1",
+            "prod_table2",
+            "prod_table3",
+        ]
+    );
+    assert_eq!(
+        destination.checkpointed_data.tables["prod_table1"].len(),
+        25
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn component_tables_are_synced_to_their_own_schema() -> anyhow::Result<()> {
+    let mut source = FakeSource::default();
+    source.insert("jobs", hashmap! { "name".to_string() => json!("App job") });
+    source.insert(
+        "workpool/jobs",
+        hashmap! { "name".to_string() => json!("Queued job") },
+    );
+    source.insert(
+        "workpool/jobs",
+        hashmap! { "name".to_string() => json!("Running job") },
+    );
+
+    // Component tables are skipped unless components are synced
+    let mut destination = FakeDestination::default();
+    destination
+        .receive(sync(
+            source.clone(),
+            None,
+            SyncOptions::default(),
+            SchemaCache::default(),
+        ))
+        .await?;
+    assert_eq!(destination.checkpointed_data.tables["jobs"].len(), 1);
+    assert_eq!(destination.checkpointed_data.tables.len(), 1);
+
+    let mut destination = FakeDestination {
+        multiple_schemas: true,
+        ..FakeDestination::default()
+    };
+    let options = SyncOptions {
+        sync_components: true,
+        ..SyncOptions::default()
+    };
+    destination
+        .receive(sync(
+            source.clone(),
+            None,
+            options.clone(),
+            SchemaCache::default(),
+        ))
+        .await?;
+    assert_eq!(destination.checkpointed_data.tables["app.jobs"].len(), 1);
+    assert_eq!(
+        destination.checkpointed_data.tables["workpool.jobs"].len(),
+        2
+    );
+
+    // Changes are synced to the schema of their component too
+    source.delete("workpool/jobs", 0);
+    destination
+        .receive(sync(
+            source,
+            destination.latest_state(),
+            options,
+            SchemaCache::default(),
+        ))
+        .await?;
+    assert_eq!(destination.checkpointed_data.tables["app.jobs"].len(), 1);
+    assert_eq!(
+        destination.checkpointed_data.tables["workpool.jobs"].len(),
+        1
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn additional_deployments_are_synced_to_their_own_schema() -> anyhow::Result<()> {
+    let production = FakeSource::seeded();
+    let mut staging = FakeSource::default();
+    staging.insert(
+        "jobs",
+        hashmap! { "name".to_string() => json!("Staging job") },
+    );
+    let options = |schema_name: &str| SyncOptions {
+        schema_name: Some(schema_name.to_string()),
+        ..SyncOptions::default()
+    };
+    let deployments = |staging: &FakeSource| {
+        vec![AdditionalDeployment {
+            schema_name: "staging".to_string(),
+            source: staging.clone(),
+            options: options("staging"),
+            schema_cache: SchemaCache::default(),
+        }]
+    };
+
+    let mut destination = FakeDestination {
+        multiple_schemas: true,
+        ..FakeDestination::default()
+    };
+    destination
+        .receive(sync_deployments(
+            production.clone(),
+            None,
+            options("production"),
+            SchemaCache::default(),
+            deployments(&staging),
+        ))
+        .await?;
+    assert_eq!(
+        destination.checkpointed_data.tables["production.table1"].len(),
+        production.tables()["table1"].len()
+    );
+    assert_eq!(
+        destination.checkpointed_data.tables["staging.jobs"].len(),
+        1
+    );
+
+    // Every deployment resumes from its own checkpoint
+    let state = destination.latest_state().unwrap();
+    assert!(matches!(state.checkpoint, Checkpoint::DeltaUpdates { .. }));
+    assert!(matches!(
+        state.deployments["staging"].checkpoint,
+        Checkpoint::DeltaUpdates { .. }
+    ));
+    // The main deployment is idle, so only the staging deployment checkpoints
+    staging.delete("jobs", 0);
+    destination
+        .receive(sync_deployments(
+            production.clone(),
+            Some(state.clone()),
+            options("production"),
+            SchemaCache::default(),
+            deployments(&staging),
+        ))
+        .await?;
+    assert_eq!(
+        destination.checkpointed_data.tables["production.table1"].len(),
+        production.tables()["table1"].len()
+    );
+    assert!(destination.checkpointed_data.tables["staging.jobs"].is_empty());
+    let idle_state = destination.latest_state().unwrap();
+    assert_eq!(idle_state.checkpoint, state.checkpoint);
+    assert_ne!(
+        idle_state.deployments["staging"].checkpoint,
+        state.deployments["staging"].checkpoint
+    );
+
+    // Syncs where nothing changed anywhere don’t need a checkpoint
+    destination
+        .receive(sync_deployments(
+            production.clone(),
+            Some(idle_state.clone()),
+            options("production"),
+            SchemaCache::default(),
+            deployments(&staging),
+        ))
+        .await?;
+    assert_eq!(destination.latest_state(), Some(idle_state));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn tables_differing_by_case_are_not_merged() -> anyhow::Result<()> {
+    let mut source = FakeSource::default();
+    source.insert("users", hashmap! { "name".to_string() => json!("Ada") });
+    source.insert("Users", hashmap! { "name".to_string() => json!("Bob") });
+    source.insert("Users", hashmap! { "name".to_string() => json!("Eve") });
+    let mut destination = FakeDestination::default();
+
+    destination
+        .receive(sync(
+            source,
+            None,
+            SyncOptions {
+                table_renames: table_renames(["users", "Users"]),
+                ..SyncOptions::default()
+            },
+            SchemaCache::default(),
+        ))
+        .await?;
+
+    let tables = &destination.checkpointed_data.tables;
+    assert_eq!(tables["users"].len(), 1);
+    assert_eq!(tables["Users_2"].len(), 2);
+    assert!(!tables.contains_key("Users"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn table_renames_are_kept_when_a_colliding_table_appears() -> anyhow::Result<()> {
+    let mut source = FakeSource::default();
+    source.insert("users", hashmap! { "name".to_string() => json!("Ada") });
+    source.insert("Users", hashmap! { "name".to_string() => json!("Bob") });
+    let mut destination = FakeDestination::default();
+    destination
+        .receive(sync(
+            source.clone(),
+            None,
+            SyncOptions {
+                table_renames: table_renames(["users", "Users"]),
+                ..SyncOptions::default()
+            },
+            SchemaCache::default(),
+        ))
+        .await?;
+
+    // Syncs resuming from the state don’t need the renames of the schema
+    source.insert("Users", hashmap! { "name".to_string() => json!("Eve") });
+    source.insert("USERS", hashmap! { "name".to_string() => json!("Joe") });
+    for _ in 0..2 {
+        destination
+            .receive(sync(
+                source.clone(),
+                destination.latest_state(),
+                SyncOptions::default(),
+                SchemaCache::default(),
+            ))
+            .await?;
+    }
+
+    let tables = &destination.checkpointed_data.tables;
+    assert_eq!(tables["users"].len(), 1);
+    assert_eq!(tables["Users_2"].len(), 2);
+    assert_eq!(tables["USERS_3"].len(), 1);
+    let rename_warnings = destination
+        .current_data
+        .logs
+        .iter()
+        .filter(|(_, message)| message.contains("synced as USERS_3"))
+        .count();
+    assert_eq!(rename_warnings, 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn column_names_are_sanitized() -> anyhow::Result<()> {
+    let mut source = FakeSource::default();
+    source.insert(
+        "users",
+        hashmap! {
+            "user_name".to_string() => json!("Ada"),
+            "userName".to_string() => json!("ada"),
+        },
+    );
+    let id = source.insert(
+        "users",
+        hashmap! {
+            "userName".to_string() => json!("bob"),
+            "lastSeen.at".to_string() => json!("today"),
+        },
+    );
+    let mut destination = FakeDestination::default();
+
+    destination
+        .receive(sync(
+            source,
+            None,
+            SyncOptions {
+                sanitize_column_names: true,
+                ..SyncOptions::default()
+            },
+            SchemaCache::default(),
+        ))
+        .await?;
+
+    // The column colliding in the other document is renamed in this one too
+    let row = destination.checkpointed_data.tables["users"]
+        .iter()
+        .find(|row| row["_id"] == FivetranValue::String(id.clone()))
+        .expect("The document wasn’t synced");
+    assert_eq!(row["user_name_2"], FivetranValue::String("bob".to_string()));
+    assert_eq!(
+        row["last_seen_at"],
+        FivetranValue::String("today".to_string())
+    );
+    assert!(!row.contains_key("user_name"));
+    assert!(row.contains_key("_creationTime"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn sanitized_column_names_dont_change_when_a_colliding_column_appears() -> anyhow::Result<()>
+{
+    let mut source = FakeSource::default();
+    source.insert(
+        "users",
+        hashmap! {
+            "userName".to_string() => json!("ada"),
+        },
+    );
+    let mut destination = FakeDestination::default();
+    let options = || SyncOptions {
+        sanitize_column_names: true,
+        ..SyncOptions::default()
+    };
+
+    destination
+        .receive(sync(
+            source.clone(),
+            None,
+            options(),
+            SchemaCache::default(),
+        ))
+        .await?;
+
+    // A column that the schema would name first appears after the first sync
+    let colliding_id = source.insert(
+        "users",
+        hashmap! {
+            "user_name".to_string() => json!("Bob"),
+        },
+    );
+    let id = source.insert(
+        "users",
+        hashmap! {
+            "userName".to_string() => json!("carol"),
+        },
+    );
+    destination
+        .receive(sync(
+            source,
+            destination.latest_state(),
+            options(),
+            SchemaCache::default(),
+        ))
+        .await?;
+
+    let row = |id: &str| {
+        destination.checkpointed_data.tables["users"]
+            .iter()
+            .find(|row| row["_id"] == FivetranValue::String(id.to_string()))
+            .expect("The document wasn’t synced")
+            .clone()
+    };
+    assert_eq!(
+        row(&id)["user_name"],
+        FivetranValue::String("carol".to_string())
+    );
+    assert_eq!(
+        row(&colliding_id)["user_name_2"],
+        FivetranValue::String("Bob".to_string())
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn excluded_tables_are_not_synced() -> anyhow::Result<()> {
+    let mut source = FakeSource::seeded();
+    let mut destination = FakeDestination::default();
+    let options = || SyncOptions {
+        table_filter: TableFilter::new(
+            vec![parse_table_pattern("table*").unwrap()],
+            vec![parse_table_pattern("table2").unwrap()],
+        ),
+        ..SyncOptions::default()
+    };
+
+    destination
+        .receive(sync(
+            source.clone(),
+            None,
+            options(),
+            SchemaCache::default(),
+        ))
+        .await?;
+    assert!(!destination.checkpointed_data.tables.contains_key("table2"));
+    assert_eq!(destination.checkpointed_data.tables["table1"].len(), 25);
+
+    source.insert(
+        "table2",
+        hashmap! { "name".to_string() => json!("Excluded") },
+    );
+    source.insert(
+        "scratch",
+        hashmap! { "name".to_string() => json!("Not included") },
+    );
+    source.insert(
+        "table3",
+        hashmap! { "name".to_string() => json!("Included") },
+    );
+    destination
+        .receive(sync(
+            source.clone(),
+            destination.latest_state(),
+            options(),
+            SchemaCache::default(),
+        ))
+        .await?;
+    assert!(!destination.checkpointed_data.tables.contains_key("table2"));
+    assert!(!destination.checkpointed_data.tables.contains_key("scratch"));
+    assert_eq!(destination.checkpointed_data.tables["table3"].len(), 26);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn documents_not_matching_their_row_filter_are_not_synced() -> anyhow::Result<()> {
+    let mut source = FakeSource::seeded();
+    let mut destination = FakeDestination::default();
+    let options = || SyncOptions {
+        row_filters: RowFilters::new(BTreeMap::from([(
+            "table1".to_string(),
+            "index >= 20".parse().unwrap(),
+        )])),
+        ..SyncOptions::default()
+    };
+
+    destination
+        .receive(sync(
+            source.clone(),
+            None,
+            options(),
+            SchemaCache::default(),
+        ))
+        .await?;
+    assert_eq!(destination.checkpointed_data.tables["table1"].len(), 5);
+    assert_eq!(destination.checkpointed_data.tables["table2"].len(), 25);
+
+    // Documents that stop matching the filter are deleted, and those that
+    // start matching it are synced.
+    source.patch("table1", 22, json!({ "index": 3 }));
+    source.patch("table1", 2, json!({ "index": 30 }));
+    source.insert("table1", hashmap! { "index".to_string() => json!(1) });
+    destination
+        .receive(sync(
+            source.clone(),
+            destination.latest_state(),
+            options(),
+            SchemaCache::default(),
+        ))
+        .await?;
+    let mut indexes: Vec<f64> = destination.checkpointed_data.tables["table1"]
+        .iter()
+        .map(|row| match row["index"] {
+            FivetranValue::Double(index) => index,
+            ref value => panic!("Unexpected index {value:?}"),
+        })
+        .collect();
+    indexes.sort_by(f64::total_cmp);
+    assert_eq!(indexes, vec![20.0, 21.0, 23.0, 24.0, 30.0]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn stats_only_tables_are_synced_as_aggregates() -> anyhow::Result<()> {
+    let mut source = FakeSource::seeded();
+    let mut destination = FakeDestination::default();
+    let options = || SyncOptions {
+        stats_only_tables: BTreeSet::from(["table2".to_string()]),
+        ..SyncOptions::default()
+    };
+    let total = |destination: &FakeDestination, column: &str| -> i64 {
+        destination.checkpointed_data.tables[TABLE_STATS_TABLE_NAME]
+            .iter()
+            .map(|row| match row.get(column) {
+                Some(FivetranValue::Long(count)) => *count,
+                value => panic!("Unexpected {column}: {value:?}"),
+            })
+            .sum()
+    };
+
+    destination
+        .receive(sync(
+            source.clone(),
+            None,
+            options(),
+            SchemaCache::default(),
+        ))
+        .await?;
+    assert!(!destination.checkpointed_data.tables.contains_key("table2"));
+    assert_eq!(destination.checkpointed_data.tables["table1"].len(), 25);
+    assert_eq!(total(&destination, "upserts"), 25);
+
+    source.delete("table2", 0);
+    destination
+        .receive(sync(
+            source.clone(),
+            destination.latest_state(),
+            options(),
+            SchemaCache::default(),
+        ))
+        .await?;
+    assert_eq!(total(&destination, "upserts"), 25);
+    assert_eq!(total(&destination, "deletes"), 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn stored_files_are_synced_to_the_files_table() -> anyhow::Result<()> {
+    let mut source = FakeSource::seeded();
+    for content_type in ["image/png", "application/pdf"] {
+        source.insert(
+            STORAGE_TABLE_NAME,
+            hashmap! {
+                "contentType".to_string() => json!(content_type),
+                "size".to_string() => json!(1024),
+                "sha256".to_string() => json!("3q2+7w=="),
+            },
+        );
+    }
+    let mut destination = FakeDestination::default();
+    let options = || SyncOptions {
+        files_table: true,
+        file_download_urls: Some(Url::parse("https://aware-llama-900.convex.cloud").unwrap()),
+        table_filter: TableFilter::new(vec![], vec![parse_table_pattern("_storage").unwrap()]),
+        ..SyncOptions::default()
+    };
+
+    destination
+        .receive(sync(
+            source.clone(),
+            None,
+            options(),
+            SchemaCache::default(),
+        ))
+        .await?;
+    let files = &destination.checkpointed_data.tables[FILES_TABLE_NAME];
+    assert_eq!(files.len(), 2);
+    assert!(files.iter().all(|row| row.contains_key("download_url")));
+    assert!(!destination
+        .checkpointed_data
+        .tables
+        .contains_key(STORAGE_TABLE_NAME));
+
+    // Deleted files are deleted from the files table
+    source.delete(STORAGE_TABLE_NAME, 0);
+    destination
+        .receive(sync(
+            source.clone(),
+            destination.latest_state(),
+            options(),
+            SchemaCache::default(),
+        ))
+        .await?;
+    assert_eq!(
+        destination.checkpointed_data.tables[FILES_TABLE_NAME].len(),
+        1
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn truncates_are_recorded_in_the_metadata_table() -> anyhow::Result<()> {
+    let mut source = FakeSource::seeded();
+    let mut destination = FakeDestination::default();
+
+    destination
+        .receive(sync(
+            source.clone(),
+            None,
+            SyncOptions::default(),
+            SchemaCache::default(),
+        ))
+        .await?;
+
+    let truncated_tables = |destination: &FakeDestination| {
+        let mut tables: Vec<(FivetranValue, FivetranValue)> = destination
+            .checkpointed_data
+            .metadata
+            .iter()
+            .map(|row| {
+                (
+                    row.get("table_name").unwrap().clone(),
+                    row.get("reason").unwrap().clone(),
+                )
+            })
+            .collect();
+        tables.sort_by_key(|(table_name, _)| format!("{table_name:?}"));
+        tables
+    };
+    let initial_sync = FivetranValue::String("initial_sync".to_string());
+    assert_eq!(
+        truncated_tables(&destination),
+        vec![
+            (
+                FivetranValue::String("table1".to_string()),
+                initial_sync.clone()
+            ),
+            (
+                FivetranValue::String("table2".to_string()),
+                initial_sync.clone()
+            ),
+            (
+                FivetranValue::String("table3".to_string()),
+                initial_sync.clone()
+            ),
+        ]
+    );
+
+    source.insert(
+        "table4",
+        hashmap! {
+            "name".to_string() => json!("Document of a new table"),
+        },
+    );
+    destination
+        .receive(sync(
+            source.clone(),
+            destination.latest_state(),
+            SyncOptions::default(),
+            SchemaCache::default(),
+        ))
+        .await?;
+    assert_eq!(
+        truncated_tables(&destination).last(),
+        Some(&(
+            FivetranValue::String("table4".to_string()),
+            FivetranValue::String("new_table".to_string())
+        ))
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn refuses_resync_from_scratch_when_protected() -> anyhow::Result<()> {
+    let source = FakeSource::seeded();
+    let mut destination = FakeDestination::default();
+    let options = SyncOptions {
+        refuse_resync: true,
+        ..SyncOptions::default()
+    };
+
+    let result = destination
+        .receive(sync(source.clone(), None, options, SchemaCache::default()))
+        .await;
+
+    assert_eq!(
+        ConnectorError::from(result.unwrap_err()).code(),
+        tonic::Code::FailedPrecondition
+    );
+    assert_eq!(destination, FakeDestination::default());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn protection_from_resync_allows_delta_syncs() -> anyhow::Result<()> {
+    let mut source = FakeSource::seeded();
+    let mut destination = FakeDestination::default();
+    let options = SyncOptions {
+        refuse_resync: true,
+        ..SyncOptions::default()
+    };
+
+    destination
+        .receive(sync(
+            source.clone(),
+            None,
+            SyncOptions::default(),
+            SchemaCache::default(),
+        ))
+        .await?;
+
+    source.delete("table1", 3);
+    destination
+        .receive(sync(
+            source.clone(),
+            destination.latest_state(),
+            options,
+            SchemaCache::default(),
+        ))
+        .await?;
+    assert_in_sync(source, &destination).await;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn delta_sync_logs_new_columns() -> anyhow::Result<()> {
+    let mut source = FakeSource::seeded();
+    let mut destination = FakeDestination::default();
+    let schema_cache = SchemaCache::default();
+
+    destination
+        .receive(sync(
+            source.clone(),
+            None,
+            SyncOptions::default(),
+            schema_cache.clone(),
+        ))
+        .await?;
+
+    source.insert(
+        "table1",
+        hashmap! {
+            "name".to_string() => json!("Document with known columns"),
+        },
+    );
+    destination
+        .receive(sync(
+            source.clone(),
+            destination.latest_state(),
+            SyncOptions::default(),
+            schema_cache.clone(),
+        ))
+        .await?;
+    assert!(!destination.has_log("Discovered new columns"));
+
+    source.insert(
+        "table1",
+        hashmap! {
+            "color".to_string() => json!("blue"),
+        },
+    );
+    destination
+        .receive(sync(
+            source.clone(),
+            destination.latest_state(),
+            SyncOptions::default(),
+            schema_cache.clone(),
+        ))
+        .await?;
+    assert!(destination.has_log("Discovered new columns in table table1: color"));
+    assert_in_sync(source, &destination).await;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn delta_sync_refreshes_the_schema_once_per_page_with_new_columns() -> anyhow::Result<()> {
+    let mut source = FakeSource::seeded();
+    let mut destination = FakeDestination::default();
+    let schema_cache = SchemaCache::default();
+    let options = || SyncOptions {
+        schema_refresh_interval: None,
+        ..SyncOptions::default()
+    };
+    destination
+        .receive(sync(source.clone(), None, options(), schema_cache.clone()))
+        .await?;
+
+    // A single page of changes, every one adding a column
+    for i in 0..5 {
+        source.insert(
+            "table1",
+            hashmap! {
+                format!("column{i}") => json!(i),
+            },
+        );
+    }
+    let faulty_source = FaultySource::new(source.clone());
+    let call_counts = faulty_source.call_counts();
+    destination
+        .receive(sync(
+            faulty_source,
+            destination.latest_state(),
+            options(),
+            schema_cache.clone(),
+        ))
+        .await?;
+
+    assert_eq!(call_counts.get(SourceMethod::GetJsonSchemas), 1);
+    assert_eq!(call_counts.get(SourceMethod::GetTablesAndColumns), 0);
+    assert!(schema_cache.contains("table1", ["column4".to_string()].iter()));
+    assert_in_sync(source, &destination).await;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn delta_sync_refreshes_the_schema_regularly() -> anyhow::Result<()> {
+    let mut source = FakeSource::seeded();
+    let mut destination = FakeDestination::default();
+    let schema_cache = SchemaCache::default();
+    let options = SyncOptions {
+        schema_refresh_interval: Some(Duration::ZERO),
+        ..SyncOptions::default()
+    };
+
+    for _ in 0..2 {
+        destination
+            .receive(sync(
+                source.clone(),
+                destination.latest_state(),
+                options.clone(),
+                schema_cache.clone(),
+            ))
+            .await?;
+    }
+    assert!(!destination.has_log("Discovered new columns"));
+
+    source.insert(
+        "table4",
+        hashmap! {
+            "name".to_string() => json!("Document of a new table"),
+        },
+    );
+    destination
+        .receive(sync(
+            source.clone(),
+            destination.latest_state(),
+            options,
+            schema_cache.clone(),
+        ))
+        .await?;
+    assert!(destination.has_log("Discovered new columns in table table4: _creationTime, _id, name"));
+    assert!(schema_cache.json_schemas().is_some());
+    assert_in_sync(source, &destination).await;
+
+    Ok(())
+}
+
+/// A source failing half of its calls.
+fn unreliable_source(source: FakeSource) -> FaultySource {
+    SourceMethod::ALL
+        .into_iter()
+        .fold(FaultySource::new(source), |source, method| {
+            source.fail(method, |_| {
+                if rand::thread_rng().gen_bool(0.5) {
+                    anyhow::bail!("Unreliable source error");
+                }
+                Ok(())
+            })
+        })
+}
+
+#[tokio::test]
+async fn can_perform_an_initial_sync_from_an_unreliable_source() -> anyhow::Result<()> {
+    let source = FakeSource::seeded();
+    let mut destination = FakeDestination::default();
+
+    while destination
+        .receive(sync(
+            unreliable_source(source.clone()),
+            destination.latest_state(),
+            SyncOptions::default(),
+            SchemaCache::default(),
+        ))
+        .await
+        .is_err()
+    {}
+
+    assert_in_sync(source, &destination).await;
+
+    Ok(())
+}
+
+/// A source whose calls are pending the first time they are polled, as if
+/// they were slow.
+fn slow_source(source: FakeSource) -> FaultySource {
+    SourceMethod::ALL
+        .into_iter()
+        .fold(FaultySource::new(source), FaultySource::slow)
+}
+
+#[tokio::test]
+async fn emits_heartbeats_while_waiting_on_the_source() -> anyhow::Result<()> {
+    let mut source = FakeSource::seeded();
+    let mut destination = FakeDestination::default();
+    let options = || SyncOptions {
+        heartbeat_interval: Some(Duration::from_secs(30)),
+        clock: Arc::new(FakeClock::new(SystemTime::UNIX_EPOCH)),
+        ..SyncOptions::default()
+    };
+
+    destination
+        .receive(sync(
+            slow_source(source.clone()),
+            None,
+            options(),
+            SchemaCache::default(),
+        ))
+        .await?;
+    assert!(destination.has_log("Still waiting for a page of the initial sync"));
+
+    source.delete("table1", 0);
+    destination
+        .receive(sync(
+            slow_source(source.clone()),
+            destination.latest_state(),
+            options(),
+            SchemaCache::default(),
+        ))
+        .await?;
+    assert!(destination.has_log("Still waiting for changes"));
+    assert_in_sync(source, &destination).await;
+
+    Ok(())
+}
+
+/// The number of `list_snapshot` calls made by the time the first row of an
+/// initial sync is received and the runtime got a chance to run other tasks.
+async fn list_snapshot_calls_at_first_row(options: SyncOptions) -> anyhow::Result<u32> {
+    let source = FaultySource::new(FakeSource::seeded());
+    let call_counts = source.call_counts();
+    let mut updates = sync(source, None, options, SchemaCache::default());
+    while let Some(message) = updates.next().await {
+        if let UpdateMessage::Update { table_name, .. } = message? {
+            if table_name != METADATA_TABLE_NAME {
+                break;
+            }
+        }
+    }
+    for _ in 0..10 {
+        tokio::task::yield_now().await;
+    }
+    Ok(call_counts.get(SourceMethod::ListSnapshot))
+}
+
+#[tokio::test]
+async fn prefetches_the_next_page_while_streaming_rows() -> anyhow::Result<()> {
+    assert_eq!(
+        list_snapshot_calls_at_first_row(SyncOptions::default()).await?,
+        2
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn doesnt_prefetch_pages_when_paced() -> anyhow::Result<()> {
+    let options = SyncOptions {
+        page_pacing: Some(Duration::from_secs(1)),
+        clock: Arc::new(FakeClock::new(SystemTime::UNIX_EPOCH)),
+        ..SyncOptions::default()
+    };
+    assert_eq!(list_snapshot_calls_at_first_row(options).await?, 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn restarts_from_the_last_checkpoint_when_a_prefetched_page_fails() -> anyhow::Result<()> {
+    let source = FakeSource::seeded();
+    let mut table_names: Vec<&String> = source.tables().keys().collect();
+    table_names.sort();
+    let expected_ids: Vec<String> = table_names
+        .into_iter()
+        .flat_map(|table_name| &source.tables()[table_name])
+        .map(|document| document["_id"].as_str().unwrap().to_string())
+        .collect();
+    // Every call but the first fetches a prefetched page, and the fourth one
+    // fails once: the calls of the restarted sync are counted too.
+    let faulty_source = FaultySource::new(source).fail(SourceMethod::ListSnapshot, |call| {
+        if call.index == 3 {
+            let error = anyhow::anyhow!("The connection was reset");
+            return Err(ConnectorError::Unavailable(error).into());
+        }
+        Ok(())
+    });
+    let clock = Arc::new(FakeClock::new(SystemTime::UNIX_EPOCH));
+    let options = SyncOptions {
+        clock: clock.clone(),
+        ..SyncOptions::default()
+    };
+    let messages: Vec<UpdateMessage> = restart_on_transient_errors(None, 1, clock, |state| {
+        sync(
+            faulty_source.clone(),
+            state,
+            options.clone(),
+            SchemaCache::default(),
+        )
+    })
+    .try_collect()
+    .await?;
+
+    // The rows are received in order, and a restart sends the rows after the
+    // last checkpoint again. Checkpoints never skip rows that weren’t sent.
+    let mut next_row = 0;
+    let mut checkpointed_rows = 0;
+    let mut restarts = 0;
+    for message in messages {
+        match message {
+            UpdateMessage::Update {
+                table_name,
+                op_type: OpType::Upsert,
+                row,
+                ..
+            } if table_name != METADATA_TABLE_NAME => {
+                assert_eq!(
+                    row["_id"],
+                    FivetranValue::String(expected_ids[next_row].clone()),
+                    "Row {next_row} received out of order"
+                );
+                next_row += 1;
+            },
+            UpdateMessage::Checkpoint(state) => {
+                if let Checkpoint::InitialSync { cursor, .. } = state.checkpoint {
+                    assert_eq!(cursor.0.parse::<usize>()?, next_row);
+                } else {
+                    assert_eq!(next_row, expected_ids.len());
+                }
+                checkpointed_rows = next_row;
+            },
+            UpdateMessage::Log(LogLevel::Warning, message)
+                if message.contains("restarting it from the last checkpoint") =>
+            {
+                restarts += 1;
+                next_row = checkpointed_rows;
+            },
+            _ => {},
+        }
+    }
+    assert_eq!(restarts, 1);
+    assert_eq!(next_row, expected_ids.len());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn adapts_the_page_size_to_the_target_page_bytes() -> anyhow::Result<()> {
+    let source = FakeSource::seeded();
+    let counting_source = FaultySource::new(source.clone());
+    let call_counts = counting_source.call_counts();
+    let mut destination = FakeDestination::default();
+
+    // Every document is larger than the target, so pages shrink to a single
+    // document once the first page has been measured.
+    destination
+        .receive(sync(
+            counting_source,
+            None,
+            SyncOptions {
+                page_size: Some(10),
+                target_page_bytes: Some(1),
+                ..SyncOptions::default()
+            },
+            SchemaCache::default(),
+        ))
+        .await?;
+
+    assert!(call_counts.get(SourceMethod::ListSnapshot) > 40);
+    assert_in_sync(source, &destination).await;
+
+    Ok(())
+}
+
+/// A source whose `document_deltas` calls fail with a transient error a given
+/// number of times before succeeding.
+fn flaky_source(source: FakeSource, failures: u32) -> FaultySource {
+    FaultySource::new(source).fail(SourceMethod::DocumentDeltas, move |call| {
+        if call.index < failures {
+            let error = anyhow::anyhow!("The backend is overloaded");
+            return Err(ConnectorError::Unavailable(error).into());
+        }
+        Ok(())
+    })
+}
+
+#[tokio::test]
+async fn retries_changes_failing_with_transient_errors() -> anyhow::Result<()> {
+    let mut source = FakeSource::seeded();
+    let mut destination = FakeDestination::default();
+    destination
+        .receive(sync(
+            source.clone(),
+            None,
+            SyncOptions::default(),
+            SchemaCache::default(),
+        ))
+        .await?;
+
+    source.delete("table1", 0);
+    let clock = Arc::new(FakeClock::new(SystemTime::UNIX_EPOCH));
+    destination
+        .receive(sync(
+            flaky_source(source.clone(), 2),
+            destination.latest_state(),
+            SyncOptions {
+                clock: clock.clone(),
+                ..SyncOptions::default()
+            },
+            SchemaCache::default(),
+        ))
+        .await?;
+
+    assert!(destination.has_log("failed with a transient error, retrying in 1s (attempt 1/5)"));
+    assert_eq!(
+        clock.sleeps(),
+        vec![Duration::from_secs(1), Duration::from_secs(2)]
+    );
+    assert_in_sync(source, &destination).await;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn fails_after_too_many_transient_errors() -> anyhow::Result<()> {
+    let source = FakeSource::seeded();
+    let mut destination = FakeDestination::default();
+    destination
+        .receive(sync(
+            source.clone(),
+            None,
+            SyncOptions::default(),
+            SchemaCache::default(),
+        ))
+        .await?;
+
+    let result = destination
+        .receive(sync(
+            flaky_source(source, 6),
+            destination.latest_state(),
+            SyncOptions {
+                clock: Arc::new(FakeClock::new(SystemTime::UNIX_EPOCH)),
+                ..SyncOptions::default()
+            },
+            SchemaCache::default(),
+        ))
+        .await;
+
+    assert_eq!(
+        ConnectorError::from(result.unwrap_err()).code(),
+        tonic::Code::Unavailable
+    );
+
+    Ok(())
+}
+
+/// The error of a call rate limited by the Convex backend, which asks to
+/// retry after 30 seconds.
+fn rate_limited_error(endpoint: &str) -> anyhow::Error {
+    ApiError::RateLimited {
+        endpoint: endpoint.to_string(),
+        deploy_url: Url::parse("https://aware-llama-900.convex.cloud").unwrap(),
+        retry_after: Duration::from_secs(30),
+    }
+    .into()
+}
+
+#[tokio::test]
+async fn waits_for_the_backend_when_changes_are_rate_limited() -> anyhow::Result<()> {
+    let mut source = FakeSource::seeded();
+    let mut destination = FakeDestination::default();
+    destination
+        .receive(sync(
+            source.clone(),
+            None,
+            SyncOptions::default(),
+            SchemaCache::default(),
+        ))
+        .await?;
+
+    source.delete("table1", 0);
+    let rate_limited_source =
+        FaultySource::new(source.clone()).fail(SourceMethod::DocumentDeltas, |call| {
+            if call.index < 2 {
+                return Err(rate_limited_error("document_deltas"));
+            }
+            Ok(())
+        });
+    let clock = Arc::new(FakeClock::new(SystemTime::UNIX_EPOCH));
+    destination
+        .receive(sync(
+            rate_limited_source,
+            destination.latest_state(),
+            SyncOptions {
+                clock: clock.clone(),
+                ..SyncOptions::default()
+            },
+            SchemaCache::default(),
+        ))
+        .await?;
+
+    assert!(destination.has_log("was rate limited by the Convex backend, retrying in 30s"));
+    assert_eq!(clock.sleeps(), vec![Duration::from_secs(30); 2]);
+    assert_in_sync(source, &destination).await;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn waits_for_the_backend_when_the_initial_sync_is_rate_limited() -> anyhow::Result<()> {
+    let source = FakeSource::seeded();
+    // The second page, which is prefetched, is rate limited once.
+    let rate_limited_source =
+        FaultySource::new(source.clone()).fail(SourceMethod::ListSnapshot, |call| {
+            if call.index == 1 {
+                return Err(rate_limited_error("list_snapshot"));
+            }
+            Ok(())
+        });
+    let clock = Arc::new(FakeClock::new(SystemTime::UNIX_EPOCH));
+    let mut destination = FakeDestination::default();
+    destination
+        .receive(sync(
+            rate_limited_source,
+            None,
+            SyncOptions {
+                clock: clock.clone(),
+                ..SyncOptions::default()
+            },
+            SchemaCache::default(),
+        ))
+        .await?;
+
+    assert!(destination.has_log(
+        "The initial sync of fake_source was rate limited by the Convex backend, retrying in 30s \
+         (attempt 1/10)"
+    ));
+    assert_eq!(clock.sleeps(), vec![Duration::from_secs(30)]);
+    assert_in_sync(source, &destination).await;
+
+    Ok(())
+}
+
+/// An error returned by the Convex API with the given code.
+fn backend_error(endpoint: &str, code: &str) -> anyhow::Error {
+    ApiError::Backend {
+        endpoint: endpoint.to_string(),
+        deploy_url: Url::parse("https://aware-llama-900.convex.cloud").unwrap(),
+        status: StatusCode::BAD_REQUEST,
+        error: BackendError {
+            code: code.to_string(),
+            message: "Something went wrong".to_string(),
+        },
+    }
+    .into()
+}
+
+/// A source whose `document_deltas` endpoint refuses every cursor as too old,
+/// as if the connector had been paused for too long.
+fn expired_cursor_source(source: FakeSource) -> FaultySource {
+    FaultySource::new(source).fail(SourceMethod::DocumentDeltas, |_| {
+        Err(backend_error("document_deltas", "OutOfRetention"))
+    })
+}
+
+#[tokio::test]
+async fn resyncs_from_scratch_when_the_cursor_is_too_old() -> anyhow::Result<()> {
+    let mut source = FakeSource::seeded();
+    let mut destination = FakeDestination::default();
+    destination
+        .receive(sync(
+            source.clone(),
+            None,
+            SyncOptions::default(),
+            SchemaCache::default(),
+        ))
+        .await?;
+
+    source.delete("table1", 0);
+    source.insert("table4", hashmap! { "name".to_string() => json!("New") });
+    destination
+        .receive(sync(
+            expired_cursor_source(source.clone()),
+            destination.latest_state(),
+            SyncOptions::default(),
+            SchemaCache::default(),
+        ))
+        .await?;
+
+    assert!(destination.has_log("Syncing all data again from scratch"));
+    let cursor_expired = FivetranValue::String("cursor_expired".to_string());
+    let truncated_tables: Vec<&FivetranValue> = destination
+        .checkpointed_data
+        .metadata
+        .iter()
+        .filter(|row| row.get("reason") == Some(&cursor_expired))
+        .map(|row| row.get("table_name").unwrap())
+        .collect();
+    assert_eq!(
+        truncated_tables,
+        ["table1", "table2", "table3"]
+            .map(|table_name| FivetranValue::String(table_name.to_string()))
+            .iter()
+            .collect::<Vec<_>>()
+    );
+    assert!(matches!(
+        destination.latest_state().unwrap().checkpoint,
+        Checkpoint::DeltaUpdates { .. }
+    ));
+    assert_in_sync(source, &destination).await;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn doesnt_resync_when_the_cursor_is_too_old_if_protected() -> anyhow::Result<()> {
+    let source = FakeSource::seeded();
+    let mut destination = FakeDestination::default();
+    destination
+        .receive(sync(
+            source.clone(),
+            None,
+            SyncOptions::default(),
+            SchemaCache::default(),
+        ))
+        .await?;
+    let synced_destination = destination.checkpointed_data.clone();
+
+    let result = destination
+        .receive(sync(
+            expired_cursor_source(source),
+            destination.latest_state(),
+            SyncOptions {
+                refuse_resync: true,
+                ..SyncOptions::default()
+            },
+            SchemaCache::default(),
+        ))
+        .await;
+
+    assert_eq!(
+        ConnectorError::from(result.unwrap_err()).code(),
+        tonic::Code::FailedPrecondition
+    );
+    assert_eq!(destination.checkpointed_data, synced_destination);
+
+    Ok(())
+}
+
+/// A source that no longer retains a given snapshot, as if an initial sync had
+/// been paused for too long.
+fn expired_snapshot_source(source: FakeSource, expired_snapshot: i64) -> FaultySource {
+    FaultySource::new(source).fail(SourceMethod::ListSnapshot, move |call| {
+        if call.snapshot == Some(expired_snapshot) {
+            return Err(backend_error("list_snapshot", "OutOfRetention"));
+        }
+        Ok(())
+    })
+}
+
+#[tokio::test]
+async fn restarts_the_initial_sync_when_its_snapshot_expired() -> anyhow::Result<()> {
+    let mut source = FakeSource::seeded();
+    let mut destination = FakeDestination::default();
+    destination
+        .receive(sync(
+            source.clone(),
+            None,
+            SyncOptions::default(),
+            SchemaCache::default(),
+        ))
+        .await?;
+
+    // Resume from the middle of an initial sync whose snapshot has expired
+    // since, with documents deleted in the meantime.
+    source.delete("table1", 0);
+    let expired_state = State::create(
+        Checkpoint::InitialSync {
+            snapshot: -1,
+            cursor: ListSnapshotCursor::from("40".to_string()),
+            page_size: None,
+            documents_synced: Some(40),
+        },
+        ["table1", "table2"].map(String::from).into(),
+    );
+    destination
+        .receive(sync(
+            expired_snapshot_source(source.clone(), -1),
+            Some(expired_state),
+            SyncOptions::default(),
+            SchemaCache::default(),
+        ))
+        .await?;
+
+    assert!(destination.has_log("Starting it again from scratch"));
+    let snapshot_expired = FivetranValue::String("snapshot_expired".to_string());
+    let truncated_tables: Vec<&FivetranValue> = destination
+        .checkpointed_data
+        .metadata
+        .iter()
+        .filter(|row| row.get("reason") == Some(&snapshot_expired))
+        .map(|row| row.get("table_name").unwrap())
+        .collect();
+    assert_eq!(
+        truncated_tables,
+        ["table1", "table2"]
+            .map(|table_name| FivetranValue::String(table_name.to_string()))
+            .iter()
+            .collect::<Vec<_>>()
+    );
+    assert!(destination.has_log("Initial sync successful"));
+    assert_in_sync(source, &destination).await;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn warns_about_deprecations_when_the_sync_starts() -> anyhow::Result<()> {
+    let source = FakeSource::seeded();
+    let mut destination = FakeDestination::default();
+    destination
+        .receive(sync(
+            source.clone(),
+            None,
+            SyncOptions {
+                deprecations: BTreeSet::from([
+                    Deprecation::LegacyState,
+                    Deprecation::UnusedConfigField("page_cursor".to_string()),
+                ]),
+                ..SyncOptions::default()
+            },
+            SchemaCache::default(),
+        ))
+        .await?;
+
+    let warnings: Vec<&String> = destination
+        .current_data
+        .logs
+        .iter()
+        .filter(|(level, _)| *level == LogLevel::Warning)
+        .map(|(_, message)| message)
+        .collect();
+    assert_eq!(warnings.len(), 2);
+    assert!(warnings[0].starts_with("Deprecation warning [legacy_state]"));
+    assert!(warnings[1].starts_with("Deprecation warning [unused_config_field]"));
+    assert_in_sync(source, &destination).await;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn warns_once_about_missing_system_tables() -> anyhow::Result<()> {
+    let source = FakeSource::seeded();
+    let mut destination = FakeDestination::default();
+    destination
+        .receive(sync(
+            source.clone(),
+            None,
+            SyncOptions {
+                missing_system_tables: vec!["_scheduled_functions".to_string()],
+                ..SyncOptions::default()
+            },
+            SchemaCache::default(),
+        ))
+        .await?;
+
+    let warnings: Vec<&String> = destination
+        .current_data
+        .logs
+        .iter()
+        .filter(|(level, _)| *level == LogLevel::Warning)
+        .map(|(_, message)| message)
+        .collect();
+    assert_eq!(
+        warnings,
+        [
+            "fake_source doesn’t list the system tables _scheduled_functions, probably because \
+             its version of Convex doesn’t support syncing system tables, or because they are \
+             empty. They are only synced once the deployment returns them."
+        ]
+        .iter()
+        .collect::<Vec<_>>()
+    );
+    assert_in_sync(source, &destination).await;
+
+    Ok(())
+}
+
+/// Property tests checking that syncs always bring the destination in sync
+/// with the source, whatever the sizes of the tables, the page sizes, the
+/// interruptions and the changes made between syncs.
+mod pagination_properties {
+    use std::collections::{
+        BTreeMap,
+        HashMap,
+    };
+
+    use futures::StreamExt;
+    use maplit::hashmap;
+    use proptest::prelude::*;
+    use serde_json::json;
+
+    use super::{
+        FakeDestination,
+        FivetranValue,
+    };
+    use crate::{
+        convert::{
+            to_fivetran_row,
+            RowOptions,
+        },
+        schema_cache::SchemaCache,
+        sync::{
+            sync,
+            SyncOptions,
+        },
+        testing::FakeSource,
+    };
+
+    /// A change made to the deployment between two syncs. Tables and documents
+    /// are designated by positions, wrapped around the number of tables and
+    /// the size of the table.
+    #[derive(Debug, Clone)]
+    enum Change {
+        Insert { table: usize },
+        Patch { table: usize, position: usize },
+        Delete { table: usize, position: usize },
+    }
+
+    impl Change {
+        fn apply(&self, source: &mut FakeSource, tables_count: usize) {
+            let table_name = |table: usize| format!("table{}", table % tables_count);
+            let len = |source: &FakeSource, table_name: &str| {
+                source.tables().get(table_name).map_or(0, Vec::len)
+            };
+            match *self {
+                Change::Insert { table } => {
+                    source.insert(
+                        &table_name(table),
+                        hashmap! { "value".to_string() => json!(table) },
+                    );
+                },
+                Change::Patch { table, position } => {
+                    let table_name = table_name(table);
+                    let len = len(source, &table_name);
+                    if len > 0 {
+                        source.patch(&table_name, position % len, json!({ "value": position }));
+                    }
+                },
+                Change::Delete { table, position } => {
+                    let table_name = table_name(table);
+                    let len = len(source, &table_name);
+                    if len > 0 {
+                        source.delete(&table_name, position % len);
+                    }
+                },
+            }
+        }
+    }
+
+    fn change() -> impl Strategy<Value = Change> {
+        prop_oneof![
+            any::<usize>().prop_map(|table| Change::Insert { table }),
+            (any::<usize>(), any::<usize>())
+                .prop_map(|(table, position)| Change::Patch { table, position }),
+            (any::<usize>(), any::<usize>())
+                .prop_map(|(table, position)| Change::Delete { table, position }),
+        ]
+    }
+
+    fn page_size() -> impl Strategy<Value = Option<u32>> {
+        proptest::option::of(1..20u32)
+    }
+
+    /// Syncs the destination from its latest state, stopping after the given
+    /// number of messages as if the connector had been interrupted. Like
+    /// Fivetran, the rows received after the last checkpoint of an interrupted
+    /// sync are discarded.
+    async fn sync_once(
+        destination: &mut FakeDestination,
+        source: &FakeSource,
+        page_size: Option<u32>,
+        interrupted_after: Option<usize>,
+    ) {
+        destination.current_data = destination.checkpointed_data.clone();
+        let updates = sync(
+            source.clone(),
+            destination.latest_state(),
+            SyncOptions {
+                page_size,
+                ..SyncOptions::default()
+            },
+            SchemaCache::default(),
+        );
+        destination
+            .receive(updates.take(interrupted_after.unwrap_or(usize::MAX)))
+            .await
+            .expect("Unexpected error during the synchronization");
+    }
+
+    type Rows = BTreeMap<String, HashMap<String, FivetranValue>>;
+
+    /// The rows of a table, by ID.
+    fn rows_by_id(rows: impl IntoIterator<Item = HashMap<String, FivetranValue>>) -> Rows {
+        rows.into_iter()
+            .map(|row| match &row["_id"] {
+                FivetranValue::String(id) => (id.clone(), row),
+                other => panic!("Unexpected ID {other:?}"),
+            })
+            .collect()
+    }
+
+    /// Verifies that the checkpointed rows of the destination are the
+    /// documents of the source, ignoring the tables whose documents were all
+    /// deleted, which a new initial sync doesn’t create.
+    fn assert_converged(source: &FakeSource, destination: &FakeDestination) {
+        let expected: BTreeMap<String, Rows> = source
+            .tables()
+            .iter()
+            .filter(|(_, documents)| !documents.is_empty())
+            .map(|(table_name, documents)| {
+                let rows = documents.iter().map(|document| {
+                    to_fivetran_row(document.clone(), &RowOptions::default()).unwrap()
+                });
+                (table_name.clone(), rows_by_id(rows))
+            })
+            .collect();
+        let actual: BTreeMap<String, Rows> = destination
+            .checkpointed_data
+            .tables
+            .iter()
+            .filter(|(_, rows)| !rows.is_empty())
+            .map(|(table_name, rows)| (table_name.clone(), rows_by_id(rows.iter().cloned())))
+            .collect();
+        assert_eq!(actual, expected);
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig {
+            cases: 64, failure_persistence: None, ..ProptestConfig::default()
+        })]
+        #[test]
+        fn syncs_converge_to_the_source(
+            table_sizes in prop::collection::vec(0..30usize, 1..4),
+            interrupted_initial_syncs in prop::collection::vec(
+                (page_size(), 1..200usize, prop::collection::vec(change(), 0..5)),
+                0..4,
+            ),
+            page_size in page_size(),
+            rounds in prop::collection::vec(
+                (prop::collection::vec(change(), 0..15), proptest::option::of(1..50usize)),
+                0..5,
+            ),
+        ) {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            runtime.block_on(async {
+                let mut source = FakeSource::default();
+                for (table, size) in table_sizes.iter().enumerate() {
+                    for i in 0..*size {
+                        source.insert(
+                            &format!("table{table}"),
+                            hashmap! { "value".to_string() => json!(i) },
+                        );
+                    }
+                }
+                let mut destination = FakeDestination::default();
+
+                // Every interrupted attempt resumes from the last checkpoint of
+                // the previous one, possibly with a different page size, after
+                // the deployment changed.
+                for (page_size, interrupted_after, changes) in interrupted_initial_syncs {
+                    sync_once(&mut destination, &source, page_size, Some(interrupted_after)).await;
+                    for change in changes {
+                        change.apply(&mut source, table_sizes.len());
+                    }
+                }
+                sync_once(&mut destination, &source, page_size, None).await;
+                assert_converged(&source, &destination);
+
+                for (changes, interrupted_after) in rounds {
+                    for change in changes {
+                        change.apply(&mut source, table_sizes.len());
+                    }
+                    if interrupted_after.is_some() {
+                        sync_once(&mut destination, &source, page_size, interrupted_after).await;
+                    }
+                    sync_once(&mut destination, &source, page_size, None).await;
+                    assert_converged(&source, &destination);
+                }
+            });
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! Throttling of the initial sync (backfill), so that it doesn’t compete with
+//! production traffic on the deployment. Delta syncs are never throttled.
+
+use std::{
+    fmt::Display,
+    str::FromStr,
+    time::{
+        Duration,
+        SystemTime,
+    },
+};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// A daily time range (in UTC) during which the initial sync is paused, e.g.
+/// `09:00-17:00`. The range can span midnight (e.g. `22:00-06:00`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuietHours {
+    /// Seconds since midnight UTC.
+    start: u64,
+    /// Seconds since midnight UTC.
+    end: u64,
+}
+
+impl QuietHours {
+    /// If `now` is within the quiet hours, returns the time left until they
+    /// end.
+    pub fn remaining(&self, now: SystemTime) -> Option<Duration> {
+        let time_of_day = now
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+            % SECONDS_PER_DAY;
+        let in_range = if self.start <= self.end {
+            self.start <= time_of_day && time_of_day < self.end
+        } else {
+            time_of_day >= self.start || time_of_day < self.end
+        };
+        in_range.then(|| {
+            Duration::from_secs((self.end + SECONDS_PER_DAY - time_of_day) % SECONDS_PER_DAY)
+        })
+    }
+}
+
+impl FromStr for QuietHours {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_time = |time: &str| -> anyhow::Result<u64> {
+            let Some((hours, minutes)) = time.trim().split_once(':') else {
+                anyhow::bail!("Invalid time: {time}");
+            };
+            let (hours, minutes): (u64, u64) = (hours.parse()?, minutes.parse()?);
+            anyhow::ensure!(hours < 24 && minutes < 60, "Invalid time: {time}");
+            Ok(hours * 3600 + minutes * 60)
+        };
+
+        let Some((start, end)) = s.split_once('-') else {
+            anyhow::bail!("Invalid time range: {s}");
+        };
+        let quiet_hours = QuietHours {
+            start: parse_time(start)?,
+            end: parse_time(end)?,
+        };
+        anyhow::ensure!(
+            quiet_hours.start != quiet_hours.end,
+            "Empty time range: {s}"
+        );
+        Ok(quiet_hours)
+    }
+}
+
+impl Display for QuietHours {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}-{:02}:{:02} UTC",
+            self.start / 3600,
+            self.start % 3600 / 60,
+            self.end / 3600,
+            self.end % 3600 / 60
+        )
+    }
+}
+
+/// Limits applied to the initial sync.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BackfillThrottle {
+    pub max_rows_per_minute: Option<u32>,
+    pub quiet_hours: Option<QuietHours>,
+}
+
+impl BackfillThrottle {
+    /// The time to wait for before fetching more documents, so that the sync
+    /// doesn’t exceed the maximum throughput, given that `rows` documents were
+    /// synced in `elapsed`.
+    pub fn rate_delay(&self, rows: u64, elapsed: Duration) -> Option<Duration> {
+        let max_rows_per_minute = self.max_rows_per_minute?;
+        let expected = Duration::from_secs_f64(rows as f64 * 60.0 / max_rows_per_minute as f64);
+        expected
+            .checked_sub(elapsed)
+            .filter(|delay| !delay.is_zero())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(hours: u64, minutes: u64) -> SystemTime {
+        // Some day at the given time (UTC)
+        SystemTime::UNIX_EPOCH
+            + Duration::from_secs(19_000 * SECONDS_PER_DAY + hours * 3600 + minutes * 60)
+    }
+
+    #[test]
+    fn parses_quiet_hours() -> anyhow::Result<()> {
+        assert_eq!(
+            "09:00-17:30".parse::<QuietHours>()?,
+            QuietHours {
+                start: 9 * 3600,
+                end: 17 * 3600 + 30 * 60
+            }
+        );
+        assert_eq!(
+            " 22:00 - 06:00 ".parse::<QuietHours>()?.to_string(),
+            "22:00-06:00 UTC"
+        );
+        for invalid in [
+            "",
+            "09:00",
+            "9-17",
+            "09:00-24:00",
+            "09:60-10:00",
+            "09:00-09:00",
+        ] {
+            assert!(invalid.parse::<QuietHours>().is_err(), "{invalid}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn computes_the_time_left_in_quiet_hours() -> anyhow::Result<()> {
+        let quiet_hours: QuietHours = "09:00-17:00".parse()?;
+        assert_eq!(quiet_hours.remaining(at(8, 59)), None);
+        assert_eq!(
+            quiet_hours.remaining(at(9, 0)),
+            Some(Duration::from_secs(8 * 3600))
+        );
+        assert_eq!(
+            quiet_hours.remaining(at(16, 30)),
+            Some(Duration::from_secs(30 * 60))
+        );
+        assert_eq!(quiet_hours.remaining(at(17, 0)), None);
+        Ok(())
+    }
+
+    #[test]
+    fn supports_quiet_hours_spanning_midnight() -> anyhow::Result<()> {
+        let quiet_hours: QuietHours = "22:00-06:00".parse()?;
+        assert_eq!(
+            quiet_hours.remaining(at(23, 0)),
+            Some(Duration::from_secs(7 * 3600))
+        );
+        assert_eq!(
+            quiet_hours.remaining(at(5, 0)),
+            Some(Duration::from_secs(3600))
+        );
+        assert_eq!(quiet_hours.remaining(at(12, 0)), None);
+        Ok(())
+    }
+
+    #[test]
+    fn delays_syncs_exceeding_the_throughput_cap() {
+        let throttle = BackfillThrottle {
+            max_rows_per_minute: Some(600),
+            quiet_hours: None,
+        };
+        assert_eq!(
+            throttle.rate_delay(100, Duration::from_secs(4)),
+            Some(Duration::from_secs(6))
+        );
+        assert_eq!(throttle.rate_delay(100, Duration::from_secs(10)), None);
+        assert_eq!(
+            BackfillThrottle::default().rate_delay(100, Duration::ZERO),
+            None
+        );
+    }
+}
@@ -0,0 +1,212 @@
+//! Reconciliation of the row counts of a destination with the document counts
+//! of a deployment at a given snapshot, for the `verify` command.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+};
+
+use anyhow::Context;
+
+use crate::convex_api::{
+    ListSnapshotCursor,
+    Source,
+};
+
+/// Parses the row counts of the destination tables, given either as a JSON
+/// object (`{"messages": 120}`) or as CSV lines (`messages,120`) with an
+/// optional header line.
+pub fn parse_destination_counts(input: &str) -> anyhow::Result<BTreeMap<String, u64>> {
+    if input.trim_start().starts_with('{') {
+        return serde_json::from_str(input)
+            .context("Invalid JSON row counts (expected an object of table names to counts)");
+    }
+
+    let mut counts = BTreeMap::new();
+    for (index, line) in input.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let Some((table_name, count)) = line.split_once(',') else {
+            anyhow::bail!("Invalid CSV row counts on line {}: {line}", index + 1);
+        };
+        let Ok(count) = count.trim().parse() else {
+            if index == 0 {
+                // Header line
+                continue;
+            }
+            anyhow::bail!("Invalid row count on line {}: {line}", index + 1);
+        };
+        counts.insert(table_name.trim().to_string(), count);
+    }
+    Ok(counts)
+}
+
+/// Counts the documents of every table of the deployment at the given
+/// snapshot, by listing them. The snapshot must still be retained by the
+/// deployment.
+pub async fn snapshot_counts(
+    source: &impl Source,
+    snapshot: i64,
+) -> anyhow::Result<BTreeMap<String, u64>> {
+    let mut counts = BTreeMap::new();
+    let mut cursor: Option<ListSnapshotCursor> = None;
+    loop {
+        let page = source
+            .list_snapshot(Some(snapshot), cursor, None, None)
+            .await
+            .with_context(|| format!("Failed to list the documents at the snapshot {snapshot}"))?;
+        for value in page.values.iter().filter(|value| !value.deleted) {
+            *counts.entry(value.table.clone()).or_default() += 1;
+        }
+        if !page.has_more {
+            return Ok(counts);
+        }
+        cursor = Some(ListSnapshotCursor::from(
+            page.cursor
+                .context("Missing cursor when has_more was set")?,
+        ));
+    }
+}
+
+/// The comparison of a table of the deployment with its destination table.
+#[derive(Debug, PartialEq, Eq)]
+pub struct TableReport {
+    pub table_name: String,
+    /// The number of documents at the snapshot, or `None` if the table doesn’t
+    /// exist in the deployment.
+    pub source_count: Option<u64>,
+    /// The number of rows in the destination, or `None` if the table wasn’t
+    /// listed.
+    pub destination_count: Option<u64>,
+}
+
+impl TableReport {
+    pub fn matches(&self) -> bool {
+        self.source_count.unwrap_or_default() == self.destination_count.unwrap_or_default()
+    }
+}
+
+impl Display for TableReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let count = |count: Option<u64>| count.map_or("-".to_string(), |count| count.to_string());
+        write!(
+            f,
+            "{}: {} in Convex, {} in the destination",
+            self.table_name,
+            count(self.source_count),
+            count(self.destination_count)
+        )?;
+        if !self.matches() {
+            let difference = self.destination_count.unwrap_or_default() as i128
+                - self.source_count.unwrap_or_default() as i128;
+            write!(f, " (MISMATCH: {difference:+})")?;
+        }
+        Ok(())
+    }
+}
+
+/// Compares the counts of every table, in alphabetical order. The destination
+/// tables starting with `_` are ignored: they are created by the connector or
+/// by Fivetran, since Convex table names can’t start with `_`.
+pub fn compare(
+    source_counts: &BTreeMap<String, u64>,
+    destination_counts: &BTreeMap<String, u64>,
+) -> Vec<TableReport> {
+    let mut table_names: Vec<&String> = source_counts
+        .keys()
+        .chain(
+            destination_counts
+                .keys()
+                .filter(|table_name| !table_name.starts_with('_')),
+        )
+        .collect();
+    table_names.sort();
+    table_names.dedup();
+    table_names
+        .into_iter()
+        .map(|table_name| TableReport {
+            table_name: table_name.clone(),
+            source_count: source_counts.get(table_name).copied(),
+            destination_count: destination_counts.get(table_name).copied(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use maplit::btreemap;
+
+    use super::*;
+    use crate::testing::FakeSource;
+
+    #[test]
+    fn parses_json_and_csv_counts() -> anyhow::Result<()> {
+        let expected = btreemap! {
+            "messages".to_string() => 120,
+            "users".to_string() => 3,
+        };
+        assert_eq!(
+            parse_destination_counts(r#"{"messages": 120, "users": 3}"#)?,
+            expected
+        );
+        assert_eq!(
+            parse_destination_counts("table,count\nmessages, 120\n\nusers,3\n")?,
+            expected
+        );
+        assert!(parse_destination_counts("messages,many").is_err());
+        assert!(parse_destination_counts("messages,1\nusers,many").is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn counts_the_documents_at_the_snapshot() -> anyhow::Result<()> {
+        let mut source = FakeSource::seeded();
+        source.delete("table1", 0);
+        let snapshot = source.list_snapshot(None, None, None, None).await?.snapshot;
+
+        assert_eq!(
+            snapshot_counts(&source, snapshot).await?,
+            btreemap! {
+                "table1".to_string() => 24,
+                "table2".to_string() => 25,
+                "table3".to_string() => 25,
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn reports_the_discrepancies() {
+        let reports = compare(
+            &btreemap! {
+                "messages".to_string() => 120,
+                "users".to_string() => 3,
+                "empty".to_string() => 0,
+            },
+            &btreemap! {
+                "messages".to_string() => 118,
+                "users".to_string() => 3,
+                "archived".to_string() => 5,
+                "_convex_sync_metadata".to_string() => 10,
+            },
+        );
+
+        let mismatches: Vec<String> = reports
+            .iter()
+            .filter(|report| !report.matches())
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            mismatches,
+            vec![
+                "archived: - in Convex, 5 in the destination (MISMATCH: +5)",
+                "messages: 120 in Convex, 118 in the destination (MISMATCH: -2)",
+            ]
+        );
+        assert_eq!(reports.len(), 4);
+    }
+}
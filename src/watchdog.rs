@@ -0,0 +1,133 @@
+use std::time::Duration;
+
+use async_stream::try_stream;
+use futures::{
+    stream::BoxStream,
+    Stream,
+    StreamExt,
+};
+use tokio::time::Instant;
+
+use crate::{
+    error::ConnectorError,
+    fivetran_sdk::LogLevel,
+    log_severe,
+    sync::{
+        Checkpoint,
+        UpdateMessage,
+    },
+};
+
+/// Ends an update stream with an error if it doesn’t make any progress (no
+/// row, log or checkpoint emitted, heartbeats aside) for longer than
+/// `stall_timeout`, so that Fivetran can retry the sync instead of waiting on
+/// a stuck connector indefinitely.
+pub fn watchdog(
+    mut stream: BoxStream<'static, anyhow::Result<UpdateMessage>>,
+    stall_timeout: Duration,
+) -> impl Stream<Item = anyhow::Result<UpdateMessage>> {
+    try_stream! {
+        let mut last_checkpoint: Option<Checkpoint> = None;
+        let mut deadline = Instant::now() + stall_timeout;
+
+        loop {
+            let message = match tokio::time::timeout_at(deadline, stream.next()).await {
+                Ok(Some(message)) => message?,
+                Ok(None) => break,
+                Err(_) => {
+                    let diagnostic = format!(
+                        "The sync made no progress for {}s while {}. Aborting it so that it can be \
+                         retried.",
+                        stall_timeout.as_secs(),
+                        describe_phase(last_checkpoint.as_ref()),
+                    );
+                    log_severe(&diagnostic);
+                    yield UpdateMessage::Log(LogLevel::Severe, diagnostic.clone());
+                    Err(ConnectorError::Unavailable(anyhow::anyhow!(diagnostic)))?;
+                },
+            };
+
+            if let UpdateMessage::Checkpoint(ref state) = message {
+                last_checkpoint = Some(state.checkpoint.clone());
+            }
+            if !matches!(message, UpdateMessage::Heartbeat(_)) {
+                deadline = Instant::now() + stall_timeout;
+            }
+            yield message;
+        }
+    }
+}
+
+fn describe_phase(last_checkpoint: Option<&Checkpoint>) -> String {
+    match last_checkpoint {
+        None => "starting the sync (no checkpoint emitted yet)".to_string(),
+        Some(Checkpoint::InitialSync {
+            snapshot, cursor, ..
+        }) => {
+            format!("performing the initial sync (snapshot {snapshot}, last cursor {cursor})")
+        },
+        Some(Checkpoint::DeltaUpdates { cursor }) => {
+            format!("applying changes (last cursor {cursor})")
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use futures::stream;
+
+    use super::*;
+    use crate::sync::State;
+
+    #[tokio::test]
+    async fn forwards_messages_of_streams_making_progress() {
+        let stream = stream::iter([
+            Ok(UpdateMessage::Log(LogLevel::Info, "Hello".to_string())),
+            Ok(UpdateMessage::Checkpoint(State::create(
+                Checkpoint::DeltaUpdates { cursor: 42.into() },
+                HashSet::new(),
+            ))),
+        ])
+        .boxed();
+
+        let results: Vec<_> = watchdog(stream, Duration::from_secs(60)).collect().await;
+
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|result| result.is_ok()));
+    }
+
+    #[tokio::test]
+    async fn aborts_stuck_streams() {
+        let stream = stream::iter([Ok(UpdateMessage::Checkpoint(State::create(
+            Checkpoint::DeltaUpdates { cursor: 42.into() },
+            HashSet::new(),
+        )))])
+        .chain(stream::pending())
+        .boxed();
+
+        let results: Vec<_> = watchdog(stream, Duration::from_millis(50)).collect().await;
+
+        assert_eq!(results.len(), 3);
+        let Ok(UpdateMessage::Log(LogLevel::Severe, ref diagnostic)) = results[1] else {
+            panic!("Expected a SEVERE log entry");
+        };
+        assert!(diagnostic.contains("last cursor 42"));
+        assert!(results[2].is_err());
+    }
+
+    #[tokio::test]
+    async fn heartbeats_dont_count_as_progress() {
+        let stream = stream::repeat_with(|| Ok(UpdateMessage::Heartbeat("Waiting".to_string())))
+            .then(|message| async {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                message
+            })
+            .boxed();
+
+        let results: Vec<_> = watchdog(stream, Duration::from_millis(50)).collect().await;
+
+        assert!(matches!(results.last(), Some(Err(_))));
+    }
+}